@@ -31,35 +31,60 @@ fn main() {
     };
 
     let pg_version = get("PG_VERSION");
-    let pgvector_version = get("PGVECTOR_VERSION");
-    let pgvector_tag = get("PGVECTOR_COMPILED_TAG");
-    let pgvector_repo = get("PGVECTOR_COMPILED_REPO");
 
     println!("cargo:rustc-env=PG_VERSION={}", pg_version);
-    println!("cargo:rustc-env=PGVECTOR_VERSION={}", pgvector_version);
-    println!("cargo:rustc-env=PGVECTOR_COMPILED_TAG={}", pgvector_tag);
-    println!("cargo:rustc-env=PGVECTOR_COMPILED_REPO={}", pgvector_repo);
 
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
 
+    // pg0 downloads other PostgreSQL versions for the same platform, from
+    // the same place unless PG0_BINARIES_BASE_URL says otherwise at runtime
+    println!(
+        "cargo:rustc-env=POSTGRESQL_TARGET={}",
+        postgresql_target(&env::var("TARGET").unwrap()).unwrap_or("")
+    );
+    println!("cargo:rerun-if-env-changed=PG0_BINARIES_BASE_URL");
+    println!(
+        "cargo:rustc-env=POSTGRESQL_BINARIES_BASE_URL={}",
+        binaries_base_url()
+    );
     bundle_postgresql(&pg_version, &out_dir);
-    bundle_pgvector(&pg_version, &pgvector_tag, &pgvector_repo, &out_dir);
+    bundle_extensions(&versions, &pg_version, &out_dir);
     bundle_runtime_libs(&versions, &out_dir);
 }
 
+/// Where the theseus-rs PostgreSQL archives are downloaded from:
+/// PG0_BINARIES_BASE_URL, for a mirror laid out like the GitHub releases
+/// (`<base>/<version>/<file>`), or the GitHub releases themselves.
+fn binaries_base_url() -> String {
+    env::var("PG0_BINARIES_BASE_URL")
+        .ok()
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| {
+            "https://github.com/theseus-rs/postgresql-binaries/releases/download".to_string()
+        })
+}
+
+/// Map Rust target to theseus-rs binary name
+fn postgresql_target(target: &str) -> Option<&'static str> {
+    match target {
+        "aarch64-apple-darwin" => Some("aarch64-apple-darwin"),
+        "x86_64-apple-darwin" => Some("x86_64-apple-darwin"),
+        "x86_64-unknown-linux-gnu" => Some("x86_64-unknown-linux-gnu"),
+        "x86_64-unknown-linux-musl" => Some("x86_64-unknown-linux-musl"),
+        "aarch64-unknown-linux-gnu" => Some("aarch64-unknown-linux-gnu"),
+        "aarch64-unknown-linux-musl" => Some("aarch64-unknown-linux-musl"),
+        "x86_64-pc-windows-msvc" => Some("x86_64-pc-windows-msvc"),
+        _ => None,
+    }
+}
+
 fn bundle_postgresql(pg_version: &str, out_dir: &PathBuf) {
     let target = env::var("TARGET").unwrap();
 
-    // Map Rust target to theseus-rs binary name
-    let pg_target = match target.as_str() {
-        "aarch64-apple-darwin" => "aarch64-apple-darwin",
-        "x86_64-apple-darwin" => "x86_64-apple-darwin",
-        "x86_64-unknown-linux-gnu" => "x86_64-unknown-linux-gnu",
-        "x86_64-unknown-linux-musl" => "x86_64-unknown-linux-musl",
-        "aarch64-unknown-linux-gnu" => "aarch64-unknown-linux-gnu",
-        "aarch64-unknown-linux-musl" => "aarch64-unknown-linux-musl",
-        "x86_64-pc-windows-msvc" => "x86_64-pc-windows-msvc",
-        _ => {
+    let pg_target = match postgresql_target(&target) {
+        Some(pg_target) => pg_target,
+        None => {
             eprintln!(
                 "Warning: Unknown target {}, PostgreSQL will not be bundled",
                 target
@@ -81,10 +106,7 @@ fn bundle_postgresql(pg_version: &str, out_dir: &PathBuf) {
         "tar.gz"
     };
     let filename = format!("postgresql-{}-{}.{}", pg_version, pg_target, ext);
-    let url = format!(
-        "https://github.com/theseus-rs/postgresql-binaries/releases/download/{}/{}",
-        pg_version, filename
-    );
+    let url = format!("{}/{}/{}", binaries_base_url(), pg_version, filename);
 
     let bundle_path = out_dir.join(&filename);
 
@@ -106,60 +128,118 @@ fn bundle_postgresql(pg_version: &str, out_dir: &PathBuf) {
     );
 }
 
-fn bundle_pgvector(pg_version: &str, pgvector_tag: &str, pgvector_repo: &str, out_dir: &PathBuf) {
+/// Download the extensions listed in BUNDLED_EXTENSIONS and generate
+/// `bundled_extensions.rs`, the table main.rs embeds them from. See
+/// versions.env for the keys each extension takes.
+fn bundle_extensions(versions: &HashMap<String, String>, pg_version: &str, out_dir: &PathBuf) {
     let target = env::var("TARGET").unwrap();
-
-    // Map Rust target to pgvector platform name
-    let pgvector_platform = match target.as_str() {
-        "aarch64-apple-darwin" => "aarch64-apple-darwin",
-        "x86_64-apple-darwin" => "x86_64-apple-darwin",
-        "x86_64-unknown-linux-gnu" => "x86_64-unknown-linux-gnu",
-        "x86_64-unknown-linux-musl" => "x86_64-unknown-linux-gnu", // musl uses gnu pgvector
-        "aarch64-unknown-linux-gnu" => "aarch64-unknown-linux-gnu",
-        "aarch64-unknown-linux-musl" => "aarch64-unknown-linux-gnu", // musl uses gnu pgvector
-        "x86_64-pc-windows-msvc" => "x86_64-pc-windows-msvc",
-        _ => {
-            eprintln!(
-                "Warning: Unknown target {}, pgvector will not be bundled",
-                target
-            );
-            let marker = out_dir.join("pgvector_bundle.tar.gz");
-            fs::write(&marker, b"").expect("Failed to create empty pgvector marker");
-            println!(
-                "cargo:rustc-env=PGVECTOR_BUNDLE_PATH={}",
-                marker.display()
-            );
-            return;
-        }
+    let target_key = target.to_uppercase().replace('-', "_");
+
+    // Map Rust target to the platform name extension builds are published for
+    let platform = match target.as_str() {
+        "aarch64-apple-darwin" => Some("aarch64-apple-darwin"),
+        "x86_64-apple-darwin" => Some("x86_64-apple-darwin"),
+        "x86_64-unknown-linux-gnu" => Some("x86_64-unknown-linux-gnu"),
+        "x86_64-unknown-linux-musl" => Some("x86_64-unknown-linux-gnu"), // musl uses gnu builds
+        "aarch64-unknown-linux-gnu" => Some("aarch64-unknown-linux-gnu"),
+        "aarch64-unknown-linux-musl" => Some("aarch64-unknown-linux-gnu"), // musl uses gnu builds
+        "x86_64-pc-windows-msvc" => Some("x86_64-pc-windows-msvc"),
+        _ => None,
     };
+    // Extension registries serve the same platform builds at runtime
+    println!(
+        "cargo:rustc-env=EXTENSION_PLATFORM={}",
+        platform.unwrap_or("")
+    );
 
     // Get PG major version (e.g., "18" from "18.1.0")
     let pg_major = pg_version.split('.').next().unwrap_or("18");
 
-    let filename = format!("pgvector-{}-pg{}.tar.gz", pgvector_platform, pg_major);
-    let url = format!(
-        "https://github.com/{}/releases/download/{}/{}",
-        pgvector_repo, pgvector_tag, filename
-    );
+    let names = versions
+        .get("BUNDLED_EXTENSIONS")
+        .map(String::as_str)
+        .unwrap_or("");
+    let names: Vec<&str> = names
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .collect();
+    // pgvector ships in every pg0 binary; there is no build without it.
+    if !names.contains(&"vector") {
+        panic!("BUNDLED_EXTENSIONS in versions.env must include vector");
+    }
+    let mut entries = Vec::new();
+    for name in names {
+        let key = name.to_uppercase().replace('-', "_");
+        let lookup = |suffix: &str| versions.get(&format!("{}_{}", key, suffix)).cloned();
+        let version =
+            lookup("VERSION").unwrap_or_else(|| panic!("Missing {}_VERSION in versions.env", key));
+
+        let Some(platform) = platform else {
+            eprintln!(
+                "Warning: Unknown target {}, {} will not be bundled",
+                target, name
+            );
+            continue;
+        };
+        let url = lookup(&format!("URL_{}", target_key))
+            .or_else(|| lookup("URL"))
+            .unwrap_or_else(|| panic!("Missing {}_URL in versions.env", key));
+        if url.is_empty() {
+            if name == "vector" {
+                panic!(
+                    "{}_URL_{} is empty, but pgvector must be bundled for every target",
+                    key, target_key
+                );
+            }
+            eprintln!("No {} build for {}, it will not be bundled", name, target);
+            continue;
+        }
+        let url = url
+            .replace("{platform}", platform)
+            .replace("{pg_major}", pg_major)
+            .replace("{version}", &version);
+
+        let bundle_path = out_dir.join(format!(
+            "ext-{}-{}-{}-pg{}.tar.gz",
+            name, version, platform, pg_major
+        ));
 
-    let bundle_path = out_dir.join(&filename);
+        // Download if not already cached
+        if !bundle_path.exists() {
+            eprintln!(
+                "Downloading {} {} for {} (PG {})...",
+                name, version, platform, pg_major
+            );
+            download_file(&url, &bundle_path)
+                .unwrap_or_else(|e| panic!("Failed to download {} bundle: {}", name, e));
+            eprintln!("Downloaded to {}", bundle_path.display());
+        } else {
+            eprintln!("Using cached {} bundle: {}", name, bundle_path.display());
+        }
+        match lookup(&format!("SHA256_{}", target_key)).filter(|sha256| !sha256.is_empty()) {
+            Some(sha256) => verify_sha256(&bundle_path, &sha256),
+            None => panic!(
+                "Missing {}_SHA256_{} in versions.env; refusing to bundle {} unverified (the downloaded {} has SHA256 {})",
+                key,
+                target_key,
+                name,
+                bundle_path.display(),
+                sha256_file(&bundle_path)
+            ),
+        }
 
-    // Download if not already cached
-    if !bundle_path.exists() {
-        eprintln!(
-            "Downloading pgvector for {} (PG {})...",
-            pgvector_platform, pg_major
-        );
-        download_file(&url, &bundle_path).expect("Failed to download pgvector bundle");
-        eprintln!("Downloaded to {}", bundle_path.display());
-    } else {
-        eprintln!("Using cached pgvector bundle: {}", bundle_path.display());
+        entries.push(format!(
+            "    BundledExtension {{ name: {:?}, version: {:?}, archive: include_bytes!({:?}) }},\n",
+            name,
+            version,
+            bundle_path.display().to_string()
+        ));
     }
 
-    println!(
-        "cargo:rustc-env=PGVECTOR_BUNDLE_PATH={}",
-        bundle_path.display()
-    );
+    let table = format!("&[\n{}]\n", entries.concat());
+    fs::write(out_dir.join("bundled_extensions.rs"), table)
+        .expect("Failed to write bundled_extensions.rs");
 }
 
 fn download_file(url: &str, dest: &PathBuf) -> io::Result<()> {
@@ -295,22 +375,26 @@ fn sha256_short(input: &str) -> String {
     hex::encode(&hasher.finalize()[..8])
 }
 
-fn verify_sha256(path: &Path, expected: &str) {
-    let mut file = File::open(path).expect("open deb for hashing");
+fn sha256_file(path: &Path) -> String {
+    let mut file = File::open(path).expect("open file for hashing");
     let mut hasher = Sha256::new();
     let mut buf = [0u8; 64 * 1024];
     loop {
-        let n = file.read(&mut buf).expect("read deb");
+        let n = file.read(&mut buf).expect("read file for hashing");
         if n == 0 {
             break;
         }
         hasher.update(&buf[..n]);
     }
-    let actual = hex::encode(hasher.finalize());
+    hex::encode(hasher.finalize())
+}
+
+fn verify_sha256(path: &Path, expected: &str) {
+    let actual = sha256_file(path);
     assert_eq!(
         actual,
         expected,
-        "SHA256 mismatch for {} — refusing to ship a deb that doesn't match versions.env",
+        "SHA256 mismatch for {} — refusing to ship a file that doesn't match versions.env",
         path.display()
     );
 }
@@ -94,6 +94,28 @@ else
     echo "⚠️  pgvector extension failed (expected on some platforms)"
 fi
 
+echo ""
+echo "=== Testing the connection pooler ==="
+PSQL=\$(ls -d ~/.pg0/installation/*/bin/psql | head -1)
+pg0 pool enable --port 5440
+PGPASSWORD=postgres "\$PSQL" -h 127.0.0.1 -p 5440 -U postgres -tAc "SELECT count(*) FROM test;" | grep -qx 2
+if PGPASSWORD=wrong "\$PSQL" -h 127.0.0.1 -p 5440 -U postgres -tAc "SELECT 1;" 2>/dev/null; then
+    echo "❌ pooler accepted a wrong password"
+    exit 1
+fi
+echo "✅ Pooler working correctly"
+
+echo ""
+echo "=== Testing --auth scram with the pooler and health checks ==="
+pg0 stop
+pg0 start --auth scram --health-port 5450
+sleep 3
+grep -q "^host.*scram-sha-256" ~/.pg0/instances/default/data/pg_hba.conf
+PGPASSWORD=postgres "\$PSQL" -h 127.0.0.1 -p 5440 -U postgres -tAc "SELECT count(*) FROM test;" | grep -qx 2
+curl -fsS http://127.0.0.1:5450/readyz
+echo "✅ SCRAM authentication working correctly"
+pg0 pool disable
+
 echo ""
 echo "=== Stopping PostgreSQL ==="
 pg0 stop
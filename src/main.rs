@@ -14,8 +14,17 @@ use tracing_subscriber::EnvFilter;
 /// The embedded PostgreSQL bundle
 static POSTGRESQL_BUNDLE: &[u8] = include_bytes!(env!("POSTGRESQL_BUNDLE_PATH"));
 
-/// The embedded pgvector bundle
-static PGVECTOR_BUNDLE: &[u8] = include_bytes!(env!("PGVECTOR_BUNDLE_PATH"));
+/// An extension archive embedded at build time (BUNDLED_EXTENSIONS in
+/// versions.env)
+struct BundledExtension {
+    name: &'static str,
+    version: &'static str,
+    archive: &'static [u8],
+}
+
+/// The embedded extension bundles, generated by build.rs
+static BUNDLED_EXTENSIONS: &[BundledExtension] =
+    include!(concat!(env!("OUT_DIR"), "/bundled_extensions.rs"));
 
 /// Extra runtime libraries (libxml2.so.2 + the libicu major it transitively
 /// loads) that the bundled PostgreSQL binary dynamic-links against. Empty on
@@ -53,88 +62,575 @@ enum CliError {
 #[command(version)]
 struct Cli {
     /// Enable verbose logging
-    #[arg(short, long, global = true)]
+    #[arg(short, long, global = true, env = "PG0_VERBOSE")]
     verbose: bool,
 
+    /// Directory for instances, installations and backups (default: ~/.pg0,
+    /// or $XDG_DATA_HOME/pg0 on new installs)
+    #[arg(long, global = true, env = "PG0_HOME", value_name = "DIR")]
+    base_dir: Option<String>,
+
+    /// Extension registry (base URL of a mirror) tried before the built-in
+    /// sources by install-extension and list-extensions; repeat or separate
+    /// with ';' for several
+    #[arg(
+        long = "extension-registry",
+        global = true,
+        env = "PG0_EXTENSION_REGISTRY",
+        hide_env_values = true,
+        value_name = "URL",
+        value_delimiter = ';'
+    )]
+    extension_registries: Vec<String>,
+
+    /// Never download anything: use only the bundled PostgreSQL, installed
+    /// versions and extensions, and fail when something would need a download
+    #[arg(long, global = true, env = "PG0_OFFLINE", value_parser = clap::builder::BoolishValueParser::new())]
+    offline: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 const DEFAULT_INSTANCE_NAME: &str = "default";
 
+// Parsed once per run; `Start` carries most of the flags.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Start PostgreSQL server
     Start {
         /// Instance name (allows running multiple instances)
-        #[arg(long, default_value = DEFAULT_INSTANCE_NAME)]
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
         name: String,
 
+        /// Use the instance of the project in the current directory, named
+        /// after it and recorded in a .pg0 marker file
+        #[arg(long, conflicts_with = "name")]
+        local: bool,
+
         /// Port to listen on (auto-allocates if not specified and default port is in use)
-        #[arg(short, long)]
+        #[arg(short, long, env = "PG0_PORT")]
         port: Option<u16>,
 
-        /// PostgreSQL version (must match bundled version)
-        #[arg(short = 'V', long, default_value = env!("PG_VERSION"))]
-        version: String,
+        /// PostgreSQL version (default: the bundled version, which works
+        /// offline; other versions are downloaded)
+        #[arg(short = 'V', long, env = "PG0_VERSION")]
+        version: Option<String>,
 
         /// Data directory (defaults to ~/.pg0/instances/<name>/data)
-        #[arg(short, long)]
+        #[arg(short, long, env = "PG0_DATA_DIR")]
         data_dir: Option<String>,
 
-        /// Username for the database
-        #[arg(short, long, default_value = "postgres")]
-        username: String,
+        /// Keep the data directory in RAM (/dev/shm on Linux, a RAM disk of
+        /// SIZE on macOS; default 1GB). All data is discarded on stop
+        #[arg(long, value_name = "SIZE", num_args = 0..=1, require_equals = true, default_missing_value = "1GB", conflicts_with = "data_dir", env = "PG0_IN_MEMORY")]
+        in_memory: Option<String>,
 
-        /// Password for the database
-        #[arg(short = 'P', long, default_value = "postgres")]
-        password: String,
+        /// Username for the database (default: postgres)
+        #[arg(short, long, env = "PG0_USERNAME")]
+        username: Option<String>,
 
-        /// Database name to create
-        #[arg(short = 'n', long, default_value = "postgres")]
-        database: String,
+        /// Password for the database (default: postgres)
+        #[arg(short = 'P', long, env = "PG0_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+
+        /// Generate a random password for a new instance; it is hidden in
+        /// pg0's output unless --show-secrets is passed
+        #[arg(long, conflicts_with = "password", env = "PG0_RANDOM_PASSWORD")]
+        random_password: bool,
+
+        /// Print a generated password instead of hiding it
+        #[arg(long)]
+        show_secrets: bool,
+
+        /// Keep the password in the OS keychain (macOS Keychain, Windows
+        /// Credential Manager or Secret Service) instead of instance.json
+        #[arg(long, env = "PG0_KEYCHAIN")]
+        keychain: bool,
+
+        /// Password authentication of TCP connections (default: password)
+        #[arg(long, value_enum, env = "PG0_AUTH")]
+        auth: Option<AuthMode>,
+
+        /// Serve TLS with a certificate from a generated local CA
+        #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", env = "PG0_TLS")]
+        tls: Option<bool>,
+
+        /// Also listen on these addresses, e.g. 0.0.0.0 for every interface
+        /// (can be used multiple times; localhost alone turns it off)
+        #[arg(long, value_name = "ADDR", value_delimiter = ',', env = "PG0_LISTEN")]
+        listen: Vec<String>,
+
+        /// Only accept remote connections from these networks, e.g.
+        /// 192.168.1.0/24 (can be used multiple times; default: any)
+        #[arg(long, value_name = "CIDR", value_delimiter = ',', env = "PG0_ALLOW")]
+        allow: Vec<String>,
 
-        /// PostgreSQL configuration options (can be used multiple times)
+        /// Put the Unix socket in this directory; pg0 and the connection
+        /// strings it prints then connect through it
+        #[arg(long, value_name = "PATH", env = "PG0_SOCKET_DIR")]
+        socket_dir: Option<String>,
+
+        /// Don't listen on TCP at all, only on the Unix socket (in the
+        /// instance directory unless --socket-dir is given)
+        #[arg(long, conflicts_with_all = ["listen", "tls", "health_port", "health_host"], env = "PG0_SOCKET_ONLY")]
+        socket_only: bool,
+
+        /// Database name to create (default: postgres)
+        #[arg(short = 'n', long, env = "PG0_DATABASE")]
+        database: Option<String>,
+
+        /// Create or update a role with access to the database; OPTIONS is a
+        /// comma-separated list of readwrite (the default), readonly, owner,
+        /// superuser and createdb (can be used multiple times; separated by
+        /// ';' in PG0_ROLE)
+        /// Example: --role app:secret --role report:secret:readonly
+        #[arg(
+            long = "role",
+            value_name = "NAME:PASSWORD[:OPTIONS]",
+            env = "PG0_ROLE",
+            hide_env_values = true,
+            value_delimiter = ';'
+        )]
+        roles: Vec<String>,
+
+        /// Run the *.sql, *.sql.gz and *.sh files in this directory, in name
+        /// order, when the data directory is first initialized (like
+        /// /docker-entrypoint-initdb.d)
+        #[arg(long, value_name = "DIR", env = "PG0_INIT_DIR")]
+        init_dir: Option<String>,
+
+        /// Run this SQL file against the database when the data directory is
+        /// first initialized, after --init-dir (can be used multiple times;
+        /// separated by ';' in PG0_INIT_SQL)
+        #[arg(long, value_name = "FILE", env = "PG0_INIT_SQL", value_delimiter = ';')]
+        init_sql: Vec<String>,
+
+        /// Create these extensions in the database once the server is up,
+        /// e.g. --with vector,pg_trgm (they must be installed; see
+        /// `pg0 install-extension`)
+        #[arg(
+            long = "with",
+            value_name = "EXTENSION",
+            value_delimiter = ',',
+            env = "PG0_WITH"
+        )]
+        extensions: Vec<String>,
+
+        /// PostgreSQL configuration options (can be used multiple times;
+        /// separated by ';' in PG0_CONFIG)
         /// Example: -c shared_buffers=512MB -c work_mem=128MB
-        #[arg(short = 'c', long = "config", value_name = "KEY=VALUE")]
+        #[arg(
+            short = 'c',
+            long = "config",
+            value_name = "KEY=VALUE",
+            env = "PG0_CONFIG",
+            hide_env_values = true,
+            value_delimiter = ';'
+        )]
         config: Vec<String>,
+
+        /// Tuning preset: vector (index builds, the default), oltp (many short
+        /// transactions), analytics (large sorts, parallel queries), minimal
+        /// (tiny footprint for CI)
+        #[arg(long, value_enum, env = "PG0_PRESET")]
+        preset: Option<Preset>,
+
+        /// Size memory and parallelism settings from this machine's RAM and CPU
+        /// cores (or --memory-limit/--cpu-limit) on top of the preset
+        #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", env = "PG0_AUTO_TUNE")]
+        auto_tune: Option<bool>,
+
+        /// Durability mode: safe (crash-safe, the default), balanced (may lose
+        /// the last few commits on crash, never corrupts), fast (not crash-safe)
+        #[arg(long, value_enum, env = "PG0_DURABILITY")]
+        durability: Option<Durability>,
+
+        /// UNSAFE: shorthand for --durability fast. Fastest startup and writes
+        /// for CI and tests; a crash can corrupt the cluster
+        #[arg(long, conflicts_with = "durability", env = "PG0_NO_DURABILITY")]
+        no_durability: bool,
+
+        /// Serve /healthz and /readyz on this port for liveness/readiness probes
+        #[arg(long, value_name = "PORT", env = "PG0_HEALTH_PORT")]
+        health_port: Option<u16>,
+
+        /// Address the health check server listens on (default: 127.0.0.1);
+        /// e.g. 0.0.0.0 for probes from outside the machine
+        #[arg(long, value_name = "ADDRESS", env = "PG0_HEALTH_HOST")]
+        health_host: Option<String>,
+
+        /// Stop the instance after it had no client connections for this
+        /// long, e.g. 30m or 2h
+        #[arg(long, value_name = "DURATION", env = "PG0_IDLE_TIMEOUT")]
+        idle_timeout: Option<String>,
+
+        /// Stop (or drop, see --ttl-action) the instance this long after
+        /// starting it, e.g. 2h
+        #[arg(long, value_name = "DURATION", env = "PG0_TTL")]
+        ttl: Option<String>,
+
+        /// What to do when the --ttl expires
+        #[arg(
+            long,
+            value_enum,
+            default_value = "stop",
+            requires = "ttl",
+            env = "PG0_TTL_ACTION"
+        )]
+        ttl_action: TtlAction,
+
+        /// Cap the memory of the postgres process tree, e.g. 2G (Linux cgroup
+        /// v2, Windows job object)
+        #[arg(long, value_name = "SIZE", env = "PG0_MEMORY_LIMIT")]
+        memory_limit: Option<String>,
+
+        /// Cap the CPU use of the postgres process tree in cores, e.g. 2 or 0.5
+        /// (Linux cgroup v2, Windows job object)
+        #[arg(long, value_name = "CPUS", env = "PG0_CPU_LIMIT")]
+        cpu_limit: Option<f64>,
+
+        /// Log execution plans of statements slower than MS milliseconds
+        /// (auto_explain; default 100). View them with `pg0 logs --plans`.
+        #[arg(long, value_name = "MS", num_args = 0..=1, require_equals = true, default_missing_value = "100", env = "PG0_AUTO_EXPLAIN")]
+        auto_explain: Option<u64>,
+
+        /// Audit log statements of these pgaudit classes (pgaudit.log, e.g.
+        /// ddl,write; default all; none turns it off). Needs `pg0
+        /// install-extension pgaudit`; view with `pg0 logs --grep AUDIT`.
+        #[arg(long, value_name = "CLASSES", num_args = 0..=1, require_equals = true, default_missing_value = "all", env = "PG0_AUDIT")]
+        audit: Option<String>,
+
+        /// Locale of a new cluster (initdb --locale; first start only)
+        #[arg(long, env = "PG0_LOCALE")]
+        locale: Option<String>,
+
+        /// Encoding of a new cluster (initdb --encoding; default UTF8; first start only)
+        #[arg(long, env = "PG0_ENCODING")]
+        encoding: Option<String>,
+
+        /// Collation order of a new cluster (initdb --lc-collate; first start only)
+        #[arg(long, env = "PG0_LC_COLLATE")]
+        lc_collate: Option<String>,
+
+        /// Locale provider of a new cluster (initdb --locale-provider; first start only)
+        #[arg(long, value_enum, env = "PG0_LOCALE_PROVIDER")]
+        locale_provider: Option<LocaleProvider>,
+
+        /// ICU locale of a new cluster, e.g. en-US; implies --locale-provider icu
+        /// (initdb --icu-locale; first start only)
+        #[arg(long, env = "PG0_ICU_LOCALE")]
+        icu_locale: Option<String>,
+
+        /// Authentication method for TCP connections of a new cluster, e.g.
+        /// scram-sha-256 or trust (initdb --auth-host; first start only)
+        #[arg(long, env = "PG0_AUTH_HOST")]
+        auth_host: Option<String>,
+
+        /// Extra initdb argument for a new cluster (can be used multiple times)
+        /// Example: --initdb-arg=--data-checksums
+        #[arg(
+            long,
+            value_name = "ARG",
+            allow_hyphen_values = true,
+            env = "PG0_INITDB_ARG",
+            hide_env_values = true,
+            value_delimiter = ';'
+        )]
+        initdb_arg: Vec<String>,
+
+        /// Label the instance, e.g. --label team=search (can be used multiple
+        /// times). Filter on labels with `pg0 list --filter`
+        #[arg(
+            long,
+            value_name = "KEY=VALUE",
+            env = "PG0_LABEL",
+            value_delimiter = ';'
+        )]
+        label: Vec<String>,
+
+        /// Run a shell command at a lifecycle event: pre-start, post-start
+        /// or post-stop, e.g. --hook post-start='npm run migrate' (can be
+        /// used multiple times; an empty command removes the hook)
+        #[arg(
+            long,
+            value_name = "EVENT=COMMAND",
+            env = "PG0_HOOK",
+            hide_env_values = true,
+            value_delimiter = ';'
+        )]
+        hook: Vec<String>,
+
+        /// Don't reuse the options of the previous start; flags that aren't
+        /// passed fall back to pg0's defaults
+        #[arg(long)]
+        reset: bool,
     },
     /// Stop PostgreSQL server
     Stop {
         /// Instance name
-        #[arg(long, default_value = DEFAULT_INSTANCE_NAME)]
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
         name: String,
+
+        /// Stop every running instance with these labels instead (KEY=VALUE,
+        /// or KEY for any value; can be used multiple times)
+        #[arg(long, value_name = "LABEL", conflicts_with = "name")]
+        filter: Vec<String>,
+
+        /// How to shut down
+        #[arg(long, value_enum, default_value_t = ShutdownMode::Fast, env = "PG0_STOP_MODE")]
+        mode: ShutdownMode,
+
+        /// How long to wait for the shutdown, e.g. 30s or 5m
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "60s",
+            env = "PG0_STOP_TIMEOUT"
+        )]
+        timeout: String,
+
+        /// If the shutdown doesn't finish in time, stop in immediate mode:
+        /// the server exits without a checkpoint and recovers on next start
+        #[arg(long)]
+        force: bool,
     },
     /// Drop an instance (stop if running, delete all data)
     Drop {
         /// Instance name
-        #[arg(long, default_value = DEFAULT_INSTANCE_NAME)]
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
         name: String,
 
-        /// Skip confirmation prompt
+        /// Skip confirmation prompt and stop a running instance in immediate
+        /// mode
         #[arg(short, long)]
         force: bool,
+
+        /// Drop every instance with these labels instead (KEY=VALUE, or KEY
+        /// for any value; can be used multiple times)
+        #[arg(long, value_name = "LABEL", conflicts_with = "name")]
+        filter: Vec<String>,
     },
     /// Show PostgreSQL server info (status, connection URI, etc.)
     Info {
         /// Instance name
-        #[arg(long, default_value = DEFAULT_INSTANCE_NAME)]
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
         name: String,
 
         /// Output format
-        #[arg(short, long, default_value = "text")]
-        output: OutputFormat,
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: ListFormat,
+
+        /// Refresh every second with live connection counts and status
+        /// changes, until interrupted (text output only)
+        #[arg(short, long, conflicts_with = "output")]
+        watch: bool,
+
+        /// Print only the connection string, in this client's format
+        #[arg(long, value_enum, conflicts_with_all = ["output", "watch"])]
+        format: Option<ConnectionFormat>,
+
+        /// Print a generated password instead of hiding it
+        #[arg(long)]
+        show_secrets: bool,
+    },
+    /// Print only the connection URI, e.g. for DATABASE_URL=$(pg0 url)
+    Url {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Database to connect to (defaults to the instance's database)
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Leave the password out of the URI
+        #[arg(long)]
+        no_password: bool,
+    },
+    /// Print DATABASE_URL and libpq PG* variables, e.g. for eval "$(pg0 env)"
+    Env {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Write the variables to this .env file instead, updating the keys
+        /// it already has and keeping every other line
+        #[arg(long, value_name = "PATH")]
+        dotenv: Option<PathBuf>,
     },
     /// List all instances
     List {
+        /// Only list instances with these labels (KEY=VALUE, or KEY for any
+        /// value; can be used multiple times)
+        #[arg(long, value_name = "LABEL")]
+        filter: Vec<String>,
+
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: ListFormat,
+
+        /// Print generated passwords instead of hiding them
+        #[arg(long)]
+        show_secrets: bool,
+    },
+    /// Summarize disk usage of everything pg0 manages, with housekeeping suggestions (all local)
+    Report {
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Interactive dashboard of all instances (start, stop, psql, logs)
+    Ui,
+    /// List client connections (user, database, client, state, query)
+    Sessions {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Terminate a client session, or all sessions idle in a transaction
+    #[command(group(clap::ArgGroup::new("target").required(true).args(["pid", "idle_in_transaction"])))]
+    KillSession {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Backend pid of the session to terminate (see `pg0 sessions`)
+        pid: Option<u32>,
+
+        /// Terminate every session that is idle in a transaction
+        #[arg(long)]
+        idle_in_transaction: bool,
+    },
+    /// VACUUM (and optionally ANALYZE) every database of an instance, via vacuumdb
+    Maintain {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Only maintain this database (default: all databases)
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Also update planner statistics (VACUUM (ANALYZE))
+        #[arg(long)]
+        analyze: bool,
+
+        /// VACUUM FULL: rewrite tables to return space to the OS (takes exclusive locks)
+        #[arg(long)]
+        full: bool,
+
+        /// Number of tables to process in parallel
+        #[arg(short, long, default_value_t = 1)]
+        jobs: u32,
+    },
+    /// Rebuild indexes of an instance, via reindexdb
+    Reindex {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Database to reindex (defaults to the instance's database)
+        #[arg(long, conflicts_with = "all")]
+        database: Option<String>,
+
+        /// Only rebuild this index (can be used multiple times)
+        #[arg(long)]
+        index: Vec<String>,
+
+        /// Only rebuild the indexes of this table (can be used multiple times)
+        #[arg(long)]
+        table: Vec<String>,
+
+        /// Reindex every database of the instance
+        #[arg(long, conflicts_with_all = ["index", "table"])]
+        all: bool,
+
+        /// Rebuild without blocking writes (REINDEX CONCURRENTLY; slower)
+        #[arg(long)]
+        concurrently: bool,
+
+        /// Number of indexes or tables to process in parallel
+        #[arg(short, long, default_value_t = 1)]
+        jobs: u32,
+    },
+    /// Verify data files: amcheck while running, page checksums while stopped
+    Verify {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Only check this database (default: all databases; online only)
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Also check that every heap tuple is present in its indexes (slower; online only)
+        #[arg(long)]
+        heapallindexed: bool,
+
+        /// Number of relations to check in parallel (online only)
+        #[arg(short, long, default_value_t = 1)]
+        jobs: u32,
+    },
+    /// Estimate table and index bloat, flagging VACUUM FULL / REINDEX candidates
+    Bloat {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Only check this database (default: all databases)
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Flag relations with at least this much estimated bloat (percent)
+        #[arg(long, default_value_t = 30.0)]
+        threshold: f64,
+
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Show lock waits as a tree of who blocks whom
+    Locks {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Live view of active queries, with cancel/terminate
+    Top {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+    /// Check the environment and instances for common problems and suggest fixes
+    Doctor {
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Show disk usage of instances, their databases and shared installations under ~/.pg0
+    Du {
+        /// Sort entries by size (largest first) or by name
+        #[arg(long, value_enum, default_value = "size")]
+        sort: DuSort,
+
         /// Output format
-        #[arg(short, long, default_value = "text")]
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
         output: OutputFormat,
     },
     /// Open psql shell connected to the running instance
     Psql {
         /// Instance name
-        #[arg(long, default_value = DEFAULT_INSTANCE_NAME)]
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
         name: String,
 
         /// Additional arguments to pass to psql
@@ -142,9 +638,13 @@ enum Commands {
         args: Vec<String>,
     },
     /// Show PostgreSQL logs
+    #[command(args_conflicts_with_subcommands = true)]
     Logs {
+        #[command(subcommand)]
+        command: Option<LogsCommands>,
+
         /// Instance name
-        #[arg(long, default_value = DEFAULT_INSTANCE_NAME)]
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
         name: String,
 
         /// Number of lines to show (default: all)
@@ -154,1333 +654,18279 @@ enum Commands {
         /// Follow log output (like tail -f)
         #[arg(short, long)]
         follow: bool,
+
+        /// Only show execution plans logged by auto_explain (-n limits the number of plans)
+        #[arg(long, conflicts_with = "follow")]
+        plans: bool,
+
+        /// Only show lines containing PATTERN, e.g. AUDIT for pgaudit entries
+        #[arg(long, value_name = "PATTERN", conflicts_with = "plans")]
+        grep: Option<String>,
     },
     /// Install a PostgreSQL extension (e.g., pgvector)
     InstallExtension {
         /// Instance name
-        #[arg(long, default_value = DEFAULT_INSTANCE_NAME)]
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Extension name (e.g., "vector", "postgis"), optionally with a
+        /// version to pin (e.g., "vector@0.7.4"). With --from, picks the
+        /// extension to set up when the source holds several
+        #[arg(required_unless_present = "from")]
+        extension: Option<String>,
+
+        /// Install from a local .tar.gz or directory instead of downloading
+        #[arg(long, value_name = "PATH", conflicts_with = "upgrade")]
+        from: Option<PathBuf>,
+
+        /// Install into this instance's private extension directory instead
+        /// of the installation shared by all instances of its version
+        /// (PostgreSQL 18+)
+        #[arg(long)]
+        isolated: bool,
+
+        /// Also run CREATE EXTENSION once it is installed
+        #[arg(long)]
+        create: bool,
+
+        /// Reinstall the latest version even if the extension is installed,
+        /// and update it in every database that has it
+        #[arg(long)]
+        upgrade: bool,
+
+        /// Install downloads that cannot be verified against a checksum
+        /// manifest
+        #[arg(long)]
+        insecure: bool,
+
+        /// Compile the extension from its source release with make and the
+        /// instance's pg_config, for platforms or PostgreSQL versions without
+        /// precompiled builds
+        #[arg(long, conflicts_with = "from")]
+        build_from_source: bool,
+
+        /// Database to create the extension in with --create (can be used
+        /// multiple times; default: the instance database)
+        #[arg(
+            short = 'd',
+            long = "database",
+            value_name = "DATABASE",
+            requires = "create"
+        )]
+        databases: Vec<String>,
+    },
+    /// Drop an extension from every database and remove its files
+    UninstallExtension {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
         name: String,
 
-        /// Extension name (e.g., "vector", "postgis")
+        /// Extension name
         extension: String,
+
+        /// Also drop the objects that depend on the extension
+        #[arg(long)]
+        cascade: bool,
+
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        force: bool,
     },
     /// List available extensions
-    ListExtensions,
-}
+    ListExtensions {
+        /// Instance name (with --installed)
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
 
-#[derive(Clone, Debug, Default, clap::ValueEnum)]
-enum OutputFormat {
-    #[default]
-    Text,
-    Json,
-}
+        /// List the extensions installed for an instance and the databases
+        /// they are created in, instead of the download catalog
+        #[arg(long)]
+        installed: bool,
 
-#[derive(Serialize, Deserialize)]
-struct InstanceInfo {
-    pid: u32,
-    port: u16,
-    data_dir: PathBuf,
-    installation_dir: PathBuf,
-    username: String,
-    password: String,
-    database: String,
-    version: String,
-}
+        /// Output format (with --installed)
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+    /// List the collations of a running instance (ICU by default)
+    ListCollations {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
 
-#[derive(Serialize)]
-struct InfoOutput {
-    name: String,
-    running: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pid: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    port: Option<u16>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    version: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    username: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    database: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    data_dir: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    uri: Option<String>,
-}
+        /// Only list collations whose name contains this text, e.g. "de-"
+        filter: Option<String>,
 
-fn get_base_dir() -> Result<PathBuf, CliError> {
-    dirs::home_dir()
-        .map(|h| h.join(".pg0"))
-        .ok_or(CliError::NoDataDir)
-}
+        /// Collation provider to list
+        #[arg(long, value_enum, default_value = "icu")]
+        provider: LocaleProvider,
 
-fn get_instances_dir() -> Result<PathBuf, CliError> {
-    Ok(get_base_dir()?.join("instances"))
-}
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Take a binary backup of a running instance with pg_basebackup
+    Basebackup {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
 
-fn get_instance_dir(name: &str) -> Result<PathBuf, CliError> {
-    Ok(get_instances_dir()?.join(name))
-}
+        /// Directory to write the backup into (defaults to ~/.pg0/backups/<name>/<timestamp>)
+        #[arg(short = 'D', long)]
+        output: Option<String>,
 
-fn get_state_file(name: &str) -> Result<PathBuf, CliError> {
-    Ok(get_instance_dir(name)?.join("instance.json"))
-}
+        /// Skip verifying the backup manifest checksums after the backup completes
+        #[arg(long)]
+        no_verify: bool,
 
-fn load_instance(name: &str) -> Result<Option<InstanceInfo>, CliError> {
-    let state_file = get_state_file(name)?;
-    if state_file.exists() {
-        let content = fs::read_to_string(&state_file)?;
-        Ok(Some(serde_json::from_str(&content)?))
-    } else {
-        Ok(None)
-    }
-}
+        /// Back up even if the instance crashed while running in fast durability mode
+        #[arg(long)]
+        force: bool,
+    },
+    /// Stop an instance and move its data directory into compressed cold storage
+    Archive {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
 
-fn save_instance(name: &str, info: &InstanceInfo) -> Result<(), CliError> {
-    let instance_dir = get_instance_dir(name)?;
-    fs::create_dir_all(&instance_dir)?;
-    let state_file = get_state_file(name)?;
-    fs::write(&state_file, serde_json::to_string_pretty(info)?)?;
-    Ok(())
-}
+        /// Archive file or directory to write to (defaults to
+        /// ~/.pg0/archive/<name>.tar.gz), or an http(s) URL of an object
+        /// store to upload it to (a trailing / appends <name>.tar.gz)
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Restore an archived instance's data directory so it can be started again
+    Unarchive {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
 
-fn remove_instance(name: &str) -> Result<(), CliError> {
-    let state_file = get_state_file(name)?;
-    if state_file.exists() {
-        fs::remove_file(&state_file)?;
-    }
-    Ok(())
-}
+        /// Keep the archive file after restoring
+        #[arg(long)]
+        keep: bool,
 
-fn list_instances() -> Result<Vec<String>, CliError> {
-    let instances_dir = get_instances_dir()?;
-    if !instances_dir.exists() {
-        return Ok(Vec::new());
-    }
+        /// Restore from this file or http(s) URL instead of where the archive
+        /// was written, e.g. a download URL of an object store that only
+        /// accepted the upload through a separate pre-signed URL
+        #[arg(long, value_name = "FILE|URL")]
+        from: Option<String>,
+    },
+    /// Manage streaming replicas of local instances
+    Replica {
+        #[command(subcommand)]
+        command: ReplicaCommands,
+    },
+    /// Promote a replica instance to a standalone primary
+    Promote {
+        /// Instance name of the replica to promote
+        #[arg(long)]
+        name: String,
 
-    let mut names = Vec::new();
-    for entry in fs::read_dir(&instances_dir)? {
-        let entry = entry?;
-        if entry.path().is_dir() {
-            if let Some(name) = entry.file_name().to_str() {
-                // Check if it has an instance.json file
-                if entry.path().join("instance.json").exists() {
-                    names.push(name.to_string());
-                }
-            }
-        }
-    }
-    names.sort();
-    Ok(names)
-}
+        /// Re-point the other replicas of the old primary at the promoted instance
+        #[arg(long)]
+        repoint: bool,
+    },
+    /// Set up logical replication of tables from one instance to another
+    Replicate {
+        /// Instance to publish changes from
+        #[arg(long)]
+        from: String,
 
-fn is_process_running(pid: u32) -> bool {
+        /// Instance to subscribe on
+        #[arg(long)]
+        to: String,
+
+        /// Tables to replicate: schema.table or schema.* (comma-separated; default: all tables)
+        #[arg(long, value_delimiter = ',')]
+        tables: Vec<String>,
+
+        /// Database on both instances (defaults to each instance's database)
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Copy the table definitions to the target before subscribing
+        #[arg(long)]
+        copy_schema: bool,
+
+        /// Tear down a previously created replication instead
+        #[arg(long)]
+        drop: bool,
+    },
+    /// Attach remote PostgreSQL databases as foreign schemas (postgres_fdw)
+    Fdw {
+        #[command(subcommand)]
+        command: FdwCommands,
+    },
+    /// Stream row changes from an instance as JSON lines (change data capture)
+    #[command(args_conflicts_with_subcommands = true)]
+    Cdc {
+        #[command(subcommand)]
+        command: Option<CdcCommands>,
+
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Logical replication slot to read from (created if missing)
+        #[arg(long, default_value = "pg0_cdc")]
+        slot: String,
+
+        /// Logical decoding output plugin
+        #[arg(long, value_enum, default_value = "pgoutput")]
+        plugin: CdcPlugin,
+
+        /// Tables to capture: schema.table or schema.* (comma-separated; default: all tables).
+        /// Applied when the slot is created.
+        #[arg(long, value_delimiter = ',')]
+        tables: Vec<String>,
+
+        /// Database to capture changes from (defaults to the instance's database)
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Keep streaming new changes instead of exiting once caught up
+        #[arg(short, long)]
+        follow: bool,
+
+        /// Drop the slot (and its publication) instead of streaming
+        #[arg(long)]
+        drop: bool,
+    },
+    /// Serve a token-protected HTTP endpoint for running SQL (POST /query)
+    Serve {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Port to listen on
+        #[arg(long, default_value = "8787")]
+        port: u16,
+
+        /// Address to bind (use 0.0.0.0 to accept connections from other machines)
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+
+        /// Access token (defaults to $PG0_SERVE_TOKEN, else a token generated and
+        /// stored with the instance)
+        #[arg(long)]
+        token: Option<String>,
+    },
+    /// Manage the instance's built-in connection pooler
+    Pool {
+        #[command(subcommand)]
+        command: PoolCommands,
+    },
+    /// Manage the instance's Prometheus metrics endpoint
+    Metrics {
+        #[command(subcommand)]
+        command: MetricsCommands,
+    },
+    /// Idle and TTL monitor process (started by `pg0 start --idle-timeout/--ttl`)
+    #[command(hide = true)]
+    AutostopWorker {
+        #[arg(long)]
+        name: String,
+    },
+    /// Health check server process (started by `pg0 start --health-port`)
+    #[command(hide = true)]
+    HealthWorker {
+        #[arg(long)]
+        name: String,
+    },
+    /// OpenTelemetry stats exporter process (started by `pg0 start` when
+    /// OTEL_EXPORTER_OTLP_ENDPOINT is set)
+    #[command(hide = true)]
+    OtelWorker {
+        #[arg(long)]
+        name: String,
+    },
+    /// Metrics exporter process (started by `pg0 metrics enable`)
+    #[command(hide = true)]
+    MetricsWorker {
+        #[arg(long)]
+        name: String,
+    },
+    /// Connection pooler process (started by `pg0 pool enable`)
+    #[command(hide = true)]
+    PoolWorker {
+        #[arg(long)]
+        name: String,
+    },
+    /// Background delivery worker for a CDC sink (started by `pg0 cdc sink add`)
+    #[command(hide = true)]
+    CdcWorker {
+        #[arg(long)]
+        name: String,
+
+        #[arg(long)]
+        sink: String,
+    },
+    /// Create or update an instance from a declarative spec (pg0.toml)
+    Apply {
+        /// Spec file
+        #[arg(default_value = "pg0.toml")]
+        file: String,
+    },
+    /// Manage an instance's pg0-managed PostgreSQL configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Manage an instance's pg_hba.conf rules, kept across restarts
+    Hba {
+        #[command(subcommand)]
+        command: HbaCommands,
+    },
+    /// Manage the libraries an instance preloads (shared_preload_libraries)
+    Preload {
+        #[command(subcommand)]
+        command: PreloadCommands,
+    },
+    /// Debugging helpers for a running instance
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommands,
+    },
+    /// Query statistics for a running instance
+    Stats {
+        #[command(subcommand)]
+        command: StatsCommands,
+    },
+    /// Run a command against throwaway instances of several PostgreSQL versions
+    Matrix {
+        #[command(subcommand)]
+        command: MatrixCommands,
+    },
+    /// Carry PostgreSQL versions and extensions to machines without internet
+    /// access
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ReplicaCommands {
+    /// Create a new instance that streams from a running primary
+    Create {
+        /// Name of the primary instance to replicate from
+        #[arg(long)]
+        of: String,
+
+        /// Name of the new replica instance
+        #[arg(long)]
+        name: String,
+
+        /// Port for the replica (auto-allocates if not specified)
+        #[arg(short, long)]
+        port: Option<u16>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Edit the managed configuration in $EDITOR, validate it and apply it
+    Edit {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+    /// Change settings of a running instance with ALTER SYSTEM and reload it
+    Set {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Settings to change (e.g. work_mem=256MB)
+        #[arg(required = true, value_name = "KEY=VALUE")]
+        settings: Vec<String>,
+
+        /// Restart without asking if a setting only takes effect after a restart
+        #[arg(long)]
+        restart: bool,
+    },
+    /// Show the effective value and source of the settings pg0 manages
+    Show {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Only show settings that differ from PostgreSQL's defaults
+        #[arg(long)]
+        diff: bool,
+
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum HbaCommands {
+    /// List the rules added with `pg0 hba add`
+    List {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+    /// Add a rule ahead of pg0's own rules (reloads a running instance)
+    Add {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Connection type: local, host, hostssl, hostnossl, hostgssenc or hostnogssenc
+        #[arg(long = "type", value_name = "TYPE", default_value = "host")]
+        kind: String,
+
+        /// Database(s) the rule applies to
+        #[arg(long, default_value = "all")]
+        database: String,
+
+        /// User(s) the rule applies to
+        #[arg(long, default_value = "all")]
+        user: String,
+
+        /// Client address: a network like 10.0.0.0/8, an IP address, a host
+        /// name, samehost, samenet or all (not for local rules)
+        #[arg(long)]
+        address: Option<String>,
+
+        /// Authentication method, e.g. scram-sha-256, trust or reject
+        #[arg(long, default_value = "scram-sha-256")]
+        method: String,
+
+        /// Authentication option (can be used multiple times)
+        #[arg(long = "option", value_name = "KEY=VALUE")]
+        options: Vec<String>,
+    },
+    /// Remove a rule by its number in `pg0 hba list` (reloads a running instance)
+    Remove {
+        /// Rule number
+        number: usize,
+
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum PreloadCommands {
+    /// List the preloaded libraries
+    List {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+    /// Preload libraries from the instance's installation
+    Add {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Libraries to add (e.g. pg_stat_statements)
+        #[arg(required = true, value_name = "LIBRARY")]
+        libraries: Vec<String>,
+
+        /// Restart a running instance without asking
+        #[arg(long)]
+        restart: bool,
+    },
+    /// Stop preloading libraries
+    Remove {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Libraries to remove
+        #[arg(required = true, value_name = "LIBRARY")]
+        libraries: Vec<String>,
+
+        /// Restart a running instance without asking
+        #[arg(long)]
+        restart: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MetricsCommands {
+    /// Serve Prometheus metrics for the instance
+    Enable {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Port to serve /metrics on
+        #[arg(long, default_value = "9187")]
+        port: u16,
+    },
+    /// Stop serving metrics
+    Disable {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum PoolCommands {
+    /// Expose a pooled port in front of the instance
+    Enable {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// When server connections go back to the pool
+        #[arg(long, value_enum, default_value = "transaction")]
+        mode: PoolMode,
+
+        /// Maximum server connections per user/database pair
+        #[arg(long, default_value = "20")]
+        size: usize,
+
+        /// Pooled port (defaults to the next free one after the instance port
+        /// in PG0_PORT_RANGE)
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// Print a generated password instead of hiding it
+        #[arg(long)]
+        show_secrets: bool,
+    },
+    /// Stop the pooler and forget its configuration
+    Disable {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum FdwCommands {
+    /// Create a foreign server for a remote database and import its tables
+    Add {
+        /// Foreign server name (also the default local schema name)
+        #[arg(long)]
+        name: String,
+
+        /// Connection URI of the remote database
+        #[arg(long)]
+        uri: String,
+
+        /// Local instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        instance: String,
+
+        /// Local database to import into (defaults to the instance's database)
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Remote schema to import
+        #[arg(long, default_value = "public")]
+        remote_schema: String,
+
+        /// Local schema to import into (defaults to the server name)
+        #[arg(long)]
+        local_schema: Option<String>,
+    },
+    /// Drop a foreign server, its user mapping and its imported schema
+    Remove {
+        /// Foreign server name
+        #[arg(long)]
+        name: String,
+
+        /// Local instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        instance: String,
+
+        /// Local database (defaults to the instance's database)
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Local schema the tables were imported into (defaults to the server name)
+        #[arg(long)]
+        local_schema: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CdcCommands {
+    /// Deliver captured changes continuously to a webhook or Kafka topic
+    Sink {
+        #[command(subcommand)]
+        command: SinkCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum SinkCommands {
+    /// Add a sink and start its background delivery worker
+    #[command(group(clap::ArgGroup::new("target").required(true).args(["url", "kafka"])))]
+    Add {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Sink id: letters, digits, '_' and '-' (defaults to sink1, sink2, ...)
+        #[arg(long)]
+        id: Option<String>,
+
+        /// Webhook URL that receives each batch of changes as a JSON array (POST)
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Kafka broker and topic to produce changes to, as host:port/topic
+        #[arg(long)]
+        kafka: Option<String>,
+
+        /// Tables to capture: schema.table or schema.* (comma-separated; default: all tables)
+        #[arg(long, value_delimiter = ',')]
+        tables: Vec<String>,
+
+        /// Database to capture changes from (defaults to the instance's database)
+        #[arg(long)]
+        database: Option<String>,
+    },
+    /// List sinks with their worker status and delivery cursor
+    List {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+    /// Stop a sink's worker and drop its replication slot
+    Remove {
+        /// Sink id
+        id: String,
+
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DebugCommands {
+    /// Turn statement logging on or off at runtime, or tail the logged statements
+    Queries {
+        /// on: log every statement with its duration; off: stop; tail: stream logged statements
+        #[arg(value_enum)]
+        action: QueryLogAction,
+
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum QueryLogAction {
+    On,
+    Off,
+    Tail,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum LocaleProvider {
+    /// The operating system's C library (differs between platforms)
+    Libc,
+    /// ICU, bundled with pg0 (same sort order everywhere)
+    Icu,
+    /// PostgreSQL's built-in provider (PostgreSQL 17+)
+    Builtin,
+}
+
+impl LocaleProvider {
+    fn as_str(self) -> &'static str {
+        match self {
+            LocaleProvider::Libc => "libc",
+            LocaleProvider::Icu => "icu",
+            LocaleProvider::Builtin => "builtin",
+        }
+    }
+
+    /// Value of `pg_collation.collprovider`.
+    fn catalog_code(self) -> char {
+        match self {
+            LocaleProvider::Libc => 'c',
+            LocaleProvider::Icu => 'i',
+            LocaleProvider::Builtin => 'b',
+        }
+    }
+}
+
+/// `pg_ctl stop -m` modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ShutdownMode {
+    /// Wait for clients to disconnect
+    Smart,
+    /// Roll back open transactions and disconnect clients
+    Fast,
+    /// Exit without a checkpoint; the next start runs crash recovery
+    Immediate,
+}
+
+impl ShutdownMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShutdownMode::Smart => "smart",
+            ShutdownMode::Fast => "fast",
+            ShutdownMode::Immediate => "immediate",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum DuSort {
+    Size,
+    Name,
+}
+
+#[derive(Subcommand)]
+enum LogsCommands {
+    /// Summarize slow statements, errors and checkpoint warnings from the server logs
+    Analyze {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Log every statement slower than this many milliseconds from now
+        /// on (log_min_duration_statement), or "off" to stop
+        #[arg(long, value_name = "MS|off")]
+        min_duration: Option<String>,
+
+        /// Number of slow statements and errors to show
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum StatsCommands {
+    /// Top queries from pg_stat_statements (loaded on first use, which restarts the instance)
+    Statements {
+        /// Instance name
+        #[arg(long, env = "PG0_NAME", default_value_t = discovered_instance_name())]
+        name: String,
+
+        /// Only show statements run against this database
+        #[arg(long)]
+        database: Option<String>,
+
+        /// Sort by total time, mean time or number of calls
+        #[arg(long, value_enum, default_value = "total")]
+        order_by: StatementOrder,
+
+        /// Number of statements to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /// Clear the collected statistics instead of showing them
+        #[arg(long)]
+        reset: bool,
+
+        /// Output format
+        #[arg(short, long, env = "PG0_OUTPUT", default_value = "text")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum StatementOrder {
+    Total,
+    Mean,
+    Calls,
+}
+
+impl StatementOrder {
+    fn column(&self) -> &'static str {
+        match self {
+            StatementOrder::Total => "total_exec_time",
+            StatementOrder::Mean => "mean_exec_time",
+            StatementOrder::Calls => "calls",
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum MatrixCommands {
+    /// Start one instance per version, run the command against each with
+    /// DATABASE_URL set, and report pass/fail per version
+    Run {
+        /// Comma-separated PostgreSQL versions (e.g. 16,17,18)
+        #[arg(long, value_delimiter = ',', required = true)]
+        versions: Vec<String>,
+
+        /// Keep the instances after the run instead of dropping them
+        #[arg(long)]
+        keep: bool,
+
+        /// Drop existing matrix-<version> instances (e.g. kept by an earlier
+        /// --keep run) instead of refusing to start
+        #[arg(long)]
+        force: bool,
+
+        /// Command to run (after --)
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum BundleCommands {
+    /// Write PostgreSQL versions and extensions into a bundle file,
+    /// downloading what is not installed yet
+    Export {
+        /// Bundle file to write
+        output: PathBuf,
+
+        /// PostgreSQL versions to include (comma-separated or repeated;
+        /// default: the bundled version)
+        #[arg(long = "version", value_name = "VERSION", value_delimiter = ',')]
+        versions: Vec<String>,
+
+        /// Extensions to include for each version, as NAME or NAME@VERSION
+        /// (comma-separated or repeated)
+        #[arg(long = "extension", value_name = "EXTENSION", value_delimiter = ',')]
+        extensions: Vec<String>,
+    },
+    /// Verify a bundle file and install its contents into the base directory
+    Import {
+        /// Bundle file written by `pg0 bundle export`
+        bundle: PathBuf,
+    },
+}
+
+/// Logical decoding output plugins `pg0 cdc` can read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum CdcPlugin {
+    /// Built-in plugin used by logical replication (decoded by pg0)
+    Pgoutput,
+    /// wal2json plugin (must be installed in the instance)
+    Wal2json,
+}
+
+#[derive(Clone, Debug, Default, clap::ValueEnum)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Output formats of `pg0 list` and `pg0 info`.
+#[derive(Clone, Debug, Default, clap::ValueEnum)]
+enum ListFormat {
+    #[default]
+    Text,
+    Json,
+    /// Aligned columns, one row per instance
+    Table,
+    Yaml,
+}
+
+/// Connection string flavours of `pg0 info --format`.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ConnectionFormat {
+    /// postgresql:// URI, as accepted by libpq, psql and most drivers
+    Uri,
+    /// JDBC URL for the PostgreSQL JDBC driver
+    Jdbc,
+    /// SQLAlchemy URL using psycopg2
+    Sqlalchemy,
+    /// ADO.NET connection string for Npgsql
+    Dotnet,
+    /// libpq keyword/value connection string
+    Keyword,
+}
+
+/// Connection string of the instance in every `ConnectionFormat` but the
+/// plain URI, included in the JSON output of `pg0 info`.
+#[derive(Serialize)]
+struct ConnectionStrings {
+    jdbc: String,
+    sqlalchemy: String,
+    dotnet: String,
+    keyword: String,
+}
+
+impl ConnectionStrings {
+    fn new(info: &InstanceInfo) -> Self {
+        Self {
+            jdbc: connection_string(info, ConnectionFormat::Jdbc),
+            sqlalchemy: connection_string(info, ConnectionFormat::Sqlalchemy),
+            dotnet: connection_string(info, ConnectionFormat::Dotnet),
+            keyword: connection_string(info, ConnectionFormat::Keyword),
+        }
+    }
+}
+
+/// Connection string for the instance's user and database in `format`.
+/// Instances with a socket directory are reached through the socket.
+fn connection_string(info: &InstanceInfo, format: ConnectionFormat) -> String {
+    let (user, password, port, database) =
+        (&info.username, &info.password, info.port, &info.database);
+    let host = instance_host(info);
+    match format {
+        ConnectionFormat::Uri => instance_uri(info, database),
+        // The JDBC driver has no Unix socket support of its own; junixsocket
+        // provides the socket factory.
+        ConnectionFormat::Jdbc => match &info.socket_dir {
+            Some(dir) if info.socket_only => format!(
+                "jdbc:postgresql://localhost/{}?user={}&password={}&socketFactory=org.newsclub.net.unix.AFUNIXSocketFactory$FactoryArg&socketFactoryArg={}",
+                database,
+                user,
+                password,
+                percent_encode(&dir.join(format!(".s.PGSQL.{}", port)).display().to_string())
+            ),
+            _ => format!(
+                "jdbc:postgresql://127.0.0.1:{}/{}?user={}&password={}",
+                port, database, user, password
+            ),
+        },
+        ConnectionFormat::Sqlalchemy => match &info.socket_dir {
+            Some(dir) => format!(
+                "postgresql+psycopg2://{}:{}@/{}?host={}&port={}",
+                user,
+                password,
+                database,
+                percent_encode(&dir.display().to_string()),
+                port
+            ),
+            None => format!(
+                "postgresql+psycopg2://{}:{}@127.0.0.1:{}/{}",
+                user, password, port, database
+            ),
+        },
+        ConnectionFormat::Dotnet => format!(
+            "Host={};Port={};Database={};Username={};Password={}",
+            host, port, database, user, password
+        ),
+        ConnectionFormat::Keyword => {
+            // Values with spaces, quotes or backslashes must be quoted.
+            let value = |v: &str| {
+                if !v.is_empty() && !v.contains(|c: char| c.is_whitespace() || c == '\'' || c == '\\') {
+                    v.to_string()
+                } else {
+                    format!("'{}'", v.replace('\\', "\\\\").replace('\'', "\\'"))
+                }
+            };
+            format!(
+                "host={} port={} dbname={} user={} password={}",
+                value(&host),
+                port,
+                value(database),
+                value(user),
+                value(password)
+            )
+        }
+    }
+}
+
+/// Host pg0 and the printed connection strings use for an instance: its
+/// socket directory if it has one, loopback TCP otherwise.
+fn instance_host(info: &InstanceInfo) -> String {
+    match &info.socket_dir {
+        Some(dir) => dir.display().to_string(),
+        None => "127.0.0.1".to_string(),
+    }
+}
+
+/// postgresql:// URI of `database` on the instance, as its own user.
+fn instance_uri(info: &InstanceInfo, database: &str) -> String {
+    local_uri(
+        info.socket_dir.as_deref(),
+        &format!("{}:{}", info.username, info.password),
+        info.port,
+        database,
+    )
+}
+
+/// `instance_uri` without the password, for client programs that get it
+/// through PGPASSWORD; other users can read a command line but not that.
+fn instance_uri_without_password(info: &InstanceInfo, database: &str) -> String {
+    local_uri(
+        info.socket_dir.as_deref(),
+        &info.username,
+        info.port,
+        database,
+    )
+}
+
+/// postgresql:// URI of a server on this machine, through the socket in
+/// `socket_dir` if given. `credentials` is `user` or `user:password`.
+fn local_uri(socket_dir: Option<&Path>, credentials: &str, port: u16, database: &str) -> String {
+    match socket_dir {
+        Some(dir) => format!(
+            "postgresql://{}@/{}?host={}&port={}",
+            credentials,
+            database,
+            percent_encode(&dir.display().to_string()),
+            port
+        ),
+        None => format!(
+            "postgresql://{}@127.0.0.1:{}/{}",
+            credentials, port, database
+        ),
+    }
+}
+
+/// Workload-specific groups of memory and planner settings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Preset {
+    /// Generous maintenance memory and parallelism for vector index builds
+    #[default]
+    Vector,
+    /// Many short transactions: small per-query memory, no JIT
+    Oltp,
+    /// Large sorts and hashes, parallel queries, detailed statistics
+    Analytics,
+    /// Smallest footprint, for CI runners and small containers
+    Minimal,
+}
+
+impl Preset {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Preset::Vector => "vector",
+            Preset::Oltp => "oltp",
+            Preset::Analytics => "analytics",
+            Preset::Minimal => "minimal",
+        }
+    }
+
+    fn settings(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Preset::Vector => &[
+                ("shared_buffers", "256MB"),
+                ("maintenance_work_mem", "512MB"),
+                ("effective_cache_size", "1GB"),
+                ("max_parallel_maintenance_workers", "4"),
+                ("work_mem", "64MB"),
+            ],
+            Preset::Oltp => &[
+                ("shared_buffers", "256MB"),
+                ("maintenance_work_mem", "128MB"),
+                ("effective_cache_size", "1GB"),
+                ("work_mem", "8MB"),
+                ("max_connections", "200"),
+                ("random_page_cost", "1.1"),
+                ("jit", "off"),
+            ],
+            Preset::Analytics => &[
+                ("shared_buffers", "512MB"),
+                ("maintenance_work_mem", "1GB"),
+                ("effective_cache_size", "2GB"),
+                ("work_mem", "256MB"),
+                ("max_parallel_workers_per_gather", "4"),
+                ("max_parallel_maintenance_workers", "4"),
+                ("random_page_cost", "1.1"),
+                ("default_statistics_target", "500"),
+            ],
+            Preset::Minimal => &[
+                ("shared_buffers", "16MB"),
+                ("maintenance_work_mem", "16MB"),
+                ("effective_cache_size", "128MB"),
+                ("work_mem", "4MB"),
+                ("max_connections", "20"),
+                ("max_parallel_workers_per_gather", "0"),
+                ("max_parallel_maintenance_workers", "0"),
+                ("min_wal_size", "32MB"),
+                ("max_wal_size", "256MB"),
+                ("autovacuum_max_workers", "1"),
+            ],
+        }
+    }
+}
+
+/// Total physical memory of this machine in bytes.
+fn total_memory_bytes() -> Option<u64> {
+    if cfg!(target_os = "linux") {
+        let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+        let kb = meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix("MemTotal:"))?
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        Some(kb * 1024)
+    } else if cfg!(target_os = "macos") {
+        let output = std::process::Command::new("sysctl")
+            .args(["-n", "hw.memsize"])
+            .output()
+            .ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    } else if cfg!(windows) {
+        let output = std::process::Command::new("powershell")
+            .args([
+                "-NoProfile",
+                "-Command",
+                "(Get-CimInstance Win32_ComputerSystem).TotalPhysicalMemory",
+            ])
+            .output()
+            .ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Result of `--auto-tune`: what was detected and the settings derived from it.
+struct AutoTune {
+    summary: String,
+    settings: Vec<(&'static str, String)>,
+}
+
+/// pgtune-style memory and parallelism settings. Without a memory limit
+/// PostgreSQL shares the machine with everything else, so it gets the
+/// "desktop" share of RAM; with `--memory-limit` the limit is all its own.
+fn auto_tune_settings(
+    limits: Option<&ResourceLimits>,
+    max_connections: u64,
+) -> Result<AutoTune, CliError> {
+    const MB: u64 = 1 << 20;
+    let ram = total_memory_bytes().ok_or_else(|| {
+        CliError::Other("--auto-tune could not determine this machine's memory".to_string())
+    })?;
+    let memory_limit = limits.and_then(|l| l.memory_bytes);
+    let memory = memory_limit.map_or(ram, |limit| limit.min(ram));
+    let mut cores = std::thread::available_parallelism()
+        .map(|n| n.get() as u64)
+        .unwrap_or(1);
+    if let Some(cpus) = limits.and_then(|l| l.cpus) {
+        cores = cores.min(cpus.ceil() as u64).max(1);
+    }
+
+    let (shared_buffers, effective_cache_size) = if memory_limit.is_some() {
+        (memory / 4, memory * 3 / 4)
+    } else {
+        (memory / 16, memory / 4)
+    };
+    let shared_buffers = shared_buffers.clamp(32 * MB, 8192 * MB);
+    let per_gather = (cores / 2).clamp(1, 4);
+    let mut work_mem = memory.saturating_sub(shared_buffers) / (max_connections * 3) / per_gather;
+    if memory_limit.is_none() {
+        work_mem /= 6;
+    }
+    let work_mem = work_mem.max(4 * MB);
+    let maintenance_work_mem = (memory / 16).clamp(16 * MB, 2048 * MB);
+
+    let summary = format!(
+        "{} {}, {} CPU core{}",
+        format_size(memory),
+        if memory_limit.is_some() {
+            "memory limit"
+        } else {
+            "RAM"
+        },
+        cores,
+        if cores == 1 { "" } else { "s" }
+    );
+    let settings = vec![
+        ("shared_buffers", format!("{}MB", shared_buffers / MB)),
+        (
+            "effective_cache_size",
+            format!("{}MB", effective_cache_size / MB),
+        ),
+        ("work_mem", format!("{}MB", work_mem / MB)),
+        (
+            "maintenance_work_mem",
+            format!("{}MB", maintenance_work_mem / MB),
+        ),
+        ("max_worker_processes", cores.max(8).to_string()),
+        ("max_parallel_workers", cores.to_string()),
+        ("max_parallel_workers_per_gather", per_gather.to_string()),
+        ("max_parallel_maintenance_workers", per_gather.to_string()),
+    ];
+    Ok(AutoTune { summary, settings })
+}
+
+/// How TCP connections authenticate, as set in pg_hba.conf.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AuthMode {
+    /// The method initdb was given (pg0 uses `password` unless --auth-host
+    /// says otherwise)
+    #[default]
+    Password,
+    /// Require scram-sha-256, as production servers do
+    Scram,
+}
+
+impl AuthMode {
+    /// pg_hba.conf method of `host` lines.
+    fn hba_method(&self) -> &'static str {
+        match self {
+            AuthMode::Password => "password",
+            AuthMode::Scram => "scram-sha-256",
+        }
+    }
+}
+
+/// How hard PostgreSQL works to keep committed data on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Durability {
+    /// fsync, synchronous commits and full page writes: survives crashes and
+    /// power loss
+    #[default]
+    Safe,
+    /// Asynchronous commit: a crash can lose the most recent transactions but
+    /// never corrupts the cluster
+    Balanced,
+    /// No fsync, no full page writes and lazy WAL writes: a crash or power
+    /// loss can corrupt the cluster
+    Fast,
+}
+
+impl Durability {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Durability::Safe => "safe",
+            Durability::Balanced => "balanced",
+            Durability::Fast => "fast",
+        }
+    }
+
+    /// The coherent group of settings behind each mode. fsync is always set
+    /// explicitly because postgresql_embedded starts the postmaster with -F
+    /// (fsync=off) unless told otherwise.
+    fn settings(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Durability::Safe => &[
+                ("fsync", "on"),
+                ("synchronous_commit", "on"),
+                ("full_page_writes", "on"),
+            ],
+            Durability::Balanced => &[
+                ("fsync", "on"),
+                ("synchronous_commit", "off"),
+                ("full_page_writes", "on"),
+            ],
+            Durability::Fast => &[
+                ("fsync", "off"),
+                ("synchronous_commit", "off"),
+                ("full_page_writes", "off"),
+                ("wal_writer_delay", "10s"),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct InstanceInfo {
+    pid: u32,
+    /// Identity of the postmaster behind `pid`, to detect PID reuse.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    process: Option<ProcessIdentity>,
+    port: u16,
+    data_dir: PathBuf,
+    installation_dir: PathBuf,
+    username: String,
+    password: String,
+    /// Whether the password was generated by `--random-password`, and is
+    /// hidden in pg0's output
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    secret_password: bool,
+    /// OS keychain entry holding the password, with `--keychain`; the
+    /// password is then left out of instance.json
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keychain_account: Option<String>,
+    database: String,
+    version: String,
+    /// Set while the instance's data directory lives in cold storage (see
+    /// `pg0 archive`); points at the compressed archive, a file or the
+    /// http(s) URL it was uploaded to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archive: Option<PathBuf>,
+    /// Name of the primary instance this instance streams from (see
+    /// `pg0 replica create`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replica_of: Option<String>,
+    /// Physical replication slot reserved for this replica on its primary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replication_slot: Option<String>,
+    #[serde(default)]
+    durability: Durability,
+    #[serde(default)]
+    auth: AuthMode,
+    /// Certificates of `--tls`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls: Option<TlsConfig>,
+    #[serde(default)]
+    preset: Preset,
+    /// Whether memory and parallelism settings were sized from the hardware.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    auto_tune: bool,
+    /// Set when the instance was found to have crashed while running in
+    /// fast mode, i.e. its data files may be corrupt.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    crashed_in_fast_mode: bool,
+    /// Built-in connection pooler, if enabled (see `pg0 pool enable`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pool: Option<PoolConfig>,
+    /// Prometheus metrics endpoint, if enabled (see `pg0 metrics enable`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metrics: Option<MetricsConfig>,
+    /// Liveness/readiness endpoint, if requested (see `pg0 start --health-port`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    health: Option<HealthConfig>,
+    /// auto_explain threshold in milliseconds, if started with `--auto-explain`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auto_explain: Option<u64>,
+    /// pgaudit.log classes, if started with `--audit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audit: Option<String>,
+    /// Stop after this many seconds without client connections, if started
+    /// with `--idle-timeout`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    idle_timeout: Option<u64>,
+    /// Expiry of the instance, if started with `--ttl`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<TtlConfig>,
+    /// cgroup limits, if started with `--memory-limit` or `--cpu-limit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limits: Option<ResourceLimits>,
+    /// RAM-backed storage holding the data directory, if started with
+    /// `--in-memory`. Released (and the data discarded) on stop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    in_memory: Option<InMemoryStorage>,
+    /// `-c` overrides passed to `pg0 start`, reused on the next start.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    config: Vec<String>,
+    /// User-defined labels from `pg0 start --label`.
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    labels: std::collections::BTreeMap<String, String>,
+    /// Addresses of `pg0 start --listen`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    listen: Vec<String>,
+    /// Networks of `pg0 start --allow`, as CIDRs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allow: Vec<String>,
+    /// Directory of the Unix socket, if started with `--socket-dir` or
+    /// `--socket-only`. Clients connect through it instead of TCP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    socket_dir: Option<PathBuf>,
+    /// Set when started with `--socket-only`: there is no TCP listener.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    socket_only: bool,
+    /// Shell commands of `pg0 start --hook`, by lifecycle event.
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    hooks: std::collections::BTreeMap<HookEvent, String>,
+}
+
+#[derive(Serialize)]
+struct InfoOutput {
+    name: String,
+    running: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    /// Path of the Unix socket, if started with `--socket-dir` or `--socket-only`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    socket: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    socket_only: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    database: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri: Option<String>,
+    /// The URI in other clients' formats, while running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection_strings: Option<ConnectionStrings>,
+    /// URI that verifies the server certificate, while running with TLS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archive: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replica_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    durability: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pool: Option<PoolConfig>,
+    /// Connection URI through the pooler, while it is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pool_uri: Option<String>,
+    /// Prometheus scrape URL, while the exporter is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metrics_url: Option<String>,
+    /// Base URL of the health check server, while it is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health_url: Option<String>,
+    /// Whether the data directory lives in RAM and is discarded on stop
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    in_memory: bool,
+    /// When the instance expires, if started with `--ttl`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<TtlConfig>,
+    #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    labels: std::collections::BTreeMap<String, String>,
+    /// Live statistics, only gathered by `pg0 info` of a running instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server: Option<ServerStats>,
+    /// A generated password, to hide from the connection strings
+    #[serde(skip)]
+    secret: Option<String>,
+}
+
+/// Stands in for generated passwords in pg0's output.
+const REDACTED: &str = "********";
+
+impl InfoOutput {
+    /// Replace a generated password in every connection string.
+    fn redact_secrets(&mut self) {
+        let Some(secret) = self.secret.take() else {
+            return;
+        };
+        let redact = |s: &mut String| *s = s.replace(&secret, REDACTED);
+        self.uri.iter_mut().for_each(redact);
+        self.tls_uri.iter_mut().for_each(redact);
+        self.pool_uri.iter_mut().for_each(redact);
+        if let Some(strings) = &mut self.connection_strings {
+            for s in [
+                &mut strings.jdbc,
+                &mut strings.sqlalchemy,
+                &mut strings.dotnet,
+                &mut strings.keyword,
+            ] {
+                redact(s);
+            }
+        }
+    }
+}
+
+/// Build the `info`/`list` view of an instance. Connection details (pid, uri)
+/// are only included while the instance is running.
+fn build_info_output(name: &str, instance: Option<InstanceInfo>) -> InfoOutput {
+    match instance {
+        Some(info) => {
+            let running = is_instance_running(&info);
+            let uri = if running {
+                Some(connection_string(&info, ConnectionFormat::Uri))
+            } else {
+                None
+            };
+            let connection_strings = if running {
+                Some(ConnectionStrings::new(&info))
+            } else {
+                None
+            };
+            let tls_uri = match &info.tls {
+                Some(tls) if running => Some(format!(
+                    "postgresql://{}:{}@127.0.0.1:{}/{}?sslmode=verify-full&sslrootcert={}",
+                    info.username,
+                    info.password,
+                    info.port,
+                    info.database,
+                    tls.ca_file.display()
+                )),
+                _ => None,
+            };
+            let pool_uri = match &info.pool {
+                Some(pool) if running && running_pool_worker(name).is_some() => Some(format!(
+                    "postgresql://{}:{}@127.0.0.1:{}/{}",
+                    info.username, info.password, pool.port, info.database
+                )),
+                _ => None,
+            };
+            let metrics_url = match &info.metrics {
+                Some(metrics) if running && running_metrics_worker(name).is_some() => {
+                    Some(format!("http://127.0.0.1:{}/metrics", metrics.port))
+                }
+                _ => None,
+            };
+            let health_url = match &info.health {
+                Some(health) if running && running_health_worker(name).is_some() => {
+                    Some(health.url())
+                }
+                _ => None,
+            };
+            InfoOutput {
+                name: name.to_string(),
+                running,
+                pid: if running { Some(info.pid) } else { None },
+                port: Some(info.port),
+                socket: info.socket_dir.as_ref().map(|dir| {
+                    dir.join(format!(".s.PGSQL.{}", info.port))
+                        .display()
+                        .to_string()
+                }),
+                socket_only: info.socket_only,
+                version: Some(info.version),
+                username: Some(info.username),
+                database: Some(info.database),
+                data_dir: Some(info.data_dir.display().to_string()),
+                uri,
+                connection_strings,
+                tls_uri,
+                archive: info
+                    .archive
+                    .map(|p| without_query(&p.display().to_string()).to_string()),
+                replica_of: info.replica_of,
+                durability: Some(info.durability.as_str().to_string()),
+                preset: Some(info.preset.as_str().to_string()),
+                pool: info.pool,
+                pool_uri,
+                metrics_url,
+                health_url,
+                in_memory: info.in_memory.is_some(),
+                ttl: if running { info.ttl } else { None },
+                labels: info.labels,
+                server: None,
+                secret: if info.secret_password || info.keychain_account.is_some() {
+                    Some(info.password)
+                } else {
+                    None
+                },
+            }
+        }
+        None => InfoOutput {
+            name: name.to_string(),
+            running: false,
+            pid: None,
+            port: None,
+            socket: None,
+            socket_only: false,
+            version: None,
+            username: None,
+            database: None,
+            data_dir: None,
+            uri: None,
+            connection_strings: None,
+            tls_uri: None,
+            archive: None,
+            replica_of: None,
+            durability: None,
+            preset: None,
+            pool: None,
+            pool_uri: None,
+            metrics_url: None,
+            health_url: None,
+            in_memory: false,
+            ttl: None,
+            labels: Default::default(),
+            server: None,
+            secret: None,
+        },
+    }
+}
+
+fn get_base_dir() -> Result<PathBuf, CliError> {
+    // --base-dir ends up here too, see main.
+    if let Some(dir) = std::env::var("PG0_HOME").ok().filter(|d| !d.is_empty()) {
+        let dir = expand_path(&dir);
+        return Ok(if dir.is_relative() {
+            std::env::current_dir()?.join(dir)
+        } else {
+            dir
+        });
+    }
+
+    let home_dir = dirs::home_dir()
+        .map(|h| h.join(".pg0"))
+        .ok_or(CliError::NoDataDir)?;
+    // Existing installations keep using ~/.pg0.
+    if !home_dir.exists() {
+        if let Some(data_home) = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|d| d.is_absolute())
+        {
+            return Ok(data_home.join("pg0"));
+        }
+    }
+    Ok(home_dir)
+}
+
+/// Marker file tying a project directory to its instance (see
+/// `pg0 start --local`).
+const LOCAL_MARKER_FILE: &str = ".pg0";
+
+#[derive(Deserialize)]
+struct LocalMarker {
+    name: String,
+}
+
+/// The instance commands use without `--name`: the one recorded in a `.pg0`
+/// marker in the current directory or one of its parents, else "default".
+fn discovered_instance_name() -> String {
+    static NAME: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    NAME.get_or_init(|| {
+        std::env::current_dir()
+            .ok()
+            .and_then(|dir| find_local_marker(&dir))
+            .map(|(_, name)| name)
+            .unwrap_or_else(|| DEFAULT_INSTANCE_NAME.to_string())
+    })
+    .clone()
+}
+
+/// Closest `.pg0` marker file at or above `dir`, with its instance name.
+/// ~/.pg0 is pg0's own directory, not a marker.
+fn find_local_marker(dir: &Path) -> Option<(PathBuf, String)> {
+    dir.ancestors().find_map(|dir| {
+        let path = dir.join(LOCAL_MARKER_FILE);
+        if !path.is_file() {
+            return None;
+        }
+        let marker: LocalMarker = toml::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+        Some((path, marker.name))
+    })
+}
+
+/// Instance of the project in the current directory. On first use the name
+/// is derived from the directory and recorded in a `.pg0` marker there.
+fn local_instance_name() -> Result<String, CliError> {
+    use std::hash::{Hash, Hasher};
+
+    let cwd = std::env::current_dir()?;
+    if let Some((_, name)) = find_local_marker(&cwd) {
+        return Ok(name);
+    }
+
+    let base: String = cwd
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let base = base.trim_matches('-');
+    // Projects with the same directory name in different places get
+    // different instances.
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    cwd.hash(&mut hasher);
+    let hash = hasher.finish() as u32;
+    let name = if base.is_empty() {
+        format!("local-{:08x}", hash)
+    } else {
+        format!("{}-{:08x}", base, hash)
+    };
+
+    let marker = cwd.join(LOCAL_MARKER_FILE);
+    fs::write(
+        &marker,
+        format!(
+            "# pg0 instance of this project, used by pg0 commands run here without --name\nname = \"{}\"\n",
+            name
+        ),
+    )?;
+    println!("Created {} for instance '{}'.", marker.display(), name);
+    Ok(name)
+}
+
+fn get_instances_dir() -> Result<PathBuf, CliError> {
+    Ok(get_base_dir()?.join("instances"))
+}
+
+fn get_instance_dir(name: &str) -> Result<PathBuf, CliError> {
+    Ok(get_instances_dir()?.join(name))
+}
+
+fn get_state_file(name: &str) -> Result<PathBuf, CliError> {
+    Ok(get_instance_dir(name)?.join("instance.json"))
+}
+
+/// Names of the instances whose lock this process holds, so nested calls
+/// (apply restarting an instance, a TTL drop) don't wait on themselves.
+static HELD_INSTANCE_LOCKS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+/// Exclusive hold on an instance, released when dropped.
+struct InstanceLock {
+    name: String,
+    /// None when an outer call in this process holds the lock.
+    file: Option<fs::File>,
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            HELD_INSTANCE_LOCKS
+                .lock()
+                .unwrap()
+                .retain(|held| held != &self.name);
+        }
+    }
+}
+
+/// Take the advisory lock that serializes start, stop, restart, drop and
+/// every other change to an instance's state across pg0 processes.
+fn lock_instance(name: &str) -> Result<InstanceLock, CliError> {
+    if HELD_INSTANCE_LOCKS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|held| held == name)
+    {
+        return Ok(InstanceLock {
+            name: name.to_string(),
+            file: None,
+        });
+    }
+    // Outside the instance directory, which drop deletes.
+    let dir = get_base_dir()?.join("locks");
+    fs::create_dir_all(&dir)?;
+    let file = acquire_file_lock(
+        &dir.join(format!("{}.lock", name)),
+        &format!("instance '{}'", name),
+    )?;
+    HELD_INSTANCE_LOCKS.lock().unwrap().push(name.to_string());
+    Ok(InstanceLock {
+        name: name.to_string(),
+        file: Some(file),
+    })
+}
+
+/// Open `path` and lock it exclusively; the lock lasts as long as the
+/// returned file. Waits up to PG0_LOCK_TIMEOUT (default 5m; 0 fails right
+/// away) for another pg0 command to release it.
+fn acquire_file_lock(path: &Path, what: &str) -> Result<fs::File, CliError> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    let timeout = match std::env::var("PG0_LOCK_TIMEOUT") {
+        Ok(value) if value.trim() == "0" => 0,
+        Ok(value) if !value.is_empty() => parse_duration_secs(&value)?,
+        _ => 300,
+    };
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(timeout);
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(file),
+            Err(fs::TryLockError::WouldBlock) => {}
+            Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+        }
+        if std::time::Instant::now() >= deadline {
+            return Err(CliError::Other(format!(
+                "Another pg0 command is still working on {} (waited {}; lock: {})",
+                what,
+                format_duration_secs(timeout),
+                path.display()
+            )));
+        }
+        if !waiting {
+            eprintln!("Waiting for another pg0 command on {} to finish...", what);
+            waiting = true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+/// Schema of instance.json, stored in its `schema_version` field. Bump it
+/// together with a new entry in [`INSTANCE_STATE_MIGRATIONS`] when a change
+/// can't be expressed with serde defaults.
+const INSTANCE_STATE_VERSION: u64 = 1;
+
+/// Upgrades of instance.json; entry `n` takes a state file from schema `n`
+/// to `n + 1`.
+const INSTANCE_STATE_MIGRATIONS: [fn(&mut serde_json::Map<String, serde_json::Value>);
+    INSTANCE_STATE_VERSION as usize] = [
+    // 0 -> 1: files from before the schema was versioned. Every field added
+    // since has a default, so only the version is new.
+    |_| {},
+];
+
+/// instance.json as written: the schema version, then the instance.
+#[derive(Serialize)]
+struct VersionedInstanceState<'a> {
+    schema_version: u64,
+    #[serde(flatten)]
+    info: &'a InstanceInfo,
+}
+
+/// Parse instance.json, migrating it from an older schema. A file from a newer
+/// pg0 is refused rather than read with fields missing.
+fn parse_instance_state(content: &str) -> Result<InstanceInfo, CliError> {
+    let mut state: serde_json::Map<String, serde_json::Value> = serde_json::from_str(content)?;
+    let version = match state.remove("schema_version") {
+        None => 0,
+        Some(value) => value.as_u64().ok_or_else(|| {
+            CliError::Other(format!("Invalid schema_version {} in instance.json", value))
+        })?,
+    };
+    if version > INSTANCE_STATE_VERSION {
+        return Err(CliError::Other(format!(
+            "instance.json has schema version {}, but this pg0 ({}) only reads up to {}; upgrade pg0",
+            version,
+            env!("CARGO_PKG_VERSION"),
+            INSTANCE_STATE_VERSION
+        )));
+    }
+    for migrate in &INSTANCE_STATE_MIGRATIONS[version as usize..] {
+        migrate(&mut state);
+    }
+    Ok(serde_json::from_value(serde_json::Value::Object(state))?)
+}
+
+/// Replace `path` with `content` so that readers, and a crash halfway,
+/// see either the old file or the new one. Unix creates it owner-only.
+fn write_file_atomic(path: &Path, content: &[u8]) -> Result<(), CliError> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let written = options.open(&temp).and_then(|mut file| {
+        std::io::Write::write_all(&mut file, content)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|()| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e.into());
+    }
+    // Persist the rename itself.
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let _ = fs::File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+fn load_instance(name: &str) -> Result<Option<InstanceInfo>, CliError> {
+    let state_file = get_state_file(name)?;
+    if state_file.exists() {
+        let content = fs::read_to_string(&state_file)?;
+        let mut info = parse_instance_state(&content).map_err(|e| {
+            CliError::Other(format!("Failed to read {}: {}", state_file.display(), e))
+        })?;
+        if let Some(account) = &info.keychain_account {
+            match keychain_load(account) {
+                Ok(password) => info.password = password,
+                Err(e) => eprintln!(
+                    "Warning: The password of instance '{}' is unavailable: {}",
+                    name, e
+                ),
+            }
+        }
+        Ok(Some(info))
+    } else {
+        Ok(None)
+    }
+}
+
+fn save_instance(name: &str, info: &InstanceInfo) -> Result<(), CliError> {
+    let instance_dir = get_instance_dir(name)?;
+    fs::create_dir_all(&instance_dir)?;
+    let state_file = get_state_file(name)?;
+    let stored;
+    let info_to_store = if info.keychain_account.is_some() {
+        stored = InstanceInfo {
+            password: String::new(),
+            ..info.clone()
+        };
+        &stored
+    } else {
+        info
+    };
+    let content = serde_json::to_string_pretty(&VersionedInstanceState {
+        schema_version: INSTANCE_STATE_VERSION,
+        info: info_to_store,
+    })?;
+    write_file_atomic(&state_file, content.as_bytes())?;
+    write_instance_metadata(name, info)?;
+    Ok(())
+}
+
+/// Service name of pg0's entries in the OS keychain.
+const KEYCHAIN_SERVICE: &str = "pg0";
+
+fn keychain_entry(account: &str) -> Result<keyring::Entry, CliError> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, account)
+        .map_err(|e| CliError::Other(format!("OS keychain unavailable: {}", e)))
+}
+
+fn keychain_store(account: &str, password: &str) -> Result<(), CliError> {
+    keychain_entry(account)?
+        .set_password(password)
+        .map_err(|e| {
+            CliError::Other(format!(
+                "Failed to store the password in the OS keychain: {}",
+                e
+            ))
+        })
+}
+
+fn keychain_load(account: &str) -> Result<String, CliError> {
+    keychain_entry(account)?.get_password().map_err(|e| {
+        CliError::Other(format!(
+            "Failed to read the password from the OS keychain: {}",
+            e
+        ))
+    })
+}
+
+/// Forget a dropped instance's password. Best effort: the entry may already
+/// be gone.
+fn keychain_delete(account: &str) {
+    if let Ok(entry) = keychain_entry(account) {
+        let _ = entry.delete_credential();
+    }
+}
+
+/// Make a file that holds the instance's password readable by its owner only.
+fn restrict_to_owner(path: &Path) -> Result<(), CliError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Version of the `metadata.json` schema. Bump only on breaking changes;
+/// adding fields is backwards compatible.
+const METADATA_SCHEMA_VERSION: u32 = 1;
+
+/// Stable, documented per-instance discovery file for IDE database plugins
+/// and tools like dbt. Unlike instance.json (pg0's internal state, which may
+/// change shape between releases), this schema is a public contract - see
+/// "Tool Integration" in the README.
+#[derive(Serialize)]
+struct InstanceMetadata {
+    schema_version: u32,
+    name: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    host: String,
+    port: u16,
+    username: String,
+    database: String,
+    uri: String,
+    version: String,
+    data_dir: String,
+    capabilities: Vec<String>,
+    updated_at: u64,
+}
+
+fn get_metadata_file(name: &str) -> Result<PathBuf, CliError> {
+    Ok(get_instance_dir(name)?.join("metadata.json"))
+}
+
+/// Refresh `metadata.json` for an instance. Called on every lifecycle event
+/// (start, stop, archive, unarchive) so the file always reflects the last
+/// known status.
+fn write_instance_metadata(name: &str, info: &InstanceInfo) -> Result<(), CliError> {
+    let running = is_instance_running(info);
+    let status = if running {
+        "running"
+    } else if info.archive.is_some() {
+        "archived"
+    } else {
+        "stopped"
+    };
+    let updated_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let metadata = InstanceMetadata {
+        schema_version: METADATA_SCHEMA_VERSION,
+        name: name.to_string(),
+        status: status.to_string(),
+        pid: if running { Some(info.pid) } else { None },
+        host: instance_host(info),
+        port: info.port,
+        username: info.username.clone(),
+        database: info.database.clone(),
+        // A password kept in the keychain stays out of files.
+        uri: if info.keychain_account.is_some() {
+            local_uri(
+                info.socket_dir.as_deref(),
+                &info.username,
+                info.port,
+                &info.database,
+            )
+        } else {
+            connection_string(info, ConnectionFormat::Uri)
+        },
+        version: info.version.clone(),
+        data_dir: info.data_dir.display().to_string(),
+        capabilities: available_extensions_on_disk(&info.installation_dir.join(&info.version)),
+        updated_at,
+    };
+
+    let metadata_file = get_metadata_file(name)?;
+    write_file_atomic(
+        &metadata_file,
+        serde_json::to_string_pretty(&metadata)?.as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Names of the extensions whose control files are present in a
+/// version-specific installation directory (e.g. "vector", "pg_trgm").
+fn available_extensions_on_disk(version_dir: &Path) -> Vec<String> {
+    let extension_dir = version_dir.join("share").join("extension");
+    let mut names: Vec<String> = match fs::read_dir(&extension_dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|e| {
+                e.file_name()
+                    .to_str()
+                    .and_then(|n| n.strip_suffix(".control"))
+                    .map(|n| n.to_string())
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    names
+}
+
+fn remove_instance(name: &str) -> Result<(), CliError> {
+    let state_file = get_state_file(name)?;
+    if state_file.exists() {
+        fs::remove_file(&state_file)?;
+    }
+    Ok(())
+}
+
+/// Load an instance that must currently be running. Stale state for a stopped
+/// instance is cleaned up, except for archived instances whose state file is
+/// the only record of where their data went.
+fn load_running_instance(name: &str) -> Result<InstanceInfo, CliError> {
+    let info = load_instance(name)?.ok_or(CliError::NoInstance)?;
+
+    if !is_instance_running(&info) {
+        if info.archive.is_none() {
+            remove_instance(name)?;
+        }
+        return Err(CliError::NoInstance);
+    }
+
+    Ok(info)
+}
+
+fn list_instances() -> Result<Vec<String>, CliError> {
+    let instances_dir = get_instances_dir()?;
+    if !instances_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut names = Vec::new();
+    for entry in fs::read_dir(&instances_dir)? {
+        let entry = entry?;
+        if entry.path().is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                // Check if it has an instance.json file
+                if entry.path().join("instance.json").exists() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Whether a process with this PID exists. One owned by another user counts.
+fn is_process_running(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // 0 and negative PIDs address process groups, not a process.
+        let pid = match libc::pid_t::try_from(pid) {
+            Ok(pid) if pid > 0 => pid,
+            _ => return false,
+        };
+        // Signal 0 delivers nothing; it only checks that the PID exists.
+        if unsafe { libc::kill(pid, 0) } == 0 {
+            return true;
+        }
+        std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::{
+            CloseHandle, GetLastError, ERROR_ACCESS_DENIED, STILL_ACTIVE,
+        };
+        use windows_sys::Win32::System::Threading::{
+            GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+        };
+        if pid == 0 {
+            return false;
+        }
+        let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+        if handle.is_null() {
+            return unsafe { GetLastError() } == ERROR_ACCESS_DENIED;
+        }
+        let mut code = 0u32;
+        let queried = unsafe { GetExitCodeProcess(handle, &mut code) } != 0;
+        unsafe { CloseHandle(handle) };
+        queried && code == STILL_ACTIVE as u32
+    }
+}
+
+/// What tells a process apart from a later one that got the same PID: when
+/// it started and which executable it runs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ProcessIdentity {
+    /// Seconds since the Unix epoch
+    started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    executable: Option<PathBuf>,
+}
+
+impl ProcessIdentity {
+    /// Whether `current` is the same process. Start times derived from boot
+    /// time and clock ticks may be off by a second.
+    fn matches(&self, current: &ProcessIdentity) -> bool {
+        self.started_at.abs_diff(current.started_at) <= 2
+            && match (&self.executable, &current.executable) {
+                (Some(recorded), Some(current)) => recorded == current,
+                _ => true,
+            }
+    }
+}
+
+/// Start time and executable of a running process, if the OS reveals them.
+#[cfg(target_os = "linux")]
+fn process_identity(pid: u32) -> Option<ProcessIdentity> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name (field 2) may contain spaces and parentheses; the
+    // start time is field 22, counted in clock ticks since boot.
+    let fields = stat.get(stat.rfind(')')? + 1..)?;
+    let ticks: u64 = fields.split_whitespace().nth(19)?.parse().ok()?;
+    let boot_time: u64 = fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    let ticks_per_sec = u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) })
+        .ok()
+        .filter(|&hz| hz > 0)?;
+    // Unreadable for other users' processes; a replaced binary shows up
+    // with a " (deleted)" suffix.
+    let executable = fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .map(|path| {
+            let path = path.to_string_lossy();
+            PathBuf::from(path.strip_suffix(" (deleted)").unwrap_or(&path))
+        });
+    Some(ProcessIdentity {
+        started_at: boot_time + ticks / ticks_per_sec,
+        executable,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn process_identity(pid: u32) -> Option<ProcessIdentity> {
+    use std::os::unix::ffi::OsStrExt;
+    let pid = libc::c_int::try_from(pid).ok()?;
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if written != size {
+        return None;
+    }
+    let mut path = vec![0u8; libc::PROC_PIDPATHINFO_MAXSIZE as usize];
+    let len = unsafe {
+        libc::proc_pidpath(
+            pid,
+            path.as_mut_ptr() as *mut libc::c_void,
+            path.len() as u32,
+        )
+    };
+    let executable = usize::try_from(len)
+        .ok()
+        .filter(|&len| len > 0)
+        .map(|len| PathBuf::from(std::ffi::OsStr::from_bytes(&path[..len])));
+    Some(ProcessIdentity {
+        started_at: info.pbi_start_tvsec,
+        executable,
+    })
+}
+
+#[cfg(windows)]
+fn process_identity(pid: u32) -> Option<ProcessIdentity> {
+    use std::os::windows::ffi::OsStringExt;
+    use windows_sys::Win32::Foundation::{CloseHandle, FILETIME};
+    use windows_sys::Win32::System::Threading::{
+        GetProcessTimes, OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+        PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if handle.is_null() {
+        return None;
+    }
+    let zero = FILETIME {
+        dwLowDateTime: 0,
+        dwHighDateTime: 0,
+    };
+    let (mut created, mut exited, mut kernel, mut user) = (zero, zero, zero, zero);
+    let timed =
+        unsafe { GetProcessTimes(handle, &mut created, &mut exited, &mut kernel, &mut user) } != 0;
+    let mut path = vec![0u16; 32768];
+    let mut len = path.len() as u32;
+    let named = unsafe {
+        QueryFullProcessImageNameW(handle, PROCESS_NAME_WIN32, path.as_mut_ptr(), &mut len)
+    } != 0;
+    unsafe { CloseHandle(handle) };
+    if !timed {
+        return None;
+    }
+    // FILETIME counts 100ns intervals since 1601-01-01.
+    let intervals = (u64::from(created.dwHighDateTime) << 32) | u64::from(created.dwLowDateTime);
+    let started_at = (intervals / 10_000_000).checked_sub(11_644_473_600)?;
+    let executable =
+        named.then(|| PathBuf::from(std::ffi::OsString::from_wide(&path[..len as usize])));
+    Some(ProcessIdentity {
+        started_at,
+        executable,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn process_identity(_pid: u32) -> Option<ProcessIdentity> {
+    None
+}
+
+/// Whether the instance's postmaster is running. After a reboot the recorded
+/// PID can belong to an unrelated process, so its start time and executable
+/// have to match as well. Instances started before pg0 recorded them, or
+/// processes the OS won't describe, are judged by the PID alone.
+fn is_instance_running(info: &InstanceInfo) -> bool {
+    if !is_process_running(info.pid) {
+        return false;
+    }
+    match (&info.process, process_identity(info.pid)) {
+        (Some(recorded), Some(current)) => recorded.matches(&current),
+        _ => true,
+    }
+}
+
+/// How [`signal_process`] asks a process to go away.
+#[derive(Clone, Copy, PartialEq)]
+enum Termination {
+    /// SIGTERM: PostgreSQL's "smart" shutdown.
+    Graceful,
+    /// SIGKILL.
+    Forced,
+}
+
+/// Send a termination signal to `pid`. A process that is already gone is not
+/// an error. Windows has no SIGTERM for a detached process, so both kinds
+/// terminate it.
+fn signal_process(pid: u32, termination: Termination) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let pid = match libc::pid_t::try_from(pid) {
+            Ok(pid) if pid > 0 => pid,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid pid {}", pid),
+                ))
+            }
+        };
+        let signal = match termination {
+            Termination::Graceful => libc::SIGTERM,
+            Termination::Forced => libc::SIGKILL,
+        };
+        if unsafe { libc::kill(pid, signal) } == 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ESRCH) {
+            return Ok(());
+        }
+        Err(err)
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, ERROR_INVALID_PARAMETER};
+        use windows_sys::Win32::System::Threading::{
+            OpenProcess, TerminateProcess, PROCESS_TERMINATE,
+        };
+        let _ = termination;
+        let handle = unsafe { OpenProcess(PROCESS_TERMINATE, 0, pid) };
+        if handle.is_null() {
+            // ERROR_INVALID_PARAMETER is what OpenProcess reports for a PID
+            // that no longer exists.
+            if unsafe { GetLastError() } == ERROR_INVALID_PARAMETER {
+                return Ok(());
+            }
+            return Err(std::io::Error::last_os_error());
+        }
+        let terminated = unsafe { TerminateProcess(handle, 1) } != 0;
+        let err = std::io::Error::last_os_error();
+        unsafe { CloseHandle(handle) };
+        if terminated || !is_process_running(pid) {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+}
+
+/// Stop `pid` with SIGTERM, then SIGKILL if it is still there after `grace`.
+/// Fails if the process can't be signalled or outlives SIGKILL.
+fn terminate_process(pid: u32, grace: std::time::Duration) -> Result<(), CliError> {
+    let exited = |within: std::time::Duration| {
+        let deadline = std::time::Instant::now() + within;
+        while is_process_running(pid) {
+            if std::time::Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        true
+    };
+    let signal = |termination| {
+        signal_process(pid, termination)
+            .map_err(|e| CliError::Other(format!("Failed to signal process {}: {}", pid, e)))
+    };
+
+    signal(Termination::Graceful)?;
+    if exited(grace) {
+        return Ok(());
+    }
+    signal(Termination::Forced)?;
+    if exited(std::time::Duration::from_secs(5)) {
+        return Ok(());
+    }
+    Err(CliError::Other(format!(
+        "Process {} did not exit after SIGKILL",
+        pid
+    )))
+}
+
+/// Read the PID from PostgreSQL's postmaster.pid file
+fn read_postmaster_pid(data_dir: &PathBuf) -> Result<u32, CliError> {
+    let pid_file = data_dir.join("postmaster.pid");
+    let content = fs::read_to_string(&pid_file)?;
+    // First line of postmaster.pid is the PID
+    content
+        .lines()
+        .next()
+        .and_then(|line| line.trim().parse().ok())
+        .ok_or(CliError::PidParse)
+}
+
+/// Map a requested version to the bundled PostgreSQL release when it refers to
+/// it ("18" or "18.1" with 18.1.0 bundled). Other requests are returned as-is.
+fn resolve_version(requested: &str) -> String {
+    let bundled = env!("PG_VERSION");
+    let mut bundled_prefix = String::new();
+    for part in bundled.split('.') {
+        if !bundled_prefix.is_empty() {
+            bundled_prefix.push('.');
+        }
+        bundled_prefix.push_str(part);
+        if requested == bundled_prefix {
+            return bundled.to_string();
+        }
+    }
+    requested.to_string()
+}
+
+/// Expand ~ to home directory
+fn expand_path(path: &str) -> PathBuf {
+    if path.starts_with("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(&path[2..]);
+        }
+    }
+    PathBuf::from(path)
+}
+
+/// Check if a port is available for binding
+fn is_port_available(port: u16) -> bool {
+    std::net::TcpListener::bind(("127.0.0.1", port)).is_ok()
+}
+
+/// ~/.pg0/ports.json: the port each instance is assigned. Instances keep
+/// their port while stopped, so auto-allocation doesn't hand it to another.
+/// The ports of an instance's pooler, health check and metrics exporter are
+/// keyed `<name>/pool`, `<name>/health` and `<name>/metrics`.
+#[derive(Default, Serialize, Deserialize)]
+struct PortRegistry {
+    #[serde(default)]
+    instances: std::collections::BTreeMap<String, u16>,
+}
+
+/// Load the port registry, bringing it in line with the instances on disk:
+/// instances that are gone lose their entry, and instances the registry
+/// doesn't know yet (e.g. from before it existed) are added unless another
+/// instance already holds their port.
+fn load_port_registry(base_dir: &Path) -> Result<PortRegistry, CliError> {
+    let path = base_dir.join("ports.json");
+    let mut registry: PortRegistry = match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => PortRegistry::default(),
+        Err(e) => return Err(e.into()),
+    };
+    // An instance directory without instance.json may be one being created.
+    let instances_dir = base_dir.join("instances");
+    registry
+        .instances
+        .retain(|key, _| instances_dir.join(port_owner(key)).is_dir());
+    for name in list_instances()? {
+        if registry.instances.contains_key(&name) {
+            continue;
+        }
+        let state = fs::read_to_string(instances_dir.join(&name).join("instance.json"));
+        let Ok(info) = state
+            .map_err(CliError::from)
+            .and_then(|content| parse_instance_state(&content))
+        else {
+            continue;
+        };
+        if info.socket_only || registry.instances.values().any(|&port| port == info.port) {
+            continue;
+        }
+        registry.instances.insert(name, info.port);
+    }
+    Ok(registry)
+}
+
+/// Ports auto-allocation picks from: PG0_PORT_RANGE, e.g. "5432-5999" (the
+/// default).
+fn port_range() -> Result<std::ops::RangeInclusive<u16>, CliError> {
+    let Some(value) = std::env::var("PG0_PORT_RANGE")
+        .ok()
+        .filter(|v| !v.is_empty())
+    else {
+        return Ok(5432..=5999);
+    };
+    let invalid = || {
+        CliError::Other(format!(
+            "Invalid PG0_PORT_RANGE '{}': use e.g. 5432-5999",
+            value
+        ))
+    };
+    let (low, high) = value.split_once('-').ok_or_else(invalid)?;
+    let low: u16 = low.trim().parse().map_err(|_| invalid())?;
+    let high: u16 = high.trim().parse().map_err(|_| invalid())?;
+    if low == 0 || low > high {
+        return Err(invalid());
+    }
+    Ok(low..=high)
+}
+
+/// The instance a port registry key belongs to.
+fn port_owner(key: &str) -> &str {
+    key.split('/').next().unwrap_or(key)
+}
+
+/// Record the port of `name` (an instance, or a registry key such as
+/// `<name>/pool`) in the port registry. An explicitly requested port is
+/// taken as is; otherwise `port` is kept when it is already `name`'s, or lies
+/// in [`port_range`], no other instance is assigned it and it is free, and
+/// the next port of [`port_range`] that is all three is used instead.
+fn assign_port(name: &str, port: u16, port_was_specified: bool) -> Result<u16, CliError> {
+    let base_dir = get_base_dir()?;
+    fs::create_dir_all(&base_dir)?;
+    let _lock = acquire_file_lock(&base_dir.join("ports.lock"), "the port registry")?;
+    let mut registry = load_port_registry(&base_dir)?;
+    let assigned_to = |port: u16| {
+        registry
+            .instances
+            .iter()
+            .find(|(other, &assigned)| assigned == port && other.as_str() != name)
+            .map(|(other, _)| other.clone())
+    };
+
+    let assigned = if port_was_specified {
+        if let Some(other) = assigned_to(port) {
+            eprintln!(
+                "Warning: Port {} is also assigned to instance '{}'",
+                port, other
+            );
+        }
+        port
+    } else if registry.instances.get(name) != Some(&port) && !port_range()?.contains(&port) {
+        next_unassigned_port(port, &assigned_to)?
+    } else if let Some(other) = assigned_to(port) {
+        let new_port = next_unassigned_port(port, &assigned_to)?;
+        println!(
+            "Port {} is assigned to instance '{}', using port {} instead.",
+            port, other, new_port
+        );
+        new_port
+    } else if !is_port_available(port) {
+        let new_port = next_unassigned_port(port, &assigned_to)?;
+        println!("Port {} is in use, using port {} instead.", port, new_port);
+        new_port
+    } else {
+        port
+    };
+
+    registry.instances.insert(name.to_string(), assigned);
+    // Reserves the entry against pruning until instance.json exists.
+    fs::create_dir_all(get_instance_dir(port_owner(name))?)?;
+    write_file_atomic(
+        &base_dir.join("ports.json"),
+        serde_json::to_string_pretty(&registry)?.as_bytes(),
+    )?;
+    Ok(assigned)
+}
+
+/// First port of [`port_range`] from `after` on (wrapping around) that no
+/// other instance is assigned and nothing listens on.
+fn next_unassigned_port(
+    after: u16,
+    assigned_to: &dyn Fn(u16) -> Option<String>,
+) -> Result<u16, CliError> {
+    let range = port_range()?;
+    let start = if range.contains(&after) {
+        after
+    } else {
+        *range.start()
+    };
+    (start..=*range.end())
+        .chain(*range.start()..start)
+        .find(|&port| assigned_to(port).is_none() && is_port_available(port))
+        .ok_or_else(|| {
+            CliError::Other(format!(
+                "No free port left in {}-{}; set PG0_PORT_RANGE to a wider range",
+                range.start(),
+                range.end()
+            ))
+        })
+}
+
+/// Forget the port of `name`; for an instance, also those of its pooler,
+/// health check and metrics exporter.
+fn release_port(name: &str) -> Result<(), CliError> {
+    let base_dir = get_base_dir()?;
+    if !base_dir.join("ports.json").exists() {
+        return Ok(());
+    }
+    let _lock = acquire_file_lock(&base_dir.join("ports.lock"), "the port registry")?;
+    let mut registry = load_port_registry(&base_dir)?;
+    registry
+        .instances
+        .retain(|key, _| key != name && port_owner(key) != name);
+    write_file_atomic(
+        &base_dir.join("ports.json"),
+        serde_json::to_string_pretty(&registry)?.as_bytes(),
+    )
+}
+
+/// Read the latest PostgreSQL log file content (last 20 lines)
+fn read_latest_pg_log(data_dir: &PathBuf) -> Option<String> {
+    let log_dir = data_dir.join("log");
+    let entries = fs::read_dir(&log_dir).ok()?;
+
+    // Find the most recent log file
+    let mut log_files: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().map(|ext| ext == "log").unwrap_or(false))
+        .collect();
+    log_files.sort_by_key(|e| {
+        std::cmp::Reverse(
+            e.metadata()
+                .and_then(|m| m.modified())
+                .unwrap_or(std::time::SystemTime::UNIX_EPOCH),
+        )
+    });
+
+    let log_file = log_files.first()?;
+    let content = fs::read_to_string(log_file.path()).ok()?;
+
+    // Get last 20 lines
+    let lines: Vec<&str> = content.lines().collect();
+    let last_lines: Vec<&str> = lines.iter().rev().take(20).rev().cloned().collect();
+
+    if last_lines.is_empty() {
+        None
+    } else {
+        Some(last_lines.join("\n"))
+    }
+}
+
+/// Name of the main PostgreSQL server binary for the current target platform.
+/// theseus-rs bundles `postgres.exe` on Windows and `postgres` everywhere else.
+#[cfg(windows)]
+const POSTGRES_BINARY: &str = "postgres.exe";
+#[cfg(not(windows))]
+const POSTGRES_BINARY: &str = "postgres";
+
+/// Extract the embedded bundle into `version_dir`, stripping the top-level
+/// directory entry (e.g. "postgresql-18.1.0-<target>/"). theseus-rs publishes
+/// the Windows bundle as a ZIP and every other platform as tar.gz.
+#[cfg(not(windows))]
+fn extract_postgresql_archive(bundle: &[u8], version_dir: &std::path::Path) -> Result<(), CliError> {
+    let decoder = GzDecoder::new(bundle);
+    let mut archive = Archive::new(decoder);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?;
+
+        let stripped_path: PathBuf = path.components().skip(1).collect();
+        if stripped_path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let dest_path = version_dir.join(&stripped_path);
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if entry.header().entry_type().is_dir() {
+            fs::create_dir_all(&dest_path)?;
+        } else {
+            entry.unpack(&dest_path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn extract_postgresql_archive(bundle: &[u8], version_dir: &std::path::Path) -> Result<(), CliError> {
+    use std::io::Cursor;
+    let reader = Cursor::new(bundle);
+    let mut archive = zip::ZipArchive::new(reader)
+        .map_err(|e| CliError::Other(format!("Failed to read PostgreSQL ZIP archive: {}", e)))?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| CliError::Other(format!("Failed to read ZIP entry {}: {}", i, e)))?;
+
+        let entry_path = match entry.enclosed_name() {
+            Some(p) => p.to_path_buf(),
+            None => continue, // Skip unsafe / absolute / traversal-containing names
+        };
+
+        let stripped_path: PathBuf = entry_path.components().skip(1).collect();
+        if stripped_path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let dest_path = version_dir.join(&stripped_path);
+        if entry.is_dir() {
+            fs::create_dir_all(&dest_path)?;
+        } else {
+            if let Some(parent) = dest_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut out = fs::File::create(&dest_path)?;
+            std::io::copy(&mut entry, &mut out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Extract the bundled PostgreSQL to the installation directory
+/// Returns the path to the version-specific directory (e.g., ~/.pg0/installation/18.1.0)
+fn extract_bundled_postgresql(
+    installation_dir: &Path,
+    pg_version: &str,
+) -> Result<PathBuf, CliError> {
+    extract_postgresql(installation_dir, pg_version, "bundled PostgreSQL", || {
+        if POSTGRESQL_BUNDLE.is_empty() {
+            return Err(CliError::Other(
+                "PostgreSQL bundle is empty - this binary was not built with BUNDLE_POSTGRESQL=true".to_string()
+            ));
+        }
+        Ok(std::borrow::Cow::Borrowed(POSTGRESQL_BUNDLE))
+    })
+}
+
+/// Extract the PostgreSQL release `archive` returns to
+/// `installation_dir/<pg_version>`, unless it is extracted already, and make
+/// its runtime libraries available. `label` names the release in messages.
+fn extract_postgresql(
+    installation_dir: &Path,
+    pg_version: &str,
+    label: &str,
+    archive: impl FnOnce() -> Result<std::borrow::Cow<'static, [u8]>, CliError>,
+) -> Result<PathBuf, CliError> {
+    let version_dir = installation_dir.join(pg_version);
+
+    // Check if already extracted
+    let bin_dir = version_dir.join("bin");
+    let already_extracted = bin_dir.exists() && bin_dir.join(POSTGRES_BINARY).exists();
+
+    if !already_extracted {
+        let archive = archive()?;
+
+        println!("Extracting {} {}...", label, pg_version);
+        fs::create_dir_all(&version_dir)?;
+
+        extract_postgresql_archive(&archive, &version_dir)?;
+
+        if !bin_dir.join(POSTGRES_BINARY).exists() {
+            return Err(CliError::Other(format!(
+                "PostgreSQL extraction failed - {} not found at {}",
+                POSTGRES_BINARY,
+                bin_dir.display()
+            )));
+        }
+
+        // Make binaries executable on Unix
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Ok(entries) = fs::read_dir(&bin_dir) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if path.is_file() {
+                        if let Ok(metadata) = path.metadata() {
+                            let mut perms = metadata.permissions();
+                            perms.set_mode(0o755);
+                            let _ = fs::set_permissions(&path, perms);
+                        }
+                    }
+                }
+            }
+            // Also make lib files executable/accessible
+            let lib_dir = version_dir.join("lib");
+            if let Ok(entries) = fs::read_dir(&lib_dir) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if path.is_file() {
+                        if let Ok(metadata) = path.metadata() {
+                            let mut perms = metadata.permissions();
+                            perms.set_mode(0o755);
+                            let _ = fs::set_permissions(&path, perms);
+                        }
+                    }
+                }
+            }
+        }
+    } else {
+        tracing::debug!("PostgreSQL already extracted at {}", version_dir.display());
+    }
+
+    // Always ensure the runtime libs are unpacked and LD_LIBRARY_PATH points
+    // at them. Runs even when the postgres install is cached so users
+    // upgrading from a pg0 version that didn't ship the bundle pick up the
+    // new libs without manually wiping ~/.pg0/installation/.
+    ensure_runtime_libs(&version_dir)?;
+    #[cfg(target_os = "linux")]
+    prepend_lib_dir_to_ld_library_path(&version_dir.join("lib"));
+
+    // Final guard: if any required .so is still unresolved, surface a clear
+    // error instead of letting initdb / postgres start with a confusing
+    // dlopen failure.
+    #[cfg(target_os = "linux")]
+    check_shared_libraries(&bin_dir)?;
+
+    if !already_extracted {
+        println!("PostgreSQL {} extracted successfully.", pg_version);
+    }
+    Ok(version_dir)
+}
+
+/// Unpack RUNTIME_LIBS_BUNDLE into `<version_dir>/lib/` and create the SONAME
+/// symlinks the dynamic linker looks up (e.g. libxml2.so.2 ->
+/// libxml2.so.2.9.14). No-op when the bundle is empty (non-Linux-GNU targets)
+/// or when the libs are already present.
+fn ensure_runtime_libs(version_dir: &Path) -> Result<(), CliError> {
+    if RUNTIME_LIBS_BUNDLE.is_empty() {
+        return Ok(());
+    }
+
+    let lib_dir = version_dir.join("lib");
+    fs::create_dir_all(&lib_dir)?;
+
+    let decoder = GzDecoder::new(RUNTIME_LIBS_BUNDLE);
+    let mut archive = Archive::new(decoder);
+    let mut extracted_names: Vec<String> = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let basename = match path.file_name().and_then(|s| s.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let dest = lib_dir.join(&basename);
+        if !dest.exists() {
+            entry.unpack(&dest)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mut perms = fs::metadata(&dest)?.permissions();
+                perms.set_mode(0o755);
+                let _ = fs::set_permissions(&dest, perms);
+            }
+        }
+        extracted_names.push(basename);
+    }
+
+    // Create SONAME symlinks: libxml2.so.2 -> libxml2.so.2.9.14, etc.
+    // The dynamic linker only looks up files by SONAME (".so.<major>"), not
+    // the fully-versioned filename, so the symlinks are what actually makes
+    // the bundled libs reachable.
+    #[cfg(unix)]
+    for name in &extracted_names {
+        if let Some(soname) = soname_for(name) {
+            let link = lib_dir.join(&soname);
+            if link.exists() {
+                continue;
+            }
+            // Symlink relative to lib_dir so it stays valid if the install
+            // directory is moved.
+            if let Err(e) = std::os::unix::fs::symlink(name, &link) {
+                if e.kind() != std::io::ErrorKind::AlreadyExists {
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Given a fully-versioned shared-library filename like
+/// `libxml2.so.2.9.13` or `libicudata.so.70.1`, return the SONAME the dynamic
+/// linker actually resolves: `libxml2.so.2`, `libicudata.so.70`. Returns None
+/// for filenames we don't recognise (so we don't accidentally create bogus
+/// symlinks).
+fn soname_for(filename: &str) -> Option<String> {
+    let so_idx = filename.find(".so.")?;
+    let (stem, rest) = filename.split_at(so_idx + ".so.".len());
+    // `stem` ends with ".so."; `rest` is the version tail e.g. "2.9.14".
+    let major = rest.split('.').next()?;
+    if major.is_empty() {
+        return None;
+    }
+    Some(format!("{}{}", stem, major))
+}
+
+/// Walk up from a `bin/<tool>` path to the version-specific install dir and
+/// make sure the runtime libs are present + LD_LIBRARY_PATH points at them.
+/// Used by `pg0 psql` and the other commands that spawn bundled client tools
+/// against an instance whose install directory was set up by an earlier
+/// `pg0 start` (possibly from a previous pg0 release that didn't ship the
+/// libs bundle).
+fn ensure_runtime_libs_for_binary(binary_path: &Path) -> Result<(), CliError> {
+    let version_dir = match binary_path.parent().and_then(|p| p.parent()) {
+        Some(p) => p.to_path_buf(),
+        None => return Ok(()),
+    };
+    ensure_runtime_libs(&version_dir)?;
+    #[cfg(target_os = "linux")]
+    prepend_lib_dir_to_ld_library_path(&version_dir.join("lib"));
+    Ok(())
+}
+
+/// Prepend `lib_dir` to the process LD_LIBRARY_PATH so that subprocesses
+/// (initdb, postgres, pg_ctl, psql) find the bundled libs first. Existing
+/// entries are preserved.
+#[cfg(target_os = "linux")]
+fn prepend_lib_dir_to_ld_library_path(lib_dir: &Path) {
+    let lib_dir_s = lib_dir.to_string_lossy().to_string();
+    let new = match std::env::var("LD_LIBRARY_PATH") {
+        Ok(existing) if !existing.is_empty() => {
+            // Avoid duplicating ourselves on repeat calls.
+            if existing
+                .split(':')
+                .any(|p| p == lib_dir_s)
+            {
+                return;
+            }
+            format!("{}:{}", lib_dir_s, existing)
+        }
+        _ => lib_dir_s,
+    };
+    std::env::set_var("LD_LIBRARY_PATH", new);
+}
+
+/// The `ldd` lines of the shared libraries `path` needs that the loader
+/// cannot find. Empty if ldd is unavailable.
+#[cfg(target_os = "linux")]
+fn unresolved_libraries(path: &Path) -> Vec<String> {
+    let output = match std::process::Command::new("ldd").arg(path).output() {
+        Ok(output) => output,
+        Err(e) => {
+            tracing::debug!("Could not run ldd to check shared libraries: {}", e);
+            return Vec::new();
+        }
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.contains("not found"))
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+/// Check that the postgres binary can find all required shared libraries.
+/// Only called on Linux. If ldd is unavailable, silently skips the check.
+#[cfg(target_os = "linux")]
+fn check_shared_libraries(bin_dir: &std::path::Path) -> Result<(), CliError> {
+    let missing = unresolved_libraries(&bin_dir.join("postgres"));
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let missing_list = missing.join("\n  ");
+    Err(CliError::Other(format!(
+        "The bundled PostgreSQL binary is missing required system libraries:\n  \
+         {}\n\n\
+         Install the missing libraries using your system package manager. For example:\n  \
+         Arch Linux:    sudo pacman -S <package>\n  \
+         Ubuntu/Debian: sudo apt install <package>\n  \
+         Fedora/RHEL:   sudo dnf install <package>",
+        missing_list
+    )))
+}
+
+/// Where PostgreSQL releases that aren't bundled are downloaded from:
+/// PG0_BINARIES_BASE_URL, for a mirror of the theseus-rs releases laid out
+/// like them (`<base>/<version>/<archive>`, each archive with a `.sha256`),
+/// else the base URL pg0 was built with (GitHub unless built with
+/// PG0_BINARIES_BASE_URL). Returns the URL and whether it is a mirror.
+fn binaries_base_url() -> (String, bool) {
+    let default = env!("POSTGRESQL_BINARIES_BASE_URL");
+    let url = std::env::var("PG0_BINARIES_BASE_URL")
+        .ok()
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| default.to_string());
+    let mirrored = !url.starts_with("https://github.com/theseus-rs/postgresql-binaries/");
+    (url, mirrored)
+}
+
+/// Whether pg0 runs offline (`--offline` / PG0_OFFLINE), where nothing is
+/// downloaded.
+fn offline_mode() -> bool {
+    std::env::var("PG0_OFFLINE")
+        .map(|v| {
+            !matches!(
+                v.trim().to_lowercase().as_str(),
+                "" | "0" | "n" | "no" | "f" | "false" | "off"
+            )
+        })
+        .unwrap_or(false)
+}
+
+/// Check that `what` can be downloaded from `url` before starting to, so
+/// pg0 fails fast instead of waiting for a download to time out: always in
+/// offline mode, and when the host can't be reached within a few seconds
+/// (no network). `hint` says what is available offline.
+fn ensure_network(what: &str, url: &str, hint: &str) -> Result<(), CliError> {
+    if offline_mode() {
+        return Err(CliError::Other(format!(
+            "{} has to be downloaded from {}, but pg0 is offline (--offline / PG0_OFFLINE). {}",
+            what, url, hint
+        )));
+    }
+    // Behind a proxy the host itself may be unreachable while downloads work
+    let proxied = [
+        "HTTPS_PROXY",
+        "https_proxy",
+        "HTTP_PROXY",
+        "http_proxy",
+        "ALL_PROXY",
+        "all_proxy",
+    ]
+    .iter()
+    .any(|var| std::env::var_os(var).is_some_and(|v| !v.is_empty()));
+    let Some((host, port)) = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| Some((url.host_str()?.to_string(), url.port_or_known_default()?)))
+    else {
+        return Ok(());
+    };
+    if !proxied && !host_reachable(&host, port) {
+        return Err(CliError::Other(format!(
+            "{} has to be downloaded from {}, but {} can't be reached; this machine looks offline. {}",
+            what, url, host, hint
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a TCP connection to `host` succeeds within a few seconds,
+/// name resolution included.
+fn host_reachable(host: &str, port: u16) -> bool {
+    use std::net::ToSocketAddrs;
+    let timeout = std::time::Duration::from_secs(3);
+    let address = format!("{}:{}", host, port);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    // Name resolution can't time out, so it runs in a thread that is left
+    // behind if it hangs
+    std::thread::spawn(move || {
+        let reachable = address
+            .to_socket_addrs()
+            .map(|mut addresses| {
+                addresses.any(|a| std::net::TcpStream::connect_timeout(&a, timeout).is_ok())
+            })
+            .unwrap_or(false);
+        let _ = sender.send(reachable);
+    });
+    receiver.recv_timeout(timeout * 2).unwrap_or(false)
+}
+
+/// Why a download attempt failed.
+enum DownloadFailure {
+    /// Worth another attempt (connection problems, server errors)
+    Retry(String),
+    /// Retrying won't help (the server refused the URL, local I/O errors)
+    Fatal(String),
+}
+
+impl From<std::io::Error> for DownloadFailure {
+    fn from(e: std::io::Error) -> Self {
+        DownloadFailure::Fatal(e.to_string())
+    }
+}
+
+/// Classify a response status: `Ok` for success, else how it failed.
+fn download_status(status: reqwest::StatusCode) -> Result<(), DownloadFailure> {
+    if status.is_success() {
+        Ok(())
+    } else if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        Err(DownloadFailure::Retry(format!("HTTP {}", status)))
+    } else {
+        Err(DownloadFailure::Fatal(format!("HTTP {}", status)))
+    }
+}
+
+/// Run `attempt` against `url` until it succeeds, retrying failures up to
+/// PG0_DOWNLOAD_RETRIES times (default 5). The first retry waits
+/// PG0_DOWNLOAD_BACKOFF seconds (default 2), each further one twice as long,
+/// up to a minute.
+fn with_download_retries<T>(
+    url: &str,
+    mut attempt: impl FnMut(&reqwest::blocking::Client) -> Result<T, DownloadFailure>,
+) -> Result<T, CliError> {
+    let setting = |name: &str, default: u64| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .unwrap_or(default)
+    };
+    let retries = setting("PG0_DOWNLOAD_RETRIES", 5);
+    let mut delay = std::time::Duration::from_secs(setting("PG0_DOWNLOAD_BACKOFF", 2));
+    // The timeout applies to each read, so it catches stalled transfers
+    // without limiting how long a large download may take
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(30))
+        .timeout(std::time::Duration::from_secs(60))
+        .build()
+        .map_err(|e| CliError::Other(format!("Could not download {}: {}", url, e)))?;
+    let mut failures = 0;
+    loop {
+        match attempt(&client) {
+            Ok(result) => return Ok(result),
+            Err(DownloadFailure::Retry(e)) if failures < retries => {
+                failures += 1;
+                eprintln!(
+                    "Download of {} failed ({}); retrying in {}s ({}/{})...",
+                    url,
+                    e,
+                    delay.as_secs(),
+                    failures,
+                    retries
+                );
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(std::time::Duration::from_secs(60));
+            }
+            Err(DownloadFailure::Retry(e)) => {
+                return Err(CliError::Other(format!(
+                    "Could not download {} after {} attempts: {}",
+                    url,
+                    failures + 1,
+                    e
+                )))
+            }
+            Err(DownloadFailure::Fatal(e)) => {
+                return Err(CliError::Other(format!(
+                    "Could not download {}: {}",
+                    url, e
+                )))
+            }
+        }
+    }
+}
+
+/// Download a small file, such as a checksum or an index, into memory.
+fn download(url: &str) -> Result<Vec<u8>, CliError> {
+    if let Some(file) = url.strip_prefix("file://") {
+        return fs::read(file)
+            .map_err(|e| CliError::Other(format!("Could not read {}: {}", url, e)));
+    }
+    with_download_retries(url, |client| {
+        let response = client
+            .get(url)
+            .send()
+            .map_err(|e| DownloadFailure::Retry(e.to_string()))?;
+        download_status(response.status())?;
+        Ok(response
+            .bytes()
+            .map_err(|e| DownloadFailure::Retry(e.to_string()))?
+            .to_vec())
+    })
+}
+
+/// Download `url` to `dest`, showing progress on a terminal. Data goes to
+/// `<dest>.partial` first, and an interrupted download continues from
+/// there with a range request, on retries as well as in later runs.
+/// file:// URLs are copied.
+fn download_file(url: &str, dest: &Path) -> Result<(), CliError> {
+    use std::io::{IsTerminal, Read, Write};
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if let Some(file) = url.strip_prefix("file://") {
+        fs::copy(file, dest)
+            .map_err(|e| CliError::Other(format!("Could not read {}: {}", url, e)))?;
+        return Ok(());
+    }
+    let mut partial = dest.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let name = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| url.to_string());
+    let show_progress = std::io::stderr().is_terminal();
+
+    with_download_retries(url, |client| {
+        let offset = fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+        let mut request = client.get(url);
+        if offset > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        }
+        let mut response = request
+            .send()
+            .map_err(|e| DownloadFailure::Retry(e.to_string()))?;
+        let resumed = match response.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT => true,
+            // The partial file is no prefix of the file (any more): start over
+            reqwest::StatusCode::RANGE_NOT_SATISFIABLE => {
+                fs::remove_file(&partial)?;
+                return Err(DownloadFailure::Retry(
+                    "the interrupted download can't be resumed".to_string(),
+                ));
+            }
+            status => {
+                download_status(status)?;
+                false
+            }
+        };
+        if resumed {
+            println!("Resuming {} at {}...", name, format_size(offset));
+        }
+        let mut written = if resumed { offset } else { 0 };
+        let total = response.content_length().map(|length| length + written);
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&partial)?;
+
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut shown = std::time::Instant::now();
+        loop {
+            let read = match response.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    if show_progress {
+                        eprintln!();
+                    }
+                    return Err(DownloadFailure::Retry(e.to_string()));
+                }
+            };
+            file.write_all(&buffer[..read])?;
+            written += read as u64;
+            if show_progress && shown.elapsed() >= std::time::Duration::from_millis(200) {
+                shown = std::time::Instant::now();
+                match total {
+                    Some(total) if total > 0 => eprint!(
+                        "\r  {}: {} / {} ({}%)   ",
+                        name,
+                        format_size(written),
+                        format_size(total),
+                        written * 100 / total
+                    ),
+                    _ => eprint!("\r  {}: {}   ", name, format_size(written)),
+                }
+            }
+        }
+        if show_progress {
+            eprintln!("\r  {}: {}{}", name, format_size(written), " ".repeat(24));
+        }
+        if total.is_some_and(|total| written < total) {
+            return Err(DownloadFailure::Retry(
+                "the connection closed early".to_string(),
+            ));
+        }
+        file.sync_all()?;
+        Ok(())
+    })?;
+    fs::rename(&partial, dest)?;
+    Ok(())
+}
+
+/// Download a large artifact into memory through a resumable download (see
+/// `download_file`) in the downloads directory, saved as `name`.
+fn download_artifact(url: &str, name: &str) -> Result<Vec<u8>, CliError> {
+    let dest = downloads_dir()?.join(name);
+    download_file(url, &dest)?;
+    let artifact = fs::read(&dest)?;
+    fs::remove_file(&dest)?;
+    Ok(artifact)
+}
+
+/// The PostgreSQL versions installed below `installation_dir`, such as
+/// releases downloaded earlier.
+fn installed_postgresql_versions(installation_dir: &Path) -> Vec<postgresql_embedded::Version> {
+    let mut versions: Vec<_> = fs::read_dir(installation_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.path().join("bin").is_dir())
+                .filter_map(|entry| {
+                    postgresql_embedded::Version::parse(entry.file_name().to_str()?).ok()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    versions.sort();
+    versions
+}
+
+/// The theseus-rs platform name PostgreSQL downloads are built for; empty
+/// when there are none for this build's target.
+const POSTGRESQL_TARGET: &str = env!("POSTGRESQL_TARGET");
+
+/// Directory of the PostgreSQL archives pg0 downloaded, each next to the
+/// SHA256 it was verified against (`<archive>.sha256`), and of interrupted
+/// downloads.
+fn downloads_dir() -> Result<PathBuf, CliError> {
+    Ok(get_base_dir()?.join("downloads"))
+}
+
+/// File name of the theseus-rs archive of PostgreSQL `version`.
+fn postgresql_archive_name(version: &str) -> String {
+    format!(
+        "postgresql-{}-{}.{}",
+        version,
+        POSTGRESQL_TARGET,
+        if cfg!(windows) { "zip" } else { "tar.gz" }
+    )
+}
+
+/// The PostgreSQL versions with a verified archive in `downloads_dir`.
+fn cached_postgresql_versions(downloads_dir: &Path) -> Vec<postgresql_embedded::Version> {
+    let mut versions: Vec<_> = fs::read_dir(downloads_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| {
+                    let name = entry.file_name().to_str()?.to_string();
+                    let version = name
+                        .strip_prefix("postgresql-")?
+                        .split('-')
+                        .next()?
+                        .to_string();
+                    (name == postgresql_archive_name(&version)
+                        && downloads_dir.join(format!("{}.sha256", name)).exists())
+                    .then(|| postgresql_embedded::Version::parse(&version).ok())?
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    versions.sort();
+    versions
+}
+
+/// Install the newest PostgreSQL release matching `version_req` (requested
+/// as `version`) below `installation_dir` and return its directory. An
+/// installed release is used as it is; otherwise the release is extracted
+/// from a downloaded archive, downloading it first if needed.
+fn install_downloaded_postgresql(
+    installation_dir: &Path,
+    version: &str,
+    version_req: &VersionReq,
+) -> Result<PathBuf, CliError> {
+    let downloads_dir = downloads_dir()?;
+    let installed = installed_postgresql_versions(installation_dir)
+        .into_iter()
+        .chain(cached_postgresql_versions(&downloads_dir))
+        .filter(|v| version_req.matches(v))
+        .max();
+    let release = match installed {
+        Some(release) => release.to_string(),
+        None => {
+            if POSTGRESQL_TARGET.is_empty() {
+                return Err(CliError::Other(format!(
+                    "pg0 can't download PostgreSQL {} for this platform; use the bundled PostgreSQL {}",
+                    version,
+                    env!("PG_VERSION")
+                )));
+            }
+            let others: Vec<String> = installed_postgresql_versions(installation_dir)
+                .iter()
+                .map(|v| v.to_string())
+                .filter(|v| v != env!("PG_VERSION"))
+                .collect();
+            let (base_url, mirrored) = binaries_base_url();
+            ensure_network(
+                &format!("PostgreSQL {}", version),
+                &base_url,
+                &format!(
+                    "Offline, use the bundled PostgreSQL {}{}.",
+                    env!("PG_VERSION"),
+                    if others.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" or an installed version ({})", others.join(", "))
+                    }
+                ),
+            )?;
+            let release = if mirrored {
+                // Mirrors only serve files, so there are no releases to search
+                postgresql_embedded::Version::parse(version)
+                    .map_err(|_| {
+                        CliError::Other(format!(
+                            "The PostgreSQL mirror {} can't be searched for the latest {} release; ask for an exact version such as 17.2.0",
+                            base_url, version
+                        ))
+                    })?
+                    .to_string()
+            } else {
+                postgresql_archive::blocking::get_version(
+                    postgresql_archive::configuration::theseus::URL,
+                    version_req,
+                )
+                .map_err(|e| {
+                    CliError::Other(format!(
+                        "Could not find a PostgreSQL {} release: {}",
+                        version, e
+                    ))
+                })?
+                .to_string()
+            };
+            println!(
+                "PostgreSQL {} is not bundled with this pg0 binary (bundled: {}), downloading it...",
+                release,
+                env!("PG_VERSION")
+            );
+            download_postgresql_archive(&downloads_dir, &release)?;
+            release
+        }
+    };
+    extract_postgresql(installation_dir, &release, "PostgreSQL", || {
+        verified_postgresql_archive(&downloads_dir, &release).map(std::borrow::Cow::Owned)
+    })
+}
+
+/// Download the theseus-rs archive of PostgreSQL `version` into
+/// `downloads_dir`, verified against the SHA256 published next to it, and
+/// record the digest for later extractions.
+fn download_postgresql_archive(downloads_dir: &Path, version: &str) -> Result<(), CliError> {
+    let name = postgresql_archive_name(version);
+    let url = format!("{}/{}/{}", binaries_base_url().0, version, name);
+    let published = download(&format!("{}.sha256", url)).map_err(|e| {
+        CliError::Other(format!(
+            "{}; refusing to install PostgreSQL {} without its checksum",
+            e, version
+        ))
+    })?;
+    let expected = String::from_utf8_lossy(&published)
+        .split_whitespace()
+        .next()
+        .filter(|sha256| sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|sha256| sha256.to_lowercase())
+        .ok_or_else(|| CliError::Other(format!("Invalid checksum file {}.sha256", url)))?;
+    let path = downloads_dir.join(&name);
+    download_file(&url, &path)?;
+    if let Err(e) = verify_artifact(&name, &fs::read(&path)?, Some(&expected), false) {
+        let _ = fs::remove_file(&path);
+        return Err(e);
+    }
+    // Written last: an archive without it is not used
+    fs::write(
+        downloads_dir.join(format!("{}.sha256", name)),
+        format!("{}  {}\n", expected, name),
+    )?;
+    Ok(())
+}
+
+/// The downloaded archive of PostgreSQL `version`, after checking it against
+/// the digest recorded when it was downloaded.
+fn verified_postgresql_archive(downloads_dir: &Path, version: &str) -> Result<Vec<u8>, CliError> {
+    use sha2::{Digest, Sha256};
+    let name = postgresql_archive_name(version);
+    let path = downloads_dir.join(&name);
+    let recorded = fs::read_to_string(downloads_dir.join(format!("{}.sha256", name)))
+        .ok()
+        .and_then(|digest| {
+            digest
+                .split_whitespace()
+                .next()
+                .map(|sha256| sha256.to_lowercase())
+        })
+        .ok_or_else(|| CliError::Other(format!("PostgreSQL {} is not downloaded", version)))?;
+    let archive = fs::read(&path)?;
+    let actual = format!("{:x}", Sha256::digest(&archive));
+    if actual != recorded {
+        return Err(CliError::Other(format!(
+            "{} does not match the SHA256 recorded when it was downloaded (expected {}, got {}): it is corrupted or was modified. Delete it to download PostgreSQL {} again.",
+            path.display(),
+            recorded,
+            actual,
+            version
+        )));
+    }
+    Ok(archive)
+}
+
+/// Run initdb for a new data directory the way postgresql_embedded does
+/// (bootstrap superuser "postgres", password auth), plus `options`.
+fn initialize_cluster(
+    settings: &Settings,
+    version_dir: &Path,
+    options: &InitdbOptions,
+) -> Result<(), CliError> {
+    let initdb_path = find_pg_binary(version_dir, "initdb")?;
+    ensure_runtime_libs_for_binary(&initdb_path)?;
+
+    if let Some(parent) = settings.password_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Owner-only, like every other file pg0 keeps a secret in.
+    write_file_atomic(&settings.password_file, settings.password.as_bytes())?;
+
+    let args = options.args();
+    println!("Initializing database cluster ({})...", args.join(" "));
+    let output = std::process::Command::new(&initdb_path)
+        .arg("--pgdata")
+        .arg(&settings.data_dir)
+        .args([
+            "--username",
+            postgresql_embedded::BOOTSTRAP_SUPERUSER,
+            "--auth=password",
+        ])
+        .arg("--pwfile")
+        .arg(&settings.password_file)
+        .args(&args)
+        .output()?;
+    let _ = fs::remove_file(&settings.password_file);
+    if !output.status.success() {
+        return Err(CliError::Other(format!(
+            "initdb failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Where an extension archive entry (given by its path components) goes
+/// below the installation's `version_dir`: libraries to lib/, anything under
+/// share/ or bin/ keeping its layout, loose control and SQL files to
+/// share/extension/. `None` for entries that are skipped.
+fn extension_file_destination(version_dir: &Path, components: &[&str]) -> Option<PathBuf> {
+    let &name = components.last()?;
+    if is_library_file(name) {
+        Some(version_dir.join("lib").join(name))
+    } else if let Some(i) = components.iter().position(|c| *c == "share" || *c == "bin") {
+        // Keep the layout below share/ or bin/, reading share/postgresql/
+        // (the pgxs layout) as share/
+        let mut rest = &components[i + 1..];
+        if components[i] == "share" && rest.len() > 1 && rest[0] == "postgresql" {
+            rest = &rest[1..];
+        }
+        Some(
+            rest.iter()
+                .fold(version_dir.join(components[i]), |dir, c| dir.join(c)),
+        )
+    } else if name.ends_with(".control") || name.ends_with(".sql") {
+        Some(version_dir.join("share").join("extension").join(name))
+    } else {
+        None
+    }
+}
+
+fn is_library_file(name: &str) -> bool {
+    name.ends_with(".so")
+        || name.contains(".so.")
+        || name.ends_with(".dylib")
+        || name.ends_with(".dll")
+}
+
+/// The path components of a tar entry, or `None` for entries that are not
+/// regular files or that climb out of the archive with `..`.
+fn archive_entry_components<R: std::io::Read>(entry: &tar::Entry<R>) -> Option<Vec<String>> {
+    if !entry.header().entry_type().is_file() {
+        return None;
+    }
+    let path = entry.path().ok()?;
+    if path
+        .components()
+        .any(|c| matches!(c, std::path::Component::ParentDir))
+    {
+        return None;
+    }
+    Some(
+        path.components()
+            .filter_map(|c| match c {
+                std::path::Component::Normal(part) => part.to_str().map(|p| p.to_string()),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+/// Extract an extension archive into the installation at `version_dir`.
+fn unpack_extension_archive<R: std::io::Read>(
+    mut archive: Archive<R>,
+    version_dir: &Path,
+) -> Result<(), CliError> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(components) = archive_entry_components(&entry) else {
+            continue;
+        };
+        let components: Vec<&str> = components.iter().map(|c| c.as_str()).collect();
+        let Some(dest) = extension_file_destination(version_dir, &components) else {
+            continue;
+        };
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        entry.unpack(&dest)?;
+        // Make libraries and programs executable on Unix
+        #[cfg(unix)]
+        if is_library_file(components[components.len() - 1]) || components.contains(&"bin") {
+            use std::os::unix::fs::PermissionsExt;
+            if let Ok(metadata) = dest.metadata() {
+                let mut perms = metadata.permissions();
+                perms.set_mode(0o755);
+                let _ = fs::set_permissions(&dest, perms);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Install a bundled extension's files into the PostgreSQL installation
+fn install_bundled_extension(
+    installation_dir: &Path,
+    pg_version: &str,
+    extension: &BundledExtension,
+) -> Result<(), CliError> {
+    let pg_major = pg_version.split('.').next().unwrap_or("16");
+
+    // Find the version-specific installation directory
+    let version_dir = fs::read_dir(installation_dir)?
+        .filter_map(|e| e.ok())
+        .find(|e| e.path().is_dir() && e.file_name().to_string_lossy().starts_with(pg_major))
+        .map(|e| e.path())
+        .ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "PostgreSQL installation directory not found"
+        ))?;
+
+    let lib_dir = version_dir.join("lib");
+    let extension_dir = version_dir.join("share").join("extension");
+
+    // Check if the extension is already installed (output plugins such as
+    // wal2json have a library but no control file)
+    if extension_dir
+        .join(format!("{}.control", extension.name))
+        .exists()
+        || has_library(&lib_dir, extension.name)
+    {
+        tracing::debug!("{} already installed", extension.name);
+        return Ok(());
+    }
+
+    println!("Installing {} {}...", extension.name, extension.version);
+    unpack_extension_archive(
+        Archive::new(GzDecoder::new(extension.archive)),
+        &version_dir,
+    )?;
+
+    println!(
+        "{} {} installed successfully!",
+        extension.name, extension.version
+    );
+    Ok(())
+}
+
+/// Whether `lib_dir` holds the shared library `name`.
+fn has_library(lib_dir: &Path, name: &str) -> bool {
+    ["so", "dylib", "dll"]
+        .iter()
+        .any(|ext| lib_dir.join(format!("{}.{}", name, ext)).exists())
+}
+
+/// The bundled extension that provides `name`: either the bundle of that
+/// name, or one whose archive ships `<name>.control` (e.g. postgis_raster
+/// from the postgis bundle).
+fn find_bundled_extension(name: &str) -> Option<&'static BundledExtension> {
+    let name = name.to_lowercase();
+    let control = format!("{}.control", name);
+    BUNDLED_EXTENSIONS
+        .iter()
+        .find(|e| e.name == name)
+        .or_else(|| {
+            BUNDLED_EXTENSIONS.iter().find(|e| {
+                let mut archive = Archive::new(GzDecoder::new(e.archive));
+                archive
+                    .entries()
+                    .map(|entries| {
+                        entries.filter_map(|entry| entry.ok()).any(|entry| {
+                            entry
+                                .path()
+                                .map(|p| {
+                                    p.file_name()
+                                        .is_some_and(|f| f.to_string_lossy() == control)
+                                })
+                                .unwrap_or(false)
+                        })
+                    })
+                    .unwrap_or(false)
+            })
+        })
+}
+
+/// Point PROJ and GDAL at the data files bundled extensions (PostGIS)
+/// install under share/, unless the environment already does. The server
+/// inherits them from pg0.
+fn set_extension_data_env(version_dir: &Path) {
+    let share = version_dir.join("share");
+    for (dir, vars) in [
+        ("proj", &["PROJ_DATA", "PROJ_LIB"][..]),
+        ("gdal", &["GDAL_DATA"][..]),
+    ] {
+        let path = share.join(dir);
+        if !path.is_dir() {
+            continue;
+        }
+        for var in vars {
+            if std::env::var_os(var).is_none() {
+                std::env::set_var(var, &path);
+            }
+        }
+    }
+}
+
+/// Everything `pg0 start` needs to bring an instance up. Other commands that
+/// start instances (replica, matrix) fill in what they need and take the CLI
+/// defaults for the rest.
+struct StartOptions {
+    name: String,
+    port: u16,
+    port_was_specified: bool,
+    version: Option<String>,
+    data_dir: Option<String>,
+    in_memory: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    random_password: bool,
+    show_secrets: bool,
+    keychain: bool,
+    auth: Option<AuthMode>,
+    tls: Option<bool>,
+    listen: Vec<String>,
+    allow: Vec<String>,
+    socket_dir: Option<String>,
+    socket_only: bool,
+    database: Option<String>,
+    roles: Vec<String>,
+    init_dir: Option<String>,
+    init_sql: Vec<String>,
+    extensions: Vec<String>,
+    config: Vec<String>,
+    preset: Option<Preset>,
+    auto_tune: Option<bool>,
+    durability: Option<Durability>,
+    health_port: Option<u16>,
+    health_host: Option<String>,
+    idle_timeout: Option<String>,
+    ttl: Option<String>,
+    ttl_action: TtlAction,
+    memory_limit: Option<String>,
+    cpu_limit: Option<f64>,
+    auto_explain: Option<u64>,
+    audit: Option<String>,
+    initdb: InitdbOptions,
+    labels: Vec<String>,
+    /// Lifecycle hooks as EVENT=COMMAND.
+    hooks: Vec<String>,
+    /// Ignore the options remembered from the previous start.
+    reset: bool,
+}
+
+/// Cluster-level choices made by initdb. They only apply when a new data
+/// directory is initialized and can't be changed afterwards.
+#[derive(Default)]
+struct InitdbOptions {
+    locale: Option<String>,
+    encoding: Option<String>,
+    lc_collate: Option<String>,
+    locale_provider: Option<LocaleProvider>,
+    icu_locale: Option<String>,
+    auth_host: Option<String>,
+    extra_args: Vec<String>,
+}
+
+impl InitdbOptions {
+    fn is_empty(&self) -> bool {
+        self.locale.is_none()
+            && self.encoding.is_none()
+            && self.lc_collate.is_none()
+            && self.locale_provider.is_none()
+            && self.icu_locale.is_none()
+            && self.auth_host.is_none()
+            && self.extra_args.is_empty()
+    }
+
+    /// initdb arguments on top of the ones postgresql_embedded uses.
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![format!(
+            "--encoding={}",
+            self.encoding.as_deref().unwrap_or("UTF8")
+        )];
+        if let Some(locale) = &self.locale {
+            args.push(format!("--locale={}", locale));
+        }
+        if let Some(lc_collate) = &self.lc_collate {
+            args.push(format!("--lc-collate={}", lc_collate));
+        }
+        let locale_provider = match (self.locale_provider, &self.icu_locale) {
+            (None, Some(_)) => Some(LocaleProvider::Icu),
+            (provider, _) => provider,
+        };
+        if let Some(provider) = locale_provider {
+            args.push(format!("--locale-provider={}", provider.as_str()));
+        }
+        if let Some(icu_locale) = &self.icu_locale {
+            args.push(format!("--icu-locale={}", icu_locale));
+        }
+        if let Some(auth_host) = &self.auth_host {
+            args.push(format!("--auth-host={}", auth_host));
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
+
+impl Default for StartOptions {
+    fn default() -> Self {
+        StartOptions {
+            name: DEFAULT_INSTANCE_NAME.to_string(),
+            port: 5432,
+            port_was_specified: false,
+            version: None,
+            data_dir: None,
+            in_memory: None,
+            username: None,
+            password: None,
+            random_password: false,
+            show_secrets: false,
+            keychain: false,
+            auth: None,
+            tls: None,
+            listen: Vec::new(),
+            allow: Vec::new(),
+            socket_dir: None,
+            socket_only: false,
+            database: None,
+            roles: Vec::new(),
+            init_dir: None,
+            init_sql: Vec::new(),
+            extensions: Vec::new(),
+            config: Vec::new(),
+            preset: None,
+            auto_tune: None,
+            durability: None,
+            health_port: None,
+            health_host: None,
+            idle_timeout: None,
+            ttl: None,
+            ttl_action: TtlAction::Stop,
+            memory_limit: None,
+            cpu_limit: None,
+            auto_explain: None,
+            audit: None,
+            initdb: InitdbOptions::default(),
+            labels: Vec::new(),
+            hooks: Vec::new(),
+            reset: false,
+        }
+    }
+}
+
+fn start(options: StartOptions) -> Result<(), CliError> {
+    let StartOptions {
+        name,
+        port,
+        port_was_specified,
+        version,
+        data_dir,
+        in_memory,
+        username,
+        password,
+        random_password,
+        show_secrets,
+        keychain,
+        auth,
+        tls,
+        listen,
+        allow,
+        socket_dir,
+        socket_only,
+        database,
+        roles,
+        init_dir,
+        init_sql,
+        extensions,
+        config,
+        preset,
+        auto_tune,
+        durability,
+        health_port,
+        health_host,
+        idle_timeout,
+        ttl,
+        ttl_action,
+        memory_limit,
+        cpu_limit,
+        auto_explain,
+        audit,
+        initdb,
+        labels,
+        hooks,
+        reset,
+    } = options;
+    let _lock = lock_instance(&name)?;
+
+    // Flags that aren't passed are taken from the previous start, so
+    // `pg0 start --name x` brings a stopped instance back as it was.
+    let previous = load_instance(&name)?;
+    let remembered = previous.as_ref().filter(|_| !reset);
+    let port = match remembered {
+        Some(previous) if !port_was_specified => previous.port,
+        _ => port,
+    };
+    let version = version
+        .or_else(|| remembered.map(|p| p.version.clone()))
+        .unwrap_or_else(|| env!("PG_VERSION").to_string());
+    // The data directory is where the instance lives, not an option.
+    let data_dir = data_dir.or_else(|| {
+        previous
+            .as_ref()
+            .filter(|p| p.in_memory.is_none() && in_memory.is_none())
+            .map(|p| p.data_dir.display().to_string())
+    });
+    let username = username
+        .or_else(|| remembered.map(|p| p.username.clone()))
+        .unwrap_or_else(|| "postgres".to_string());
+    let password = match password.or_else(|| remembered.map(|p| p.password.clone())) {
+        Some(password) => password,
+        None if random_password => generate_password()?,
+        None => "postgres".to_string(),
+    };
+    // Generated passwords stay hidden for as long as the instance keeps them.
+    let secret_password =
+        random_password || remembered.is_some_and(|p| p.secret_password && p.password == password);
+    let keychain = keychain || remembered.is_some_and(|p| p.keychain_account.is_some());
+    let database = database
+        .or_else(|| remembered.map(|p| p.database.clone()))
+        .unwrap_or_else(|| "postgres".to_string());
+    // Roles live in the cluster, so they aren't remembered.
+    let roles: Vec<UserSpec> = roles
+        .iter()
+        .map(|role| parse_role(role))
+        .collect::<Result<_, _>>()?;
+    // Init scripts only run for a new cluster, so they aren't remembered
+    // either. Listed now, so a bad directory fails before anything starts.
+    let mut init_scripts = match &init_dir {
+        Some(dir) => list_init_scripts(&expand_path(dir))?,
+        None => Vec::new(),
+    };
+    for file in &init_sql {
+        let path = expand_path(file);
+        if !path.is_file() {
+            return Err(CliError::Other(format!(
+                "Init SQL file {} not found",
+                path.display()
+            )));
+        }
+        init_scripts.push(path);
+    }
+    // -c overrides replace remembered ones for the same setting only.
+    let config: Vec<String> = match remembered {
+        Some(previous) => {
+            let setting_key = |cfg: &String| cfg.split_once('=').map(|(k, _)| k.trim().to_string());
+            let passed: Vec<String> = config.iter().filter_map(setting_key).collect();
+            previous
+                .config
+                .iter()
+                .filter(|cfg| !setting_key(cfg).is_some_and(|key| passed.contains(&key)))
+                .cloned()
+                .chain(config)
+                .collect()
+        }
+        None => config,
+    };
+    let preset = preset.or(remembered.map(|p| p.preset)).unwrap_or_default();
+    let auto_tune = auto_tune
+        .or(remembered.map(|p| p.auto_tune))
+        .unwrap_or(false);
+    let durability = durability
+        .or(remembered.map(|p| p.durability))
+        .unwrap_or_default();
+    let auth = auth.or(remembered.map(|p| p.auth)).unwrap_or_default();
+    let was_scram = previous.as_ref().is_some_and(|p| p.auth == AuthMode::Scram);
+    let tls = tls.unwrap_or_else(|| remembered.is_some_and(|p| p.tls.is_some()));
+    let listen = match remembered {
+        Some(previous) if listen.is_empty() => previous.listen.clone(),
+        _ => listen,
+    };
+    let allow = match remembered {
+        Some(previous) if allow.is_empty() => previous.allow.clone(),
+        _ => allow
+            .iter()
+            .map(|cidr| parse_cidr(cidr))
+            .collect::<Result<_, _>>()?,
+    };
+    let socket_only = socket_only || remembered.is_some_and(|p| p.socket_only);
+    // The server would take a relative directory as relative to its data.
+    let socket_dir = match socket_dir {
+        Some(dir) => Some(std::path::absolute(expand_path(&dir))?),
+        None => remembered.and_then(|p| p.socket_dir.clone()),
+    };
+    let remote = listen.iter().any(|address| !is_local_address(address));
+    if remote && password == "postgres" {
+        return Err(CliError::Other(
+            "Refusing to listen beyond localhost with the default password. Pass --password or --random-password."
+                .to_string(),
+        ));
+    }
+    let remembered_health = remembered.and_then(|p| p.health.as_ref());
+    let health = match (
+        health_port.or(remembered_health.map(|h| h.port)),
+        health_host,
+    ) {
+        (Some(port), host) => Some(HealthConfig {
+            port,
+            host: host
+                .or(remembered_health.map(|h| h.host.clone()))
+                .unwrap_or_else(default_health_host),
+        }),
+        (None, Some(_)) => {
+            return Err(CliError::Other(
+                "--health-host needs --health-port".to_string(),
+            ))
+        }
+        (None, None) => None,
+    };
+    let idle_timeout = idle_timeout.or_else(|| {
+        remembered
+            .and_then(|p| p.idle_timeout)
+            .map(|secs| secs.to_string())
+    });
+    let remembered_limits = remembered.and_then(|p| p.limits.as_ref());
+    let memory_limit = memory_limit.or_else(|| {
+        remembered_limits
+            .and_then(|l| l.memory_bytes)
+            .map(|bytes| bytes.to_string())
+    });
+    let cpu_limit = cpu_limit.or(remembered_limits.and_then(|l| l.cpus));
+    let auto_explain = auto_explain.or(remembered.and_then(|p| p.auto_explain));
+    let audit = audit.or(remembered.and_then(|p| p.audit.clone()));
+    let mut labels_by_key = remembered.map(|p| p.labels.clone()).unwrap_or_default();
+    labels_by_key.extend(parse_labels(&labels)?);
+    let labels = labels_by_key;
+    let mut hooks_by_event = remembered.map(|p| p.hooks.clone()).unwrap_or_default();
+    for (event, command) in parse_hooks(&hooks)? {
+        if command.is_empty() {
+            hooks_by_event.remove(&event);
+        } else {
+            hooks_by_event.insert(event, command);
+        }
+    }
+    let hooks = hooks_by_event;
+
+    let idle_timeout = idle_timeout
+        .as_deref()
+        .map(parse_duration_secs)
+        .transpose()?;
+    let ttl = match ttl {
+        Some(ttl) => Some(TtlConfig {
+            expires_at: unix_now() + parse_duration_secs(&ttl)?,
+            action: ttl_action,
+        }),
+        None => None,
+    };
+    let limits = if memory_limit.is_some() || cpu_limit.is_some() {
+        if cpu_limit.is_some_and(|cpus| cpus <= 0.0) {
+            return Err(CliError::Other(
+                "--cpu-limit must be greater than 0".to_string(),
+            ));
+        }
+        Some(ResourceLimits {
+            memory_bytes: memory_limit.as_deref().map(parse_size_bytes).transpose()?,
+            cpus: cpu_limit,
+        })
+    } else {
+        None
+    };
+
+    if socket_only {
+        // Remembered options can meet flags clap couldn't compare them with.
+        let tcp_only = [
+            (!listen.is_empty(), "--listen"),
+            (tls, "--tls"),
+            (health.is_some(), "--health-port"),
+            (
+                previous.as_ref().is_some_and(|p| p.pool.is_some()),
+                "the connection pooler",
+            ),
+        ];
+        if let Some((_, what)) = tcp_only.iter().find(|(used, _)| *used) {
+            return Err(CliError::Other(format!(
+                "{} needs TCP and can't be used with --socket-only. Start with --reset to turn --socket-only off.",
+                what
+            )));
+        }
+    }
+
+    // Replication topology, the pooler and the metrics exporter survive
+    // restarts; everything else is rebuilt from the flags below.
+    let mut replica_of = None;
+    let mut replication_slot = None;
+    let mut crashed_in_fast_mode = false;
+    let mut pool = None;
+    let mut metrics = None;
+    let stale = previous.is_some();
+
+    // Check if already running
+    if let Some(info) = previous {
+        replica_of = info.replica_of.clone();
+        replication_slot = info.replication_slot.clone();
+        crashed_in_fast_mode = info.crashed_in_fast_mode;
+        pool = info.pool.clone();
+        metrics = info.metrics.clone();
+        if info.archive.is_some() {
+            return Err(CliError::Other(format!(
+                "Instance '{}' is archived. Use 'pg0 unarchive --name {}' to restore it first.",
+                name, name
+            )));
+        }
+        if is_instance_running(&info) {
+            return Err(CliError::AlreadyRunning(info.pid));
+        }
+        // In-memory data doesn't outlive the server process.
+        if let Some(storage) = &info.in_memory {
+            release_in_memory_storage(storage)?;
+        }
+        // Stale instance: clean up instance metadata but preserve data directory.
+        // Remove stale postmaster.pid so PostgreSQL can start with existing data.
+        let pid_file = info.data_dir.join("postmaster.pid");
+        if pid_file.exists() {
+            println!("Removing stale postmaster.pid (process {} no longer running)...", info.pid);
+            fs::remove_file(&pid_file)?;
+            if info.durability == Durability::Fast {
+                eprintln!(
+                    "Warning: Instance '{}' was not shut down cleanly while running in fast (non-durable) mode. Its data may be corrupt.",
+                    name
+                );
+                crashed_in_fast_mode = true;
+            }
+        }
+    }
+
+    if let Some(health) = &health {
+        // This instance's previous health server may still hold the port.
+        if let Ok((pid_file, _)) = health_worker_files(&name) {
+            stop_worker(&pid_file);
+        }
+        if !is_port_available(health.port) {
+            return Err(CliError::Other(format!(
+                "Health port {} is already in use",
+                health.port
+            )));
+        }
+    }
+
+    // Only forget the stopped instance once nothing above refused to start it.
+    if stale {
+        remove_instance(&name)?;
+    }
+
+    // Without TCP the port only names the socket file.
+    let port = if socket_only {
+        port
+    } else {
+        assign_port(&name, port, port_was_specified)?
+    };
+    match &health {
+        Some(health) => {
+            assign_port(&format!("{}/health", name), health.port, true)?;
+        }
+        None => release_port(&format!("{}/health", name))?,
+    }
+
+    let base_dir = get_base_dir()?;
+    let instance_dir = get_instance_dir(&name)?;
+
+    let tls = if tls {
+        Some(ensure_tls_files(&instance_dir)?)
+    } else {
+        None
+    };
+
+    let socket_dir = match socket_dir {
+        Some(dir) => Some(dir),
+        None if socket_only => Some(instance_dir.clone()),
+        None => None,
+    };
+    if let Some(dir) = &socket_dir {
+        check_socket_dir(dir, port)?;
+        fs::create_dir_all(dir)?;
+    }
+
+    // Store the password before the server comes up, so an unreachable
+    // keychain fails the start instead of losing the password.
+    let keychain_account = if keychain {
+        let account = instance_dir.display().to_string();
+        keychain_store(&account, &password)?;
+        Some(account)
+    } else {
+        None
+    };
+
+    let in_memory = match in_memory {
+        Some(size) => Some(create_in_memory_storage(&name, &size)?),
+        None => None,
+    };
+
+    // Use provided data_dir or default to instance-specific directory
+    let data_dir = match (&in_memory, data_dir) {
+        (Some(storage), _) => storage.path.join("data"),
+        (None, Some(dir)) => expand_path(&dir),
+        (None, None) => instance_dir.join("data"),
+    };
+
+    let installation_dir = base_dir.join("installation");
+
+    fs::create_dir_all(&data_dir)?;
+    fs::create_dir_all(&installation_dir)?;
+
+    // "18" or "18.1" mean the bundled release when it matches; anything else
+    // is resolved and downloaded by postgresql_embedded below.
+    let version = resolve_version(&version);
+    let bundled = version == env!("PG_VERSION");
+
+    println!("Setting up PostgreSQL {}...", version);
+
+    let version_req: VersionReq = version.parse().map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid version: {}", e),
+        )
+    })?;
+
+    // Build configuration HashMap with sensible defaults
+    let mut configuration: HashMap<String, String> = HashMap::new();
+
+    // Tuning preset (vector/AI workloads unless chosen otherwise)
+    for (key, value) in preset.settings() {
+        configuration.insert(key.to_string(), value.to_string());
+    }
+    if auto_tune {
+        let max_connections = configuration
+            .get("max_connections")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(100);
+        let tuned = auto_tune_settings(limits.as_ref(), max_connections)?;
+        println!("Auto-tuning for {}:", tuned.summary);
+        for (key, value) in tuned.settings {
+            println!("  {} = {}", key, value);
+            configuration.insert(key.to_string(), value);
+        }
+    }
+
+    // Durability mode (individual settings can still be overridden with -c)
+    for (key, value) in durability.settings() {
+        configuration.insert(key.to_string(), value.to_string());
+    }
+
+    // File logging and timezone pinning (see SERVER_SETTINGS)
+    for (key, value) in SERVER_SETTINGS {
+        configuration.insert(key.to_string(), value.to_string());
+    }
+
+    // Settings from the managed configuration layer (pg0.conf, see
+    // `pg0 config edit`) and from ALTER SYSTEM (`pg0 config set`) replace
+    // pg0's defaults. They must not be passed on the command line, which
+    // would shadow the files and make later reloads ineffective.
+    let file_settings: Vec<(String, String)> = read_managed_config(&data_dir)?
+        .into_iter()
+        .chain(read_auto_config(&data_dir)?)
+        .collect();
+    for (key, value) in file_settings.iter().cloned() {
+        if COMMAND_LINE_SETTINGS.contains(&key.as_str()) {
+            configuration.insert(key, value);
+        } else {
+            configuration.remove(&key);
+        }
+    }
+
+    // auto_explain and pgaudit presets. Their libraries have to join
+    // whatever the configuration files preload, since the command line
+    // shadows them.
+    let preload: Vec<&str> = [
+        auto_explain.map(|_| "auto_explain"),
+        audit.as_ref().map(|_| "pgaudit"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !preload.is_empty() {
+        let mut libraries: Vec<String> = file_settings
+            .iter()
+            .rev()
+            .find(|(key, _)| key == "shared_preload_libraries")
+            .map(|(_, value)| {
+                value
+                    .split(',')
+                    .map(|l| l.trim().to_string())
+                    .filter(|l| !l.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        for library in preload {
+            if !libraries.iter().any(|l| l == library) {
+                libraries.push(library.to_string());
+            }
+        }
+        configuration.insert("shared_preload_libraries".to_string(), libraries.join(","));
+    }
+    if let Some(min_duration) = auto_explain {
+        for (key, value) in [
+            (
+                "auto_explain.log_min_duration",
+                format!("{}ms", min_duration),
+            ),
+            ("auto_explain.log_analyze", "on".to_string()),
+            ("auto_explain.log_buffers", "on".to_string()),
+            ("auto_explain.log_nested_statements", "on".to_string()),
+        ] {
+            configuration.insert(key.to_string(), value);
+        }
+    }
+    if let Some(classes) = &audit {
+        for (key, value) in [
+            ("pgaudit.log", classes.clone()),
+            ("pgaudit.log_catalog", "off".to_string()),
+            ("pgaudit.log_relation", "on".to_string()),
+        ] {
+            configuration.insert(key.to_string(), value);
+        }
+    }
+
+    // Parse and apply custom config options (these override defaults)
+    for cfg in &config {
+        if let Some((key, value)) = cfg.split_once('=') {
+            configuration.insert(key.trim().to_string(), value.trim().to_string());
+        } else {
+            eprintln!("Warning: Invalid config format '{}', expected KEY=VALUE", cfg);
+        }
+    }
+
+    // An MD5 hash couldn't be used with scram-sha-256 in pg_hba.conf.
+    if auth == AuthMode::Scram {
+        configuration.insert(
+            "password_encryption".to_string(),
+            "scram-sha-256".to_string(),
+        );
+    }
+    if !listen.is_empty() {
+        configuration.insert("listen_addresses".to_string(), listen_addresses(&listen));
+    }
+    if remote {
+        configuration.insert(
+            "password_encryption".to_string(),
+            "scram-sha-256".to_string(),
+        );
+    }
+    if let Some(dir) = &socket_dir {
+        configuration.insert(
+            "unix_socket_directories".to_string(),
+            dir.display().to_string(),
+        );
+    }
+    if socket_only {
+        configuration.insert("listen_addresses".to_string(), String::new());
+    }
+    if let Some(tls) = &tls {
+        configuration.insert("ssl".to_string(), "on".to_string());
+        configuration.insert(
+            "ssl_cert_file".to_string(),
+            tls.cert_file.display().to_string(),
+        );
+        configuration.insert(
+            "ssl_key_file".to_string(),
+            tls.key_file.display().to_string(),
+        );
+    }
+
+    // Extract bundled PostgreSQL. Other versions are downloaded from
+    // theseus-rs, verified, and extracted into installation_dir/<version>.
+    let version_install_dir = {
+        let mut span = operation_span("extract_postgresql");
+        span.attr("postgresql.version", &version);
+        let result = if bundled {
+            extract_bundled_postgresql(&installation_dir, &version)
+        } else {
+            install_downloaded_postgresql(&installation_dir, &version, &version_req)
+        };
+        if let Err(e) = &result {
+            span.fail(e);
+        }
+        result?
+    };
+
+    let settings = Settings {
+        version: version_req,
+        // postgresql_embedded creates the database over this host, where a
+        // percent-encoded path stands for a socket directory.
+        host: match &socket_dir {
+            Some(dir) if socket_only => {
+                percent_encode(&dir.display().to_string()).replace('/', "%2F")
+            }
+            _ => "localhost".to_string(),
+        },
+        port,
+        username: username.clone(),
+        password: password.clone(),
+        data_dir: data_dir.clone(),
+        installation_dir: version_install_dir,
+        configuration,
+        trust_installation_dir: true, // Use our extracted files
+        temporary: false, // Never delete data directory on drop - pg0 manages data lifecycle explicitly
+        timeout: Some(std::time::Duration::from_secs(600)), // 10 minute timeout for slow systems (ARM64 emulation under QEMU)
+        ..Default::default()
+    };
+
+    let initialized = data_dir.join("postgresql.conf").exists();
+
+    // postgresql_embedded runs initdb with fixed arguments, so new clusters
+    // with initdb options are initialized here; setup() then skips initdb.
+    if !initdb.is_empty() {
+        if initialized {
+            eprintln!(
+                "Warning: '{}' is already initialized; --locale, --encoding, --lc-collate, --locale-provider, --icu-locale, --auth-host and --initdb-arg only apply to new instances.",
+                data_dir.display()
+            );
+        } else {
+            initialize_cluster(&settings, &settings.installation_dir, &initdb)?;
+        }
+    }
+
+    let mut postgresql = PostgreSQL::new(settings);
+    {
+        // Runs initdb for new instances.
+        let mut span = operation_span("setup");
+        span.attr("postgresql.version", &version);
+        span.attr("postgresql.bundled", bundled);
+        postgresql.setup()?;
+    }
+    ensure_managed_config_include(&data_dir)?;
+    if tls.is_some() {
+        ensure_hostssl_rules(&data_dir)?;
+    }
+    write_remote_rules(
+        &data_dir,
+        if remote { &allow } else { &[] },
+        tls.is_some(),
+        remote,
+    )?;
+    apply_hba_rules(&data_dir, &load_hba_rules(&name)?)?;
+
+    // Record the exact version that was downloaded (e.g. "16" -> "16.11.0").
+    let version = if bundled {
+        version
+    } else {
+        postgresql
+            .settings()
+            .installation_dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.to_string())
+            .unwrap_or(version)
+    };
+
+    // Install the bundled extensions. They are built against the bundled
+    // PostgreSQL major only.
+    if bundled {
+        for extension in BUNDLED_EXTENSIONS {
+            let mut span = operation_span("install_extension");
+            span.attr("extension", extension.name);
+            if let Err(e) = install_bundled_extension(&installation_dir, &version, extension) {
+                span.fail(&e);
+                eprintln!("Warning: Failed to install {}: {}", extension.name, e);
+                eprintln!(
+                    "You can try installing it manually with: pg0 install-extension {}",
+                    extension.name
+                );
+            }
+        }
+    } else if !BUNDLED_EXTENSIONS.is_empty() {
+        let names: Vec<&str> = BUNDLED_EXTENSIONS.iter().map(|e| e.name).collect();
+        println!(
+            "Note: the bundled extensions ({}) target PostgreSQL {}; use 'pg0 install-extension <name>' for {}.",
+            names.join(", "),
+            env!("PG_VERSION"),
+            version
+        );
+    }
+    set_extension_data_env(&installation_dir.join(&version));
+
+    if audit.is_some() {
+        let lib_dir = installation_dir.join(&version).join("lib");
+        if !has_library(&lib_dir, "pgaudit") {
+            return Err(CliError::Other(format!(
+                "--audit needs pgaudit, which is not installed for PostgreSQL {}. Start without --audit, run 'pg0 install-extension --name {} pgaudit', then restart with --audit.",
+                version, name
+            )));
+        }
+    }
+
+    if let Some(command) = hooks.get(&HookEvent::PreStart) {
+        let vars = connection_vars(socket_dir.as_deref(), &username, &password, port, &database);
+        run_hook(&name, HookEvent::PreStart, command, &data_dir, vars)?;
+    }
+
+    println!("Starting PostgreSQL on port {}...", port);
+    let mut start_span = operation_span("start_postgresql");
+    start_span.attr("server.port", port);
+    if let Err(e) = postgresql.start() {
+        // Try to read the PostgreSQL log for more context
+        let log_context = read_latest_pg_log(&data_dir);
+        let error_msg = if let Some(log) = log_context {
+            format!("Failed to start PostgreSQL: {}\n\nPostgreSQL log:\n{}", e, log)
+        } else {
+            format!("Failed to start PostgreSQL: {}", e)
+        };
+        start_span.fail(&error_msg);
+        return Err(CliError::Other(error_msg));
+    }
+    drop(start_span);
+
+    // Statements as the bootstrap superuser. The password goes through
+    // PGPASSWORD and the SQL (which may contain it) through stdin, so
+    // neither shows up in the process list.
+    let run_as_postgres = |sql: &str| -> Result<std::process::ExitStatus, CliError> {
+        use std::io::Write;
+        let psql_path = find_psql_binary(&postgresql.settings().installation_dir)?;
+        let mut child = std::process::Command::new(&psql_path)
+            .arg(local_uri(
+                socket_dir.as_deref(),
+                "postgres",
+                port,
+                "postgres",
+            ))
+            .env("PGPASSWORD", &password)
+            .args(["-X", "-q", "-v", "ON_ERROR_STOP=1", "-f", "-"])
+            .stdin(std::process::Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(sql.as_bytes())?;
+        }
+        Ok(child.wait()?)
+    };
+
+    // Create the user if it's not the default 'postgres'
+    // Note: postgresql_embedded always creates 'postgres' as the superuser
+    if username != "postgres" {
+        println!("Creating user '{}'...", username);
+        let create_user_sql = format!(
+            "DO $$ BEGIN IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = {}) THEN CREATE USER {} WITH SUPERUSER PASSWORD {}; END IF; END $$;",
+            quote_literal(&username),
+            quote_ident(&username),
+            quote_literal(&password)
+        );
+        let status = run_as_postgres(&create_user_sql)?;
+        if !status.success() {
+            eprintln!("Warning: Failed to create user '{}'", username);
+        }
+    }
+
+    // Create the database if it doesn't exist and it's not the default 'postgres'
+    if database != "postgres" {
+        // Pre-check existence rather than relying on the duplicate-database error
+        // string, which is localized by PostgreSQL's lc_messages (e.g. on Windows
+        // with a Chinese locale: `数据库 "x" 已经存在`). See vectorize-io/pg0#13.
+        if !postgresql.database_exists(&database)? {
+            println!("Creating database '{}'...", database);
+            postgresql.create_database(&database)?;
+        }
+        // Grant privileges to the user on the database
+        if username != "postgres" {
+            let grant_sql = format!(
+                "GRANT ALL PRIVILEGES ON DATABASE {} TO {};",
+                quote_ident(&database),
+                quote_ident(&username)
+            );
+            let _ = run_as_postgres(&grant_sql);
+        }
+    }
+
+    // Read PID from postmaster.pid file
+    let pid = read_postmaster_pid(&data_dir)?;
+
+    // Detach - let the process continue running, even if a later step
+    // (roles, extensions, init scripts, hooks) fails
+    std::mem::forget(postgresql);
+
+    let info = InstanceInfo {
+        pid,
+        process: process_identity(pid),
+        port,
+        data_dir: data_dir.clone(),
+        installation_dir,
+        username: username.clone(),
+        password: password.clone(),
+        secret_password,
+        keychain_account,
+        database: database.clone(),
+        version: version.clone(),
+        archive: None,
+        replica_of,
+        replication_slot,
+        durability,
+        auth,
+        tls,
+        preset,
+        auto_tune,
+        crashed_in_fast_mode,
+        pool,
+        metrics,
+        health,
+        auto_explain,
+        audit,
+        idle_timeout,
+        ttl,
+        limits,
+        in_memory: in_memory.clone(),
+        config,
+        labels,
+        listen,
+        allow,
+        socket_dir,
+        socket_only,
+        hooks,
+    };
+
+    // Remote rules require SCRAM, which an MD5 hash can't be used with.
+    if remote && info.replica_of.is_none() {
+        rehash_passwords_scram(&info)?;
+    }
+
+    // Only touch pg_hba.conf when SCRAM is asked for or was in force, so
+    // --auth-host choices of other instances stay as they are.
+    if auth == AuthMode::Scram || was_scram {
+        set_host_auth(&info)?;
+    }
+
+    save_instance(&name, &info)?;
+    if !roles.is_empty() {
+        if info.replica_of.is_some() {
+            eprintln!("Warning: '{}' is a read-only replica; its roles come from the primary, --role is ignored", name);
+        } else {
+            create_roles(&info, &roles, &info.database)?;
+        }
+    }
+    if !extensions.is_empty() {
+        if info.replica_of.is_some() {
+            eprintln!("Warning: '{}' is a read-only replica; its extensions come from the primary, --with is ignored", name);
+        } else {
+            for extension in extensions
+                .iter()
+                .map(|e| e.trim())
+                .filter(|e| !e.is_empty())
+            {
+                create_extension(&name, &info, &info.database, extension)?;
+            }
+        }
+    }
+    if !init_scripts.is_empty() {
+        if initialized {
+            println!(
+                "Skipping init scripts: '{}' was initialized before.",
+                data_dir.display()
+            );
+        } else {
+            run_init_scripts(&info, &init_scripts)?;
+        }
+    }
+    if let Some(command) = info.hooks.get(&HookEvent::PostStart) {
+        run_hook(
+            &name,
+            HookEvent::PostStart,
+            command,
+            &data_dir,
+            env_vars(&info),
+        )
+        .map_err(|e| CliError::Other(format!("{}; PostgreSQL is running", e)))?;
+    }
+    resume_sink_workers(&name);
+    resume_pool_worker(&name, &info);
+    resume_metrics_worker(&name, &info);
+    resume_otel_worker(&name);
+    resume_health_worker(&name, &info);
+    resume_autostop_worker(&name, &info);
+    let limits_applied = info.limits.as_ref().is_some_and(|limits| {
+        apply_resource_limits(&name, info.pid, limits)
+            .map_err(|e| eprintln!("Warning: The instance runs without resource limits: {}", e))
+            .is_ok()
+    });
+
+    println!();
+    println!("PostgreSQL is running!");
+    println!("  Instance: {}", name);
+    println!("  PID:      {}", pid);
+    println!("  Port:     {}", port);
+    println!("  Username: {}", username);
+    let shown_password = if (secret_password || keychain) && !show_secrets {
+        REDACTED
+    } else {
+        password.as_str()
+    };
+    if shown_password == REDACTED {
+        let source = if keychain {
+            "in the OS keychain"
+        } else {
+            "generated"
+        };
+        println!(
+            "  Password: {} ({}; see pg0 info --show-secrets)",
+            REDACTED, source
+        );
+    } else {
+        println!("  Password: {}", password);
+    }
+    println!("  Database: {}", database);
+    if in_memory.is_some() {
+        println!(
+            "  Data dir: {} (in memory, discarded on stop)",
+            data_dir.display()
+        );
+    } else {
+        println!("  Data dir: {}", data_dir.display());
+    }
+    if durability != Durability::Safe {
+        print_durability(Some(durability.as_str()));
+    }
+    if auth == AuthMode::Scram {
+        println!("  Auth:     scram-sha-256");
+    }
+    if let Some(tls) = &info.tls {
+        println!("  TLS:      on (CA certificate: {})", tls.ca_file.display());
+    }
+    if let Some(dir) = &info.socket_dir {
+        let tcp = if info.socket_only { " (no TCP)" } else { "" };
+        println!(
+            "  Socket:   {}{}",
+            dir.join(format!(".s.PGSQL.{}", port)).display(),
+            tcp
+        );
+    }
+    if remote {
+        let from = if info.allow.is_empty() {
+            "any address".to_string()
+        } else {
+            info.allow.join(", ")
+        };
+        println!(
+            "  Listen:   {} (remote clients from {})",
+            listen_addresses(&info.listen),
+            from
+        );
+    }
+    if preset != Preset::Vector {
+        println!("  Preset:   {}", preset.as_str());
+    }
+    if let Some(pool) = &info.pool {
+        println!(
+            "  Pool:     port {} ({} mode)",
+            pool.port,
+            pool.mode.as_str()
+        );
+    }
+    if let Some(health) = &info.health {
+        println!("  Health:   {}/healthz, /readyz", health.url());
+    }
+    if let Some(min_duration) = info.auto_explain {
+        println!(
+            "  Explain:  plans of statements over {}ms (pg0 logs --plans)",
+            min_duration
+        );
+    }
+    if let Some(classes) = &info.audit {
+        println!(
+            "  Audit:    pgaudit.log = {} (pg0 logs --grep AUDIT)",
+            classes
+        );
+    }
+    if let Some(timeout) = info.idle_timeout {
+        println!(
+            "  Idle:     stops after {} without connections",
+            format_duration_secs(timeout)
+        );
+    }
+    if let Some(ttl) = &info.ttl {
+        print_ttl(ttl);
+    }
+    if let (Some(limits), true) = (&info.limits, limits_applied) {
+        println!("  Limits:   {}", limits.describe());
+    }
+    println!();
+    println!(
+        "Connection URI: {}",
+        local_uri(
+            info.socket_dir.as_deref(),
+            &format!("{}:{}", username, shown_password),
+            port,
+            &database
+        )
+    );
+    println!();
+    if name == DEFAULT_INSTANCE_NAME {
+        println!("Use 'pg0 stop' to stop the server.");
+    } else {
+        println!("Use 'pg0 stop --name {}' to stop the server.", name);
+    }
+
+    Ok(())
+}
+
+/// How `pg0 stop` shuts an instance down. Other commands that stop instances
+/// take the CLI defaults.
+struct StopOptions {
+    mode: ShutdownMode,
+    timeout_secs: u64,
+    force: bool,
+}
+
+impl Default for StopOptions {
+    fn default() -> Self {
+        StopOptions {
+            mode: ShutdownMode::Fast,
+            timeout_secs: 60,
+            force: false,
+        }
+    }
+}
+
+fn stop(name: String, options: &StopOptions) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+
+    if !is_instance_running(&info) {
+        if info.in_memory.is_some() {
+            return discard_in_memory_instance(&name, &info);
+        }
+        write_instance_metadata(&name, &info)?;
+        println!("PostgreSQL instance '{}' is not running.", name);
+        return Ok(());
+    }
+
+    println!("Stopping PostgreSQL instance '{}' (pid: {})...", name, info.pid);
+    shutdown_postmaster(&name, &info, options)?;
+
+    if let Some(command) = info.hooks.get(&HookEvent::PostStop) {
+        if let Err(e) = run_hook(
+            &name,
+            HookEvent::PostStop,
+            command,
+            &info.data_dir,
+            env_vars(&info),
+        ) {
+            eprintln!("Warning: {}", e);
+        }
+    }
+
+    if info.in_memory.is_some() {
+        println!("PostgreSQL instance '{}' stopped.", name);
+        return discard_in_memory_instance(&name, &info);
+    }
+
+    write_instance_metadata(&name, &info)?;
+
+    println!("PostgreSQL instance '{}' stopped.", name);
+
+    Ok(())
+}
+
+/// Where an in-memory instance keeps its data directory.
+#[derive(Clone, Serialize, Deserialize)]
+struct InMemoryStorage {
+    /// Directory on tmpfs, or the mount point of the RAM disk.
+    path: PathBuf,
+    /// RAM disk device to detach on stop (macOS).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+}
+
+/// Parse a size such as "512MB", "2G" or "1073741824" into bytes.
+fn parse_size_bytes(value: &str) -> Result<u64, CliError> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => {
+            return Err(CliError::Other(format!(
+                "Invalid size '{}': use e.g. 512MB or 2GB",
+                value
+            )))
+        }
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| CliError::Other(format!("Invalid size '{}': use e.g. 512MB or 2GB", value)))?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Shut the postmaster down with `pg_ctl stop`, which waits for
+/// postmaster.pid to disappear. With `force`, a shutdown that doesn't finish
+/// in time is repeated in immediate mode.
+fn shutdown_postmaster(
+    name: &str,
+    info: &InstanceInfo,
+    options: &StopOptions,
+) -> Result<(), CliError> {
+    let pg_ctl_path = find_instance_binary(info, "pg_ctl")?;
+    ensure_runtime_libs_for_binary(&pg_ctl_path)?;
+    // The reason pg_ctl gave up, if it did.
+    let pg_ctl_stop = |mode: ShutdownMode| -> Result<Option<String>, CliError> {
+        let output = std::process::Command::new(&pg_ctl_path)
+            .arg("stop")
+            .arg("-D")
+            .arg(&info.data_dir)
+            .args(["-m", mode.as_str(), "-w", "-t"])
+            .arg(options.timeout_secs.to_string())
+            .output()?;
+        if output.status.success() {
+            return Ok(None);
+        }
+        // Only the first line: pg_ctl's hints name its own options.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().next().unwrap_or("pg_ctl stop failed");
+        Ok(Some(
+            reason.trim().trim_start_matches("pg_ctl: ").to_string(),
+        ))
+    };
+
+    let mut mode = options.mode;
+    let mut failure = pg_ctl_stop(mode)?;
+    if failure.is_some()
+        && options.force
+        && mode != ShutdownMode::Immediate
+        && is_instance_running(info)
+    {
+        eprintln!(
+            "Warning: '{}' did not stop in {} mode within {}; stopping in immediate mode",
+            name,
+            mode.as_str(),
+            format_duration_secs(options.timeout_secs)
+        );
+        mode = ShutdownMode::Immediate;
+        failure = pg_ctl_stop(mode)?;
+    }
+    if let Some(reason) = failure {
+        if is_instance_running(info) {
+            let hint = if mode == ShutdownMode::Immediate {
+                ""
+            } else {
+                "\nUse --force to stop it in immediate mode if it doesn't finish."
+            };
+            return Err(CliError::Other(format!(
+                "Instance '{}' did not stop in {} mode within {}: {}{}",
+                name,
+                mode.as_str(),
+                format_duration_secs(options.timeout_secs),
+                reason,
+                hint
+            )));
+        }
+    }
+
+    // The postmaster removes postmaster.pid just before it exits.
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while is_instance_running(info) {
+        if std::time::Instant::now() >= deadline {
+            return Err(CliError::Other(format!(
+                "Instance '{}' removed postmaster.pid but process {} is still running",
+                name, info.pid
+            )));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Parse a duration such as "90s", "30m", "2h" or "1d" into seconds. A bare
+/// number means seconds.
+fn parse_duration_secs(value: &str) -> Result<u64, CliError> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" | "min" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if multiplier > 0 && number > 0 => Ok(number * multiplier),
+        _ => Err(CliError::Other(format!(
+            "Invalid duration '{}': use e.g. 90s, 30m, 2h or 1d",
+            value
+        ))),
+    }
+}
+
+/// Format seconds compactly, e.g. "45s", "30m", "1h30m".
+fn format_duration_secs(seconds: u64) -> String {
+    let (days, hours, minutes, secs) = (
+        seconds / 86400,
+        seconds % 86400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    );
+    let mut out = String::new();
+    for (value, unit) in [(days, "d"), (hours, "h"), (minutes, "m"), (secs, "s")] {
+        if value > 0 {
+            out.push_str(&format!("{}{}", value, unit));
+        }
+    }
+    if out.is_empty() {
+        out.push_str("0s");
+    }
+    out
+}
+
+/// Create fresh RAM-backed storage for an in-memory instance: a directory on
+/// /dev/shm on Linux, a RAM disk of `size` on macOS.
+fn create_in_memory_storage(name: &str, size: &str) -> Result<InMemoryStorage, CliError> {
+    let size_bytes = parse_size_bytes(size)?;
+    let label = format!(
+        "pg0-{}-{}",
+        std::env::var("USER").unwrap_or_else(|_| "user".to_string()),
+        name
+    );
+
+    if cfg!(target_os = "macos") {
+        let mount_point = PathBuf::from("/Volumes").join(&label);
+        if mount_point.exists() {
+            // Left over from an instance that didn't stop cleanly.
+            let _ = std::process::Command::new("hdiutil")
+                .arg("detach")
+                .arg("-force")
+                .arg(&mount_point)
+                .output();
+        }
+        let sectors = size_bytes.div_ceil(512);
+        let output = std::process::Command::new("hdiutil")
+            .args(["attach", "-nomount", &format!("ram://{}", sectors)])
+            .output()?;
+        if !output.status.success() {
+            return Err(CliError::Other(format!(
+                "Failed to create a {} RAM disk: {}",
+                format_size(size_bytes),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let device = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let status = std::process::Command::new("diskutil")
+            .args(["erasevolume", "HFS+", &label, &device])
+            .stdout(std::process::Stdio::null())
+            .status()?;
+        if !status.success() {
+            let _ = std::process::Command::new("hdiutil")
+                .args(["detach", &device])
+                .output();
+            return Err(CliError::Other(format!(
+                "Failed to format RAM disk {}",
+                device
+            )));
+        }
+        println!(
+            "Created {} RAM disk at {}.",
+            format_size(size_bytes),
+            mount_point.display()
+        );
+        return Ok(InMemoryStorage {
+            path: mount_point,
+            device: Some(device),
+        });
+    }
+
+    let shm = Path::new("/dev/shm");
+    if !cfg!(unix) || !shm.is_dir() {
+        return Err(CliError::Other(
+            "--in-memory needs /dev/shm (Linux) or hdiutil (macOS); it is not supported on this platform".to_string(),
+        ));
+    }
+    // /dev/shm is sized by the OS; make sure the requested size fits.
+    if let Some(available) = std::process::Command::new("df")
+        .args(["-Pk"])
+        .arg(shm)
+        .output()
+        .ok()
+        .and_then(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .nth(1)
+                .and_then(|line| {
+                    line.split_whitespace()
+                        .nth(3)
+                        .and_then(|kb| kb.parse::<u64>().ok())
+                })
+        })
+    {
+        if available * 1024 < size_bytes {
+            return Err(CliError::Other(format!(
+                "/dev/shm has only {} free, less than the requested {}. Pass a smaller --in-memory=SIZE.",
+                format_size(available * 1024),
+                format_size(size_bytes)
+            )));
+        }
+    }
+    let path = shm.join(&label);
+    if path.exists() {
+        fs::remove_dir_all(&path)?;
+    }
+    fs::create_dir_all(&path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(InMemoryStorage { path, device: None })
+}
+
+/// Delete the data of an in-memory instance and detach its RAM disk.
+fn release_in_memory_storage(storage: &InMemoryStorage) -> Result<(), CliError> {
+    match &storage.device {
+        Some(device) => {
+            let output = std::process::Command::new("hdiutil")
+                .args(["detach", "-force", device])
+                .output()?;
+            if !output.status.success() {
+                return Err(CliError::Other(format!(
+                    "Failed to detach RAM disk {}: {}",
+                    device,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+        }
+        None => {
+            if storage.path.exists() {
+                fs::remove_dir_all(&storage.path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// An in-memory instance only exists while it runs: release its storage and
+/// forget it.
+fn discard_in_memory_instance(name: &str, info: &InstanceInfo) -> Result<(), CliError> {
+    if let Some(storage) = &info.in_memory {
+        release_in_memory_storage(storage)?;
+    }
+    remove_instance(name)?;
+    println!("Discarded the in-memory data of '{}'.", name);
+    Ok(())
+}
+
+fn drop_instance(name: String, force: bool) -> Result<(), CliError> {
+    let Some(info) = load_instance(&name)? else {
+        // A start that failed early may have reserved a port.
+        let _lock = lock_instance(&name)?;
+        release_port(&name)?;
+        println!("Instance '{}' does not exist.", name);
+        return Ok(());
+    };
+
+    // Confirmation prompt unless --force
+    if !force {
+        println!("This will permanently delete instance '{}' and all its data:", name);
+        println!("  Data dir: {}", info.data_dir.display());
+        println!();
+        print!("Are you sure? [y/N] ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        if !input.trim().eq_ignore_ascii_case("y") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    // Not held across the prompt; the instance may have changed meanwhile.
+    let _lock = lock_instance(&name)?;
+    let Some(info) = load_instance(&name)? else {
+        release_port(&name)?;
+        println!("Instance '{}' does not exist.", name);
+        return Ok(());
+    };
+
+    // Stop if running
+    if is_instance_running(&info) {
+        println!("Stopping PostgreSQL instance '{}' (pid: {})...", name, info.pid);
+        // The data is about to be deleted, so --force skips the checkpoint.
+        let mode = if force {
+            ShutdownMode::Immediate
+        } else {
+            ShutdownMode::Fast
+        };
+        shutdown_postmaster(
+            &name,
+            &info,
+            &StopOptions {
+                mode,
+                ..Default::default()
+            },
+        )?;
+    }
+
+    // Release the replication slot on the primary, otherwise it keeps
+    // retaining WAL for a replica that no longer exists.
+    if let (Some(primary_name), Some(slot)) = (&info.replica_of, &info.replication_slot) {
+        drop_replication_slot(primary_name, slot);
+    }
+
+    // Delete data directory
+    if let Some(storage) = &info.in_memory {
+        release_in_memory_storage(storage)?;
+    } else if info.data_dir.exists() {
+        println!("Deleting data directory: {}", info.data_dir.display());
+        fs::remove_dir_all(&info.data_dir)?;
+    }
+
+    // Delete the cold-storage copy of an archived instance
+    if let Some(url) = info
+        .archive
+        .as_ref()
+        .and_then(|a| a.to_str())
+        .filter(|a| is_archive_url(a))
+    {
+        println!(
+            "The archive stays at {}; delete it there if no longer needed.",
+            without_query(url)
+        );
+    } else if let Some(archive) = &info.archive {
+        if archive.exists() {
+            println!("Deleting archive: {}", archive.display());
+            fs::remove_file(archive)?;
+        }
+    }
+
+    if let Some(account) = &info.keychain_account {
+        keychain_delete(account);
+    }
+
+    // Delete instance directory (contains instance.json)
+    let instance_dir = get_instance_dir(&name)?;
+    if instance_dir.exists() {
+        fs::remove_dir_all(&instance_dir)?;
+    }
+    release_port(&name)?;
+
+    println!("Instance '{}' dropped.", name);
+
+    Ok(())
+}
+
+fn info(name: String, output_format: ListFormat, show_secrets: bool) -> Result<(), CliError> {
+    let instance = load_instance(&name)?;
+    if let (ListFormat::Table, Some(info)) = (&output_format, &instance) {
+        print_instance_table(&[list_entry(&name, info.clone())]);
+        return Ok(());
+    }
+    let stats = instance
+        .as_ref()
+        .filter(|info| is_instance_running(info))
+        .and_then(server_stats);
+    let mut output = build_info_output(&name, instance);
+    output.server = stats;
+    if !show_secrets {
+        output.redact_secrets();
+    }
+
+    match output_format {
+        ListFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+        ListFormat::Yaml => {
+            print!("{}", to_yaml(&serde_json::to_value(&output)?));
+        }
+        ListFormat::Text | ListFormat::Table => print_info(&name, &output),
+    }
+
+    Ok(())
+}
+
+/// `pg0 info --format`: print just the connection string, for scripts and
+/// framework config files.
+fn info_connection_string(name: String, format: ConnectionFormat) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    println!("{}", connection_string(&info, format));
+    Ok(())
+}
+
+/// `pg0 url`: print the connection URI on a single line.
+fn url(name: String, database: Option<String>, no_password: bool) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let credentials = if no_password {
+        info.username.clone()
+    } else {
+        format!("{}:{}", info.username, info.password)
+    };
+    println!(
+        "{}",
+        local_uri(
+            info.socket_dir.as_deref(),
+            &credentials,
+            info.port,
+            database.as_deref().unwrap_or(&info.database)
+        )
+    );
+    Ok(())
+}
+
+/// Environment variables of `pg0 env`: the URI plus libpq's own variables.
+fn env_vars(info: &InstanceInfo) -> Vec<(&'static str, String)> {
+    connection_vars(
+        info.socket_dir.as_deref(),
+        &info.username,
+        &info.password,
+        info.port,
+        &info.database,
+    )
+}
+
+/// `env_vars` of a server that may not be up yet.
+fn connection_vars(
+    socket_dir: Option<&Path>,
+    username: &str,
+    password: &str,
+    port: u16,
+    database: &str,
+) -> Vec<(&'static str, String)> {
+    let host = socket_dir.map_or_else(|| "127.0.0.1".to_string(), |dir| dir.display().to_string());
+    vec![
+        (
+            "DATABASE_URL",
+            local_uri(
+                socket_dir,
+                &format!("{}:{}", username, password),
+                port,
+                database,
+            ),
+        ),
+        ("PGHOST", host),
+        ("PGPORT", port.to_string()),
+        ("PGUSER", username.to_string()),
+        ("PGPASSWORD", password.to_string()),
+        ("PGDATABASE", database.to_string()),
+    ]
+}
+
+/// Single-quote a value for POSIX shells and .env files, unless it is made of
+/// characters that need no quoting.
+fn quote_env_value(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./_-".contains(c))
+    {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// `pg0 env`: print `export` lines to eval, or update a .env file.
+fn env(name: String, dotenv: Option<PathBuf>) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let vars = env_vars(&info);
+
+    let Some(path) = dotenv else {
+        for (key, value) in &vars {
+            println!("export {}={}", key, quote_env_value(value));
+        }
+        return Ok(());
+    };
+
+    let existing = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let mut written = std::collections::HashSet::new();
+    let mut lines: Vec<String> = Vec::new();
+    for line in existing.lines() {
+        let key = line
+            .trim_start()
+            .trim_start_matches("export ")
+            .split('=')
+            .next()
+            .unwrap_or("")
+            .trim();
+        match vars.iter().find(|(k, _)| *k == key) {
+            Some((key, value)) if written.insert(*key) => {
+                lines.push(format!("{}={}", key, quote_env_value(value)))
+            }
+            // A repeated key would override the one just updated.
+            Some(_) => {}
+            None => lines.push(line.to_string()),
+        }
+    }
+    for (key, value) in &vars {
+        if !written.contains(key) {
+            lines.push(format!("{}={}", key, quote_env_value(value)));
+        }
+    }
+    fs::write(&path, lines.join("\n") + "\n")?;
+    println!(
+        "Wrote connection settings of '{}' to {}",
+        name,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Live statistics of a running instance, shown by `pg0 info`.
+#[derive(Serialize, Deserialize)]
+struct ServerStats {
+    /// Version reported by the server (e.g. "18.1")
+    server_version: String,
+    uptime_secs: u64,
+    /// Client connections, not counting pg0's own
+    connections: u64,
+    active_connections: u64,
+    max_connections: u64,
+    databases: Vec<DatabaseStats>,
+    /// Extensions installed in the instance's database (besides plpgsql),
+    /// with versions
+    extensions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DatabaseStats {
+    name: String,
+    size_bytes: u64,
+}
+
+/// Query a running instance for its `ServerStats`; None if it doesn't
+/// accept connections.
+fn server_stats(info: &InstanceInfo) -> Option<ServerStats> {
+    // Distribution builds append their package version to server_version.
+    let json = run_sql(
+        info,
+        &info.database,
+        "SELECT json_build_object( \
+           'server_version', split_part(current_setting('server_version'), ' ', 1), \
+           'uptime_secs', extract(epoch FROM now() - pg_postmaster_start_time())::bigint, \
+           'connections', count(*), \
+           'active_connections', count(*) FILTER (WHERE state = 'active'), \
+           'max_connections', current_setting('max_connections')::int, \
+           'databases', (SELECT coalesce(json_agg(json_build_object('name', datname, 'size_bytes', pg_database_size(oid)) ORDER BY datname), '[]') \
+                         FROM pg_database WHERE datallowconn AND NOT datistemplate), \
+           'extensions', (SELECT coalesce(json_agg(extname || ' ' || extversion ORDER BY extname), '[]') \
+                          FROM pg_extension WHERE extname <> 'plpgsql')) \
+         FROM pg_stat_activity WHERE backend_type = 'client backend' AND pid <> pg_backend_pid();",
+    )
+    .ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// `pg0 info --watch`: redraw the info view every second until interrupted,
+/// keeping a log of status changes (e.g. while a restore or restart runs).
+fn info_watch(name: String, show_secrets: bool) -> Result<(), CliError> {
+    const MAX_CHANGES: usize = 10;
+    let clock = |secs: u64| {
+        format!(
+            "{:02}:{:02}:{:02}",
+            secs % 86400 / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    };
+
+    let mut last_status: Option<String> = None;
+    let mut changes: Vec<String> = Vec::new();
+    loop {
+        let instance = load_instance(&name)?;
+        let stats = instance
+            .as_ref()
+            .filter(|info| is_instance_running(info))
+            .map(server_stats);
+        let mut output = build_info_output(&name, instance);
+        let status = match (&stats, output.running) {
+            (Some(None), _) => "running, not accepting connections",
+            (_, true) => "running",
+            _ if output.archive.is_some() => "archived",
+            _ if output.data_dir.is_some() => "stopped",
+            // `pg0 start` only records the instance once the server is up.
+            _ if get_instance_dir(&name)?.exists() => "starting",
+            _ => "missing",
+        };
+        output.server = stats.flatten();
+        if !show_secrets {
+            output.redact_secrets();
+        }
+        let now = unix_now();
+        if last_status.as_deref() != Some(status) {
+            if let Some(previous) = &last_status {
+                changes.push(format!("{}  {} -> {}", clock(now), previous, status));
+                if changes.len() > MAX_CHANGES {
+                    changes.remove(0);
+                }
+            }
+            last_status = Some(status.to_string());
+        }
+
+        // Clear the screen and move the cursor home.
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "pg0 info --name {} (every 1s, {} UTC; Ctrl+C to quit)",
+            name,
+            clock(now)
+        );
+        println!();
+        print_info(&name, &output);
+        if !changes.is_empty() {
+            println!();
+            println!("Status changes:");
+            for change in &changes {
+                println!("  {}", change);
+            }
+        }
+        std::io::Write::flush(&mut std::io::stdout())?;
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+/// Text view of `pg0 info`.
+fn print_info(name: &str, output: &InfoOutput) {
+    if output.running {
+        println!("PostgreSQL instance '{}' is running", name);
+        println!("  PID:      {}", output.pid.unwrap());
+        println!("  Port:     {}", output.port.unwrap());
+        if let Some(socket) = &output.socket {
+            println!(
+                "  Socket:   {}{}",
+                socket,
+                if output.socket_only { " (no TCP)" } else { "" }
+            );
+        }
+        println!("  Version:  {}", output.version.as_ref().unwrap());
+        println!("  Username: {}", output.username.as_ref().unwrap());
+        println!("  Database: {}", output.database.as_ref().unwrap());
+        if output.in_memory {
+            println!(
+                "  Data dir: {} (in memory, discarded on stop)",
+                output.data_dir.as_ref().unwrap()
+            );
+        } else {
+            println!("  Data dir: {}", output.data_dir.as_ref().unwrap());
+        }
+        print_durability(output.durability.as_deref());
+        if let Some(preset) = output.preset.as_deref().filter(|p| *p != "vector") {
+            println!("  Preset:   {}", preset);
+        }
+        if !output.labels.is_empty() {
+            println!("  Labels:   {}", format_labels(&output.labels));
+        }
+        if let Some(primary) = &output.replica_of {
+            println!("  Replica of: {}", primary);
+        }
+        if let Some(ttl) = &output.ttl {
+            print_ttl(ttl);
+        }
+        if let Some(pool) = &output.pool {
+            println!(
+                "  Pool:     port {} ({} mode, size {}{})",
+                pool.port,
+                pool.mode.as_str(),
+                pool.size,
+                if output.pool_uri.is_some() {
+                    ""
+                } else {
+                    ", not running"
+                }
+            );
+        }
+        if let Some(server) = &output.server {
+            println!();
+            println!(
+                "  Server:   PostgreSQL {}, up {}",
+                server.server_version,
+                format_duration_secs(server.uptime_secs)
+            );
+            println!(
+                "  Connections: {} of {} ({} active)",
+                server.connections, server.max_connections, server.active_connections
+            );
+            println!("  Databases:");
+            for database in &server.databases {
+                println!(
+                    "    {:<24} {}",
+                    database.name,
+                    format_size(database.size_bytes)
+                );
+            }
+            if !server.extensions.is_empty() {
+                println!("  Extensions: {}", server.extensions.join(", "));
+            }
+        }
+        println!();
+        println!("URI: {}", output.uri.as_ref().unwrap());
+        if let Some(tls_uri) = &output.tls_uri {
+            println!("TLS URI: {}", tls_uri);
+        }
+        if let Some(pool_uri) = &output.pool_uri {
+            println!("Pooled URI: {}", pool_uri);
+        }
+        if let Some(metrics_url) = &output.metrics_url {
+            println!("Metrics: {}", metrics_url);
+        }
+        if let Some(health_url) = &output.health_url {
+            println!("Health: {}/healthz, {}/readyz", health_url, health_url);
+        }
+    } else if let Some(archive) = &output.archive {
+        println!("PostgreSQL instance '{}' is archived", name);
+        println!("  Port:     {}", output.port.unwrap());
+        println!("  Version:  {}", output.version.as_ref().unwrap());
+        println!("  Archive:  {}", archive);
+        println!();
+        println!("Use 'pg0 unarchive --name {}' to restore it.", name);
+    } else if let Some(data_dir) = &output.data_dir {
+        println!("PostgreSQL instance '{}' is stopped", name);
+        println!("  Port:     {}", output.port.unwrap());
+        println!("  Version:  {}", output.version.as_ref().unwrap());
+        println!("  Username: {}", output.username.as_ref().unwrap());
+        println!("  Database: {}", output.database.as_ref().unwrap());
+        println!("  Data dir: {}", data_dir);
+        print_durability(output.durability.as_deref());
+        if let Some(preset) = output.preset.as_deref().filter(|p| *p != "vector") {
+            println!("  Preset:   {}", preset);
+        }
+        if !output.labels.is_empty() {
+            println!("  Labels:   {}", format_labels(&output.labels));
+        }
+        if let Some(primary) = &output.replica_of {
+            println!("  Replica of: {}", primary);
+        }
+        println!();
+        println!("Use 'pg0 start --name {}' to start it.", name);
+    } else {
+        println!("PostgreSQL instance '{}' does not exist", name);
+    }
+}
+
+/// Print the durability line of `pg0 info`, making fast mode impossible to
+/// miss.
+fn print_durability(durability: Option<&str>) {
+    match durability {
+        Some("fast") => {
+            println!("  Durability: fast (NOT crash-safe - data may be lost or corrupted)")
+        }
+        Some(mode) => println!("  Durability: {}", mode),
+        None => {}
+    }
+}
+
+fn find_psql_binary(installation_dir: &Path) -> Result<PathBuf, CliError> {
+    find_pg_binary(installation_dir, "psql")
+}
+
+/// Locate a PostgreSQL tool for a specific instance, preferring the
+/// installation matching the instance's version when several are installed
+/// side by side (e.g. after `pg0 matrix run`).
+fn find_instance_binary(info: &InstanceInfo, binary: &str) -> Result<PathBuf, CliError> {
+    find_pg_binary(&info.installation_dir.join(&info.version), binary)
+        .or_else(|_| find_pg_binary(&info.installation_dir, binary))
+}
+
+/// Run a SQL statement against a running instance with the bundled psql and
+/// return its unaligned, tuples-only output (one row per line, columns
+/// separated by `|`).
+fn run_sql(info: &InstanceInfo, database: &str, sql: &str) -> Result<String, CliError> {
+    let psql_path = find_instance_binary(info, "psql")?;
+    ensure_runtime_libs_for_binary(&psql_path)?;
+
+    let uri = instance_uri_without_password(info, database);
+    let output = std::process::Command::new(&psql_path)
+        .arg(&uri)
+        .env("PGPASSWORD", &info.password)
+        .args(["-X", "-A", "-t", "-q", "-v", "ON_ERROR_STOP=1", "-c"])
+        .arg(sql)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(CliError::Other(format!(
+            "Query failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string())
+}
+
+/// Like `run_sql`, but for statements without a result set: returns the
+/// command tag of the last statement (e.g. "INSERT 0 1").
+fn run_sql_command(info: &InstanceInfo, database: &str, sql: &str) -> Result<String, CliError> {
+    let psql_path = find_instance_binary(info, "psql")?;
+    ensure_runtime_libs_for_binary(&psql_path)?;
+
+    let uri = instance_uri_without_password(info, database);
+    let output = std::process::Command::new(&psql_path)
+        .arg(&uri)
+        .env("PGPASSWORD", &info.password)
+        .args(["-X", "-A", "-t", "-v", "ON_ERROR_STOP=1", "-c"])
+        .arg(sql)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(CliError::Other(format!(
+            "Query failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .rev()
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or("")
+        .to_string())
+}
+
+/// Restart a running instance in place with `pg_ctl restart`. pg_ctl reuses
+/// the postmaster's original command line (postmaster.opts), so all settings
+/// pg0 passed at start survive; only the PID changes.
+fn restart_instance(name: &str) -> Result<InstanceInfo, CliError> {
+    let _lock = lock_instance(name)?;
+    let mut info = load_running_instance(name)?;
+    let pg_ctl_path = find_instance_binary(&info, "pg_ctl")?;
+    ensure_runtime_libs_for_binary(&pg_ctl_path)?;
+
+    println!("Restarting PostgreSQL instance '{}'...", name);
+    let status = std::process::Command::new(&pg_ctl_path)
+        .arg("restart")
+        .arg("-D")
+        .arg(&info.data_dir)
+        .arg("-l")
+        .arg(info.data_dir.join("start.log"))
+        .args(["-m", "fast", "-w", "-t", "600"])
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        let log_context = read_latest_pg_log(&info.data_dir);
+        return Err(CliError::Other(match log_context {
+            Some(log) => format!("Failed to restart '{}'\n\nPostgreSQL log:\n{}", name, log),
+            None => format!("Failed to restart '{}'", name),
+        }));
+    }
+
+    info.pid = read_postmaster_pid(&info.data_dir)?;
+    info.process = process_identity(info.pid);
+    save_instance(name, &info)?;
+    // The new postmaster is a child of pg_ctl, outside the old cgroup.
+    if let Some(limits) = &info.limits {
+        if let Err(e) = apply_resource_limits(name, info.pid, limits) {
+            eprintln!("Warning: The instance runs without resource limits: {}", e);
+        }
+    }
+    Ok(info)
+}
+
+/// Memory and CPU caps for an instance's process tree.
+#[derive(Clone, Serialize, Deserialize)]
+struct ResourceLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memory_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cpus: Option<f64>,
+}
+
+impl ResourceLimits {
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(bytes) = self.memory_bytes {
+            parts.push(format!("{} memory", format_size(bytes)));
+        }
+        if let Some(cpus) = self.cpus {
+            parts.push(format!("{} CPUs", cpus));
+        }
+        parts.join(", ")
+    }
+}
+
+/// The postmaster and the processes it has forked so far. Later children
+/// inherit the postmaster's cgroup.
+#[cfg(target_os = "linux")]
+fn postmaster_tree(pid: u32) -> Vec<u32> {
+    let mut pids = vec![pid];
+    for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let Some(child) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // /proc/<pid>/stat: "pid (comm) state ppid ..."; comm may contain spaces.
+        let parent = fs::read_to_string(entry.path().join("stat"))
+            .ok()
+            .and_then(|stat| {
+                stat.rsplit_once(')')
+                    .and_then(|(_, rest)| rest.split_whitespace().nth(1)?.parse::<u32>().ok())
+            });
+        if parent == Some(pid) {
+            pids.push(child);
+        }
+    }
+    pids
+}
+
+/// Put an instance's postmaster tree under cgroup v2 limits: in a transient
+/// systemd scope of the user's service manager when there is one, otherwise
+/// in a `pg0-<name>` cgroup next to pg0's own (which must be delegated to
+/// the user).
+#[cfg(target_os = "linux")]
+fn apply_resource_limits(name: &str, pid: u32, limits: &ResourceLimits) -> Result<(), CliError> {
+    if !Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+        return Err(CliError::Other(
+            "resource limits need cgroup v2 (the unified hierarchy mounted at /sys/fs/cgroup)"
+                .to_string(),
+        ));
+    }
+    let pids = postmaster_tree(pid);
+
+    let mut properties: Vec<String> = vec!["PIDs".into(), "au".into(), pids.len().to_string()];
+    properties.extend(pids.iter().map(|p| p.to_string()));
+    let mut count = 1;
+    if let Some(bytes) = limits.memory_bytes {
+        properties.extend(["MemoryMax".into(), "t".into(), bytes.to_string()]);
+        count += 1;
+    }
+    if let Some(cpus) = limits.cpus {
+        let usec_per_sec = (cpus * 1_000_000.0) as u64;
+        properties.extend([
+            "CPUQuotaPerSecUSec".into(),
+            "t".into(),
+            usec_per_sec.to_string(),
+        ]);
+        count += 1;
+    }
+    let unit = format!("pg0-{}-{}.scope", name, pid);
+    let systemd = std::process::Command::new("busctl")
+        .args([
+            "--user",
+            "call",
+            "org.freedesktop.systemd1",
+            "/org/freedesktop/systemd1",
+            "org.freedesktop.systemd1.Manager",
+            "StartTransientUnit",
+            "ssa(sv)a(sa(sv))",
+            &unit,
+            "fail",
+            &count.to_string(),
+        ])
+        .args(&properties)
+        .arg("0")
+        .output();
+    if systemd.is_ok_and(|o| o.status.success()) {
+        return Ok(());
+    }
+
+    let own = fs::read_to_string("/proc/self/cgroup")?
+        .lines()
+        .find_map(|line| line.strip_prefix("0::").map(|p| p.to_string()))
+        .ok_or_else(|| CliError::Other("pg0 is not in a cgroup v2 hierarchy".to_string()))?;
+    let parent = Path::new("/sys/fs/cgroup").join(own.trim_start_matches('/'));
+    let parent = parent.parent().unwrap_or(&parent);
+    let cgroup = parent.join(format!("pg0-{}", name));
+    let write = |file: &str, value: String| {
+        fs::write(cgroup.join(file), value).map_err(|e| {
+            CliError::Other(format!(
+                "cannot write {}: {} (no systemd user session and the cgroup isn't delegated to this user)",
+                cgroup.join(file).display(),
+                e
+            ))
+        })
+    };
+    fs::create_dir_all(&cgroup).map_err(|e| {
+        CliError::Other(format!(
+            "cannot create {}: {} (no systemd user session and the cgroup isn't delegated to this user)",
+            cgroup.display(),
+            e
+        ))
+    })?;
+    write(
+        "memory.max",
+        limits
+            .memory_bytes
+            .map_or("max".to_string(), |bytes| bytes.to_string()),
+    )?;
+    write(
+        "cpu.max",
+        limits.cpus.map_or("max 100000".to_string(), |cpus| {
+            format!("{} 100000", (cpus * 100_000.0) as u64)
+        }),
+    )?;
+    for pid in pids {
+        write("cgroup.procs", pid.to_string())?;
+    }
+    Ok(())
+}
+
+/// The postmaster and the processes it has started so far. Later children
+/// are put in the postmaster's job object on their own.
+#[cfg(windows)]
+fn postmaster_tree(pid: u32) -> Vec<u32> {
+    use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+    let mut pids = vec![pid];
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return pids;
+    }
+    let mut entry: PROCESSENTRY32W = unsafe { std::mem::zeroed() };
+    entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+    let mut more = unsafe { Process32FirstW(snapshot, &mut entry) } != 0;
+    while more {
+        if entry.th32ParentProcessID == pid {
+            pids.push(entry.th32ProcessID);
+        }
+        more = unsafe { Process32NextW(snapshot, &mut entry) } != 0;
+    }
+    unsafe { CloseHandle(snapshot) };
+    pids
+}
+
+/// Put an instance's postmaster tree in a job object that caps its
+/// committed memory and hard-caps its CPU rate.
+#[cfg(windows)]
+fn apply_resource_limits(name: &str, pid: u32, limits: &ResourceLimits) -> Result<(), CliError> {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::JobObjects::CreateJobObjectW;
+    let job = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+    if job.is_null() {
+        return Err(CliError::Other(format!(
+            "CreateJobObject failed: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    let result = limit_job(job, name, pid, limits);
+    // The job lives on as long as processes are in it.
+    unsafe { CloseHandle(job) };
+    result
+}
+
+#[cfg(windows)]
+fn limit_job(
+    job: windows_sys::Win32::Foundation::HANDLE,
+    name: &str,
+    pid: u32,
+    limits: &ResourceLimits,
+) -> Result<(), CliError> {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, JobObjectCpuRateControlInformation,
+        JobObjectExtendedLimitInformation, SetInformationJobObject, JOBOBJECTINFOCLASS,
+        JOBOBJECT_CPU_RATE_CONTROL_INFORMATION, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_CPU_RATE_CONTROL_ENABLE, JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+        JOB_OBJECT_LIMIT_JOB_MEMORY,
+    };
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, PROCESS_SET_QUOTA, PROCESS_TERMINATE,
+    };
+    let set = |class: JOBOBJECTINFOCLASS, info: *const std::ffi::c_void, len: usize, what: &str| {
+        if unsafe { SetInformationJobObject(job, class, info, len as u32) } == 0 {
+            return Err(CliError::Other(format!(
+                "Setting the {} limit of '{}' failed: {}",
+                what,
+                name,
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    };
+
+    if let Some(bytes) = limits.memory_bytes {
+        let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_JOB_MEMORY;
+        info.JobMemoryLimit = usize::try_from(bytes).unwrap_or(usize::MAX);
+        set(
+            JobObjectExtendedLimitInformation,
+            &info as *const _ as *const std::ffi::c_void,
+            std::mem::size_of_val(&info),
+            "memory",
+        )?;
+    }
+    if let Some(cpus) = limits.cpus {
+        // The rate is in 1/100 of a percent of all cores.
+        let cores = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1) as f64;
+        let mut info: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = unsafe { std::mem::zeroed() };
+        info.ControlFlags =
+            JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+        info.Anonymous.CpuRate = (cpus / cores * 10_000.0).round().clamp(1.0, 10_000.0) as u32;
+        set(
+            JobObjectCpuRateControlInformation,
+            &info as *const _ as *const std::ffi::c_void,
+            std::mem::size_of_val(&info),
+            "CPU",
+        )?;
+    }
+
+    for process in postmaster_tree(pid) {
+        let handle = unsafe { OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, 0, process) };
+        let assigned = !handle.is_null() && unsafe { AssignProcessToJobObject(job, handle) } != 0;
+        let err = std::io::Error::last_os_error();
+        if !handle.is_null() {
+            unsafe { CloseHandle(handle) };
+        }
+        // Children may have exited meanwhile; the postmaster must be in.
+        if !assigned && process == pid {
+            return Err(CliError::Other(format!(
+                "Adding the postmaster of '{}' to a job object failed: {}",
+                name, err
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn apply_resource_limits(_name: &str, _pid: u32, _limits: &ResourceLimits) -> Result<(), CliError> {
+    Err(CliError::Other(
+        "--memory-limit and --cpu-limit are only supported on Linux (cgroup v2) and Windows"
+            .to_string(),
+    ))
+}
+
+/// Quote a string as a SQL literal.
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Quote a string as a SQL identifier.
+fn quote_ident(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// Feed a SQL script (which may contain psql meta-commands, e.g. pg_dump
+/// output) to psql on stdin. Stops at the first error.
+fn run_sql_script(info: &InstanceInfo, database: &str, script: &[u8]) -> Result<(), CliError> {
+    use std::io::Write;
+
+    let psql_path = find_instance_binary(info, "psql")?;
+    ensure_runtime_libs_for_binary(&psql_path)?;
+
+    let uri = instance_uri_without_password(info, database);
+    let mut child = std::process::Command::new(&psql_path)
+        .arg(&uri)
+        .env("PGPASSWORD", &info.password)
+        .args(["-X", "-q", "-v", "ON_ERROR_STOP=1", "-f", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(script)?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(CliError::Other(format!(
+            "Script failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Locate one of the bundled PostgreSQL client/server tools (psql,
+/// pg_basebackup, ...) inside the installation directory.
+fn find_pg_binary(installation_dir: &Path, binary: &str) -> Result<PathBuf, CliError> {
+    let binary_name = if cfg!(windows) {
+        format!("{}.exe", binary)
+    } else {
+        binary.to_string()
+    };
+
+    // Look for the binary in installation_dir/*/bin/ (version subdirectory)
+    if let Ok(entries) = fs::read_dir(installation_dir) {
+        for entry in entries.flatten() {
+            let binary_path = entry.path().join("bin").join(&binary_name);
+            if binary_path.exists() {
+                return Ok(binary_path);
+            }
+        }
+    }
+
+    // Fallback: try direct path (in case structure changes)
+    let direct_path = installation_dir.join("bin").join(&binary_name);
+    if direct_path.exists() {
+        return Ok(direct_path);
+    }
+
+    Err(CliError::Io(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!(
+            "{} not found in {}",
+            binary_name,
+            installation_dir.display()
+        ),
+    )))
+}
+
+fn psql(name: String, args: Vec<String>) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+
+    let psql_path = find_instance_binary(&info, "psql")?;
+    // psql is dynamic-linked against the same libxml2/libicu as postgres, so
+    // make sure subprocess can find the bundled libs even when this command is
+    // invoked against an instance that another `pg0 start` already extracted.
+    ensure_runtime_libs_for_binary(&psql_path)?;
+
+    // Build connection URI
+    let uri = instance_uri_without_password(&info, &info.database);
+
+    // Execute psql with the connection URI and any additional args
+    let status = std::process::Command::new(&psql_path)
+        .arg(&uri)
+        .env("PGPASSWORD", &info.password)
+        .args(&args)
+        .status()?;
+
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+
+    Ok(())
+}
+
+fn logs(
+    name: String,
+    lines: Option<usize>,
+    follow: bool,
+    grep: Option<String>,
+) -> Result<(), CliError> {
+    let instance_dir = get_instance_dir(&name)?;
+    let log_dir = instance_dir.join("data").join("log");
+
+    if !log_dir.exists() {
+        return Err(CliError::Other(format!(
+            "Log directory not found for instance '{}'. Has PostgreSQL been started?",
+            name
+        )));
+    }
+
+    // Find the most recent log file
+    let mut log_files: Vec<_> = fs::read_dir(&log_dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .collect();
+
+    if log_files.is_empty() {
+        return Err(CliError::Other(format!(
+            "No log files found for instance '{}'",
+            name
+        )));
+    }
+
+    // Sort by modification time, most recent first
+    log_files.sort_by_key(|e| std::cmp::Reverse(
+        e.metadata().and_then(|m| m.modified()).ok()
+    ));
+
+    let log_file = &log_files[0].path();
+    let matches = |line: &str| grep.as_deref().is_none_or(|pattern| line.contains(pattern));
+
+    if follow {
+        // Follow mode - use tail -f equivalent
+        println!("Following logs for instance '{}' (Ctrl+C to exit):", name);
+        println!("Log file: {}", log_file.display());
+        println!();
+
+        let mut file = fs::File::open(log_file)?;
+        let mut pos = file.metadata()?.len();
+
+        // Print existing content first
+        use std::io::{BufRead, BufReader, Seek, SeekFrom};
+        file.seek(SeekFrom::Start(0))?;
+        let reader = BufReader::new(&file);
+        for line in reader.lines() {
+            let line = line?;
+            if matches(&line) {
+                println!("{}", line);
+            }
+        }
+
+        // Now follow new content
+        loop {
+            file.seek(SeekFrom::Start(pos))?;
+            let reader = BufReader::new(&file);
+            for line in reader.lines() {
+                let line = line?;
+                if matches(&line) {
+                    println!("{}", line);
+                }
+            }
+            pos = file.metadata()?.len();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    } else {
+        // Show logs (optionally limited to N lines)
+        use std::io::{BufRead, BufReader};
+        let file = fs::File::open(log_file)?;
+        let reader = BufReader::new(file);
+        let all_lines: Vec<String> = reader
+            .lines()
+            .filter(|line| line.as_ref().map_or(true, |line| matches(line)))
+            .collect::<Result<_, _>>()?;
+
+        let lines_to_show = if let Some(n) = lines {
+            &all_lines[all_lines.len().saturating_sub(n)..]
+        } else {
+            &all_lines[..]
+        };
+
+        println!("Logs for instance '{}' ({})", name, log_file.display());
+        println!();
+        for line in lines_to_show {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the execution plans auto_explain wrote to the latest server log,
+/// optionally only the last `count`.
+fn logs_plans(name: String, count: Option<usize>) -> Result<(), CliError> {
+    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    let log_file = latest_log_file(&info.data_dir.join("log"))
+        .ok_or_else(|| CliError::Other(format!("No log files found for instance '{}'", name)))?;
+    let content = fs::read(&log_file)?;
+    let content = String::from_utf8_lossy(&content);
+
+    // A plan is a "duration: ... plan:" entry followed by tab-indented lines.
+    let mut plans: Vec<Vec<String>> = Vec::new();
+    let mut in_plan = false;
+    for line in content.lines() {
+        if let Some(continuation) = line.strip_prefix('\t') {
+            if in_plan {
+                if let Some(plan) = plans.last_mut() {
+                    plan.push(format!("  {}", continuation));
+                }
+            }
+            continue;
+        }
+        in_plan = false;
+        if let Some(entry) = parse_server_log_line(line) {
+            if let Some((duration, _)) = entry
+                .message
+                .strip_prefix("duration: ")
+                .and_then(|rest| rest.split_once("  plan:"))
+            {
+                plans.push(vec![format!(
+                    "{}  [{}]  duration: {}",
+                    entry.time, entry.pid, duration
+                )]);
+                in_plan = true;
+            }
+        }
+    }
+
+    if plans.is_empty() {
+        println!("No plans logged for '{}' yet.", name);
+        if info.auto_explain.is_none() {
+            println!("Start the instance with --auto-explain to log plans of slow statements.");
+        }
+        return Ok(());
+    }
+    let shown = &plans[plans.len().saturating_sub(count.unwrap_or(plans.len()))..];
+    for plan in shown {
+        for line in plan {
+            println!("{}", line);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn find_installed_version(installation_dir: &PathBuf) -> Result<String, CliError> {
+    if let Ok(entries) = fs::read_dir(installation_dir) {
+        for entry in entries.flatten() {
+            if entry.path().is_dir() {
+                if let Some(name) = entry.file_name().to_str() {
+                    // Check if it looks like a version directory
+                    if name.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+                        return Ok(name.to_string());
+                    }
+                }
+            }
+        }
+    }
+    Err(CliError::Io(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "No PostgreSQL version found in installation directory",
+    )))
+}
+
+/// How `install-extension` fetches and places extension files.
+#[derive(Clone, Copy)]
+struct ExtensionInstallOptions {
+    /// Into the instance's private extension directory
+    isolated: bool,
+    /// Reinstall the latest version over an installed one
+    upgrade: bool,
+    /// Accept downloads without a checksum to verify
+    insecure: bool,
+    /// Compile from the source release instead of using precompiled builds
+    build_from_source: bool,
+}
+
+fn install_extension(
+    instance_name: String,
+    extension_name: Option<String>,
+    from: Option<PathBuf>,
+    options: ExtensionInstallOptions,
+    create: bool,
+    databases: Vec<String>,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&instance_name)?;
+    let ExtensionInstallOptions {
+        isolated, upgrade, ..
+    } = options;
+    if isolated {
+        enable_extension_isolation(&info)?;
+    }
+    let root = extension_root(&info, isolated);
+    // Extension files from a local source skip the lookups below
+    let local = match &from {
+        Some(path) => {
+            let mut span = operation_span("install_extension");
+            span.attr("source", path.display());
+            if extension_name
+                .as_deref()
+                .is_some_and(|name| name.contains('@'))
+            {
+                return Err(CliError::Other(
+                    "--from installs the version it contains; drop @VERSION".to_string(),
+                ));
+            }
+            match install_local_extension(&info, path, extension_name.as_deref(), &root) {
+                Ok(local) => Some(local),
+                Err(e) => {
+                    span.fail(&e);
+                    return Err(e);
+                }
+            }
+        }
+        None => None,
+    };
+    let extension_name = match &local {
+        Some((extension, _)) => extension.clone(),
+        None => extension_name.unwrap_or_default(),
+    };
+    let (extension_name, version) = match extension_name.split_once('@') {
+        Some((name, version)) if !name.is_empty() && !version.is_empty() => {
+            (name.to_string(), Some(version.to_string()))
+        }
+        Some(_) => {
+            return Err(CliError::Other(format!(
+                "Invalid extension '{}', expected NAME or NAME@VERSION",
+                extension_name
+            )))
+        }
+        None => (extension_name, None),
+    };
+    if version.is_some() && upgrade {
+        return Err(CliError::Other(
+            "--upgrade installs the latest version; drop it to pin a version".to_string(),
+        ));
+    }
+
+    // Extensions that ship with PostgreSQL (plpython3u, ...) or are
+    // installed already need no download, unless another version is asked for
+    let installed = match &local {
+        Some((_, installed)) => *installed,
+        None => {
+            root.join("share")
+                .join("extension")
+                .join(format!("{}.control", extension_name.to_lowercase()))
+                .exists()
+                || has_library(&root.join("lib"), &extension_name.to_lowercase())
+        }
+    };
+    let (ext_name, already_installed) = match &local {
+        Some((extension, _)) => (extension.to_lowercase(), false),
+        None => install_extension_files(
+            &info,
+            &extension_name,
+            version.as_deref(),
+            options,
+            installed,
+        )?,
+    };
+    // Extensions listed in `requires` must be installed (and created) first
+    let dependencies =
+        extension_dependencies(&info, &ext_name, options, &mut vec![ext_name.clone()])?;
+    if isolated {
+        relocate_module_paths(&root)?;
+    }
+    // A library that links against something the host lacks only fails once
+    // CREATE EXTENSION loads it; report that up front instead
+    #[cfg(target_os = "linux")]
+    for extension in dependencies.iter().chain([&ext_name]) {
+        check_extension_libraries(&info, extension)?;
+    }
+    // A new version of a library that is loaded already only takes effect
+    // after a restart
+    let updating = (version.is_some() || upgrade || local.is_some()) && !already_installed;
+    // Extensions that only work from shared_preload_libraries take a restart,
+    // as do the settings their background workers read at server start
+    let mut info = info;
+    for extension in dependencies.iter().chain([&ext_name]) {
+        if let Some((_, library)) = PRELOAD_EXTENSIONS
+            .iter()
+            .find(|(preload, _)| preload == extension)
+        {
+            let settings = worker_settings(extension, &info);
+            let reload = *extension == ext_name && updating && installed;
+            info = ensure_preloaded(&instance_name, info, library, &settings, reload)?;
+        }
+    }
+
+    if !already_installed {
+        println!("Extension '{}' installed successfully!", ext_name);
+    }
+    if OUTPUT_PLUGINS.contains(&ext_name.as_str()) {
+        println!(
+            "'{}' is a logical decoding output plugin; use it with 'pg0 cdc --plugin {}' or pg_recvlogical (needs wal_level = logical).",
+            ext_name, ext_name
+        );
+        return Ok(());
+    }
+    if version.is_some() || upgrade || (local.is_some() && installed) {
+        update_extension(&info, &ext_name, version.as_deref())?;
+    }
+    if create {
+        let databases = if databases.is_empty() {
+            vec![info.database.clone()]
+        } else {
+            databases
+        };
+        for database in &databases {
+            for dependency in &dependencies {
+                create_extension(&instance_name, &info, database, dependency)?;
+            }
+            if !create_extension(&instance_name, &info, database, &ext_name)? {
+                println!("Extension '{}' already exists in '{}'.", ext_name, database);
+            }
+        }
+        if ext_name == "pg_cron" && databases.contains(&info.database) {
+            run_sql(
+                &info,
+                &info.database,
+                "SELECT cron.schedule('pg0-check', '* * * * *', 'SELECT 1'); SELECT cron.unschedule('pg0-check');",
+            )
+            .map_err(|e| CliError::Other(format!("pg_cron is installed but could not schedule a job: {}", e)))?;
+            println!(
+                "pg_cron is ready; jobs are scheduled from database '{}'.",
+                info.database
+            );
+        }
+    } else {
+        println!();
+        println!("To enable it in your database, run:");
+        if dependencies.is_empty() {
+            println!(
+                "  pg0 psql -c \"CREATE EXTENSION IF NOT EXISTS {};\"",
+                ext_name
+            );
+        } else {
+            println!(
+                "  pg0 psql -c \"CREATE EXTENSION IF NOT EXISTS {} CASCADE;\"",
+                ext_name
+            );
+            println!(
+                "(CASCADE also creates {}, which it requires.)",
+                dependencies.join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Put the files of `extension_name` (at `version`, if given) into the
+/// instance's installation: from a bundle, an extension registry, a source
+/// build or the built-in sources, in that order. `installed` says whether the
+/// extension has files there already. Returns the extension name and whether
+/// it was left as it was.
+fn install_extension_files(
+    info: &InstanceInfo,
+    extension_name: &str,
+    version: Option<&str>,
+    options: ExtensionInstallOptions,
+    installed: bool,
+) -> Result<(String, bool), CliError> {
+    let ExtensionInstallOptions {
+        isolated,
+        upgrade,
+        insecure,
+        build_from_source,
+    } = options;
+    let root = extension_root(info, isolated);
+    // Extensions with precompiled builds are only built from source on request
+    let source = SOURCE_EXTENSIONS
+        .iter()
+        .find(|e| e.name.eq_ignore_ascii_case(extension_name));
+    let build_source = source.filter(|source| build_from_source || !source.precompiled);
+    // Extensions bundled into this binary install offline, but only match
+    // the bundled PostgreSQL version (and the bundled extension version)
+    let bundle = if !build_from_source && info.version == env!("PG_VERSION") {
+        find_bundled_extension(extension_name).filter(|bundle| match version {
+            Some(version) => version == bundle.version,
+            None => true,
+        })
+    } else {
+        None
+    };
+    let mut already_installed = bundle.is_none()
+        && installed
+        && match version {
+            Some(version) => {
+                control_default_version(&extension_control_file(info, extension_name)).as_deref()
+                    == Some(version)
+            }
+            None => !upgrade,
+        };
+    let ext_name = match bundle {
+        Some(bundle) => {
+            let mut span = operation_span("install_extension");
+            span.attr("extension", bundle.name);
+            let result = if isolated {
+                unpack_extension_archive(Archive::new(GzDecoder::new(bundle.archive)), &root)
+            } else {
+                install_bundled_extension(&info.installation_dir, &info.version, bundle)
+            };
+            if let Err(e) = result {
+                span.fail(&e);
+                return Err(e);
+            }
+            extension_name.to_lowercase()
+        }
+        None if already_installed => {
+            println!("Extension '{}' is already installed.", extension_name);
+            extension_name.to_lowercase()
+        }
+        None => {
+            let mirrored = if build_from_source {
+                None
+            } else {
+                install_mirrored_extension(info, extension_name, version, insecure, &root)?
+            };
+            match (mirrored, build_source) {
+                (Some(ext_name), _) => ext_name,
+                (None, Some(source)) => {
+                    let mut span = operation_span("install_extension");
+                    span.attr("extension", source.name);
+                    if let Err(e) = build_source_extension(info, source, version, isolated.then_some(root.as_path())) {
+                        span.fail(&e);
+                        return Err(e);
+                    }
+                    source.name.to_string()
+                }
+                (None, None) if build_from_source => {
+                    return Err(CliError::Other(format!(
+                        "pg0 has no source build for '{}'; it can build: {}",
+                        extension_name,
+                        SOURCE_EXTENSIONS.iter().map(|e| e.name).collect::<Vec<_>>().join(", ")
+                    )))
+                }
+                (None, None) if isolated => {
+                    return Err(CliError::Other(format!(
+                        "'{}' can only be installed into the shared installation; drop --isolated, or install it with --from or from an extension registry",
+                        extension_name
+                    )))
+                }
+                (None, None) => match install_registry_extension(info, extension_name, version, insecure) {
+                    // Extensions that ship with PostgreSQL upgrade with it
+                    Err(CliError::ExtensionNotFound(_)) if upgrade && installed => {
+                        already_installed = true;
+                        extension_name.to_lowercase()
+                    }
+                    Err(e) if source.is_some() => {
+                        return Err(CliError::Other(format!(
+                            "No precompiled '{}' could be installed for PostgreSQL {}: {}\nBuild it from source instead with --build-from-source (needs make and a C compiler; check with 'pg0 doctor').",
+                            extension_name, info.version, e
+                        )))
+                    }
+                    result => result?,
+                },
+            }
+        }
+    };
+    Ok((ext_name, already_installed))
+}
+
+/// The extensions `extension` requires through its control file's
+/// `requires`, transitively and in the order they have to be created.
+/// Dependencies without files in the installation are installed. `visiting`
+/// holds the extensions whose dependencies are being resolved, to stop at
+/// cycles.
+fn extension_dependencies(
+    info: &InstanceInfo,
+    extension: &str,
+    options: ExtensionInstallOptions,
+    visiting: &mut Vec<String>,
+) -> Result<Vec<String>, CliError> {
+    let options = ExtensionInstallOptions {
+        upgrade: false,
+        ..options
+    };
+    let requires = fs::read_to_string(extension_control_file(info, extension))
+        .ok()
+        .and_then(|control| control_setting(&control, "requires"))
+        .unwrap_or_default();
+    let mut order: Vec<String> = Vec::new();
+    for dependency in requires
+        .split(',')
+        .map(|d| d.trim().to_lowercase())
+        .filter(|d| !d.is_empty())
+    {
+        if order.contains(&dependency) || visiting.contains(&dependency) {
+            continue;
+        }
+        if !extension_control_file(info, &dependency).exists() {
+            println!(
+                "Extension '{}' requires '{}', installing it...",
+                extension, dependency
+            );
+            install_extension_files(info, &dependency, None, options, false)?;
+        }
+        visiting.push(dependency.clone());
+        for nested in extension_dependencies(info, &dependency, options, visiting)? {
+            if !order.contains(&nested) {
+                order.push(nested);
+            }
+        }
+        visiting.pop();
+        order.push(dependency);
+    }
+    Ok(order)
+}
+
+fn uninstall_extension(
+    instance_name: String,
+    extension: String,
+    cascade: bool,
+    force: bool,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&instance_name)?;
+    let extension = extension.to_lowercase();
+
+    let databases: Vec<String> = run_sql(
+        &info,
+        "postgres",
+        "SELECT datname FROM pg_database WHERE datallowconn AND NOT datistemplate ORDER BY datname;",
+    )?
+    .lines()
+    .map(|l| l.to_string())
+    .collect();
+    let mut created_in = Vec::new();
+    for database in &databases {
+        let found = run_sql(
+            &info,
+            database,
+            &format!(
+                "SELECT 1 FROM pg_extension WHERE extname = {};",
+                quote_literal(&extension)
+            ),
+        )?;
+        if !found.is_empty() {
+            created_in.push(database.clone());
+        }
+    }
+
+    // The files to remove: the control file, its SQL scripts and the library
+    // it loads (module_pathname, or <extension>.so)
+    let module = extension_module(&info, &extension);
+    // A private copy (install-extension --isolated) is removed rather than
+    // the installation's, which other instances use
+    let private = private_extension_dir(&info);
+    let isolated = private
+        .join("share")
+        .join("extension")
+        .join(format!("{}.control", extension))
+        .exists()
+        || has_library(&private.join("lib"), &module);
+    let root = extension_root(&info, isolated);
+    let lib_dir = root.join("lib");
+    let extension_dir = root.join("share").join("extension");
+    let bundled = !isolated
+        && info.version == env!("PG_VERSION")
+        && BUNDLED_EXTENSIONS.iter().any(|e| e.name == extension);
+    let mut files: Vec<PathBuf> = Vec::new();
+    if !bundled {
+        for ext in ["so", "dylib", "dll"] {
+            files.push(lib_dir.join(format!("{}.{}", module, ext)));
+        }
+        files.push(extension_dir.join(format!("{}.control", extension)));
+        if let Ok(entries) = fs::read_dir(&extension_dir) {
+            let prefix = format!("{}--", extension);
+            files.extend(
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path())
+                    .filter(|p| {
+                        p.file_name()
+                            .and_then(|n| n.to_str())
+                            .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".sql"))
+                    }),
+            );
+        }
+        files.retain(|f| f.exists());
+        files.sort();
+    }
+    let (preloaded, _) = configured_preload_libraries(&info.data_dir)?;
+    let library = PRELOAD_EXTENSIONS
+        .iter()
+        .find(|(name, _)| *name == extension)
+        .map(|(_, library)| library.to_string())
+        .unwrap_or_else(|| module.clone());
+    let unpreload = preloaded.contains(&library);
+
+    if created_in.is_empty() && files.is_empty() && !unpreload {
+        return Err(CliError::ExtensionNotFound(extension));
+    }
+
+    if !force {
+        println!(
+            "This will uninstall '{}' from '{}':",
+            extension, instance_name
+        );
+        if !created_in.is_empty() {
+            println!(
+                "  DROP EXTENSION{} in: {}",
+                if cascade {
+                    " ... CASCADE (and every object that depends on it)"
+                } else {
+                    ""
+                },
+                created_in.join(", ")
+            );
+        }
+        if unpreload {
+            println!("  Stop preloading {}", library);
+        }
+        for file in &files {
+            println!("  Delete {}", file.display());
+        }
+        println!();
+        print!("Are you sure? [y/N] ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        if !input.trim().eq_ignore_ascii_case("y") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    for database in &created_in {
+        run_sql(
+            &info,
+            database,
+            &format!(
+                "DROP EXTENSION {}{};",
+                quote_ident(&extension),
+                if cascade { " CASCADE" } else { "" }
+            ),
+        )
+        .map_err(|e| {
+            CliError::Other(format!(
+                "Could not drop extension '{}' in '{}': {}{}",
+                extension,
+                database,
+                e,
+                if cascade {
+                    ""
+                } else {
+                    "\nUse --cascade to drop the objects that depend on it too."
+                }
+            ))
+        })?;
+        println!("Dropped extension '{}' in '{}'.", extension, database);
+    }
+
+    if unpreload {
+        let remaining: Vec<String> = preloaded.into_iter().filter(|l| *l != library).collect();
+        if remaining.is_empty() {
+            remove_managed_config_value(&info.data_dir, "shared_preload_libraries")?;
+        } else {
+            set_managed_config_value(
+                &info.data_dir,
+                "shared_preload_libraries",
+                &remaining.join(","),
+            )?;
+        }
+        for (key, _) in worker_settings(&extension, &info) {
+            remove_managed_config_value(&info.data_dir, key)?;
+        }
+        println!("Removed {} from shared_preload_libraries.", library);
+    }
+
+    for file in &files {
+        fs::remove_file(file)?;
+    }
+    if bundled {
+        println!(
+            "'{}' is bundled with pg0 and stays installed; it is only dropped from the databases.",
+            extension
+        );
+    } else if isolated {
+        println!("Removed {} file(s) from {}.", files.len(), root.display());
+    } else if !files.is_empty() {
+        println!(
+            "Removed {} file(s) from {}. Other instances on PostgreSQL {} lose the extension too.",
+            files.len(),
+            root.display(),
+            info.version
+        );
+    }
+
+    if unpreload {
+        restart_or_defer(&instance_name, force)?;
+    }
+    Ok(())
+}
+
+/// The library `extension` loads: its control file's module_pathname, or
+/// the extension's own name.
+fn extension_module(info: &InstanceInfo, extension: &str) -> String {
+    fs::read_to_string(extension_control_file(info, extension))
+        .ok()
+        .and_then(|control| {
+            control.lines().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "module_pathname").then(|| {
+                    value
+                        .trim()
+                        .trim_matches('\'')
+                        .trim_start_matches("$libdir/")
+                        .to_string()
+                })
+            })
+        })
+        .unwrap_or_else(|| extension.to_lowercase())
+}
+
+/// Extension files `install-extension --isolated` installed for this
+/// instance only, laid out like the installation (lib/, share/extension/).
+/// It lives in the data directory, so it follows the instance through
+/// clones and backups.
+fn private_extension_dir(info: &InstanceInfo) -> PathBuf {
+    info.data_dir.join("pg0_extensions")
+}
+
+/// Where `install-extension` puts extension files: the instance's private
+/// extension directory, or the installation shared by all instances of its
+/// version.
+fn extension_root(info: &InstanceInfo, isolated: bool) -> PathBuf {
+    if isolated {
+        private_extension_dir(info)
+    } else {
+        info.installation_dir.join(&info.version)
+    }
+}
+
+/// Put the instance's private extension directory in front of the
+/// installation's, through extension_control_path (PostgreSQL 18+) and
+/// dynamic_library_path, and reload the configuration if they changed.
+fn enable_extension_isolation(info: &InstanceInfo) -> Result<(), CliError> {
+    let pg_major: u32 = info
+        .version
+        .split('.')
+        .next()
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(0);
+    if pg_major < 18 {
+        return Err(CliError::Other(format!(
+            "--isolated needs PostgreSQL 18 or later (extension_control_path); this instance runs {}",
+            info.version
+        )));
+    }
+    let root = private_extension_dir(info);
+    fs::create_dir_all(root.join("lib"))?;
+    fs::create_dir_all(root.join("share").join("extension"))?;
+    let separator = if cfg!(windows) { ";" } else { ":" };
+    let settings = [
+        (
+            "extension_control_path",
+            format!("{}{}$system", root.join("share").display(), separator),
+        ),
+        (
+            "dynamic_library_path",
+            format!("{}{}$libdir", root.join("lib").display(), separator),
+        ),
+    ];
+    let mut reload = false;
+    for (key, value) in &settings {
+        let current = run_sql(
+            info,
+            "postgres",
+            &format!("SELECT current_setting({}, true);", quote_literal(key)),
+        )?;
+        if current != *value {
+            set_managed_config_value(&info.data_dir, key, value)?;
+            reload = true;
+        }
+    }
+    if reload {
+        run_sql(info, "postgres", "SELECT pg_reload_conf();")?;
+    }
+    Ok(())
+}
+
+/// Drop `$libdir/` from the module paths in the control and SQL files below
+/// `root`, so the server finds their libraries through dynamic_library_path
+/// (and so in a private extension directory first).
+fn relocate_module_paths(root: &Path) -> Result<(), CliError> {
+    let extension_dir = root.join("share").join("extension");
+    for entry in fs::read_dir(&extension_dir)?.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !path
+            .extension()
+            .is_some_and(|e| e == "control" || e == "sql")
+        {
+            continue;
+        }
+        let content = fs::read_to_string(&path)?;
+        if content.contains("$libdir/") {
+            fs::write(&path, content.replace("$libdir/", ""))?;
+        }
+    }
+    Ok(())
+}
+
+/// The control file of `extension` the instance uses: the one in its
+/// private extension directory if there is one, else the installation's.
+fn extension_control_file(info: &InstanceInfo, extension: &str) -> PathBuf {
+    let private = private_extension_dir(info)
+        .join("share")
+        .join("extension")
+        .join(format!("{}.control", extension.to_lowercase()));
+    if private.exists() {
+        return private;
+    }
+    info.installation_dir
+        .join(&info.version)
+        .join("share")
+        .join("extension")
+        .join(format!("{}.control", extension.to_lowercase()))
+}
+
+/// The `default_version` a control file declares.
+fn control_default_version(control_file: &Path) -> Option<String> {
+    fs::read_to_string(control_file)
+        .ok()
+        .and_then(|control| control_setting(&control, "default_version"))
+}
+
+/// A setting from the contents of an extension control file.
+fn control_setting(control: &str, setting: &str) -> Option<String> {
+    control.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == setting).then(|| value.trim().trim_matches('\'').to_string())
+    })
+}
+
+/// Fail if the library of `extension` (its control file's module_pathname,
+/// or `<extension>.so`) needs shared libraries the loader cannot find.
+#[cfg(target_os = "linux")]
+fn check_extension_libraries(info: &InstanceInfo, extension: &str) -> Result<(), CliError> {
+    let lib_dir = info.installation_dir.join(&info.version).join("lib");
+    let module = extension_module(info, extension);
+    let Some(library) = [private_extension_dir(info).join("lib"), lib_dir.clone()]
+        .iter()
+        .map(|dir| dir.join(format!("{}.so", module)))
+        .find(|library| library.exists())
+    else {
+        return Ok(());
+    };
+    ensure_runtime_libs(&info.installation_dir.join(&info.version))?;
+    prepend_lib_dir_to_ld_library_path(&lib_dir);
+    let missing = unresolved_libraries(&library);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let hint = if missing.iter().any(|m| m.starts_with("libpython")) {
+        "PL/Python needs the Python 3 shared library PostgreSQL was built against (e.g. sudo apt install libpython3.x)."
+    } else {
+        "Install them with your system package manager, or bundle them in the lib/ directory of the extension's archive."
+    };
+    Err(CliError::Other(format!(
+        "Extension '{}' is installed, but {} needs shared libraries that are missing:\n  {}\n\n{}",
+        extension,
+        library.display(),
+        missing.join("\n  "),
+        hint
+    )))
+}
+
+/// What `install-extension` can install without network access.
+fn offline_extensions_hint() -> String {
+    let mut hint = String::from("Offline, pg0 installs ");
+    if !BUNDLED_EXTENSIONS.is_empty() {
+        let names: Vec<&str> = BUNDLED_EXTENSIONS.iter().map(|e| e.name).collect();
+        hint.push_str(&format!("the bundled extensions ({}), ", names.join(", ")));
+    }
+    hint.push_str(
+        "archives and directories passed with --from, and extensions from file:// registries.",
+    );
+    hint
+}
+
+/// Install an extension from the postgresql_extensions registries.
+/// Returns the extension's name as the registry spells it.
+fn install_registry_extension(
+    info: &InstanceInfo,
+    extension_name: &str,
+    extension_version: Option<&str>,
+    insecure: bool,
+) -> Result<String, CliError> {
+    ensure_network(
+        &format!("Extension '{}'", extension_name),
+        "https://api.github.com",
+        &offline_extensions_hint(),
+    )?;
+    println!("Fetching available extensions...");
+
+    let available = postgresql_extensions::blocking::get_available_extensions()?;
+
+    // Find the extension (case-insensitive search)
+    let ext = available
+        .iter()
+        .find(|e| e.name().to_lowercase() == extension_name.to_lowercase())
+        .ok_or_else(|| CliError::ExtensionNotFound(extension_name.to_string()))?;
+
+    let ext_name = ext.name().to_string();
+    let ext_namespace = ext.namespace().to_string();
+    // These sources publish no checksums, and the archive is unpacked as it
+    // is downloaded
+    if !insecure {
+        return Err(CliError::Other(format!(
+            "Cannot verify '{}' from {}: it publishes no checksums. Pass --insecure to install it anyway, or serve it from an extension registry with a SHA256SUMS manifest.",
+            ext_name, ext_namespace
+        )));
+    }
+    eprintln!(
+        "Warning: installing '{}' from {} without checksum verification (--insecure)",
+        ext_name, ext_namespace
+    );
+    println!("Installing extension '{}'...", ext_name);
+    let mut span = operation_span("install_extension");
+    span.attr("extension", &ext_name);
+
+    // Get installed PostgreSQL version
+    let pg_version = if info.installation_dir.join(&info.version).exists() {
+        info.version.clone()
+    } else {
+        find_installed_version(&info.installation_dir)?
+    };
+    let version_req: VersionReq = pg_version.parse().map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid version: {}", e),
+        )
+    })?;
+
+    // Build Settings for the extension installer
+    // The installation_dir needs to point to the version-specific directory
+    let version_install_dir = info.installation_dir.join(&pg_version);
+    // Make sure the bundled libxml2/libicu are present and on the loader path
+    // before pg_config / pg_ctl are spawned on a host where the system libs
+    // are missing or have a different SONAME.
+    ensure_runtime_libs(&version_install_dir)?;
+    #[cfg(target_os = "linux")]
+    prepend_lib_dir_to_ld_library_path(&version_install_dir.join("lib"));
+    let settings = Settings {
+        version: version_req.clone(),
+        port: info.port,
+        username: info.username.clone(),
+        password: info.password.clone(),
+        data_dir: info.data_dir.clone(),
+        installation_dir: version_install_dir,
+        ..Default::default()
+    };
+
+    let extension_req = match extension_version {
+        Some(version) => VersionReq::parse(&format!("={}", version)).map_err(|e| {
+            CliError::Other(format!("Invalid extension version '{}': {}", version, e))
+        })?,
+        None => version_req,
+    };
+    postgresql_extensions::blocking::install(&settings, &ext_namespace, &ext_name, &extension_req)?;
+
+    Ok(ext_name)
+}
+
+/// A tar archive of the files below `dir`.
+fn tar_directory(dir: &Path) -> Result<Vec<u8>, CliError> {
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.append_dir_all(".", dir)?;
+    Ok(builder.into_inner()?)
+}
+
+/// Install an extension from a local tarball or directory (such as the
+/// output of a pgxs `make install DESTDIR=...`), laid out like a bundled
+/// extension. Its control files are checked before anything is copied.
+/// Files go below `root` (see `extension_root`). Returns the name of the
+/// extension to set up, and whether it was installed before.
+fn install_local_extension(
+    info: &InstanceInfo,
+    path: &Path,
+    extension_name: Option<&str>,
+    root: &Path,
+) -> Result<(String, bool), CliError> {
+    let tar = if path.is_dir() {
+        tar_directory(path)?
+    } else {
+        let bytes = fs::read(path)
+            .map_err(|e| CliError::Other(format!("Could not read {}: {}", path.display(), e)))?;
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            let mut tar = Vec::new();
+            std::io::Read::read_to_end(&mut GzDecoder::new(&bytes[..]), &mut tar).map_err(|e| {
+                CliError::Other(format!("Could not read {}: {}", path.display(), e))
+            })?;
+            tar
+        } else {
+            bytes
+        }
+    };
+
+    let mut controls: Vec<(String, String)> = Vec::new();
+    let mut scripts: Vec<String> = Vec::new();
+    let mut libraries: Vec<String> = Vec::new();
+    let mut archive = Archive::new(&tar[..]);
+    let entries = archive
+        .entries()
+        .map_err(|e| CliError::Other(format!("{} is not a tar archive: {}", path.display(), e)))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| {
+            CliError::Other(format!("{} is not a tar archive: {}", path.display(), e))
+        })?;
+        let Some(components) = archive_entry_components(&entry) else {
+            continue;
+        };
+        let Some(name) = components.last() else {
+            continue;
+        };
+        if let Some(extension) = name.strip_suffix(".control") {
+            let mut control = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut control)?;
+            controls.push((extension.to_string(), control));
+        } else if name.ends_with(".sql") {
+            scripts.push(name.clone());
+        } else if is_library_file(name) {
+            libraries.push(name.clone());
+        }
+    }
+    if controls.is_empty() && libraries.is_empty() {
+        return Err(CliError::Other(format!(
+            "No extension control file or library found in {}",
+            path.display()
+        )));
+    }
+
+    let lib_dir = info.installation_dir.join(&info.version).join("lib");
+    for (extension, control) in &controls {
+        let default_version = control_setting(control, "default_version").ok_or_else(|| {
+            CliError::Other(format!(
+                "{}.control does not set default_version",
+                extension
+            ))
+        })?;
+        let install_script = format!("{}--{}.sql", extension, default_version);
+        let update_script = format!("--{}.sql", default_version);
+        if !scripts.iter().any(|s| {
+            *s == install_script
+                || (s.starts_with(&format!("{}--", extension)) && s.ends_with(&update_script))
+        }) {
+            return Err(CliError::Other(format!(
+                "{}.control sets default_version {} but {} has no {} script",
+                extension,
+                default_version,
+                path.display(),
+                install_script
+            )));
+        }
+        if let Some(module) = control_setting(control, "module_pathname") {
+            let library = module.trim_start_matches("$libdir/");
+            if !libraries
+                .iter()
+                .any(|l| l.split('.').next() == Some(library))
+                && !has_library(&lib_dir, library)
+                && !has_library(&root.join("lib"), library)
+            {
+                return Err(CliError::Other(format!(
+                    "{}.control loads {} but {} has no such library",
+                    extension,
+                    module,
+                    path.display()
+                )));
+            }
+        }
+    }
+
+    let mut found: Vec<String> = controls
+        .iter()
+        .map(|(extension, _)| extension.clone())
+        .collect();
+    if found.is_empty() {
+        // Output plugins such as wal2json are a library without a control file
+        found = libraries
+            .iter()
+            .filter_map(|l| l.split('.').next())
+            .map(|l| l.to_string())
+            .collect();
+    }
+    found.sort();
+    found.dedup();
+    let extension = match extension_name {
+        Some(name) => found
+            .iter()
+            .find(|e| e.eq_ignore_ascii_case(name))
+            .cloned()
+            .ok_or_else(|| {
+                CliError::Other(format!(
+                    "{} does not contain extension '{}' (found: {})",
+                    path.display(),
+                    name,
+                    found.join(", ")
+                ))
+            })?,
+        None if found.len() == 1 => found.remove(0),
+        None => {
+            return Err(CliError::Other(format!(
+                "{} contains several extensions ({}); name the one to set up",
+                path.display(),
+                found.join(", ")
+            )))
+        }
+    };
+
+    let installed = root
+        .join("share")
+        .join("extension")
+        .join(format!("{}.control", extension))
+        .exists()
+        || has_library(&root.join("lib"), &extension);
+    println!("Installing {} from {}...", extension, path.display());
+    unpack_extension_archive(Archive::new(&tar[..]), root)?;
+    Ok((extension, installed))
+}
+
+/// An extension listed in an extension registry's index.json.
+#[derive(Serialize, Deserialize)]
+struct RegistryExtension {
+    name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    description: String,
+    versions: Vec<String>,
+}
+
+/// The extension registries configured with `--extension-registry` or
+/// PG0_EXTENSION_REGISTRY, in the order they are tried. The local registry
+/// `pg0 bundle import` fills comes first.
+fn extension_registries() -> Vec<String> {
+    let imported = imported_registry_dir()
+        .ok()
+        .filter(|dir| dir.join("index.json").exists())
+        .map(|dir| format!("file://{}", dir.display()));
+    imported
+        .into_iter()
+        .chain(
+            std::env::var("PG0_EXTENSION_REGISTRY")
+                .unwrap_or_default()
+                .split(';')
+                .map(|r| r.trim().trim_end_matches('/').to_string())
+                .filter(|r| !r.is_empty()),
+        )
+        .collect()
+}
+
+/// The extension registry `pg0 bundle import` unpacks extensions into.
+fn imported_registry_dir() -> Result<PathBuf, CliError> {
+    Ok(get_base_dir()?.join("registry"))
+}
+
+/// Fetch `path` below an extension registry (http(s):// or file://).
+fn fetch_registry_file(registry: &str, path: &str) -> Result<Vec<u8>, CliError> {
+    let url = format!("{}/{}", registry, path);
+    if let Some(file) = url.strip_prefix("file://") {
+        return fs::read(file)
+            .map_err(|e| CliError::Other(format!("Could not read {}: {}", url, e)));
+    }
+    ensure_network(
+        &format!("Extension registry file {}", path),
+        registry,
+        "Offline, serve the registry from a local directory (file://) instead.",
+    )?;
+    // Extension archives can be large, so they download resumably
+    match path
+        .strip_suffix(".tar.gz")
+        .and_then(|p| p.rsplit('/').next())
+    {
+        Some(archive) => download_artifact(&url, &format!("{}.tar.gz", archive)),
+        None => download(&url),
+    }
+}
+
+/// The SHA256 checksums an extension registry publishes in its SHA256SUMS
+/// manifest (`sha256sum` output, paths relative to the registry), by path.
+/// Empty when the registry has no manifest.
+fn registry_checksums(registry: &str) -> HashMap<String, String> {
+    let Ok(manifest) = fetch_registry_file(registry, "SHA256SUMS") else {
+        return HashMap::new();
+    };
+    String::from_utf8_lossy(&manifest)
+        .lines()
+        .filter_map(|line| {
+            let (sha256, path) = line.trim().split_once(char::is_whitespace)?;
+            let path = path
+                .trim_start()
+                .trim_start_matches('*')
+                .trim_start_matches("./");
+            Some((path.to_string(), sha256.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Check a downloaded `artifact` against its `expected` SHA256. Without a
+/// checksum it is refused unless `insecure`; a mismatch is always refused.
+fn verify_artifact(
+    name: &str,
+    artifact: &[u8],
+    expected: Option<&str>,
+    insecure: bool,
+) -> Result<(), CliError> {
+    use sha2::{Digest, Sha256};
+    match expected {
+        Some(expected) => {
+            let actual = format!("{:x}", Sha256::digest(artifact));
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(CliError::Other(format!(
+                    "Checksum mismatch for {}: expected {}, got {}; refusing to install it",
+                    name, expected, actual
+                )));
+            }
+        }
+        None if insecure => {
+            eprintln!("Warning: {} has no checksum to verify; installing it anyway (--insecure)", name);
+        }
+        None => {
+            return Err(CliError::Other(format!(
+                "Cannot verify {}: no checksum for it in the registry's SHA256SUMS. Pass --insecure to install it anyway.",
+                name
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// The extensions an extension registry lists in its index.json.
+fn registry_index(registry: &str) -> Result<Vec<RegistryExtension>, CliError> {
+    serde_json::from_slice(&fetch_registry_file(registry, "index.json")?).map_err(|e| {
+        CliError::Other(format!(
+            "Invalid index.json in extension registry {}: {}",
+            registry, e
+        ))
+    })
+}
+
+/// Where an extension registry keeps an extension's archive for PostgreSQL
+/// `pg_major`, laid out like a bundled extension:
+/// `<name>/<version>/<name>-<version>-<platform>-pg<major>.tar.gz`.
+fn registry_archive_path(name: &str, version: &str, pg_major: &str) -> Result<String, CliError> {
+    let platform = env!("EXTENSION_PLATFORM");
+    if platform.is_empty() {
+        return Err(CliError::Other(
+            "This pg0 build's platform has no extension builds; extension registries are not supported".to_string(),
+        ));
+    }
+    Ok(format!(
+        "{name}/{version}/{name}-{version}-{platform}-pg{pg_major}.tar.gz"
+    ))
+}
+
+/// An extension archive downloaded from an extension registry.
+struct MirroredExtension {
+    name: String,
+    version: String,
+    archive: Vec<u8>,
+}
+
+/// Download `extension_name` (at `version`, or the newest one listed) for
+/// PostgreSQL `pg_major` from the first configured extension registry that
+/// has it. The archive is verified against the registry's SHA256SUMS
+/// manifest. Returns `None` when no registry lists the extension.
+fn fetch_mirrored_extension(
+    extension_name: &str,
+    version: Option<&str>,
+    pg_major: &str,
+    insecure: bool,
+) -> Result<Option<MirroredExtension>, CliError> {
+    for registry in extension_registries() {
+        let index = registry_index(&registry)?;
+        let Some(extension) = index
+            .iter()
+            .find(|e| e.name.eq_ignore_ascii_case(extension_name))
+        else {
+            continue;
+        };
+        let version = match version {
+            Some(version) => extension.versions.iter().find(|v| *v == version),
+            None => extension.versions.iter().max_by_key(|v| {
+                v.split('.')
+                    .map(|p| p.parse::<u64>().unwrap_or(0))
+                    .collect::<Vec<_>>()
+            }),
+        };
+        let Some(version) = version else {
+            continue;
+        };
+        let path = registry_archive_path(&extension.name, version, pg_major)?;
+
+        let mut span = operation_span("download_extension");
+        span.attr("extension", &extension.name);
+        span.attr("registry", &registry);
+        println!(
+            "Downloading {} {} from {}...",
+            extension.name, version, registry
+        );
+        let checksums = registry_checksums(&registry);
+        let result = fetch_registry_file(&registry, &path).and_then(|archive| {
+            verify_artifact(
+                &path,
+                &archive,
+                checksums.get(&path).map(String::as_str),
+                insecure,
+            )?;
+            Ok(archive)
+        });
+        return match result {
+            Ok(archive) => Ok(Some(MirroredExtension {
+                name: extension.name.to_lowercase(),
+                version: version.clone(),
+                archive,
+            })),
+            Err(e) => {
+                span.fail(&e);
+                Err(e)
+            }
+        };
+    }
+    Ok(None)
+}
+
+/// Install `extension_name` from the extension registries (see
+/// `fetch_mirrored_extension`) below `root`. Nothing is unpacked unless the
+/// archive verifies. Returns `None` when no registry lists the extension.
+fn install_mirrored_extension(
+    info: &InstanceInfo,
+    extension_name: &str,
+    version: Option<&str>,
+    insecure: bool,
+    root: &Path,
+) -> Result<Option<String>, CliError> {
+    let pg_major = info.version.split('.').next().unwrap_or_default();
+    let Some(mirrored) = fetch_mirrored_extension(extension_name, version, pg_major, insecure)?
+    else {
+        return Ok(None);
+    };
+    unpack_extension_archive(Archive::new(GzDecoder::new(&mirrored.archive[..])), root)?;
+    Ok(Some(mirrored.name))
+}
+
+/// C compilers source builds can use.
+const C_COMPILERS: &[&str] = &["cc", "gcc", "clang"];
+
+/// Whether `program` is on the PATH and runs.
+fn has_program(program: &str) -> bool {
+    std::process::Command::new(program)
+        .arg("--version")
+        .output()
+        .is_ok()
+}
+
+/// How a source-built extension is compiled.
+enum SourceBuild {
+    /// cmake, with extra configure arguments
+    Cmake(&'static [&'static str]),
+    /// make against PGXS
+    Pgxs,
+}
+
+/// An extension `pg0 install-extension` builds from a source release, for
+/// extensions without relocatable binary releases (or on request).
+struct SourceExtension {
+    name: &'static str,
+    display_name: &'static str,
+    version: &'static str,
+    /// Releases for other PostgreSQL majors, for extensions that track them
+    major_versions: &'static [(&'static str, &'static str)],
+    /// Source tarball; `{version}` is substituted, and `{version_underscored}`
+    /// with dots replaced by underscores
+    url: &'static str,
+    build: SourceBuild,
+    /// Also has precompiled builds, so it is only built from source with
+    /// `install-extension --build-from-source`
+    precompiled: bool,
+}
+
+impl SourceExtension {
+    /// The release to build for PostgreSQL `pg_version`.
+    fn version_for(&self, pg_version: &str) -> &'static str {
+        let pg_major = pg_version.split('.').next().unwrap_or_default();
+        self.major_versions
+            .iter()
+            .find(|(major, _)| *major == pg_major)
+            .map(|(_, version)| *version)
+            .unwrap_or(self.version)
+    }
+}
+
+const SOURCE_EXTENSIONS: &[SourceExtension] = &[
+    SourceExtension {
+        name: "timescaledb",
+        display_name: "TimescaleDB",
+        version: "2.23.0",
+        major_versions: &[],
+        url: "https://github.com/timescale/timescaledb/archive/refs/tags/{version}.tar.gz",
+        build: SourceBuild::Cmake(&[
+            "-DREGRESS_CHECKS=OFF",
+            "-DTAP_CHECKS=OFF",
+            "-DWARNINGS_AS_ERRORS=OFF",
+            "-DUSE_OPENSSL=OFF",
+        ]),
+        precompiled: false,
+    },
+    SourceExtension {
+        name: "pg_cron",
+        display_name: "pg_cron",
+        version: "1.6.7",
+        major_versions: &[],
+        url: "https://github.com/citusdata/pg_cron/archive/refs/tags/v{version}.tar.gz",
+        build: SourceBuild::Pgxs,
+        precompiled: false,
+    },
+    SourceExtension {
+        name: "pg_partman",
+        display_name: "pg_partman",
+        version: "5.2.4",
+        major_versions: &[],
+        url: "https://github.com/pgpartman/pg_partman/archive/refs/tags/v{version}.tar.gz",
+        build: SourceBuild::Pgxs,
+        precompiled: false,
+    },
+    SourceExtension {
+        name: "wal2json",
+        display_name: "wal2json",
+        version: "2.6",
+        major_versions: &[],
+        url: "https://github.com/eulerto/wal2json/archive/refs/tags/wal2json_{version_underscored}.tar.gz",
+        build: SourceBuild::Pgxs,
+        precompiled: false,
+    },
+    SourceExtension {
+        name: "pgaudit",
+        display_name: "pgaudit",
+        version: "18.0",
+        major_versions: &[("17", "17.1"), ("16", "16.1"), ("15", "1.7.1"), ("14", "1.6.3")],
+        url: "https://github.com/pgaudit/pgaudit/archive/refs/tags/{version}.tar.gz",
+        build: SourceBuild::Pgxs,
+        precompiled: false,
+    },
+    SourceExtension {
+        name: "vector",
+        display_name: "pgvector",
+        version: "0.8.1",
+        major_versions: &[],
+        url: "https://github.com/pgvector/pgvector/archive/refs/tags/v{version}.tar.gz",
+        build: SourceBuild::Pgxs,
+        precompiled: true,
+    },
+];
+
+/// Extensions whose library has to be in shared_preload_libraries, with
+/// that library.
+const PRELOAD_EXTENSIONS: &[(&str, &str)] = &[
+    ("timescaledb", "timescaledb"),
+    ("pg_cron", "pg_cron"),
+    ("pgaudit", "pgaudit"),
+    ("pg_partman", "pg_partman_bgw"),
+    ("pg_stat_statements", "pg_stat_statements"),
+];
+
+/// Logical decoding output plugins: libraries without a control file, so
+/// there is nothing to CREATE EXTENSION.
+const OUTPUT_PLUGINS: &[&str] = &["wal2json"];
+
+/// Settings the background worker of `extension` reads at server start.
+fn worker_settings(extension: &str, info: &InstanceInfo) -> Vec<(&'static str, String)> {
+    match extension {
+        // pg_cron runs its scheduler in a single database
+        "pg_cron" => vec![("cron.database_name", info.database.clone())],
+        // pg_partman's worker runs run_maintenance() in the listed databases
+        "pg_partman" => vec![
+            ("pg_partman_bgw.dbname", info.database.clone()),
+            ("pg_partman_bgw.role", info.username.clone()),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// Build an extension from source against the instance's PostgreSQL and
+/// install it there. Needs a C compiler, plus cmake for cmake builds.
+fn build_source_extension(
+    info: &InstanceInfo,
+    extension: &SourceExtension,
+    version: Option<&str>,
+    root: Option<&Path>,
+) -> Result<(), CliError> {
+    if cfg!(windows) {
+        return Err(CliError::Other(format!(
+            "Building {} is not supported on Windows; use a pg0 binary that bundles it",
+            extension.display_name
+        )));
+    }
+    let tool = match extension.build {
+        SourceBuild::Cmake(_) => "cmake",
+        SourceBuild::Pgxs => "make",
+    };
+    if !has_program(tool) || !C_COMPILERS.iter().any(|cc| has_program(cc)) {
+        return Err(CliError::Other(format!(
+            "{} is built from source and needs {} and a C compiler (Xcode Command Line Tools on macOS, {}build-essential on Linux); check with 'pg0 doctor'",
+            extension.display_name,
+            tool,
+            if tool == "cmake" { "cmake and " } else { "" }
+        )));
+    }
+    let pg_config = find_instance_binary(info, "pg_config")?;
+    ensure_runtime_libs_for_binary(&pg_config)?;
+    let version = version.unwrap_or_else(|| extension.version_for(&info.version));
+
+    // Sources are kept in the downloads directory and unpacked afresh into a
+    // private build directory each time, never trusting an earlier tree.
+    let tarball = downloads_dir()?.join(format!("{}-{}.tar.gz", extension.name, version));
+    if !tarball.exists() {
+        let url = extension
+            .url
+            .replace("{version_underscored}", &version.replace('.', "_"))
+            .replace("{version}", version);
+        ensure_network(
+            &format!("The {} {} source", extension.display_name, version),
+            &url,
+            &format!(
+                "Offline, install a build of it with --from, or put the source tarball at {} beforehand.",
+                tarball.display()
+            ),
+        )?;
+        println!("Downloading {} {}...", extension.display_name, version);
+        download_file(&url, &tarball)?;
+    }
+    let build_root = get_base_dir()?.join("build");
+    fs::create_dir_all(&build_root)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&build_root, fs::Permissions::from_mode(0o700))?;
+    }
+    let build_dir = build_root.join(format!("{}-{}", extension.name, version));
+    if build_dir.exists() {
+        fs::remove_dir_all(&build_dir)?;
+    }
+    fs::create_dir_all(&build_dir)?;
+    let unpacked = Archive::new(GzDecoder::new(fs::File::open(&tarball)?)).unpack(&build_dir);
+    if let Err(e) = unpacked {
+        // A truncated or corrupt tarball would fail the same way next time
+        let _ = fs::remove_file(&tarball);
+        let _ = fs::remove_dir_all(&build_dir);
+        return Err(CliError::Other(format!(
+            "Failed to unpack the {} {} source: {}",
+            extension.display_name, version, e
+        )));
+    }
+    // Release tarballs unpack into a single top-level directory
+    let source_dir = fs::read_dir(&build_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| p.is_dir())
+        .ok_or_else(|| {
+            CliError::Other(format!(
+                "Unexpected {} source layout",
+                extension.display_name
+            ))
+        })?;
+
+    println!(
+        "Building {} {} (this takes a few minutes)...",
+        extension.display_name, version
+    );
+    let pg_config_arg = format!("PG_CONFIG={}", pg_config.display());
+    let steps: Vec<(&str, Vec<std::ffi::OsString>)> = match extension.build {
+        SourceBuild::Cmake(configure) => {
+            let cmake_build_dir = source_dir.join("build");
+            let mut configure_args: Vec<std::ffi::OsString> = vec![
+                "-S".into(),
+                source_dir.clone().into(),
+                "-B".into(),
+                cmake_build_dir.clone().into(),
+                format!("-D{}", pg_config_arg).into(),
+                "-DCMAKE_BUILD_TYPE=Release".into(),
+            ];
+            configure_args.extend(configure.iter().map(|a| a.into()));
+            vec![
+                ("cmake", configure_args),
+                (
+                    "cmake",
+                    vec![
+                        "--build".into(),
+                        cmake_build_dir.clone().into(),
+                        "--parallel".into(),
+                    ],
+                ),
+                ("cmake", vec!["--install".into(), cmake_build_dir.into()]),
+            ]
+        }
+        SourceBuild::Pgxs => {
+            let make_args = |target: Option<&str>| {
+                let mut args: Vec<std::ffi::OsString> = vec![
+                    "-C".into(),
+                    source_dir.clone().into(),
+                    "USE_PGXS=1".into(),
+                    pg_config_arg.clone().into(),
+                ];
+                args.extend(target.map(|t| t.into()));
+                args
+            };
+            vec![
+                ("make", make_args(None)),
+                ("make", make_args(Some("install"))),
+            ]
+        }
+    };
+    // Into a private extension directory, install into a staging directory
+    // and move the files from there
+    let staging = root.map(|_| build_dir.with_extension("destdir"));
+    if let Some(staging) = &staging {
+        let _ = fs::remove_dir_all(staging);
+    }
+    for (program, args) in &steps {
+        let mut command = std::process::Command::new(program);
+        command.args(args);
+        if let Some(staging) = &staging {
+            command.env("DESTDIR", staging);
+        }
+        let output = command.output()?;
+        if !output.status.success() {
+            let mut log = String::from_utf8_lossy(&output.stdout).to_string();
+            log.push_str(&String::from_utf8_lossy(&output.stderr));
+            let tail: Vec<&str> = log.lines().rev().take(20).collect();
+            return Err(CliError::Other(format!(
+                "Building {} failed:\n{}",
+                extension.display_name,
+                tail.into_iter().rev().collect::<Vec<_>>().join("\n")
+            )));
+        }
+    }
+    if let (Some(root), Some(staging)) = (root, &staging) {
+        // DESTDIR nests the installation's absolute path
+        let version_dir = info.installation_dir.join(&info.version);
+        let nested: PathBuf = version_dir
+            .components()
+            .filter(|c| matches!(c, std::path::Component::Normal(_)))
+            .collect();
+        let tree = staging.join(nested);
+        let tree = if tree.exists() { tree } else { staging.clone() };
+        unpack_extension_archive(Archive::new(&tar_directory(&tree)?[..]), root)?;
+        fs::remove_dir_all(staging)?;
+    }
+    let _ = fs::remove_dir_all(&build_dir);
+    Ok(())
+}
+
+/// Make sure `library` is preloaded and `settings` are in effect, adding
+/// them to the managed configuration and restarting the instance if they
+/// are not. Returns the instance as it is after a possible restart.
+fn ensure_preloaded(
+    name: &str,
+    info: InstanceInfo,
+    library: &str,
+    settings: &[(&str, String)],
+    reload: bool,
+) -> Result<InstanceInfo, CliError> {
+    let mut restart = reload;
+    for (key, value) in settings {
+        let current = run_sql(
+            &info,
+            "postgres",
+            &format!("SELECT current_setting({}, true);", quote_literal(key)),
+        )?;
+        if current != *value {
+            set_managed_config_value(&info.data_dir, key, value)?;
+            restart = true;
+        }
+    }
+    let preloaded = run_sql(&info, "postgres", "SHOW shared_preload_libraries;")?;
+    let mut libraries: Vec<String> = preloaded
+        .split(',')
+        .map(|l| l.trim().trim_matches('"').to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    if !libraries.iter().any(|l| l == library) {
+        println!(
+            "Adding {} to shared_preload_libraries on '{}'...",
+            library, name
+        );
+        libraries.push(library.to_string());
+        set_managed_config_value(
+            &info.data_dir,
+            "shared_preload_libraries",
+            &libraries.join(","),
+        )?;
+        restart = true;
+    }
+    if restart {
+        restart_instance(name)
+    } else {
+        Ok(info)
+    }
+}
+
+/// `ALTER EXTENSION ... UPDATE` in every database that has `extension`, to
+/// `version` or the installed default version.
+fn update_extension(
+    info: &InstanceInfo,
+    extension: &str,
+    version: Option<&str>,
+) -> Result<(), CliError> {
+    let databases = run_sql(
+        info,
+        "postgres",
+        "SELECT datname FROM pg_database WHERE datallowconn AND NOT datistemplate ORDER BY datname;",
+    )?;
+    let version_query = format!(
+        "SELECT extversion FROM pg_extension WHERE extname = {};",
+        quote_literal(extension)
+    );
+    for database in databases.lines() {
+        let before = run_sql(info, database, &version_query)?;
+        if before.is_empty() {
+            continue;
+        }
+        let target = match version {
+            Some(version) => format!(" TO {}", quote_literal(version)),
+            None => String::new(),
+        };
+        run_sql(
+            info,
+            database,
+            &format!(
+                "ALTER EXTENSION {} UPDATE{};",
+                quote_ident(extension),
+                target
+            ),
+        )
+        .map_err(|e| {
+            CliError::Other(format!(
+                "Could not update extension '{}' in '{}': {}",
+                extension, database, e
+            ))
+        })?;
+        let after = run_sql(info, database, &version_query)?;
+        if after == before {
+            println!(
+                "Extension '{}' in '{}' is at {}.",
+                extension, database, after
+            );
+        } else {
+            println!(
+                "Updated extension '{}' in '{}' from {} to {}.",
+                extension, database, before, after
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `CREATE EXTENSION` in `database` unless it exists already (with CASCADE,
+/// so extensions it requires come along). Returns whether it was created.
+fn create_extension(
+    name: &str,
+    info: &InstanceInfo,
+    database: &str,
+    extension: &str,
+) -> Result<bool, CliError> {
+    let installed = run_sql(
+        info,
+        database,
+        &format!(
+            "SELECT 1 FROM pg_extension WHERE extname = {};",
+            quote_literal(extension)
+        ),
+    )?;
+    if !installed.is_empty() {
+        return Ok(false);
+    }
+    run_sql(
+        info,
+        database,
+        &format!("CREATE EXTENSION IF NOT EXISTS {} CASCADE;", quote_ident(extension)),
+    )
+    .map_err(|e| {
+        CliError::Other(format!(
+            "Could not create extension '{}' in '{}': {}\nInstall it first with 'pg0 install-extension --name {} {}'.",
+            extension, database, e, name, extension
+        ))
+    })?;
+    println!("Created extension '{}' in '{}'.", extension, database);
+    Ok(true)
+}
+
+/// Parse `--label KEY=VALUE` arguments.
+fn parse_labels(labels: &[String]) -> Result<std::collections::BTreeMap<String, String>, CliError> {
+    labels
+        .iter()
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok((key.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(CliError::Other(format!(
+                "Invalid label '{}', expected KEY=VALUE",
+                label
+            ))),
+        })
+        .collect()
+}
+
+/// Lifecycle events of `pg0 start --hook`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum HookEvent {
+    PreStart,
+    PostStart,
+    PostStop,
+}
+
+impl HookEvent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HookEvent::PreStart => "pre-start",
+            HookEvent::PostStart => "post-start",
+            HookEvent::PostStop => "post-stop",
+        }
+    }
+}
+
+/// Parse `--hook EVENT=COMMAND` values. An empty command stands for removing
+/// the hook.
+fn parse_hooks(hooks: &[String]) -> Result<Vec<(HookEvent, String)>, CliError> {
+    hooks
+        .iter()
+        .map(|hook| {
+            let (event, command) = hook.split_once('=').ok_or_else(|| {
+                CliError::Other(format!("Invalid hook '{}', expected EVENT=COMMAND", hook))
+            })?;
+            let event = [
+                HookEvent::PreStart,
+                HookEvent::PostStart,
+                HookEvent::PostStop,
+            ]
+            .into_iter()
+            .find(|e| e.as_str() == event.trim())
+            .ok_or_else(|| {
+                CliError::Other(format!(
+                    "Unknown hook event '{}' (expected pre-start, post-start or post-stop)",
+                    event.trim()
+                ))
+            })?;
+            Ok((event, command.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Run a lifecycle hook through the shell, with the connection variables of
+/// `pg0 env` plus PG0_NAME, PG0_HOOK and PG0_DATA_DIR set.
+fn run_hook(
+    name: &str,
+    event: HookEvent,
+    command: &str,
+    data_dir: &Path,
+    vars: Vec<(&'static str, String)>,
+) -> Result<(), CliError> {
+    println!("Running {} hook: {}", event.as_str(), command);
+    #[cfg(unix)]
+    let mut shell = {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    };
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    };
+    let status = shell
+        .envs(vars)
+        .env("PG0_NAME", name)
+        .env("PG0_HOOK", event.as_str())
+        .env("PG0_DATA_DIR", data_dir)
+        .status()
+        .map_err(|e| {
+            CliError::Other(format!("Could not run the {} hook: {}", event.as_str(), e))
+        })?;
+    if !status.success() {
+        return Err(CliError::Other(format!(
+            "The {} hook failed ({})",
+            event.as_str(),
+            status
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `labels` satisfy every filter: KEY=VALUE matches that value,
+/// a bare KEY any value.
+fn matches_labels(
+    labels: &std::collections::BTreeMap<String, String>,
+    filters: &[String],
+) -> Result<bool, CliError> {
+    for filter in filters {
+        let matched = match filter.split_once('=') {
+            Some((key, value)) => labels.get(key.trim()).is_some_and(|v| v == value.trim()),
+            None if !filter.trim().is_empty() => labels.contains_key(filter.trim()),
+            None => return Err(CliError::Other("Empty label filter".to_string())),
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn format_labels(labels: &std::collections::BTreeMap<String, String>) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Instances whose labels match `filters`.
+fn instances_matching(filters: &[String]) -> Result<Vec<(String, InstanceInfo)>, CliError> {
+    let mut matching = Vec::new();
+    for name in list_instances()? {
+        if let Some(info) = load_instance(&name)? {
+            if matches_labels(&info.labels, filters)? {
+                matching.push((name, info));
+            }
+        }
+    }
+    Ok(matching)
+}
+
+/// `pg0 stop --filter`: stop every running instance with matching labels.
+fn stop_matching(filters: &[String], options: &StopOptions) -> Result<(), CliError> {
+    let running: Vec<String> = instances_matching(filters)?
+        .into_iter()
+        .filter(|(_, info)| is_instance_running(info))
+        .map(|(name, _)| name)
+        .collect();
+    if running.is_empty() {
+        println!("No running instances match {}.", filters.join(" "));
+        return Ok(());
+    }
+    for name in running {
+        stop(name, options)?;
+    }
+    Ok(())
+}
+
+/// `pg0 drop --filter`: drop every instance with matching labels.
+fn drop_matching(filters: &[String], force: bool) -> Result<(), CliError> {
+    let matching = instances_matching(filters)?;
+    if matching.is_empty() {
+        println!("No instances match {}.", filters.join(" "));
+        return Ok(());
+    }
+    for (name, _) in matching {
+        drop_instance(name, force)?;
+    }
+    Ok(())
+}
+
+/// One entry of `pg0 list`: the `info` view plus inventory details.
+#[derive(Serialize)]
+struct ListEntry {
+    #[serde(flatten)]
+    info: InfoOutput,
+    /// Version reported by the running server (e.g. "18.1")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uptime_secs: Option<u64>,
+    /// On-disk size of the data directory (or the archive)
+    size_bytes: u64,
+    /// Extensions installed in the instance's database (besides plpgsql),
+    /// with versions
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    extensions: Vec<String>,
+}
+
+fn list_entry(name: &str, info: InstanceInfo) -> ListEntry {
+    let size_bytes = disk_usage(info.archive.as_ref().unwrap_or(&info.data_dir));
+    let stats = if is_instance_running(&info) {
+        server_stats(&info)
+    } else {
+        None
+    };
+    ListEntry {
+        info: build_info_output(name, Some(info)),
+        server_version: stats.as_ref().map(|s| s.server_version.clone()),
+        uptime_secs: stats.as_ref().map(|s| s.uptime_secs),
+        size_bytes,
+        extensions: stats.map(|s| s.extensions).unwrap_or_default(),
+    }
+}
+
+/// `-o table` of `pg0 list` and `pg0 info`.
+fn print_instance_table(entries: &[ListEntry]) {
+    let header = [
+        "NAME",
+        "STATUS",
+        "PORT",
+        "VERSION",
+        "UPTIME",
+        "SIZE",
+        "EXTENSIONS",
+        "LABELS",
+    ];
+    let rows: Vec<[String; 8]> = entries
+        .iter()
+        .map(|entry| {
+            let info = &entry.info;
+            let status = if info.running {
+                "running"
+            } else if info.archive.is_some() {
+                "archived"
+            } else {
+                "stopped"
+            };
+            [
+                info.name.clone(),
+                status.to_string(),
+                info.port.map(|p| p.to_string()).unwrap_or_default(),
+                entry
+                    .server_version
+                    .clone()
+                    .or_else(|| info.version.clone())
+                    .unwrap_or_default(),
+                entry
+                    .uptime_secs
+                    .map(format_duration_secs)
+                    .unwrap_or_else(|| "-".to_string()),
+                format_size(entry.size_bytes),
+                entry.extensions.len().to_string(),
+                format_labels(&info.labels),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(header.to_vec());
+    for row in &rows {
+        print_row(row.iter().map(|c| c.as_str()).collect());
+    }
+}
+
+/// Render a JSON value as YAML. Strings are emitted JSON-quoted, which is
+/// valid YAML and never ambiguous (e.g. "on", "18.1").
+fn to_yaml(value: &serde_json::Value) -> String {
+    fn scalar(value: &serde_json::Value) -> String {
+        match value {
+            serde_json::Value::Null => "null".to_string(),
+            serde_json::Value::Object(map) if map.is_empty() => "{}".to_string(),
+            serde_json::Value::Array(items) if items.is_empty() => "[]".to_string(),
+            other => other.to_string(),
+        }
+    }
+    fn is_nested(value: &serde_json::Value) -> bool {
+        match value {
+            serde_json::Value::Object(map) => !map.is_empty(),
+            serde_json::Value::Array(items) => !items.is_empty(),
+            _ => false,
+        }
+    }
+    fn key(key: &str) -> String {
+        if !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            key.to_string()
+        } else {
+            serde_json::Value::String(key.to_string()).to_string()
+        }
+    }
+    fn write(value: &serde_json::Value, indent: usize, out: &mut String) {
+        let pad = " ".repeat(indent);
+        match value {
+            serde_json::Value::Object(map) if !map.is_empty() => {
+                for (k, v) in map {
+                    if is_nested(v) {
+                        out.push_str(&format!("{}{}:\n", pad, key(k)));
+                        write(v, indent + 2, out);
+                    } else {
+                        out.push_str(&format!("{}{}: {}\n", pad, key(k), scalar(v)));
+                    }
+                }
+            }
+            serde_json::Value::Array(items) if !items.is_empty() => {
+                for item in items {
+                    if is_nested(item) {
+                        // The first line of a nested item goes after the dash.
+                        let mut nested = String::new();
+                        write(item, indent + 2, &mut nested);
+                        out.push_str(&format!("{}- {}", pad, &nested[indent + 2..]));
+                    } else {
+                        out.push_str(&format!("{}- {}\n", pad, scalar(item)));
+                    }
+                }
+            }
+            other => out.push_str(&format!("{}{}\n", pad, scalar(other))),
+        }
+    }
+    let mut out = String::new();
+    write(value, 0, &mut out);
+    out
+}
+
+fn list(filter: &[String], output_format: ListFormat, show_secrets: bool) -> Result<(), CliError> {
+    let instance_names = list_instances()?;
+
+    let mut instances: Vec<ListEntry> = Vec::new();
+    for name in &instance_names {
+        if let Some(info) = load_instance(name)? {
+            if matches_labels(&info.labels, filter)? {
+                let mut entry = list_entry(name, info);
+                if !show_secrets {
+                    entry.info.redact_secrets();
+                }
+                instances.push(entry);
+            }
+        }
+    }
+
+    match output_format {
+        ListFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&instances)?);
+        }
+        ListFormat::Yaml => {
+            print!("{}", to_yaml(&serde_json::to_value(&instances)?));
+        }
+        ListFormat::Table => print_instance_table(&instances),
+        ListFormat::Text => {
+            if instances.is_empty() {
+                println!("No instances found.");
+            } else {
+                println!("Instances:");
+                println!();
+                for entry in &instances {
+                    let instance = &entry.info;
+                    let status = if instance.running {
+                        "running"
+                    } else if instance.archive.is_some() {
+                        "archived"
+                    } else {
+                        "stopped"
+                    };
+                    let mut status = status.to_string();
+                    if instance.durability.as_deref() == Some("fast") {
+                        status.push_str(", fast/non-durable");
+                    }
+                    if let Some(primary) = &instance.replica_of {
+                        status.push_str(&format!(", replica of {}", primary));
+                    }
+                    if !instance.labels.is_empty() {
+                        status.push_str(&format!(", {}", format_labels(&instance.labels)));
+                    }
+                    if instance.running {
+                        println!(
+                            "  {} ({}) - port {} - {}",
+                            instance.name,
+                            status,
+                            instance.port.unwrap(),
+                            instance.uri.as_ref().unwrap()
+                        );
+                    } else {
+                        println!(
+                            "  {} ({}) - port {} - {}",
+                            instance.name,
+                            status,
+                            instance.port.unwrap(),
+                            instance
+                                .archive
+                                .as_ref()
+                                .or(instance.data_dir.as_ref())
+                                .unwrap()
+                        );
+                    }
+
+                    let mut details = vec![format!(
+                        "PostgreSQL {}",
+                        entry
+                            .server_version
+                            .as_ref()
+                            .or(instance.version.as_ref())
+                            .unwrap()
+                    )];
+                    if let Some(uptime) = entry.uptime_secs {
+                        details.push(format!("up {}", format_duration_secs(uptime)));
+                    }
+                    details.push(format_size(entry.size_bytes));
+                    if !entry.extensions.is_empty() {
+                        details.push(format!("extensions: {}", entry.extensions.join(", ")));
+                    }
+                    println!("      {}", details.join(", "));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// How often `pg0 ui` refreshes instance status.
+const UI_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Log lines shown for the selected instance in `pg0 ui`.
+const UI_LOG_LINES: usize = 200;
+
+/// One row of the `pg0 ui` instance table.
+struct UiInstance {
+    name: String,
+    info: InstanceInfo,
+    output: InfoOutput,
+    size: u64,
+    connections: Option<usize>,
+}
+
+fn ui_snapshot() -> Vec<UiInstance> {
+    let mut rows = Vec::new();
+    for name in list_instances().unwrap_or_default() {
+        let Ok(Some(info)) = load_instance(&name) else {
+            continue;
+        };
+        let output = build_info_output(&name, Some(info.clone()));
+        let connections = if output.running {
+            run_sql(
+                &info,
+                &info.database,
+                "SELECT count(*) FROM pg_stat_activity WHERE backend_type = 'client backend' AND pid <> pg_backend_pid()",
+            )
+            .ok()
+            .and_then(|count| count.trim().parse().ok())
+        } else {
+            None
+        };
+        let size = disk_usage(&info.data_dir);
+        rows.push(UiInstance {
+            name,
+            info,
+            output,
+            size,
+            connections,
+        });
+    }
+    rows
+}
+
+/// Last lines of the instance's most recent server log.
+fn ui_log_tail(instance: &UiInstance) -> Vec<String> {
+    let Some(log_file) = latest_log_file(&instance.info.data_dir.join("log")) else {
+        return vec!["(no server log yet)".to_string()];
+    };
+    let content = fs::read_to_string(&log_file).unwrap_or_default();
+    let lines: Vec<&str> = content.lines().collect();
+    lines[lines.len().saturating_sub(UI_LOG_LINES)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Run a pg0 subcommand with its output captured, so it does not draw over
+/// the dashboard. Returns the last line it printed.
+fn ui_run_pg0(args: &[String]) -> String {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return format!("Error: {}", e),
+    };
+    match std::process::Command::new(exe)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .output()
+    {
+        Ok(output) => {
+            let text = format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            text.lines()
+                .rev()
+                .map(str::trim)
+                .find(|line| !line.is_empty() && !line.starts_with("Use 'pg0"))
+                .unwrap_or("Done.")
+                .to_string()
+        }
+        Err(e) => format!("Error: {}", e),
+    }
+}
+
+/// `pg0 start` arguments that bring a stopped instance back with the
+/// settings it was created with.
+fn ui_start_args(instance: &UiInstance) -> Vec<String> {
+    let info = &instance.info;
+    vec![
+        "start".to_string(),
+        "--name".to_string(),
+        instance.name.clone(),
+        "--port".to_string(),
+        info.port.to_string(),
+        "--version".to_string(),
+        info.version.clone(),
+        "--data-dir".to_string(),
+        info.data_dir.display().to_string(),
+        "--username".to_string(),
+        info.username.clone(),
+        "--password".to_string(),
+        info.password.clone(),
+        "--database".to_string(),
+        info.database.clone(),
+        "--durability".to_string(),
+        info.durability.as_str().to_string(),
+        "--preset".to_string(),
+        info.preset.as_str().to_string(),
+    ]
+    .into_iter()
+    .chain(info.auto_tune.then(|| "--auto-tune".to_string()))
+    .chain(info.auto_explain.map(|ms| format!("--auto-explain={}", ms)))
+    .chain(
+        info.audit
+            .as_ref()
+            .map(|classes| format!("--audit={}", classes)),
+    )
+    .collect()
+}
+
+fn ui_draw(
+    frame: &mut ratatui::Frame,
+    instances: &[UiInstance],
+    table_state: &mut ratatui::widgets::TableState,
+    log: &[String],
+    status: &str,
+) {
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::widgets::{Block, Cell, Paragraph, Row, Table};
+
+    let [table_area, log_area, status_area] = Layout::vertical([
+        Constraint::Length(instances.len().clamp(1, 12) as u16 + 3),
+        Constraint::Min(5),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let rows = instances.iter().map(|instance| {
+        let output = &instance.output;
+        let (state, color) = if output.running {
+            ("running", Color::Green)
+        } else if output.archive.is_some() {
+            ("archived", Color::Blue)
+        } else {
+            ("stopped", Color::DarkGray)
+        };
+        Row::new(vec![
+            Cell::from(instance.name.clone()),
+            Cell::from(state).style(Style::default().fg(color)),
+            Cell::from(output.port.map(|p| p.to_string()).unwrap_or_default()),
+            Cell::from(output.version.clone().unwrap_or_default()),
+            Cell::from(format_size(instance.size)),
+            Cell::from(
+                instance
+                    .connections
+                    .map(|c| c.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+            Cell::from(output.durability.clone().unwrap_or_default()),
+        ])
+    });
+    let header = Row::new([
+        "NAME",
+        "STATUS",
+        "PORT",
+        "VERSION",
+        "SIZE",
+        "CONNS",
+        "DURABILITY",
+    ])
+    .style(Style::default().add_modifier(Modifier::BOLD));
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(2),
+            Constraint::Length(9),
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(6),
+            Constraint::Length(10),
+        ],
+    )
+    .header(header)
+    .block(Block::bordered().title(" pg0 instances "))
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, table_area, table_state);
+
+    let selected = table_state.selected().and_then(|i| instances.get(i));
+    let title = match selected {
+        Some(instance) => format!(" log: {} ", instance.name),
+        None => " log ".to_string(),
+    };
+    let visible = log_area.height.saturating_sub(2) as usize;
+    let text = log[log.len().saturating_sub(visible)..].join("\n");
+    frame.render_widget(
+        Paragraph::new(text).block(Block::bordered().title(title)),
+        log_area,
+    );
+
+    let help = "↑/↓ select  s start  x stop  p/Enter psql  r refresh  q quit";
+    let line = if status.is_empty() {
+        help.to_string()
+    } else {
+        format!("{}  |  {}", status, help)
+    };
+    frame.render_widget(
+        Paragraph::new(line).style(Style::default().fg(Color::Cyan)),
+        status_area,
+    );
+}
+
+fn ui() -> Result<(), CliError> {
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+
+    let mut terminal = ratatui::try_init()?;
+    let mut instances = ui_snapshot();
+    let mut table_state = ratatui::widgets::TableState::default();
+    table_state.select(if instances.is_empty() { None } else { Some(0) });
+    let mut status = if instances.is_empty() {
+        "No instances found. Create one with 'pg0 start'.".to_string()
+    } else {
+        String::new()
+    };
+    let mut last_refresh = std::time::Instant::now();
+
+    let result = loop {
+        let selected = table_state.selected().and_then(|i| instances.get(i));
+        let log = selected.map(ui_log_tail).unwrap_or_default();
+        if let Err(e) =
+            terminal.draw(|frame| ui_draw(frame, &instances, &mut table_state, &log, &status))
+        {
+            break Err(e.into());
+        }
+
+        match event::poll(std::time::Duration::from_millis(250)) {
+            Ok(true) => {}
+            Ok(false) => {
+                if last_refresh.elapsed() >= UI_REFRESH_INTERVAL {
+                    instances = ui_snapshot();
+                    last_refresh = std::time::Instant::now();
+                }
+                continue;
+            }
+            Err(e) => break Err(e.into()),
+        }
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e.into()),
+        };
+
+        let selected = table_state.selected().and_then(|i| instances.get(i));
+        let mut action: Option<(String, Vec<String>)> = None;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => table_state.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => table_state.select_previous(),
+            KeyCode::Char('r') => status.clear(),
+            KeyCode::Char('s') => match selected {
+                Some(instance) if instance.output.running => {
+                    status = format!("'{}' is already running", instance.name)
+                }
+                Some(instance) if instance.output.archive.is_some() => {
+                    action = Some((
+                        format!("Restoring '{}'...", instance.name),
+                        vec![
+                            "unarchive".to_string(),
+                            "--name".to_string(),
+                            instance.name.clone(),
+                        ],
+                    ))
+                }
+                Some(instance) => {
+                    action = Some((
+                        format!("Starting '{}'...", instance.name),
+                        ui_start_args(instance),
+                    ))
+                }
+                None => {}
+            },
+            KeyCode::Char('x') => match selected {
+                Some(instance) if instance.output.running => {
+                    action = Some((
+                        format!("Stopping '{}'...", instance.name),
+                        vec![
+                            "stop".to_string(),
+                            "--name".to_string(),
+                            instance.name.clone(),
+                        ],
+                    ))
+                }
+                Some(instance) => status = format!("'{}' is not running", instance.name),
+                None => {}
+            },
+            KeyCode::Char('p') | KeyCode::Enter => match selected {
+                Some(instance) if instance.output.running => {
+                    // Hand the terminal to psql until it exits.
+                    ratatui::restore();
+                    let exe = std::env::current_exe()?;
+                    let _ = std::process::Command::new(exe)
+                        .args(["psql", "--name", &instance.name])
+                        .status();
+                    terminal = ratatui::try_init()?;
+                    terminal.clear()?;
+                    status.clear();
+                }
+                Some(instance) => {
+                    status = format!("'{}' is not running; press s to start it", instance.name)
+                }
+                None => {}
+            },
+            _ => {}
+        }
+
+        if let Some((message, args)) = action {
+            // Show what is happening while the (blocking) command runs.
+            let _ =
+                terminal.draw(|frame| ui_draw(frame, &instances, &mut table_state, &log, &message));
+            status = ui_run_pg0(&args);
+        }
+        if action_refresh(&key.code) {
+            instances = ui_snapshot();
+            last_refresh = std::time::Instant::now();
+            if table_state.selected().is_some_and(|i| i >= instances.len()) {
+                table_state.select(instances.len().checked_sub(1));
+            }
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+/// One client connection, as shown by `pg0 sessions` and `pg0 top`.
+#[derive(Serialize)]
+struct Session {
+    pid: u32,
+    user: String,
+    database: String,
+    /// Client address and port, or "local" for Unix socket connections
+    client: String,
+    application: String,
+    state: String,
+    /// Age of the current transaction, or of the last query outside one
+    duration_seconds: f64,
+    wait_event: String,
+    query: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TopSort {
+    Duration,
+    State,
+}
+
+/// Client connections other than our own, oldest first.
+fn list_sessions(info: &InstanceInfo) -> Result<Vec<Session>, CliError> {
+    let sql = "SELECT pid, coalesce(usename, ''), coalesce(datname, ''), \
+               coalesce(host(client_addr) || ':' || client_port, 'local'), \
+               replace(coalesce(application_name, ''), '|', '/'), coalesce(state, ''), \
+               coalesce(extract(epoch FROM now() - coalesce(xact_start, query_start, backend_start)), 0), \
+               coalesce(wait_event_type || ':' || wait_event, ''), \
+               regexp_replace(coalesce(query, ''), '\\s+', ' ', 'g') \
+               FROM pg_stat_activity WHERE backend_type = 'client backend' AND pid <> pg_backend_pid() \
+               ORDER BY backend_start";
+    let output = run_sql(info, &info.database, sql)?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            // The query is last, so a '|' inside it does not shift the columns.
+            let fields: Vec<&str> = line.splitn(9, '|').collect();
+            if fields.len() != 9 {
+                return None;
+            }
+            Some(Session {
+                pid: fields[0].parse().ok()?,
+                user: fields[1].to_string(),
+                database: fields[2].to_string(),
+                client: fields[3].to_string(),
+                application: fields[4].to_string(),
+                state: fields[5].to_string(),
+                duration_seconds: fields[6].parse().unwrap_or(0.0),
+                wait_event: fields[7].to_string(),
+                query: fields[8].to_string(),
+            })
+        })
+        .collect())
+}
+
+fn sessions(name: String, output_format: OutputFormat) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let sessions = list_sessions(&info)?;
+
+    match output_format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&sessions)?);
+        }
+        OutputFormat::Text => {
+            if sessions.is_empty() {
+                println!("No client sessions on '{}'.", name);
+                return Ok(());
+            }
+            println!(
+                "{:<8} {:<12} {:<12} {:<21} {:<20} {:>8}  QUERY",
+                "PID", "USER", "DATABASE", "CLIENT", "STATE", "TIME"
+            );
+            for session in &sessions {
+                let mut query = session.query.clone();
+                if query.chars().count() > 60 {
+                    query = format!("{}...", query.chars().take(57).collect::<String>());
+                }
+                println!(
+                    "{:<8} {:<12} {:<12} {:<21} {:<20} {:>8}  {}",
+                    session.pid,
+                    session.user,
+                    session.database,
+                    session.client,
+                    session.state,
+                    format_top_duration(session.duration_seconds),
+                    query
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn kill_session(name: String, pid: Option<u32>, idle_in_transaction: bool) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+
+    if let Some(pid) = pid {
+        let sql = format!(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity \
+             WHERE pid = {} AND backend_type = 'client backend'",
+            pid
+        );
+        return match run_sql(&info, &info.database, &sql)?.trim() {
+            "t" => {
+                println!("Terminated session {}.", pid);
+                Ok(())
+            }
+            "" => Err(CliError::Other(format!(
+                "No client session with pid {} on '{}'",
+                pid, name
+            ))),
+            _ => Err(CliError::Other(format!(
+                "Could not terminate session {}",
+                pid
+            ))),
+        };
+    }
+
+    if idle_in_transaction {
+        let sql = "SELECT pid FROM pg_stat_activity \
+                   WHERE state IN ('idle in transaction', 'idle in transaction (aborted)') \
+                   AND backend_type = 'client backend' AND pid <> pg_backend_pid() \
+                   AND pg_terminate_backend(pid)";
+        let pids: Vec<String> = run_sql(&info, &info.database, sql)?
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        if pids.is_empty() {
+            println!("No idle-in-transaction sessions on '{}'.", name);
+        } else {
+            println!(
+                "Terminated {} idle-in-transaction session(s): {}",
+                pids.len(),
+                pids.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn maintain(
+    name: String,
+    database: Option<String>,
+    analyze: bool,
+    full: bool,
+    jobs: u32,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let vacuumdb_path = find_instance_binary(&info, "vacuumdb")?;
+    ensure_runtime_libs_for_binary(&vacuumdb_path)?;
+
+    let mut command = std::process::Command::new(&vacuumdb_path);
+    match &database {
+        Some(db) => {
+            command
+                .arg("--dbname")
+                .arg(instance_uri_without_password(&info, db));
+        }
+        None => {
+            command
+                .arg("--all")
+                .arg("--maintenance-db")
+                .arg(instance_uri_without_password(&info, "postgres"));
+        }
+    }
+    if analyze {
+        command.arg("--analyze");
+    }
+    if full {
+        command.arg("--full");
+    }
+    if jobs > 1 {
+        command.arg("--jobs").arg(jobs.to_string());
+    }
+    // vacuumdb reconnects to every database; the password must not depend
+    // on it reusing the URI.
+    command.env("PGPASSWORD", &info.password);
+
+    let what = match (full, analyze) {
+        (true, true) => "VACUUM FULL (ANALYZE)",
+        (true, false) => "VACUUM FULL",
+        (false, true) => "VACUUM (ANALYZE)",
+        (false, false) => "VACUUM",
+    };
+    println!(
+        "Running {} on {} of instance '{}'{}...",
+        what,
+        database
+            .as_deref()
+            .map(|db| format!("database '{}'", db))
+            .unwrap_or_else(|| "all databases".to_string()),
+        name,
+        if jobs > 1 {
+            format!(" with {} jobs", jobs)
+        } else {
+            String::new()
+        }
+    );
+    let started = std::time::Instant::now();
+    let status = command.status()?;
+    if !status.success() {
+        return Err(CliError::Other(format!(
+            "vacuumdb failed for instance '{}'",
+            name
+        )));
+    }
+    println!(
+        "Maintenance of '{}' finished in {:.1}s.",
+        name,
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+fn reindex(
+    name: String,
+    database: Option<String>,
+    indexes: Vec<String>,
+    tables: Vec<String>,
+    all: bool,
+    concurrently: bool,
+    jobs: u32,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let reindexdb_path = find_instance_binary(&info, "reindexdb")?;
+    ensure_runtime_libs_for_binary(&reindexdb_path)?;
+
+    let database = database.unwrap_or_else(|| info.database.clone());
+    let mut command = std::process::Command::new(&reindexdb_path);
+    if all {
+        command
+            .arg("--all")
+            .arg("--maintenance-db")
+            .arg(instance_uri_without_password(&info, "postgres"));
+    } else {
+        command
+            .arg("--dbname")
+            .arg(instance_uri_without_password(&info, &database));
+    }
+    for index in &indexes {
+        command.arg("--index").arg(index);
+    }
+    for table in &tables {
+        command.arg("--table").arg(table);
+    }
+    if concurrently {
+        command.arg("--concurrently");
+    }
+    if jobs > 1 {
+        command.arg("--jobs").arg(jobs.to_string());
+    }
+    // Report each rebuilt index, unless that would list every system index.
+    if !indexes.is_empty() || !tables.is_empty() {
+        command.arg("--verbose");
+    }
+    command.env("PGPASSWORD", &info.password);
+
+    let target = if all {
+        "all databases".to_string()
+    } else if indexes.is_empty() && tables.is_empty() {
+        format!("database '{}'", database)
+    } else {
+        let mut targets: Vec<String> = indexes.iter().map(|i| format!("index {}", i)).collect();
+        targets.extend(tables.iter().map(|t| format!("table {}", t)));
+        targets.join(", ")
+    };
+    println!(
+        "Reindexing {} of instance '{}'{}...",
+        target,
+        name,
+        if concurrently { " concurrently" } else { "" }
+    );
+    let started = std::time::Instant::now();
+    let status = command.status()?;
+    if !status.success() {
+        return Err(CliError::Other(format!(
+            "reindexdb failed for instance '{}'",
+            name
+        )));
+    }
+    println!(
+        "Reindex of '{}' finished in {:.1}s.",
+        name,
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Table bloat estimate (the widely used ioguix/pgexperts query): expected
+/// pages from row count, average row width and fillfactor vs actual pages.
+/// Needs up-to-date statistics. Returns `relation|real bytes|bloat bytes`.
+const BLOAT_TABLES_SQL: &str = "
+SELECT schemaname || '.' || tblname, (bs * tblpages)::bigint,
+  CASE WHEN tblpages > 0 AND tblpages - est_tblpages_ff > 0 THEN ((tblpages - est_tblpages_ff) * bs)::bigint ELSE 0 END
+FROM (
+  SELECT ceil(reltuples / ((bs - page_hdr) * fillfactor / (tpl_size * 100))) + ceil(toasttuples / 4) AS est_tblpages_ff,
+    tblpages, bs, schemaname, tblname, is_na
+  FROM (
+    SELECT (4 + tpl_hdr_size + tpl_data_size + (2 * ma)
+        - CASE WHEN tpl_hdr_size % ma = 0 THEN ma ELSE tpl_hdr_size % ma END
+        - CASE WHEN ceil(tpl_data_size)::int % ma = 0 THEN ma ELSE ceil(tpl_data_size)::int % ma END
+      ) AS tpl_size, (heappages + toastpages) AS tblpages, reltuples, toasttuples, bs, page_hdr,
+      schemaname, tblname, fillfactor, is_na
+    FROM (
+      SELECT ns.nspname AS schemaname, tbl.relname AS tblname, tbl.reltuples,
+        tbl.relpages AS heappages, coalesce(toast.relpages, 0) AS toastpages,
+        coalesce(toast.reltuples, 0) AS toasttuples,
+        coalesce(substring(array_to_string(tbl.reloptions, ' ') FROM 'fillfactor=([0-9]+)')::smallint, 100) AS fillfactor,
+        current_setting('block_size')::numeric AS bs,
+        CASE WHEN version() ~ 'mingw32' OR version() ~ '64-bit|x86_64|ppc64|ia64|amd64|aarch64|arm64' THEN 8 ELSE 4 END AS ma,
+        24 AS page_hdr,
+        23 + CASE WHEN max(coalesce(s.null_frac, 0)) > 0 THEN (7 + count(s.attname)) / 8 ELSE 0::int END AS tpl_hdr_size,
+        sum((1 - coalesce(s.null_frac, 0)) * coalesce(s.avg_width, 0)) AS tpl_data_size,
+        bool_or(att.atttypid = 'pg_catalog.name'::regtype)
+          OR sum(CASE WHEN att.attnum > 0 THEN 1 ELSE 0 END) <> count(s.attname) AS is_na
+      FROM pg_attribute att
+        JOIN pg_class tbl ON att.attrelid = tbl.oid
+        JOIN pg_namespace ns ON ns.oid = tbl.relnamespace
+        LEFT JOIN pg_stats s ON s.schemaname = ns.nspname AND s.tablename = tbl.relname
+          AND s.inherited = false AND s.attname = att.attname
+        LEFT JOIN pg_class toast ON tbl.reltoastrelid = toast.oid
+      WHERE NOT att.attisdropped AND att.attnum > 0 AND tbl.relkind IN ('r', 'm')
+        AND ns.nspname NOT IN ('pg_catalog', 'information_schema')
+      GROUP BY 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+    ) s
+  ) s2
+) s3
+WHERE NOT is_na AND tblpages > 0";
+
+/// B-tree index bloat estimate (ioguix), same idea as `BLOAT_TABLES_SQL`.
+/// Other index types (e.g. pgvector's hnsw/ivfflat) are not estimated.
+const BLOAT_INDEXES_SQL: &str = "
+SELECT nspname || '.' || idxname, (bs * relpages)::bigint,
+  CASE WHEN relpages > est_pages_ff THEN (bs * (relpages - est_pages_ff))::bigint ELSE 0 END
+FROM (
+  SELECT coalesce(1 + ceil(reltuples / floor((bs - pageopqdata - pagehdr) * fillfactor / (100 * (4 + nulldatahdrwidth)::float))), 0) AS est_pages_ff,
+    bs, nspname, idxname, relpages, is_na
+  FROM (
+    SELECT bs, nspname, idxname, reltuples, relpages, fillfactor, pagehdr, pageopqdata, is_na,
+      (index_tuple_hdr_bm + maxalign
+        - CASE WHEN index_tuple_hdr_bm % maxalign = 0 THEN maxalign ELSE index_tuple_hdr_bm % maxalign END
+        + nulldatawidth + maxalign
+        - CASE WHEN nulldatawidth = 0 THEN 0 WHEN nulldatawidth::integer % maxalign = 0 THEN maxalign
+               ELSE nulldatawidth::integer % maxalign END
+      )::numeric AS nulldatahdrwidth
+    FROM (
+      SELECT n.nspname, i.idxname, i.reltuples, i.relpages, i.fillfactor,
+        current_setting('block_size')::numeric AS bs,
+        CASE WHEN version() ~ 'mingw32' OR version() ~ '64-bit|x86_64|ppc64|ia64|amd64|aarch64|arm64' THEN 8 ELSE 4 END AS maxalign,
+        24 AS pagehdr, 16 AS pageopqdata,
+        CASE WHEN max(coalesce(s.null_frac, 0)) = 0 THEN 8 ELSE 8 + ((32 + 8 - 1) / 8) END AS index_tuple_hdr_bm,
+        sum((1 - coalesce(s.null_frac, 0)) * coalesce(s.avg_width, 1024)) AS nulldatawidth,
+        max(CASE WHEN i.atttypid = 'pg_catalog.name'::regtype THEN 1 ELSE 0 END) > 0 AS is_na
+      FROM (
+        SELECT ct.relnamespace, ic.idxname, ic.reltuples, ic.relpages, ic.fillfactor,
+          coalesce(a1.attname, a2.attname) AS attname, coalesce(a1.atttypid, a2.atttypid) AS atttypid,
+          CASE WHEN a1.attnum IS NULL THEN ic.idxname ELSE ct.relname END AS attrelname
+        FROM (
+          SELECT ci.relname AS idxname, ci.reltuples, ci.relpages, i.indrelid AS tbloid, i.indexrelid AS idxoid,
+            coalesce(substring(array_to_string(ci.reloptions, ' ') FROM 'fillfactor=([0-9]+)')::smallint, 90) AS fillfactor,
+            string_to_array(textin(int2vectorout(i.indkey)), ' ')::int[] AS indkey,
+            generate_series(1, i.indnatts) AS attpos
+          FROM pg_index i
+          JOIN pg_class ci ON ci.oid = i.indexrelid
+          WHERE ci.relam = (SELECT oid FROM pg_am WHERE amname = 'btree') AND ci.relpages > 0
+        ) ic
+        JOIN pg_class ct ON ct.oid = ic.tbloid
+        LEFT JOIN pg_attribute a1 ON ic.indkey[ic.attpos] <> 0 AND a1.attrelid = ic.tbloid AND a1.attnum = ic.indkey[ic.attpos]
+        LEFT JOIN pg_attribute a2 ON ic.indkey[ic.attpos] = 0 AND a2.attrelid = ic.idxoid AND a2.attnum = ic.attpos
+      ) i
+      JOIN pg_namespace n ON n.oid = i.relnamespace
+      JOIN pg_stats s ON s.schemaname = n.nspname AND s.tablename = i.attrelname AND s.attname = i.attname
+      GROUP BY 1, 2, 3, 4, 5
+    ) rows_data_stats
+  ) rows_hdr_pdg_stats
+) relation_stats
+WHERE NOT is_na AND nspname NOT IN ('pg_catalog', 'information_schema')";
+
+/// Bloat below this size is never flagged, whatever the percentage: small
+/// relations always look bloated and are cheap to leave alone.
+const BLOAT_MIN_FLAG_BYTES: u64 = 1024 * 1024;
+
+#[derive(Serialize)]
+struct BloatEntry {
+    database: String,
+    /// "table" or "index"
+    kind: &'static str,
+    relation: String,
+    size_bytes: u64,
+    bloat_bytes: u64,
+    bloat_percent: f64,
+    /// Suggested fix when the bloat crosses the threshold
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recommendation: Option<String>,
+}
+
+fn bloat(
+    name: String,
+    database: Option<String>,
+    threshold: f64,
+    output_format: OutputFormat,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let databases: Vec<String> = match database {
+        Some(db) => vec![db],
+        None => run_sql(
+            &info,
+            "postgres",
+            "SELECT datname FROM pg_database WHERE datallowconn AND NOT datistemplate ORDER BY datname;",
+        )?
+        .lines()
+        .map(|l| l.to_string())
+        .collect(),
+    };
+
+    let mut entries = Vec::new();
+    for db in &databases {
+        for (kind, sql) in [("table", BLOAT_TABLES_SQL), ("index", BLOAT_INDEXES_SQL)] {
+            for line in run_sql(&info, db, sql)?.lines() {
+                let fields: Vec<&str> = line.split('|').collect();
+                let [relation, size, bloat] = fields[..] else {
+                    continue;
+                };
+                let (Ok(size_bytes), Ok(bloat_bytes)) = (size.parse::<u64>(), bloat.parse::<u64>())
+                else {
+                    continue;
+                };
+                if bloat_bytes == 0 {
+                    continue;
+                }
+                let bloat_percent = 100.0 * bloat_bytes as f64 / size_bytes.max(1) as f64;
+                let recommendation = (bloat_percent >= threshold
+                    && bloat_bytes >= BLOAT_MIN_FLAG_BYTES)
+                    .then(|| match kind {
+                        "table" => format!(
+                            "VACUUM FULL {} (or: pg0 maintain --full --database {})",
+                            relation, db
+                        ),
+                        _ => format!(
+                            "pg0 reindex --database {} --index {} --concurrently",
+                            db, relation
+                        ),
+                    });
+                entries.push(BloatEntry {
+                    database: db.clone(),
+                    kind,
+                    relation: relation.to_string(),
+                    size_bytes,
+                    bloat_bytes,
+                    bloat_percent,
+                    recommendation,
+                });
+            }
+        }
+    }
+    entries.sort_by_key(|e| std::cmp::Reverse(e.bloat_bytes));
+
+    match output_format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        }
+        OutputFormat::Text => {
+            if entries.is_empty() {
+                println!("No measurable bloat on '{}'.", name);
+            } else {
+                println!(
+                    "{:<12} {:<6} {:<40} {:>10} {:>10} {:>6}",
+                    "DATABASE", "TYPE", "RELATION", "SIZE", "BLOAT", "%"
+                );
+                for entry in &entries {
+                    println!(
+                        "{:<12} {:<6} {:<40} {:>10} {:>10} {:>5.0}%{}",
+                        entry.database,
+                        entry.kind,
+                        entry.relation,
+                        format_size(entry.size_bytes),
+                        format_size(entry.bloat_bytes),
+                        entry.bloat_percent,
+                        if entry.recommendation.is_some() {
+                            "  *"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+                let flagged: Vec<&BloatEntry> = entries
+                    .iter()
+                    .filter(|e| e.recommendation.is_some())
+                    .collect();
+                println!();
+                if flagged.is_empty() {
+                    println!("Nothing above {:.0}% bloat; no action needed.", threshold);
+                } else {
+                    println!("* Candidates (over {:.0}% bloat):", threshold);
+                    for entry in flagged {
+                        println!("  {}", entry.recommendation.as_deref().unwrap_or_default());
+                    }
+                }
+            }
+            println!();
+            println!("Estimates use planner statistics; run 'pg0 maintain --analyze' first for fresh numbers.");
+        }
+    }
+    Ok(())
+}
+
+fn verify(
+    name: String,
+    database: Option<String>,
+    heapallindexed: bool,
+    jobs: u32,
+) -> Result<(), CliError> {
+    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    if info.archive.is_some() {
+        return Err(CliError::Other(format!(
+            "Instance '{}' is archived. Use 'pg0 unarchive --name {}' to restore it first.",
+            name, name
+        )));
+    }
+    if is_instance_running(&info) {
+        verify_online(&name, &info, database, heapallindexed, jobs)
+    } else {
+        verify_offline(&name, &info)
+    }
+}
+
+/// Check tables and B-tree indexes of a running instance with pg_amcheck,
+/// installing the amcheck extension where it's missing.
+fn verify_online(
+    name: &str,
+    info: &InstanceInfo,
+    database: Option<String>,
+    heapallindexed: bool,
+    jobs: u32,
+) -> Result<(), CliError> {
+    let pg_amcheck_path = find_instance_binary(info, "pg_amcheck").map_err(|_| {
+        CliError::Other(format!(
+            "pg_amcheck is not available for PostgreSQL {} (it ships with 14 and later). Stop the instance to verify page checksums instead.",
+            info.version
+        ))
+    })?;
+    ensure_runtime_libs_for_binary(&pg_amcheck_path)?;
+
+    let mut command = std::process::Command::new(&pg_amcheck_path);
+    command
+        .args([
+            "--host",
+            &instance_host(info),
+            "--port",
+            &info.port.to_string(),
+            "--username",
+            &info.username,
+        ])
+        .args(["--install-missing", "--no-password"]);
+    match &database {
+        Some(db) => {
+            command.arg("--database").arg(db);
+        }
+        None => {
+            command.arg("--all").arg("--maintenance-db").arg("postgres");
+        }
+    }
+    if heapallindexed {
+        command.arg("--heapallindexed");
+    }
+    if jobs > 1 {
+        command.arg("--jobs").arg(jobs.to_string());
+    }
+    command.env("PGPASSWORD", &info.password);
+
+    println!(
+        "Checking tables and indexes of {} of instance '{}' with amcheck...",
+        database
+            .as_deref()
+            .map(|db| format!("database '{}'", db))
+            .unwrap_or_else(|| "all databases".to_string()),
+        name
+    );
+    let started = std::time::Instant::now();
+    let status = command.status()?;
+    // pg_amcheck exits with 2 when it found corruption, 1 on other errors.
+    match status.code() {
+        Some(0) => {
+            println!("No corruption found ({:.1}s).", started.elapsed().as_secs_f64());
+            println!("Page checksums are verified offline: run 'pg0 stop --name {}' and 'pg0 verify --name {}'.", name, name);
+            Ok(())
+        }
+        Some(2) => Err(CliError::Other(format!(
+            "amcheck found corruption in instance '{}' (see above). Restore from a backup or rebuild affected indexes with 'pg0 reindex'.",
+            name
+        ))),
+        _ => Err(CliError::Other(format!("pg_amcheck failed for instance '{}'", name))),
+    }
+}
+
+/// Verify the page checksums of a stopped instance with pg_checksums.
+fn verify_offline(name: &str, info: &InstanceInfo) -> Result<(), CliError> {
+    let pg_controldata_path = find_instance_binary(info, "pg_controldata")?;
+    ensure_runtime_libs_for_binary(&pg_controldata_path)?;
+    let output = std::process::Command::new(&pg_controldata_path)
+        .arg("-D")
+        .arg(&info.data_dir)
+        .env("LC_ALL", "C")
+        .output()?;
+    if !output.status.success() {
+        return Err(CliError::Other(format!(
+            "pg_controldata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let control = String::from_utf8_lossy(&output.stdout);
+    let checksums_enabled = control
+        .lines()
+        .find_map(|line| line.strip_prefix("Data page checksum version:"))
+        .is_some_and(|version| version.trim() != "0");
+    if !checksums_enabled {
+        return Err(CliError::Other(format!(
+            "Instance '{}' was initialized without data checksums, so its pages can't be verified offline. Start it and run 'pg0 verify --name {}' to check it with amcheck.",
+            name, name
+        )));
+    }
+
+    let pg_checksums_path = find_instance_binary(info, "pg_checksums")?;
+    ensure_runtime_libs_for_binary(&pg_checksums_path)?;
+    println!("Verifying page checksums of stopped instance '{}'...", name);
+    let started = std::time::Instant::now();
+    let status = std::process::Command::new(&pg_checksums_path)
+        .arg("--check")
+        .arg("--pgdata")
+        .arg(&info.data_dir)
+        .status()?;
+    if !status.success() {
+        return Err(CliError::Other(format!(
+            "pg_checksums reported errors for instance '{}' (see above). Restore from a backup if pages are corrupt.",
+            name
+        )));
+    }
+    println!(
+        "Page checksums verified ({:.1}s).",
+        started.elapsed().as_secs_f64()
+    );
+    println!(
+        "Indexes are verified online: start the instance and run 'pg0 verify --name {}' again.",
+        name
+    );
+    Ok(())
+}
+
+/// One row of `pg0 stats statements`.
+#[derive(Serialize)]
+struct StatementStats {
+    database: String,
+    calls: u64,
+    total_ms: f64,
+    mean_ms: f64,
+    rows: u64,
+    /// Share of block reads served from shared buffers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_hit_percent: Option<f64>,
+    query: String,
+}
+
+/// Make sure pg_stat_statements is preloaded (restarting the instance if it
+/// has to be added to shared_preload_libraries) and created in `database`.
+/// Returns the instance as it is after a possible restart.
+fn ensure_pg_stat_statements(
+    name: &str,
+    info: InstanceInfo,
+    database: &str,
+) -> Result<InstanceInfo, CliError> {
+    let info = ensure_preloaded(name, info, "pg_stat_statements", &[], false)?;
+    run_sql(
+        &info,
+        database,
+        "CREATE EXTENSION IF NOT EXISTS pg_stat_statements;",
+    )?;
+    Ok(info)
+}
+
+fn stats_statements(
+    name: String,
+    database: Option<String>,
+    order_by: StatementOrder,
+    limit: usize,
+    reset: bool,
+    output_format: OutputFormat,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let extension_db = info.database.clone();
+    let info = ensure_pg_stat_statements(&name, info, &extension_db)?;
+
+    if reset {
+        run_sql(&info, &extension_db, "SELECT pg_stat_statements_reset();")?;
+        println!("Statement statistics reset for '{}'.", name);
+        return Ok(());
+    }
+
+    let database_filter = match &database {
+        Some(db) => format!("AND d.datname = {}", quote_literal(db)),
+        None => String::new(),
+    };
+    let sql = format!(
+        "SELECT d.datname, s.calls, round(s.total_exec_time::numeric, 3), round(s.mean_exec_time::numeric, 3), \
+         s.rows, coalesce(round(100.0 * s.shared_blks_hit / nullif(s.shared_blks_hit + s.shared_blks_read, 0), 1)::text, ''), \
+         regexp_replace(s.query, '\\s+', ' ', 'g') \
+         FROM pg_stat_statements s JOIN pg_database d ON d.oid = s.dbid \
+         WHERE s.query NOT ILIKE '%pg_stat_statements%' {} \
+         ORDER BY s.{} DESC LIMIT {}",
+        database_filter,
+        order_by.column(),
+        limit
+    );
+    let statements: Vec<StatementStats> = run_sql(&info, &extension_db, &sql)?
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(7, '|').collect();
+            if fields.len() != 7 {
+                return None;
+            }
+            Some(StatementStats {
+                database: fields[0].to_string(),
+                calls: fields[1].parse().ok()?,
+                total_ms: fields[2].parse().ok()?,
+                mean_ms: fields[3].parse().ok()?,
+                rows: fields[4].parse().ok()?,
+                cache_hit_percent: fields[5].parse().ok(),
+                query: fields[6].to_string(),
+            })
+        })
+        .collect();
+
+    match output_format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&statements)?);
+        }
+        OutputFormat::Text => {
+            if statements.is_empty() {
+                println!(
+                    "No statements recorded yet on '{}'. Run your workload, then try again.",
+                    name
+                );
+                return Ok(());
+            }
+            println!(
+                "{:>8} {:>12} {:>10} {:>9} {:>6}  {:<12} QUERY",
+                "CALLS", "TOTAL(ms)", "MEAN(ms)", "ROWS", "HIT%", "DATABASE"
+            );
+            for statement in &statements {
+                let mut query = statement.query.clone();
+                if query.chars().count() > 80 {
+                    query = format!("{}...", query.chars().take(77).collect::<String>());
+                }
+                println!(
+                    "{:>8} {:>12.1} {:>10.2} {:>9} {:>6}  {:<12} {}",
+                    statement.calls,
+                    statement.total_ms,
+                    statement.mean_ms,
+                    statement.rows,
+                    statement
+                        .cache_hit_percent
+                        .map(|p| format!("{:.1}", p))
+                        .unwrap_or_else(|| "-".to_string()),
+                    statement.database,
+                    query
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A session involved in a lock wait, as shown by `pg0 locks`.
+#[derive(Serialize)]
+struct LockSession {
+    pid: u32,
+    /// Sessions holding (or queued ahead for) the lock this one waits on
+    blocked_by: Vec<u32>,
+    user: String,
+    database: String,
+    state: String,
+    /// The lock being waited for, e.g. "RowExclusiveLock on public.accounts"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waiting_for: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait_seconds: Option<f64>,
+    transaction_seconds: f64,
+    query: String,
+}
+
+fn lock_sessions(info: &InstanceInfo) -> Result<Vec<LockSession>, CliError> {
+    // waitstart only exists from PostgreSQL 14, so read it through to_jsonb.
+    // Relation names only resolve in the current database; elsewhere the oid
+    // is shown.
+    let sql = "WITH waits AS ( \
+                 SELECT pid, pg_blocking_pids(pid) AS blockers FROM pg_stat_activity \
+                 WHERE cardinality(pg_blocking_pids(pid)) > 0) \
+               SELECT a.pid, array_to_string(coalesce(w.blockers, '{}'), ','), \
+                 coalesce(a.usename, ''), coalesce(a.datname, ''), coalesce(a.state, ''), \
+                 coalesce(l.mode, ''), coalesce(l.locktype, ''), \
+                 coalesce(CASE WHEN l.database = (SELECT oid FROM pg_database WHERE datname = current_database()) \
+                   THEN l.relation::regclass::text ELSE l.relation::text END, ''), \
+                 coalesce(l.transactionid::text, ''), \
+                 CASE WHEN l.pid IS NOT NULL THEN extract(epoch FROM now() - \
+                   coalesce((to_jsonb(l) ->> 'waitstart')::timestamptz, a.query_start))::text ELSE '' END, \
+                 coalesce(extract(epoch FROM now() - coalesce(a.xact_start, a.query_start)), 0), \
+                 regexp_replace(coalesce(a.query, ''), '\\s+', ' ', 'g') \
+               FROM pg_stat_activity a \
+               LEFT JOIN waits w ON w.pid = a.pid \
+               LEFT JOIN LATERAL (SELECT * FROM pg_locks l WHERE l.pid = a.pid AND NOT l.granted LIMIT 1) l ON true \
+               WHERE a.pid IN (SELECT pid FROM waits UNION SELECT unnest(blockers) FROM waits) \
+               ORDER BY a.pid";
+    let output = run_sql(info, &info.database, sql)?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(12, '|').collect();
+            if fields.len() != 12 {
+                return None;
+            }
+            let (mode, locktype, relation, xid) = (fields[5], fields[6], fields[7], fields[8]);
+            let waiting_for = match (mode, locktype) {
+                ("", _) => None,
+                _ if !relation.is_empty() => Some(format!("{} on {}", mode, relation)),
+                (_, "transactionid") => Some(format!("{} on transaction {}", mode, xid)),
+                _ => Some(format!("{} ({})", mode, locktype)),
+            };
+            Some(LockSession {
+                pid: fields[0].parse().ok()?,
+                blocked_by: fields[1]
+                    .split(',')
+                    .filter_map(|p| p.parse().ok())
+                    .collect(),
+                user: fields[2].to_string(),
+                database: fields[3].to_string(),
+                state: fields[4].to_string(),
+                waiting_for,
+                wait_seconds: fields[9].parse().ok(),
+                transaction_seconds: fields[10].parse().unwrap_or(0.0),
+                query: fields[11].to_string(),
+            })
+        })
+        .collect())
+}
+
+fn print_lock_tree(
+    sessions: &[LockSession],
+    pid: u32,
+    prefix: &str,
+    connector: &str,
+    printed: &mut std::collections::HashSet<u32>,
+) {
+    let Some(session) = sessions.iter().find(|s| s.pid == pid) else {
+        return;
+    };
+    let mut query = session.query.clone();
+    if query.chars().count() > 60 {
+        query = format!("{}...", query.chars().take(57).collect::<String>());
+    }
+    let detail = match (&session.waiting_for, session.wait_seconds) {
+        (Some(lock), Some(wait)) => format!("waiting {} for {}", format_top_duration(wait), lock),
+        (Some(lock), None) => format!("waiting for {}", lock),
+        _ => format!(
+            "{}, transaction open {}",
+            session.state,
+            format_top_duration(session.transaction_seconds)
+        ),
+    };
+    if !printed.insert(pid) {
+        // Waits on several sessions, or closes a lock cycle (deadlock).
+        println!("{}{}{} (shown above)", prefix, connector, pid);
+        return;
+    }
+    println!(
+        "{}{}{} {}@{}  {}  {}",
+        prefix, connector, pid, session.user, session.database, detail, query
+    );
+
+    let waiters: Vec<u32> = sessions
+        .iter()
+        .filter(|s| s.blocked_by.contains(&pid))
+        .map(|s| s.pid)
+        .collect();
+    let child_prefix = match connector {
+        "" => prefix.to_string(),
+        "└─ " => format!("{}   ", prefix),
+        _ => format!("{}│  ", prefix),
+    };
+    for (i, waiter) in waiters.iter().enumerate() {
+        let connector = if i + 1 == waiters.len() {
+            "└─ "
+        } else {
+            "├─ "
+        };
+        print_lock_tree(sessions, *waiter, &child_prefix, connector, printed);
+    }
+}
+
+fn locks(name: String, output_format: OutputFormat) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let sessions = lock_sessions(&info)?;
+
+    match output_format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&sessions)?);
+        }
+        OutputFormat::Text => {
+            if sessions.is_empty() {
+                println!("No lock waits on '{}'.", name);
+                return Ok(());
+            }
+            let waiting = sessions.iter().filter(|s| !s.blocked_by.is_empty()).count();
+            println!("{} session(s) waiting for locks on '{}':", waiting, name);
+            println!();
+
+            // Roots are sessions that block others without waiting themselves.
+            // Sessions that only wait on each other (a deadlock, until
+            // PostgreSQL's deadlock detector breaks it) are printed afterwards.
+            let mut printed = std::collections::HashSet::new();
+            let roots: Vec<u32> = sessions
+                .iter()
+                .filter(|s| s.blocked_by.is_empty())
+                .map(|s| s.pid)
+                .collect();
+            for pid in roots.iter().chain(sessions.iter().map(|s| &s.pid)) {
+                if !printed.contains(pid) {
+                    print_lock_tree(&sessions, *pid, "", "", &mut printed);
+                    println!();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Busiest states first: running queries, then open transactions, then idle.
+fn top_state_rank(state: &str) -> u8 {
+    match state {
+        "active" => 0,
+        "idle in transaction (aborted)" => 1,
+        "idle in transaction" => 2,
+        "fastpath function call" => 3,
+        "idle" => 5,
+        _ => 4,
+    }
+}
+
+fn sort_top_backends(backends: &mut [Session], sort: TopSort) {
+    backends.sort_by(|a, b| {
+        let by_duration = b.duration_seconds.total_cmp(&a.duration_seconds);
+        match sort {
+            TopSort::Duration => by_duration,
+            TopSort::State => top_state_rank(&a.state)
+                .cmp(&top_state_rank(&b.state))
+                .then(by_duration),
+        }
+    });
+}
+
+fn format_top_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0);
+    if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else if seconds < 3600.0 {
+        format!("{}m{:02}s", seconds as u64 / 60, seconds as u64 % 60)
+    } else {
+        format!(
+            "{}h{:02}m",
+            seconds as u64 / 3600,
+            seconds as u64 % 3600 / 60
+        )
+    }
+}
+
+fn top_draw(
+    frame: &mut ratatui::Frame,
+    name: &str,
+    backends: &[Session],
+    table_state: &mut ratatui::widgets::TableState,
+    sort: TopSort,
+    status: &str,
+) {
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::widgets::{Block, Cell, Paragraph, Row, Table};
+
+    let [table_area, status_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+
+    let active = backends.iter().filter(|b| b.state == "active").count();
+    let title = format!(
+        " pg0 top: {} | {} connections, {} active | sorted by {} ",
+        name,
+        backends.len(),
+        active,
+        if sort == TopSort::Duration {
+            "duration"
+        } else {
+            "state"
+        }
+    );
+    let rows = backends.iter().map(|backend| {
+        let color = match backend.state.as_str() {
+            "active" if backend.duration_seconds >= 10.0 => Color::Red,
+            "active" => Color::Green,
+            "idle in transaction" | "idle in transaction (aborted)" => Color::Yellow,
+            _ => Color::DarkGray,
+        };
+        Row::new(vec![
+            Cell::from(backend.pid.to_string()),
+            Cell::from(backend.user.clone()),
+            Cell::from(backend.database.clone()),
+            Cell::from(backend.state.clone()).style(Style::default().fg(color)),
+            Cell::from(format_top_duration(backend.duration_seconds)),
+            Cell::from(backend.wait_event.clone()),
+            Cell::from(backend.query.clone()),
+        ])
+    });
+    let header = Row::new(["PID", "USER", "DATABASE", "STATE", "TIME", "WAIT", "QUERY"])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(8),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(20),
+            Constraint::Length(8),
+            Constraint::Length(20),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(header)
+    .block(Block::bordered().title(title))
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, table_area, table_state);
+
+    let help = "↑/↓ select  c cancel  t terminate  o sort  q quit";
+    let line = if status.is_empty() {
+        help.to_string()
+    } else {
+        format!("{}  |  {}", status, help)
+    };
+    frame.render_widget(
+        Paragraph::new(line).style(Style::default().fg(Color::Cyan)),
+        status_area,
+    );
+}
+
+fn top(name: String) -> Result<(), CliError> {
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+
+    let info = load_running_instance(&name)?;
+    let mut backends = list_sessions(&info)?;
+    let mut sort = TopSort::Duration;
+    sort_top_backends(&mut backends, sort);
+
+    let mut terminal = ratatui::try_init()?;
+    let mut table_state = ratatui::widgets::TableState::default();
+    let mut status = String::new();
+    let mut last_refresh = std::time::Instant::now();
+
+    let result = loop {
+        // Keep the selection on the same backend across refreshes.
+        let selected_pid = table_state
+            .selected()
+            .and_then(|i| backends.get(i))
+            .map(|b| b.pid);
+        if last_refresh.elapsed() >= std::time::Duration::from_secs(1) {
+            match list_sessions(&info) {
+                Ok(latest) => backends = latest,
+                Err(e) => status = format!("Refresh failed: {}", e),
+            }
+            sort_top_backends(&mut backends, sort);
+            last_refresh = std::time::Instant::now();
+        }
+        let position = selected_pid.and_then(|pid| backends.iter().position(|b| b.pid == pid));
+        table_state.select(position.or(if backends.is_empty() { None } else { Some(0) }));
+
+        if let Err(e) = terminal
+            .draw(|frame| top_draw(frame, &name, &backends, &mut table_state, sort, &status))
+        {
+            break Err(e.into());
+        }
+
+        match event::poll(std::time::Duration::from_millis(200)) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => break Err(e.into()),
+        }
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e.into()),
+        };
+
+        let selected = table_state
+            .selected()
+            .and_then(|i| backends.get(i))
+            .map(|b| b.pid);
+        let signal = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => {
+                table_state.select_next();
+                None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                table_state.select_previous();
+                None
+            }
+            KeyCode::Char('o') => {
+                sort = if sort == TopSort::Duration {
+                    TopSort::State
+                } else {
+                    TopSort::Duration
+                };
+                sort_top_backends(&mut backends, sort);
+                None
+            }
+            KeyCode::Char('c') => Some(("pg_cancel_backend", "Cancelled query of")),
+            KeyCode::Char('t') => Some(("pg_terminate_backend", "Terminated")),
+            _ => None,
+        };
+        if let (Some((function, done)), Some(pid)) = (signal, selected) {
+            let sql = format!("SELECT {}({})", function, pid);
+            status = match run_sql(&info, &info.database, &sql) {
+                Ok(result) if result.trim() == "t" => format!("{} backend {}", done, pid),
+                Ok(_) => format!("Backend {} is gone or not permitted", pid),
+                Err(e) => format!("Error: {}", e),
+            };
+            // Show the effect right away instead of on the next tick.
+            last_refresh = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+/// Keys after which the instance list is reloaded immediately.
+fn action_refresh(code: &ratatui::crossterm::event::KeyCode) -> bool {
+    use ratatui::crossterm::event::KeyCode;
+    matches!(code, KeyCode::Char('s' | 'x' | 'p' | 'r') | KeyCode::Enter)
+}
+
+/// Outcome of a single `pg0 doctor` check.
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Serialize)]
+struct DoctorCheck {
+    check: String,
+    status: CheckStatus,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fix: Option<String>,
+}
+
+impl DoctorCheck {
+    fn ok(check: impl Into<String>, message: impl Into<String>) -> Self {
+        DoctorCheck {
+            check: check.into(),
+            status: CheckStatus::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn warning(
+        check: impl Into<String>,
+        message: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        DoctorCheck {
+            check: check.into(),
+            status: CheckStatus::Warning,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn error(check: impl Into<String>, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        DoctorCheck {
+            check: check.into(),
+            status: CheckStatus::Error,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Run a shell snippet and return its trimmed stdout, if it succeeded.
+#[cfg(unix)]
+fn shell_output(script: &str) -> Option<String> {
+    let output = std::process::Command::new("sh")
+        .args(["-c", script])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Checks that don't depend on any instance: platform, user, tzdata,
+/// locale, file descriptor limits and the default port.
+fn doctor_environment_checks() -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+    let platform = format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+    checks.push(DoctorCheck::ok(
+        "platform",
+        format!(
+            "{} (pg0 {}, PostgreSQL {} bundled)",
+            platform,
+            env!("CARGO_PKG_VERSION"),
+            env!("PG_VERSION")
+        ),
+    ));
+
+    #[cfg(unix)]
+    {
+        if shell_output("id -u").as_deref() == Some("0") {
+            checks.push(DoctorCheck::error(
+                "user",
+                "running as root; PostgreSQL refuses to start as root",
+                "run pg0 as a regular user, e.g. `useradd -m pguser && su - pguser -c 'pg0 start'`",
+            ));
+        } else {
+            checks.push(DoctorCheck::ok("user", "not running as root"));
+        }
+
+        // pg0 pins timezone/log_timezone to UTC, so tzdata only matters
+        // once a different timezone is configured.
+        if Path::new("/usr/share/zoneinfo").is_dir() {
+            checks.push(DoctorCheck::ok("tzdata", "/usr/share/zoneinfo present"));
+        } else {
+            checks.push(DoctorCheck::warning(
+                "tzdata",
+                "/usr/share/zoneinfo not found; instances run in UTC and setting another timezone will fail",
+                "install tzdata (apt-get install tzdata / apk add tzdata); on NixOS this is expected",
+            ));
+        }
+
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty());
+        match locale {
+            None => checks.push(DoctorCheck::ok("locale", "no locale set, initdb uses C")),
+            Some(locale) if locale == "C" || locale == "POSIX" => {
+                checks.push(DoctorCheck::ok("locale", locale));
+            }
+            Some(locale) => {
+                // `locale -a` lists "en_US.utf8" for "en_US.UTF-8".
+                let normalize = |l: &str| l.to_lowercase().replace('-', "");
+                let available = shell_output("locale -a 2>/dev/null").unwrap_or_default();
+                if available.is_empty()
+                    || available
+                        .lines()
+                        .any(|l| normalize(l) == normalize(&locale))
+                {
+                    checks.push(DoctorCheck::ok("locale", locale));
+                } else {
+                    checks.push(DoctorCheck::warning(
+                        "locale",
+                        format!("locale '{}' is not installed; initdb will fail to create new instances", locale),
+                        "export LC_ALL=C.UTF-8, or generate the locale (e.g. locale-gen / apt-get install locales)",
+                    ));
+                }
+            }
+        }
+
+        match shell_output("ulimit -n").and_then(|n| n.parse::<u64>().ok()) {
+            Some(limit) if limit < 1024 => checks.push(DoctorCheck::warning(
+                "open files",
+                format!("open file limit is {}; PostgreSQL may run out of file descriptors", limit),
+                "raise it with `ulimit -n 4096` (or in /etc/security/limits.conf / launchctl limit maxfiles)",
+            )),
+            Some(limit) => checks.push(DoctorCheck::ok("open files", format!("limit {}", limit))),
+            None => {}
+        }
+    }
+
+    // Only source-built extensions need a toolchain
+    let compiler = C_COMPILERS.iter().find(|cc| has_program(cc));
+    let mut missing: Vec<&str> = Vec::new();
+    if compiler.is_none() {
+        missing.push("a C compiler");
+    }
+    if !has_program("make") {
+        missing.push("make");
+    }
+    if !missing.is_empty() {
+        checks.push(DoctorCheck::warning(
+            "build tools",
+            format!(
+                "{} not found; extensions built from source (install-extension --build-from-source, timescaledb, pg_cron, ...) can't be installed",
+                missing.join(" and ")
+            ),
+            if cfg!(target_os = "macos") {
+                "xcode-select --install"
+            } else {
+                "apt-get install build-essential (or your distribution's equivalent)"
+            },
+        ));
+    } else if !has_program("cmake") {
+        checks.push(DoctorCheck::warning(
+            "build tools",
+            "cmake not found; TimescaleDB, which builds with cmake, can't be installed",
+            if cfg!(target_os = "macos") {
+                "brew install cmake"
+            } else {
+                "apt-get install cmake"
+            },
+        ));
+    } else {
+        checks.push(DoctorCheck::ok(
+            "build tools",
+            format!(
+                "{}, make and cmake found",
+                compiler.copied().unwrap_or("cc")
+            ),
+        ));
+    }
+
+    // Downloaded PostgreSQL archives are extracted again when an
+    // installation is missing
+    if let Ok(downloads_dir) = downloads_dir() {
+        for version in cached_postgresql_versions(&downloads_dir) {
+            let check = format!("PostgreSQL {} download", version);
+            match verified_postgresql_archive(&downloads_dir, &version.to_string()) {
+                Ok(_) => checks.push(DoctorCheck::ok(check, "matches its recorded SHA256")),
+                Err(e) => checks.push(DoctorCheck::error(
+                    check,
+                    e.to_string(),
+                    format!(
+                        "rm {}",
+                        downloads_dir
+                            .join(postgresql_archive_name(&version.to_string()))
+                            .display()
+                    ),
+                )),
+            }
+        }
+    }
+
+    if is_port_available(5432) {
+        checks.push(DoctorCheck::ok("port 5432", "available"));
+    } else {
+        checks.push(DoctorCheck::ok(
+            "port 5432",
+            "in use; new instances will pick the next free port unless --port is given",
+        ));
+    }
+    checks
+}
+
+/// Checks for one instance directory: a readable instance.json, leftover
+/// postmaster.pid files, port conflicts and binaries matching the data.
+fn doctor_instance_checks(
+    name: &str,
+    claimed_ports: &mut HashMap<u16, String>,
+) -> Result<Vec<DoctorCheck>, CliError> {
+    let label = format!("instance {}", name);
+    let state_file = get_state_file(name)?;
+    let mut checks = Vec::new();
+
+    let info = match fs::read_to_string(&state_file)
+        .map_err(CliError::from)
+        .and_then(|content| parse_instance_state(&content))
+    {
+        Ok(info) => info,
+        Err(e) => {
+            checks.push(DoctorCheck::error(
+                label,
+                format!("{} is unreadable: {}", state_file.display(), e),
+                format!(
+                    "if the data directory is intact, move instance.json aside and run `pg0 start --name {}`; otherwise `pg0 drop --name {}`",
+                    name, name
+                ),
+            ));
+            return Ok(checks);
+        }
+    };
+
+    if info.archive.is_some() {
+        checks.push(DoctorCheck::ok(label, "archived"));
+        return Ok(checks);
+    }
+    if !info.data_dir.join("PG_VERSION").exists() {
+        checks.push(DoctorCheck::error(
+            label,
+            format!(
+                "data directory {} is missing or not initialized",
+                info.data_dir.display()
+            ),
+            format!(
+                "restore the directory, or remove the instance with `pg0 drop --name {}`",
+                name
+            ),
+        ));
+        return Ok(checks);
+    }
+
+    let running = is_instance_running(&info);
+    let mut problems = false;
+
+    if !running && info.data_dir.join("postmaster.pid").exists() {
+        problems = true;
+        checks.push(DoctorCheck::warning(
+            label.clone(),
+            format!(
+                "leftover postmaster.pid from process {} that is no longer running",
+                info.pid
+            ),
+            format!("`pg0 start --name {}` removes it automatically", name),
+        ));
+    }
+
+    let mut ports = vec![("port", info.port)];
+    ports.extend(info.pool.as_ref().map(|p| ("pool port", p.port)));
+    ports.extend(info.metrics.as_ref().map(|m| ("metrics port", m.port)));
+    ports.extend(info.health.as_ref().map(|h| ("health port", h.port)));
+    for (kind, port) in ports {
+        if let Some(other) = claimed_ports.insert(port, name.to_string()) {
+            problems = true;
+            checks.push(DoctorCheck::warning(
+                label.clone(),
+                format!("{} {} is also used by instance '{}'", kind, port, other),
+                "only one of them can run at a time; restart one of them with a different --port",
+            ));
+        } else if !running && !is_port_available(port) {
+            problems = true;
+            checks.push(DoctorCheck::warning(
+                label.clone(),
+                format!("{} {} is taken by another process", kind, port),
+                format!("stop the other process, or start this instance with `pg0 start --name {} --port <free port>`", name),
+            ));
+        }
+    }
+
+    let data_major = fs::read_to_string(info.data_dir.join("PG_VERSION"))?
+        .trim()
+        .to_string();
+    let instance_major = info.version.split('.').next().unwrap_or_default();
+    if data_major != instance_major {
+        problems = true;
+        checks.push(DoctorCheck::error(
+            label.clone(),
+            format!(
+                "data directory was initialized by PostgreSQL {} but the instance records {}",
+                data_major, info.version
+            ),
+            format!(
+                "start it with the matching binaries: `pg0 start --name {} --version {}`",
+                name, data_major
+            ),
+        ));
+    }
+
+    let version_dir = info.installation_dir.join(&info.version);
+    match find_pg_binary(&version_dir, "postgres") {
+        Err(_) => {
+            problems = true;
+            checks.push(DoctorCheck::warning(
+                label.clone(),
+                format!(
+                    "PostgreSQL {} is not installed in {}",
+                    info.version,
+                    info.installation_dir.display()
+                ),
+                format!(
+                    "`pg0 start --name {}` extracts (or downloads) it again",
+                    name
+                ),
+            ));
+        }
+        Ok(postgres) => {
+            let _ = ensure_runtime_libs_for_binary(&postgres);
+            let reported = std::process::Command::new(&postgres)
+                .arg("--version")
+                .output()
+                .ok()
+                .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+                .unwrap_or_default();
+            // "postgres (PostgreSQL) 18.1", possibly followed by a distro suffix
+            let binary_version = reported
+                .split("(PostgreSQL) ")
+                .nth(1)
+                .and_then(|rest| rest.split_whitespace().next())
+                .unwrap_or_default();
+            if binary_version.is_empty() {
+                problems = true;
+                checks.push(DoctorCheck::error(
+                    label.clone(),
+                    format!("{} does not run", postgres.display()),
+                    format!(
+                        "remove {} and run `pg0 start --name {}` to reinstall it; check missing system libraries with `ldd`",
+                        version_dir.display(),
+                        name
+                    ),
+                ));
+            } else if !info
+                .version
+                .split('.')
+                .zip(binary_version.split('.'))
+                .all(|(recorded, actual)| recorded == actual)
+            {
+                problems = true;
+                checks.push(DoctorCheck::error(
+                    label.clone(),
+                    format!(
+                        "{} reports {} but the instance records {}",
+                        postgres.display(),
+                        binary_version,
+                        info.version
+                    ),
+                    format!(
+                        "remove {} and run `pg0 start --name {}` to reinstall it",
+                        version_dir.display(),
+                        name
+                    ),
+                ));
+            }
+        }
+    }
+
+    if !problems {
+        let state = if running { "running" } else { "stopped" };
+        checks.push(DoctorCheck::ok(
+            label,
+            format!("{}, PostgreSQL {}, port {}", state, info.version, info.port),
+        ));
+    }
+    Ok(checks)
+}
+
+fn doctor(output_format: OutputFormat) -> Result<(), CliError> {
+    let mut checks = doctor_environment_checks();
+
+    let instances_dir = get_instances_dir()?;
+    let mut claimed_ports = HashMap::new();
+    let mut dirs: Vec<_> = fs::read_dir(&instances_dir)
+        .map(|entries| entries.flatten().filter(|e| e.path().is_dir()).collect())
+        .unwrap_or_default();
+    dirs.sort_by_key(|e| e.file_name());
+    for entry in dirs {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().join("instance.json").exists() {
+            checks.extend(doctor_instance_checks(&name, &mut claimed_ports)?);
+        } else if entry.path().join("data").join("PG_VERSION").exists() {
+            checks.push(DoctorCheck::warning(
+                format!("instance {}", name),
+                "data directory without instance.json; pg0 no longer tracks it",
+                format!(
+                    "`pg0 start --name {}` picks the existing data up again",
+                    name
+                ),
+            ));
+        }
+    }
+
+    let errors = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Error)
+        .count();
+    let warnings = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Warning)
+        .count();
+    match output_format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&checks)?),
+        OutputFormat::Text => {
+            for check in &checks {
+                let marker = match check.status {
+                    CheckStatus::Ok => "ok",
+                    CheckStatus::Warning => "WARN",
+                    CheckStatus::Error => "FAIL",
+                };
+                println!("[{:<4}] {:<20} {}", marker, check.check, check.message);
+                if let Some(fix) = &check.fix {
+                    println!("       {:<20} fix: {}", "", fix);
+                }
+            }
+            println!();
+            if errors == 0 && warnings == 0 {
+                println!("No problems found.");
+            } else {
+                println!("{} error(s), {} warning(s).", errors, warnings);
+            }
+        }
+    }
+
+    if errors > 0 {
+        return Err(CliError::Other(format!(
+            "pg0 doctor found {} error(s)",
+            errors
+        )));
+    }
+    Ok(())
+}
+
+/// A node of the `pg0 du` tree.
+#[derive(Serialize)]
+struct DuEntry {
+    name: String,
+    size_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<DuEntry>,
+}
+
+impl DuEntry {
+    fn new(name: impl Into<String>, path: Option<&Path>, size_bytes: u64) -> Self {
+        DuEntry {
+            name: name.into(),
+            size_bytes,
+            path: path.map(|p| p.display().to_string()),
+            children: Vec::new(),
+        }
+    }
+
+    /// A group whose size is the sum of its children.
+    fn group(name: impl Into<String>, path: Option<&Path>, children: Vec<DuEntry>) -> Self {
+        DuEntry {
+            name: name.into(),
+            size_bytes: children.iter().map(|c| c.size_bytes).sum(),
+            path: path.map(|p| p.display().to_string()),
+            children,
+        }
+    }
+
+    fn sort(&mut self, sort: DuSort) {
+        match sort {
+            DuSort::Size => self
+                .children
+                .sort_by_key(|c| std::cmp::Reverse(c.size_bytes)),
+            DuSort::Name => self.children.sort_by(|a, b| a.name.cmp(&b.name)),
+        }
+        for child in &mut self.children {
+            child.sort(sort);
+        }
+    }
+
+    fn print(&self, depth: usize) {
+        let label = format!("{}{}", "  ".repeat(depth), self.name);
+        println!("{:<48} {:>10}", label, format_size(self.size_bytes));
+        for child in &self.children {
+            child.print(depth + 1);
+        }
+    }
+}
+
+/// Disk usage of one instance, split into databases (while running), WAL,
+/// server logs and everything else in its directory.
+fn du_instance(name: &str, info: &InstanceInfo) -> Result<DuEntry, CliError> {
+    let instance_dir = get_instance_dir(name)?;
+    let running = is_instance_running(info);
+    let mut total = disk_usage(&instance_dir);
+    if !info.data_dir.starts_with(&instance_dir) {
+        total += disk_usage(&info.data_dir);
+    }
+
+    let status = if running {
+        "running"
+    } else if info.archive.is_some() {
+        "archived"
+    } else {
+        "stopped"
+    };
+    let mut children = Vec::new();
+    if let Some(archive) = &info.archive {
+        let size = disk_usage(archive);
+        total += size;
+        children.push(DuEntry::new("archive", Some(archive), size));
+    } else {
+        if running {
+            let sizes = run_sql(
+                info,
+                "postgres",
+                "SELECT datname, pg_database_size(oid) FROM pg_database WHERE datallowconn ORDER BY 1;",
+            )
+            .unwrap_or_default();
+            for line in sizes.lines() {
+                if let Some((db, size)) = line.split_once('|') {
+                    children.push(DuEntry::new(
+                        format!("database {}", db),
+                        None,
+                        size.parse().unwrap_or(0),
+                    ));
+                }
+            }
+        }
+        for (label, dir) in [("wal", "pg_wal"), ("logs", "log")] {
+            let path = info.data_dir.join(dir);
+            if path.exists() {
+                children.push(DuEntry::new(label, Some(&path), disk_usage(&path)));
+            }
+        }
+    }
+    let accounted: u64 = children.iter().map(|c| c.size_bytes).sum();
+    if total > accounted {
+        let label = if running {
+            "other"
+        } else {
+            "data (start the instance for per-database sizes)"
+        };
+        children.push(DuEntry::new(label, None, total - accounted));
+    }
+
+    let mut entry = DuEntry::new(format!("{} ({})", name, status), Some(&instance_dir), total);
+    entry.children = children;
+    Ok(entry)
+}
+
+fn du(sort: DuSort, output_format: OutputFormat) -> Result<(), CliError> {
+    let base_dir = get_base_dir()?;
+
+    let mut instances = Vec::new();
+    for name in list_instances()? {
+        if let Some(info) = load_instance(&name)? {
+            instances.push(du_instance(&name, &info)?);
+        }
+    }
+
+    let mut installations = Vec::new();
+    let installation_dir = base_dir.join("installation");
+    if let Ok(entries) = fs::read_dir(&installation_dir) {
+        for entry in entries.flatten().filter(|e| e.path().is_dir()) {
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+            let label = if dir_name == env!("PG_VERSION") {
+                format!("PostgreSQL {} (bundled)", dir_name)
+            } else if dir_name.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+                format!("PostgreSQL {} (downloaded)", dir_name)
+            } else {
+                dir_name
+            };
+            installations.push(DuEntry::new(
+                label,
+                Some(&entry.path()),
+                disk_usage(&entry.path()),
+            ));
+        }
+    }
+
+    let mut other = Vec::new();
+    for (label, dir) in [("backups", "backups"), ("archives", "archive")] {
+        let path = base_dir.join(dir);
+        if path.exists() {
+            other.push(DuEntry::new(label, Some(&path), disk_usage(&path)));
+        }
+    }
+
+    let mut root = DuEntry::group(
+        base_dir.display().to_string(),
+        Some(&base_dir),
+        vec![
+            DuEntry::group("instances", Some(&get_instances_dir()?), instances),
+            DuEntry::group(
+                "installations (shared)",
+                Some(&installation_dir),
+                installations,
+            ),
+            DuEntry::group("backups and archives", None, other),
+        ],
+    );
+    root.sort(sort);
+
+    match output_format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&root)?),
+        OutputFormat::Text => root.print(0),
+    }
+    Ok(())
+}
+
+/// Instances unused for this long are suggested for archiving.
+const REPORT_ARCHIVE_AFTER_DAYS: u64 = 30;
+
+/// Total size of a file or directory tree, without following symlinks.
+fn disk_usage(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|e| disk_usage(&e.path())).sum())
+        .unwrap_or(0)
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn modified_secs(path: &Path) -> Option<u64> {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+fn format_age(secs: u64, now: u64) -> String {
+    let days = now.saturating_sub(secs) / 86_400;
+    match days {
+        0 => "today".to_string(),
+        1 => "yesterday".to_string(),
+        n => format!("{} days ago", n),
+    }
+}
+
+#[derive(Serialize)]
+struct InstanceReport {
+    name: String,
+    status: String,
+    version: String,
+    size_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_used: Option<u64>,
+    /// Installed extensions; only known while the instance is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extensions: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct CacheReport {
+    name: String,
+    path: String,
+    size_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct ReportOutput {
+    instances: Vec<InstanceReport>,
+    caches: Vec<CacheReport>,
+    total_bytes: u64,
+    recommendations: Vec<String>,
+}
+
+fn report(output_format: OutputFormat) -> Result<(), CliError> {
+    let base_dir = get_base_dir()?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut instances = Vec::new();
+    let mut recommendations = Vec::new();
+    let mut versions_in_use = std::collections::HashSet::new();
+
+    for name in list_instances()? {
+        let Some(info) = load_instance(&name)? else {
+            continue;
+        };
+        versions_in_use.insert(info.version.clone());
+        let instance_dir = get_instance_dir(&name)?;
+        let running = is_instance_running(&info);
+
+        let mut size_bytes = disk_usage(&instance_dir);
+        if let Some(archive) = &info.archive {
+            size_bytes += disk_usage(archive);
+        } else if !info.data_dir.starts_with(&instance_dir) {
+            size_bytes += disk_usage(&info.data_dir);
+        }
+
+        // pg0 touches instance.json on start/stop and the server writes its
+        // log while running, so the newer of the two approximates last use.
+        let last_used = [
+            modified_secs(&get_state_file(&name)?),
+            latest_log_file(&info.data_dir.join("log")).and_then(|path| modified_secs(&path)),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+
+        let extensions = if running {
+            run_sql(
+                &info,
+                &info.database,
+                "SELECT extname FROM pg_extension WHERE extname <> 'plpgsql' ORDER BY 1;",
+            )
+            .ok()
+            .map(|rows| rows.lines().map(|l| l.to_string()).collect())
+        } else {
+            None
+        };
+
+        let status = if running {
+            "running"
+        } else if info.archive.is_some() {
+            "archived"
+        } else {
+            "stopped"
+        };
+
+        let idle_days = last_used
+            .map(|t| now.saturating_sub(t) / 86_400)
+            .unwrap_or(0);
+        if status == "stopped" && name.starts_with("matrix-") {
+            recommendations.push(format!(
+                "Drop '{}' ({}), left over from 'pg0 matrix run --keep': pg0 drop --name {}",
+                name,
+                format_size(size_bytes),
+                name
+            ));
+        } else if status == "stopped" && idle_days >= REPORT_ARCHIVE_AFTER_DAYS {
+            recommendations.push(format!(
+                "Archive '{}' ({}, unused for {} days): pg0 archive --name {}",
+                name,
+                format_size(size_bytes),
+                idle_days,
+                name
+            ));
+        }
+
+        instances.push(InstanceReport {
+            name,
+            status: status.to_string(),
+            version: info.version,
+            size_bytes,
+            last_used,
+            extensions,
+        });
+    }
+
+    let mut caches = Vec::new();
+    let installation_dir = base_dir.join("installation");
+    if let Ok(entries) = fs::read_dir(&installation_dir) {
+        let mut entries: Vec<_> = entries.flatten().filter(|e| e.path().is_dir()).collect();
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let version = entry.file_name().to_string_lossy().to_string();
+            let size_bytes = disk_usage(&entry.path());
+            let is_version = version.chars().next().is_some_and(|c| c.is_ascii_digit());
+            if is_version && !versions_in_use.contains(&version) {
+                recommendations.push(format!(
+                    "PostgreSQL {} ({}) is not used by any instance; remove {} to reclaim it",
+                    version,
+                    format_size(size_bytes),
+                    entry.path().display()
+                ));
+            }
+            caches.push(CacheReport {
+                name: if is_version {
+                    format!("PostgreSQL {}", version)
+                } else {
+                    version
+                },
+                path: entry.path().display().to_string(),
+                size_bytes,
+            });
+        }
+    }
+    for (name, dir) in [("Backups", "backups"), ("Archives", "archive")] {
+        let path = base_dir.join(dir);
+        if path.exists() {
+            caches.push(CacheReport {
+                name: name.to_string(),
+                path: path.display().to_string(),
+                size_bytes: disk_usage(&path),
+            });
+        }
+    }
+    if let Some(backups) = caches
+        .iter()
+        .find(|c| c.name == "Backups" && c.size_bytes > 0)
+    {
+        recommendations.push(format!(
+            "Backups use {}; delete old ones from {}",
+            format_size(backups.size_bytes),
+            backups.path
+        ));
+    }
+
+    let total_bytes = instances.iter().map(|i| i.size_bytes).sum::<u64>()
+        + caches.iter().map(|c| c.size_bytes).sum::<u64>();
+    let report = ReportOutput {
+        instances,
+        caches,
+        total_bytes,
+        recommendations,
+    };
+
+    match output_format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        OutputFormat::Text => {
+            println!("pg0 report ({})", base_dir.display());
+            println!();
+            println!("Instances: {}", report.instances.len());
+            for instance in &report.instances {
+                println!(
+                    "  {} ({}) - PostgreSQL {} - {} - last used {}",
+                    instance.name,
+                    instance.status,
+                    instance.version,
+                    format_size(instance.size_bytes),
+                    instance
+                        .last_used
+                        .map(|t| format_age(t, now))
+                        .unwrap_or_else(|| "unknown".to_string())
+                );
+                match &instance.extensions {
+                    Some(extensions) if !extensions.is_empty() => {
+                        println!("    Extensions: {}", extensions.join(", "))
+                    }
+                    Some(_) => println!("    Extensions: none"),
+                    None => {}
+                }
+            }
+            println!();
+            println!("Caches:");
+            if report.caches.is_empty() {
+                println!("  none");
+            }
+            for cache in &report.caches {
+                println!(
+                    "  {} - {} ({})",
+                    cache.name,
+                    format_size(cache.size_bytes),
+                    cache.path
+                );
+            }
+            println!();
+            println!("Total: {}", format_size(report.total_bytes));
+            println!();
+            if report.recommendations.is_empty() {
+                println!("Nothing to clean up.");
+            } else {
+                println!("Recommendations:");
+                for recommendation in &report.recommendations {
+                    println!("  - {}", recommendation);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn basebackup(
+    name: String,
+    output: Option<String>,
+    no_verify: bool,
+    force: bool,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+
+    // A fast-mode crash can leave torn pages behind; a backup of that cluster
+    // would faithfully preserve the corruption.
+    if info.crashed_in_fast_mode {
+        if !force {
+            return Err(CliError::Other(format!(
+                "Instance '{}' crashed while running in fast (non-durable) mode and may be corrupt. \
+                 Use --force to back it up anyway.",
+                name
+            )));
+        }
+        eprintln!(
+            "Warning: Instance '{}' crashed while running in fast (non-durable) mode; the backup may contain corrupt data.",
+            name
+        );
+    }
+
+    let backup_dir = match output {
+        Some(dir) => expand_path(&dir),
+        None => {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            get_base_dir()?
+                .join("backups")
+                .join(&name)
+                .join(timestamp.to_string())
+        }
+    };
+
+    // pg_basebackup refuses to write into a non-empty directory; fail early
+    // with a clearer message than its own.
+    if backup_dir.exists() && fs::read_dir(&backup_dir)?.next().is_some() {
+        return Err(CliError::Other(format!(
+            "Backup directory {} already exists and is not empty",
+            backup_dir.display()
+        )));
+    }
+    if let Some(parent) = backup_dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let basebackup_path = find_instance_binary(&info, "pg_basebackup")?;
+    // pg_basebackup links against the same libxml2/libicu as postgres.
+    ensure_runtime_libs_for_binary(&basebackup_path)?;
+
+    let uri = instance_uri_without_password(&info, &info.database);
+
+    println!(
+        "Backing up instance '{}' to {}...",
+        name,
+        backup_dir.display()
+    );
+
+    // Tar format with gzip compression keeps the backup to two files
+    // (base.tar.gz + pg_wal.tar.gz). WAL is streamed alongside the base backup
+    // so the result is consistent without requiring WAL archiving, and the
+    // manifest carries SHA256 checksums for pg_verifybackup.
+    let status = std::process::Command::new(&basebackup_path)
+        .arg("--dbname")
+        .arg(&uri)
+        .env("PGPASSWORD", &info.password)
+        .arg("--pgdata")
+        .arg(&backup_dir)
+        .args([
+            "--format=tar",
+            "--gzip",
+            "--wal-method=stream",
+            "--checkpoint=fast",
+            "--manifest-checksums=SHA256",
+            "--progress",
+        ])
+        .status()?;
+
+    if !status.success() {
+        return Err(CliError::Other(format!(
+            "pg_basebackup failed for instance '{}'",
+            name
+        )));
+    }
+
+    // pg_verifybackup reads tar-format backups only from PostgreSQL 18 on.
+    let server_version = run_sql(&info, "postgres", "SHOW server_version_num;")?;
+    let verifiable = server_version
+        .trim()
+        .parse::<u32>()
+        .is_ok_and(|v| v >= 180000);
+    if !no_verify && !verifiable {
+        println!("Skipping checksum verification: pg_verifybackup can't read tar-format backups before PostgreSQL 18.");
+    } else if !no_verify {
+        println!("Verifying backup checksums...");
+        let verifybackup_path = find_instance_binary(&info, "pg_verifybackup")?;
+        // WAL parsing is not supported for tar-format backups; the WAL files
+        // themselves are still covered by the manifest checksums.
+        let status = std::process::Command::new(&verifybackup_path)
+            .arg("--no-parse-wal")
+            .arg(&backup_dir)
+            .status()?;
+        if !status.success() {
+            return Err(CliError::Other(format!(
+                "Backup verification failed for {}",
+                backup_dir.display()
+            )));
+        }
+    }
+
+    println!();
+    println!("Backup of instance '{}' completed.", name);
+    println!("  Location: {}", backup_dir.display());
+
+    Ok(())
+}
+
+fn archive(name: String, to: Option<String>) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let mut info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+
+    if let Some(archive) = &info.archive {
+        return Err(CliError::Other(format!(
+            "Instance '{}' is already archived at {}",
+            name,
+            archive.display()
+        )));
+    }
+
+    if is_instance_running(&info) {
+        stop(name.clone(), &StopOptions::default())?;
+        if is_instance_running(&info) {
+            return Err(CliError::Other(format!(
+                "Instance '{}' is still running; refusing to archive it",
+                name
+            )));
+        }
+    }
+
+    if !info.data_dir.exists() {
+        return Err(CliError::Other(format!(
+            "Data directory {} does not exist",
+            info.data_dir.display()
+        )));
+    }
+
+    let upload_url = to.as_deref().filter(|to| is_archive_url(to)).map(|url| {
+        if url.ends_with('/') {
+            format!("{}{}.tar.gz", url, name)
+        } else {
+            url.to_string()
+        }
+    });
+    if upload_url.is_some() && offline_mode() {
+        return Err(CliError::Other(
+            "pg0 is offline (--offline / PG0_OFFLINE); archive to a local file instead".to_string(),
+        ));
+    }
+
+    let archive_path = match to.filter(|_| upload_url.is_none()) {
+        Some(path) => {
+            let path = expand_path(&path);
+            if path.is_dir() {
+                path.join(format!("{}.tar.gz", name))
+            } else {
+                path
+            }
+        }
+        None => get_base_dir()?
+            .join("archive")
+            .join(format!("{}.tar.gz", name)),
+    };
+    if archive_path.exists() {
+        return Err(CliError::Other(format!(
+            "Archive {} already exists",
+            archive_path.display()
+        )));
+    }
+    if let Some(parent) = archive_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    println!(
+        "Archiving instance '{}' to {}...",
+        name,
+        archive_path.display()
+    );
+
+    // Write to a temporary file first so an interrupted archive never leaves
+    // a truncated tarball behind that looks complete.
+    let partial_path = archive_path.with_extension("gz.partial");
+    let file = fs::File::create(&partial_path)?;
+    let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    builder.append_dir_all("data", &info.data_dir)?;
+    builder.into_inner()?.finish()?;
+    fs::rename(&partial_path, &archive_path)?;
+    let size = fs::metadata(&archive_path)?.len();
+
+    // The local copy only goes once the object store has the archive.
+    if let Some(url) = &upload_url {
+        println!("Uploading to {}...", without_query(url));
+        let uploaded = upload_archive(&archive_path, url);
+        fs::remove_file(&archive_path)?;
+        uploaded?;
+    }
+
+    println!("Removing data directory: {}", info.data_dir.display());
+    fs::remove_dir_all(&info.data_dir)?;
+
+    let location = upload_url.map(PathBuf::from).unwrap_or(archive_path);
+    info.archive = Some(location.clone());
+    save_instance(&name, &info)?;
+
+    println!();
+    println!("Instance '{}' archived.", name);
+    println!(
+        "  Archive: {} ({} bytes)",
+        without_query(&location.display().to_string()),
+        size
+    );
+    println!();
+    println!("Use 'pg0 unarchive --name {}' to restore it.", name);
+
+    Ok(())
+}
+
+fn unarchive(name: String, keep: bool, from: Option<String>) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let mut info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+
+    let recorded = info
+        .archive
+        .clone()
+        .ok_or_else(|| CliError::Other(format!("Instance '{}' is not archived", name)))?;
+    let source = from.map(PathBuf::from).unwrap_or(recorded);
+    let download_url = source
+        .to_str()
+        .filter(|source| is_archive_url(source))
+        .map(str::to_string);
+
+    if info.data_dir.exists() && fs::read_dir(&info.data_dir)?.next().is_some() {
+        return Err(CliError::Other(format!(
+            "Data directory {} already exists and is not empty",
+            info.data_dir.display()
+        )));
+    }
+
+    // Object-store archives are fetched into cold storage first.
+    let archive_path = match &download_url {
+        Some(url) => {
+            if offline_mode() {
+                return Err(CliError::Other(format!(
+                    "The archive has to be downloaded from {}, but pg0 is offline (--offline / PG0_OFFLINE)",
+                    without_query(url)
+                )));
+            }
+            let path = get_base_dir()?
+                .join("archive")
+                .join(format!("{}.tar.gz.download", name));
+            println!("Downloading {}...", without_query(url));
+            download_archive(url, &path)?;
+            path
+        }
+        None => expand_path(&source.display().to_string()),
+    };
+
+    if !archive_path.exists() {
+        return Err(CliError::Other(format!(
+            "Archive {} not found",
+            archive_path.display()
+        )));
+    }
+
+    println!(
+        "Restoring instance '{}' from {}...",
+        name,
+        archive_path.display()
+    );
+
+    fs::create_dir_all(&info.data_dir)?;
+    let decoder = GzDecoder::new(fs::File::open(&archive_path)?);
+    let mut archive = Archive::new(decoder);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        // pg0 writes plain files and directories only; a link could point
+        // later entries outside the data directory.
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            eprintln!(
+                "Warning: Skipping {} in the archive: not a file or directory",
+                path.display()
+            );
+            continue;
+        }
+        // Nothing may climb out of the data directory.
+        if !path
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            eprintln!(
+                "Warning: Skipping {} in the archive: unsafe path",
+                path.display()
+            );
+            continue;
+        }
+
+        // Strip the top-level "data/" directory the archive was written with.
+        let stripped_path: PathBuf = path.components().skip(1).collect();
+        if stripped_path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let dest_path = info.data_dir.join(&stripped_path);
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if entry_type.is_dir() {
+            fs::create_dir_all(&dest_path)?;
+        } else {
+            entry.unpack(&dest_path)?;
+        }
+    }
+
+    // PostgreSQL refuses to start if the data directory is group/world
+    // accessible.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&info.data_dir, fs::Permissions::from_mode(0o700))?;
+    }
+
+    info.archive = None;
+    save_instance(&name, &info)?;
+
+    if let Some(url) = &download_url {
+        // pg0 only ever writes to the object store.
+        fs::remove_file(&archive_path)?;
+        println!(
+            "The archive stays at {}; delete it there when no longer needed.",
+            without_query(url)
+        );
+    } else if !keep {
+        fs::remove_file(&archive_path)?;
+    }
+
+    println!();
+    println!("Instance '{}' restored.", name);
+    println!("  Data dir: {}", info.data_dir.display());
+    println!();
+    if name == DEFAULT_INSTANCE_NAME {
+        println!("Use 'pg0 start' to start it.");
+    } else {
+        println!("Use 'pg0 start --name {}' to start it.", name);
+    }
+
+    Ok(())
+}
+
+fn is_archive_url(location: &str) -> bool {
+    location.starts_with("https://") || location.starts_with("http://")
+}
+
+/// `url` without its query string, which holds the signature of a
+/// pre-signed URL.
+fn without_query(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(base, _)| base)
+}
+
+/// HTTP client for archive transfers: no overall timeout, since archives can
+/// be large. PG0_ARCHIVE_AUTH, if set, is sent as the Authorization header.
+fn archive_request(
+    method: reqwest::Method,
+    url: &str,
+) -> Result<reqwest::blocking::RequestBuilder, CliError> {
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(30))
+        .timeout(None)
+        .build()
+        .map_err(|e| {
+            CliError::Other(format!(
+                "Could not reach {}: {}",
+                without_query(url),
+                e.without_url()
+            ))
+        })?;
+    let mut request = client.request(method, url);
+    if let Some(auth) = std::env::var("PG0_ARCHIVE_AUTH")
+        .ok()
+        .filter(|a| !a.is_empty())
+    {
+        request = request.header(reqwest::header::AUTHORIZATION, auth);
+    }
+    Ok(request)
+}
+
+/// PUT an archive to an object store, e.g. a pre-signed S3 or GCS URL or an
+/// Azure Blob SAS URL.
+fn upload_archive(path: &Path, url: &str) -> Result<(), CliError> {
+    let file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let response = archive_request(reqwest::Method::PUT, url)?
+        // Azure Blob Storage needs the blob type; other stores ignore it.
+        .header("x-ms-blob-type", "BlockBlob")
+        .body(reqwest::blocking::Body::sized(file, size))
+        .send()
+        .map_err(|e| {
+            CliError::Other(format!(
+                "Upload to {} failed: {}",
+                without_query(url),
+                e.without_url()
+            ))
+        })?;
+    if !response.status().is_success() {
+        return Err(CliError::Other(format!(
+            "Upload to {} failed: HTTP {}",
+            without_query(url),
+            response.status()
+        )));
+    }
+    Ok(())
+}
+
+/// GET an archive from an object store into `dest`.
+fn download_archive(url: &str, dest: &Path) -> Result<(), CliError> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut response = archive_request(reqwest::Method::GET, url)?
+        .send()
+        .map_err(|e| {
+            CliError::Other(format!(
+                "Download of {} failed: {}",
+                without_query(url),
+                e.without_url()
+            ))
+        })?;
+    if !response.status().is_success() {
+        return Err(CliError::Other(format!(
+            "Download of {} failed: HTTP {}",
+            without_query(url),
+            response.status()
+        )));
+    }
+    let mut file = fs::File::create(dest)?;
+    if let Err(e) = response.copy_to(&mut file) {
+        let _ = fs::remove_file(dest);
+        return Err(CliError::Other(format!(
+            "Download of {} failed: {}",
+            without_query(url),
+            e.without_url()
+        )));
+    }
+    Ok(())
+}
+
+/// Build a name for a pg0-created server object (replication slot,
+/// publication, subscription). Slot names may only contain lower-case
+/// letters, digits and underscores, so the same rule is used for all of them.
+fn pg0_object_name(prefix: &str, instance_name: &str) -> String {
+    let sanitized: String = instance_name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}{}", prefix, sanitized)
+}
+
+fn replication_slot_name(replica_name: &str) -> String {
+    pg0_object_name("pg0_", replica_name)
+}
+
+/// Best-effort removal of a replica's slot on its primary. Failures are
+/// reported but never abort the caller.
+fn drop_replication_slot(primary_name: &str, slot: &str) {
+    let primary = match load_instance(primary_name) {
+        Ok(Some(primary)) if is_instance_running(&primary) => primary,
+        _ => {
+            eprintln!(
+                "Warning: Primary '{}' is not running; drop replication slot '{}' on it manually.",
+                primary_name, slot
+            );
+            return;
+        }
+    };
+    let sql = format!(
+        "SELECT pg_drop_replication_slot(slot_name) FROM pg_replication_slots WHERE slot_name = {};",
+        quote_literal(slot)
+    );
+    match run_sql(&primary, "postgres", &sql) {
+        Ok(_) => println!("Dropped replication slot '{}' on '{}'.", slot, primary_name),
+        Err(e) => eprintln!(
+            "Warning: Failed to drop replication slot '{}' on '{}': {}",
+            slot, primary_name, e
+        ),
+    }
+}
+
+fn replica_create(of: String, name: String, port: Option<u16>) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let primary = load_running_instance(&of)?;
+
+    if load_instance(&name)?.is_some() {
+        return Err(CliError::Other(format!(
+            "Instance '{}' already exists",
+            name
+        )));
+    }
+
+    let data_dir = get_instance_dir(&name)?.join("data");
+    if data_dir.exists() {
+        return Err(CliError::Other(format!(
+            "Data directory {} already exists",
+            data_dir.display()
+        )));
+    }
+
+    let wal_level = run_sql(&primary, "postgres", "SHOW wal_level;")?;
+    if wal_level.trim() == "minimal" {
+        return Err(CliError::Other(format!(
+            "Primary '{}' runs with wal_level=minimal. Restart it with -c wal_level=replica first.",
+            of
+        )));
+    }
+
+    // initdb's default pg_hba.conf allows replication connections from
+    // localhost; restore that rule if it has been edited away.
+    let hba_path = primary.data_dir.join("pg_hba.conf");
+    let hba = fs::read_to_string(&hba_path)?;
+    let has_replication_rule = hba.lines().any(|line| {
+        let line = line.trim();
+        !line.starts_with('#') && line.split_whitespace().nth(1) == Some("replication")
+    });
+    if !has_replication_rule {
+        println!("Allowing local replication connections on '{}'...", of);
+        let mut hba = hba;
+        if !hba.ends_with('\n') {
+            hba.push('\n');
+        }
+        hba.push_str("host    replication     all             127.0.0.1/32            password\n");
+        fs::write(&hba_path, hba)?;
+        run_sql(&primary, "postgres", "SELECT pg_reload_conf();")?;
+    }
+
+    let slot = replication_slot_name(&name);
+    println!("Creating replication slot '{}' on '{}'...", slot, of);
+    run_sql(
+        &primary,
+        "postgres",
+        &format!(
+            "SELECT pg_create_physical_replication_slot({}) WHERE NOT EXISTS (SELECT 1 FROM pg_replication_slots WHERE slot_name = {});",
+            quote_literal(&slot),
+            quote_literal(&slot)
+        ),
+    )?;
+
+    let basebackup_path = find_instance_binary(&primary, "pg_basebackup")?;
+    ensure_runtime_libs_for_binary(&basebackup_path)?;
+    if let Some(parent) = data_dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    println!("Copying '{}' into replica '{}'...", of, name);
+    let uri = instance_uri_without_password(&primary, &primary.database);
+    // -R writes standby.signal and primary_conninfo so the copy comes up as a
+    // standby streaming from the primary through the reserved slot. The
+    // password pg_basebackup took from PGPASSWORD is written there too.
+    let status = std::process::Command::new(&basebackup_path)
+        .arg("--dbname")
+        .arg(&uri)
+        .env("PGPASSWORD", &primary.password)
+        .arg("--pgdata")
+        .arg(&data_dir)
+        .arg("--slot")
+        .arg(&slot)
+        .args([
+            "--wal-method=stream",
+            "--checkpoint=fast",
+            "--write-recovery-conf",
+            "--progress",
+        ])
+        .status()?;
+    if !status.success() {
+        drop_replication_slot(&of, &slot);
+        return Err(CliError::Other(format!(
+            "pg_basebackup from '{}' failed",
+            of
+        )));
+    }
+
+    let port_was_specified = port.is_some();
+    start(StartOptions {
+        name: name.clone(),
+        port: port.unwrap_or(primary.port),
+        port_was_specified,
+        version: Some(primary.version.clone()),
+        username: Some(primary.username.clone()),
+        password: Some(primary.password.clone()),
+        database: Some(primary.database.clone()),
+        durability: Some(primary.durability),
+        preset: Some(primary.preset),
+        auto_tune: Some(primary.auto_tune),
+        ..Default::default()
+    })?;
+
+    let mut info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    info.replica_of = Some(of.clone());
+    info.replication_slot = Some(slot);
+    save_instance(&name, &info)?;
+
+    println!();
+    println!("Replica '{}' is streaming from '{}'.", name, of);
+
+    Ok(())
+}
+
+fn promote(name: String, repoint: bool) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let mut info = load_running_instance(&name)?;
+
+    let old_primary = info
+        .replica_of
+        .clone()
+        .ok_or_else(|| CliError::Other(format!("Instance '{}' is not a replica", name)))?;
+
+    // The standby may already have been promoted behind pg0's back (e.g. via
+    // pg_ctl promote); in that case only the metadata needs catching up.
+    if run_sql(&info, "postgres", "SELECT pg_is_in_recovery();")? == "t" {
+        println!(
+            "Promoting '{}' (was a replica of '{}')...",
+            name, old_primary
+        );
+        let promoted = run_sql(&info, "postgres", "SELECT pg_promote(true, 60);")?;
+        if promoted != "t" {
+            return Err(CliError::Other(format!(
+                "Promotion of '{}' did not complete within 60 seconds",
+                name
+            )));
+        }
+    }
+
+    if let Some(slot) = &info.replication_slot {
+        drop_replication_slot(&old_primary, slot);
+    }
+    info.replica_of = None;
+    info.replication_slot = None;
+    save_instance(&name, &info)?;
+
+    println!("Instance '{}' is now a primary.", name);
+
+    let siblings: Vec<String> = list_instances()?
+        .into_iter()
+        .filter(|other| other != &name)
+        .filter(|other| {
+            load_instance(other)
+                .ok()
+                .flatten()
+                .map(|i| i.replica_of.as_deref() == Some(old_primary.as_str()))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    if siblings.is_empty() {
+        return Ok(());
+    }
+
+    if !repoint {
+        println!();
+        println!(
+            "These replicas still follow '{}': {}",
+            old_primary,
+            siblings.join(", ")
+        );
+        println!(
+            "Use 'pg0 promote --name {} --repoint' to move them, or re-create them with 'pg0 replica create --of {}'.",
+            name, name
+        );
+        return Ok(());
+    }
+
+    for sibling in &siblings {
+        if let Err(e) = repoint_replica(sibling, &old_primary, &name, &info) {
+            eprintln!("Warning: Failed to re-point '{}': {}", sibling, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Move a running replica from `old_primary` to stream from `new_primary`,
+/// giving it a slot on the new primary. The replica follows the new timeline
+/// because recovery_target_timeline defaults to 'latest'.
+fn repoint_replica(
+    replica_name: &str,
+    old_primary: &str,
+    new_primary_name: &str,
+    new_primary: &InstanceInfo,
+) -> Result<(), CliError> {
+    let _lock = lock_instance(replica_name)?;
+    let mut replica = load_running_instance(replica_name)?;
+    println!(
+        "Re-pointing '{}' at '{}'...",
+        replica_name, new_primary_name
+    );
+
+    let slot = replica
+        .replication_slot
+        .clone()
+        .unwrap_or_else(|| replication_slot_name(replica_name));
+    run_sql(
+        new_primary,
+        "postgres",
+        &format!(
+            "SELECT pg_create_physical_replication_slot({}) WHERE NOT EXISTS (SELECT 1 FROM pg_replication_slots WHERE slot_name = {});",
+            quote_literal(&slot),
+            quote_literal(&slot)
+        ),
+    )?;
+
+    let uri = instance_uri(new_primary, &new_primary.database);
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    let conninfo = format!("{}{}application_name={}", uri, separator, replica_name);
+    run_sql(
+        &replica,
+        "postgres",
+        &format!(
+            "ALTER SYSTEM SET primary_conninfo = {};",
+            quote_literal(&conninfo)
+        ),
+    )?;
+    run_sql(
+        &replica,
+        "postgres",
+        &format!(
+            "ALTER SYSTEM SET primary_slot_name = {};",
+            quote_literal(&slot)
+        ),
+    )?;
+    run_sql(&replica, "postgres", "SELECT pg_reload_conf();")?;
+
+    drop_replication_slot(old_primary, &slot);
+
+    replica.replica_of = Some(new_primary_name.to_string());
+    replica.replication_slot = Some(slot);
+    save_instance(replica_name, &replica)?;
+
+    Ok(())
+}
+
+/// Render `--tables` patterns as the target of CREATE PUBLICATION.
+fn publication_target(tables: &[String]) -> Result<String, CliError> {
+    if tables.is_empty() {
+        return Ok("FOR ALL TABLES".to_string());
+    }
+
+    let mut schemas = Vec::new();
+    let mut relations = Vec::new();
+    for pattern in tables {
+        let (schema, table) = pattern
+            .split_once('.')
+            .unwrap_or(("public", pattern.as_str()));
+        if table == "*" {
+            schemas.push(format!("\"{}\"", schema.replace('"', "\"\"")));
+        } else if table.contains('*') {
+            return Err(CliError::Other(format!(
+                "Unsupported table pattern '{}': use schema.table or schema.*",
+                pattern
+            )));
+        } else {
+            relations.push(format!(
+                "\"{}\".\"{}\"",
+                schema.replace('"', "\"\""),
+                table.replace('"', "\"\"")
+            ));
+        }
+    }
+
+    let mut parts = Vec::new();
+    if !schemas.is_empty() {
+        parts.push(format!("TABLES IN SCHEMA {}", schemas.join(", ")));
+    }
+    if !relations.is_empty() {
+        parts.push(format!("TABLE {}", relations.join(", ")));
+    }
+    Ok(format!("FOR {}", parts.join(", ")))
+}
+
+fn replicate(
+    from: String,
+    to: String,
+    tables: Vec<String>,
+    database: Option<String>,
+    copy_schema: bool,
+) -> Result<(), CliError> {
+    let mut source = load_running_instance(&from)?;
+    let target = load_running_instance(&to)?;
+    let source_db = database.clone().unwrap_or_else(|| source.database.clone());
+    let target_db = database.unwrap_or_else(|| target.database.clone());
+    let publication = pg0_object_name("pg0_pub_", &to);
+    let subscription = pg0_object_name("pg0_sub_", &from);
+    let target_clause = publication_target(&tables)?;
+
+    // Logical decoding needs wal_level=logical, which only takes effect on a
+    // restart. Persist it in the managed layer so it survives future starts.
+    if run_sql(&source, "postgres", "SHOW wal_level;")? != "logical" {
+        println!("Setting wal_level = logical on '{}'...", from);
+        set_managed_config_value(&source.data_dir, "wal_level", "logical")?;
+        source = restart_instance(&from)?;
+    }
+
+    if copy_schema {
+        println!("Copying table definitions from '{}' to '{}'...", from, to);
+        let pg_dump_path = find_instance_binary(&source, "pg_dump")?;
+        ensure_runtime_libs_for_binary(&pg_dump_path)?;
+        let mut dump_args = vec![
+            "--schema-only".to_string(),
+            "--no-owner".to_string(),
+            "--no-publications".to_string(),
+            "--no-subscriptions".to_string(),
+        ];
+        for pattern in &tables {
+            let (schema, table) = pattern
+                .split_once('.')
+                .unwrap_or(("public", pattern.as_str()));
+            if table == "*" {
+                dump_args.push(format!("--schema={}", schema));
+            } else {
+                dump_args.push(format!("--table={}.{}", schema, table));
+            }
+        }
+        let dump = std::process::Command::new(&pg_dump_path)
+            .arg(instance_uri_without_password(&source, &source_db))
+            .env("PGPASSWORD", &source.password)
+            .args(&dump_args)
+            .output()?;
+        if !dump.status.success() {
+            return Err(CliError::Other(format!(
+                "pg_dump failed: {}",
+                String::from_utf8_lossy(&dump.stderr).trim()
+            )));
+        }
+        run_sql_script(&target, &target_db, &dump.stdout)?;
+    }
+
+    println!("Creating publication '{}' on '{}'...", publication, from);
+    run_sql(
+        &source,
+        &source_db,
+        &format!("CREATE PUBLICATION {} {};", publication, target_clause),
+    )?;
+
+    // CREATE SUBSCRIPTION also creates the replication slot on the source
+    // and performs the initial table copy.
+    println!("Creating subscription '{}' on '{}'...", subscription, to);
+    let conninfo = instance_uri(&source, &source_db);
+    if let Err(e) = run_sql(
+        &target,
+        &target_db,
+        &format!(
+            "CREATE SUBSCRIPTION {} CONNECTION {} PUBLICATION {};",
+            subscription,
+            quote_literal(&conninfo),
+            publication
+        ),
+    ) {
+        let _ = run_sql(
+            &source,
+            &source_db,
+            &format!("DROP PUBLICATION IF EXISTS {};", publication),
+        );
+        return Err(e);
+    }
+
+    println!();
+    println!("Logical replication from '{}' to '{}' is set up.", from, to);
+    println!("  Publication:  {} ({}/{})", publication, from, source_db);
+    println!("  Subscription: {} ({}/{})", subscription, to, target_db);
+    println!();
+    println!(
+        "Use 'pg0 replicate --from {} --to {} --drop' to tear it down.",
+        from, to
+    );
+
+    Ok(())
+}
+
+fn replicate_drop(from: String, to: String, database: Option<String>) -> Result<(), CliError> {
+    let source = load_running_instance(&from)?;
+    let target = load_running_instance(&to)?;
+    let source_db = database.clone().unwrap_or_else(|| source.database.clone());
+    let target_db = database.unwrap_or_else(|| target.database.clone());
+    let publication = pg0_object_name("pg0_pub_", &to);
+    let subscription = pg0_object_name("pg0_sub_", &from);
+
+    // Dropping the subscription also drops its slot on the source.
+    println!("Dropping subscription '{}' on '{}'...", subscription, to);
+    run_sql(
+        &target,
+        &target_db,
+        &format!("DROP SUBSCRIPTION IF EXISTS {};", subscription),
+    )?;
+    println!("Dropping publication '{}' on '{}'...", publication, from);
+    run_sql(
+        &source,
+        &source_db,
+        &format!("DROP PUBLICATION IF EXISTS {};", publication),
+    )?;
+
+    println!("Logical replication from '{}' to '{}' removed.", from, to);
+    Ok(())
+}
+
+/// Connection details parsed from a postgresql:// URI.
+#[derive(Debug, Default)]
+struct ConnectionUri {
+    host: String,
+    port: Option<u16>,
+    user: Option<String>,
+    password: Option<String>,
+    dbname: Option<String>,
+    /// Query parameters (sslmode, ...), passed through as libpq options
+    params: Vec<(String, String)>,
+}
+
+/// Percent-encode everything but unreserved characters and `/`, so paths
+/// can go into URI query strings.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn parse_connection_uri(uri: &str) -> Result<ConnectionUri, CliError> {
+    let invalid = || {
+        CliError::Other(format!(
+            "Invalid connection URI '{}': expected postgresql://[user[:password]@]host[:port][/database]",
+            uri
+        ))
+    };
+    let rest = uri
+        .strip_prefix("postgresql://")
+        .or_else(|| uri.strip_prefix("postgres://"))
+        .ok_or_else(invalid)?;
+
+    let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let (authority, dbname) = rest.split_once('/').unwrap_or((rest, ""));
+    let (userinfo, hostport) = match authority.rsplit_once('@') {
+        Some((userinfo, hostport)) => (Some(userinfo), hostport),
+        None => (None, authority),
+    };
+
+    let mut parsed = ConnectionUri::default();
+    if let Some(userinfo) = userinfo {
+        let (user, password) = match userinfo.split_once(':') {
+            Some((user, password)) => (user, Some(password)),
+            None => (userinfo, None),
+        };
+        parsed.user = Some(percent_decode(user)).filter(|u| !u.is_empty());
+        parsed.password = password.map(percent_decode);
+    }
+
+    // [v6]:port, host:port or host
+    let (host, port) = if let Some(v6) = hostport.strip_prefix('[') {
+        let (host, after) = v6.split_once(']').ok_or_else(invalid)?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match hostport.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (hostport, None),
+        }
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    parsed.host = percent_decode(host);
+    parsed.port = match port {
+        Some(port) => Some(port.parse().map_err(|_| invalid())?),
+        None => None,
+    };
+    parsed.dbname = Some(percent_decode(dbname)).filter(|d| !d.is_empty());
+    parsed.params = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (percent_decode(k), percent_decode(v)))
+        .collect();
+    Ok(parsed)
+}
+
+/// Render `key 'value', ...` for an OPTIONS (...) clause.
+fn sql_options(options: &[(String, String)]) -> String {
+    options
+        .iter()
+        .map(|(key, value)| format!("{} {}", quote_ident(key), quote_literal(value)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn fdw_add(
+    instance: String,
+    server: String,
+    uri: String,
+    database: Option<String>,
+    remote_schema: String,
+    local_schema: Option<String>,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&instance)?;
+    let database = database.unwrap_or_else(|| info.database.clone());
+    let local_schema = local_schema.unwrap_or_else(|| server.clone());
+    let remote = parse_connection_uri(&uri)?;
+
+    let mut server_options = vec![("host".to_string(), remote.host.clone())];
+    if let Some(port) = remote.port {
+        server_options.push(("port".to_string(), port.to_string()));
+    }
+    if let Some(dbname) = &remote.dbname {
+        server_options.push(("dbname".to_string(), dbname.clone()));
+    }
+    server_options.extend(remote.params.iter().cloned());
+
+    let mut user_options = Vec::new();
+    if let Some(user) = &remote.user {
+        user_options.push(("user".to_string(), user.clone()));
+    }
+    if let Some(password) = &remote.password {
+        user_options.push(("password".to_string(), password.clone()));
+    }
+
+    println!(
+        "Attaching {}:{}/{} as foreign server '{}' on '{}'...",
+        remote.host,
+        remote.port.unwrap_or(5432),
+        remote.dbname.as_deref().unwrap_or(""),
+        server,
+        instance
+    );
+
+    let mut sql = format!(
+        "CREATE EXTENSION IF NOT EXISTS postgres_fdw;\n\
+         CREATE SERVER {server} FOREIGN DATA WRAPPER postgres_fdw OPTIONS ({server_options});\n",
+        server = quote_ident(&server),
+        server_options = sql_options(&server_options)
+    );
+    if user_options.is_empty() {
+        sql.push_str(&format!(
+            "CREATE USER MAPPING FOR CURRENT_USER SERVER {};\n",
+            quote_ident(&server)
+        ));
+    } else {
+        sql.push_str(&format!(
+            "CREATE USER MAPPING FOR CURRENT_USER SERVER {} OPTIONS ({});\n",
+            quote_ident(&server),
+            sql_options(&user_options)
+        ));
+    }
+    sql.push_str(&format!(
+        "CREATE SCHEMA IF NOT EXISTS {local};\n\
+         IMPORT FOREIGN SCHEMA {remote} FROM SERVER {server} INTO {local};\n",
+        local = quote_ident(&local_schema),
+        remote = quote_ident(&remote_schema),
+        server = quote_ident(&server)
+    ));
+
+    // One transaction: a failed import (e.g. bad credentials) leaves nothing behind.
+    run_sql(&info, &database, &format!("BEGIN;\n{}COMMIT;", sql))?;
+
+    let tables = run_sql(
+        &info,
+        &database,
+        &format!(
+            "SELECT count(*) FROM information_schema.foreign_tables \
+             WHERE foreign_server_name = {} AND foreign_table_schema = {};",
+            quote_literal(&server),
+            quote_literal(&local_schema)
+        ),
+    )?;
+
+    println!("Foreign server '{}' ready.", server);
+    println!(
+        "  Imported: {} table(s) from {} into schema {}",
+        tables, remote_schema, local_schema
+    );
+    println!("  Query:    SELECT * FROM {}.<table>;", local_schema);
+    Ok(())
+}
+
+fn fdw_remove(
+    instance: String,
+    server: String,
+    database: Option<String>,
+    local_schema: Option<String>,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&instance)?;
+    let database = database.unwrap_or_else(|| info.database.clone());
+    let local_schema = local_schema.unwrap_or_else(|| server.clone());
+
+    // CASCADE removes the user mapping and the foreign tables.
+    run_sql(
+        &info,
+        &database,
+        &format!("DROP SERVER IF EXISTS {} CASCADE;", quote_ident(&server)),
+    )?;
+    // Leave the schema alone if something else was created in it.
+    if run_sql(
+        &info,
+        &database,
+        &format!("DROP SCHEMA IF EXISTS {};", quote_ident(&local_schema)),
+    )
+    .is_err()
+    {
+        eprintln!(
+            "Warning: Schema '{}' is not empty and was left in place.",
+            local_schema
+        );
+    }
+
+    println!("Removed foreign server '{}' from '{}'.", server, instance);
+    Ok(())
+}
+
+/// Column layout of a relation, as announced by pgoutput 'R' messages.
+struct PgOutputRelation {
+    schema: String,
+    table: String,
+    columns: Vec<String>,
+}
+
+/// Decoder for the pgoutput logical replication protocol (version 1), as
+/// returned row by row from pg_logical_slot_peek_binary_changes().
+#[derive(Default)]
+struct PgOutputDecoder {
+    relations: HashMap<u32, PgOutputRelation>,
+}
+
+/// Big-endian reader over one pgoutput message.
+struct MessageReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> MessageReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        MessageReader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let slice = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(slice)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn cstr(&mut self) -> Option<String> {
+        let rest = self.data.get(self.pos..)?;
+        let len = rest.iter().position(|&b| b == 0)?;
+        let s = String::from_utf8_lossy(&rest[..len]).to_string();
+        self.pos += len + 1;
+        Some(s)
+    }
+}
+
+impl PgOutputDecoder {
+    /// Decode one message. Returns a JSON change event for row changes and
+    /// truncates; None for protocol bookkeeping (begin, commit, relation, ...).
+    fn decode(&mut self, lsn: &str, xid: &str, data: &[u8]) -> Option<serde_json::Value> {
+        let mut reader = MessageReader::new(data);
+        match reader.u8()? {
+            b'R' => {
+                let relid = reader.u32()?;
+                let schema = reader.cstr()?;
+                let table = reader.cstr()?;
+                let _replica_identity = reader.u8()?;
+                let ncols = reader.u16()?;
+                let mut columns = Vec::with_capacity(ncols as usize);
+                for _ in 0..ncols {
+                    let _flags = reader.u8()?;
+                    columns.push(reader.cstr()?);
+                    let _type_oid = reader.u32()?;
+                    let _type_mod = reader.u32()?;
+                }
+                self.relations.insert(
+                    relid,
+                    PgOutputRelation {
+                        schema,
+                        table,
+                        columns,
+                    },
+                );
+                None
+            }
+            b'I' => {
+                let relid = reader.u32()?;
+                let _new = reader.u8()?;
+                let relation = self.relations.get(&relid)?;
+                let new = Self::tuple(&mut reader, relation)?;
+                Some(Self::event(lsn, xid, "insert", relation, Some(new), None))
+            }
+            b'U' => {
+                let relid = reader.u32()?;
+                let relation = self.relations.get(&relid)?;
+                let mut kind = reader.u8()?;
+                let mut old = None;
+                // 'K' (replica identity key) or 'O' (full old row) precede
+                // the new tuple when the old values are available.
+                if kind == b'K' || kind == b'O' {
+                    old = Some(Self::tuple(&mut reader, relation)?);
+                    kind = reader.u8()?;
+                }
+                if kind != b'N' {
+                    return None;
+                }
+                let new = Self::tuple(&mut reader, relation)?;
+                Some(Self::event(lsn, xid, "update", relation, Some(new), old))
+            }
+            b'D' => {
+                let relid = reader.u32()?;
+                let relation = self.relations.get(&relid)?;
+                let _kind = reader.u8()?;
+                let old = Self::tuple(&mut reader, relation)?;
+                Some(Self::event(lsn, xid, "delete", relation, None, Some(old)))
+            }
+            b'T' => {
+                let nrelations = reader.u32()?;
+                let _options = reader.u8()?;
+                let mut tables = Vec::new();
+                for _ in 0..nrelations {
+                    let relid = reader.u32()?;
+                    if let Some(relation) = self.relations.get(&relid) {
+                        tables.push(format!("{}.{}", relation.schema, relation.table));
+                    }
+                }
+                Some(serde_json::json!({
+                    "lsn": lsn,
+                    "xid": xid.parse::<u64>().ok(),
+                    "action": "truncate",
+                    "tables": tables,
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Decode TupleData into a column -> text value object. Unchanged TOASTed
+    /// values are omitted; binary values are not requested, so never appear.
+    fn tuple(reader: &mut MessageReader, relation: &PgOutputRelation) -> Option<serde_json::Value> {
+        let ncols = reader.u16()? as usize;
+        let mut values = serde_json::Map::new();
+        for idx in 0..ncols {
+            let column = relation
+                .columns
+                .get(idx)
+                .cloned()
+                .unwrap_or_else(|| format!("column{}", idx + 1));
+            match reader.u8()? {
+                b'n' => {
+                    values.insert(column, serde_json::Value::Null);
+                }
+                b'u' => {}
+                b't' | b'b' => {
+                    let len = reader.u32()? as usize;
+                    let bytes = reader.bytes(len)?;
+                    values.insert(
+                        column,
+                        serde_json::Value::String(String::from_utf8_lossy(bytes).to_string()),
+                    );
+                }
+                _ => return None,
+            }
+        }
+        Some(serde_json::Value::Object(values))
+    }
+
+    fn event(
+        lsn: &str,
+        xid: &str,
+        action: &str,
+        relation: &PgOutputRelation,
+        new: Option<serde_json::Value>,
+        old: Option<serde_json::Value>,
+    ) -> serde_json::Value {
+        let mut event = serde_json::json!({
+            "lsn": lsn,
+            "xid": xid.parse::<u64>().ok(),
+            "action": action,
+            "schema": relation.schema,
+            "table": relation.table,
+        });
+        if let Some(new) = new {
+            event["columns"] = new;
+        }
+        if let Some(old) = old {
+            event["identity"] = old;
+        }
+        event
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Where `pg0 cdc` remembers the last LSN it emitted for a slot, so a run
+/// interrupted between printing and acknowledging a batch doesn't replay it.
+fn get_cdc_checkpoint_file(name: &str, slot: &str) -> Result<PathBuf, CliError> {
+    Ok(get_instance_dir(name)?
+        .join("cdc")
+        .join(format!("{}.lsn", slot)))
+}
+
+/// Make sure the instance can do logical decoding and the slot (plus, for
+/// pgoutput, its publication) exists.
+fn ensure_cdc_slot(
+    name: &str,
+    info: InstanceInfo,
+    database: &str,
+    slot: &str,
+    plugin: CdcPlugin,
+    tables: &[String],
+) -> Result<InstanceInfo, CliError> {
+    let mut info = info;
+    if run_sql(&info, "postgres", "SHOW wal_level;")? != "logical" {
+        println!("Setting wal_level = logical on '{}'...", name);
+        set_managed_config_value(&info.data_dir, "wal_level", "logical")?;
+        info = restart_instance(name)?;
+    }
+
+    let slot_exists = run_sql(
+        &info,
+        database,
+        &format!(
+            "SELECT count(*) FROM pg_replication_slots WHERE slot_name = {};",
+            quote_literal(slot)
+        ),
+    )? != "0";
+
+    match plugin {
+        CdcPlugin::Pgoutput => {
+            let publication = format!("{}_pub", slot);
+            let publication_exists = run_sql(
+                &info,
+                database,
+                &format!(
+                    "SELECT count(*) FROM pg_publication WHERE pubname = {};",
+                    quote_literal(&publication)
+                ),
+            )? != "0";
+            if !publication_exists {
+                run_sql(
+                    &info,
+                    database,
+                    &format!(
+                        "CREATE PUBLICATION \"{}\" {};",
+                        publication,
+                        publication_target(tables)?
+                    ),
+                )?;
+            } else if !tables.is_empty() && !slot_exists {
+                eprintln!(
+                    "Warning: Publication '{}' already exists; --tables is ignored. Use --drop to reset it.",
+                    publication
+                );
+            }
+        }
+        CdcPlugin::Wal2json => {
+            if !has_library(
+                &info.installation_dir.join(&info.version).join("lib"),
+                "wal2json",
+            ) {
+                return Err(CliError::Other(format!(
+                    "The wal2json output plugin is not installed in this instance's PostgreSQL installation. Install it with 'pg0 install-extension --name {} wal2json'.",
+                    name
+                )));
+            }
+        }
+    }
+
+    if !slot_exists {
+        let plugin_name = match plugin {
+            CdcPlugin::Pgoutput => "pgoutput",
+            CdcPlugin::Wal2json => "wal2json",
+        };
+        eprintln!(
+            "Creating logical replication slot '{}' ({})...",
+            slot, plugin_name
+        );
+        run_sql(
+            &info,
+            database,
+            &format!(
+                "SELECT pg_create_logical_replication_slot({}, {});",
+                quote_literal(slot),
+                quote_literal(plugin_name)
+            ),
+        )?;
+    }
+
+    Ok(info)
+}
+
+/// Build the wal2json `add-tables` filter from `--tables` patterns.
+fn wal2json_table_filter(tables: &[String]) -> String {
+    tables
+        .iter()
+        .map(|pattern| {
+            if pattern.contains('.') {
+                pattern.clone()
+            } else {
+                format!("public.{}", pattern)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Peek query for the next batch of up to 1000 transactions on a slot.
+/// Peeking leaves the slot untouched until the batch is acknowledged.
+fn cdc_peek_sql(slot: &str, plugin: CdcPlugin, tables: &[String]) -> String {
+    match plugin {
+        CdcPlugin::Pgoutput => format!(
+            "SELECT lsn, xid, encode(data, 'hex') FROM pg_logical_slot_peek_binary_changes({}, NULL, 1000, \
+             'proto_version', '1', 'publication_names', {});",
+            quote_literal(slot),
+            quote_literal(&format!("{}_pub", slot))
+        ),
+        CdcPlugin::Wal2json => {
+            let mut options = vec![
+                "'format-version', '2'".to_string(),
+                "'include-xids', '1'".to_string(),
+                "'include-transaction', 'false'".to_string(),
+            ];
+            if !tables.is_empty() {
+                options.push(format!(
+                    "'add-tables', {}",
+                    quote_literal(&wal2json_table_filter(tables))
+                ));
+            }
+            format!(
+                "SELECT lsn, xid, data FROM pg_logical_slot_peek_changes({}, NULL, 1000, {});",
+                quote_literal(slot),
+                options.join(", ")
+            )
+        }
+    }
+}
+
+/// Catch the slot up with the last batch that was delivered before an
+/// interrupted run could acknowledge it.
+fn cdc_restore_checkpoint(
+    info: &InstanceInfo,
+    database: &str,
+    slot: &str,
+    checkpoint_file: &Path,
+) -> Result<(), CliError> {
+    if let Some(parent) = checkpoint_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if let Ok(checkpoint) = fs::read_to_string(checkpoint_file) {
+        let checkpoint = checkpoint.trim();
+        if !checkpoint.is_empty() {
+            run_sql(
+                info,
+                database,
+                &format!(
+                    "SELECT pg_replication_slot_advance(slot_name, {lsn}::pg_lsn) FROM pg_replication_slots \
+                     WHERE slot_name = {slot} AND confirmed_flush_lsn < {lsn}::pg_lsn;",
+                    lsn = quote_literal(checkpoint),
+                    slot = quote_literal(slot)
+                ),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Fetch the next batch of change events, plus the LSN to acknowledge once
+/// they are delivered (None when there was nothing new).
+fn cdc_fetch(
+    info: &InstanceInfo,
+    database: &str,
+    peek_sql: &str,
+    plugin: CdcPlugin,
+    decoder: &mut PgOutputDecoder,
+) -> Result<(Vec<serde_json::Value>, Option<String>), CliError> {
+    let rows = run_sql(info, database, peek_sql)?;
+    let mut events = Vec::new();
+    let mut last_lsn = None;
+    for row in rows.lines() {
+        let mut cols = row.splitn(3, '|');
+        let (lsn, xid, data) = match (cols.next(), cols.next(), cols.next()) {
+            (Some(lsn), Some(xid), Some(data)) => (lsn, xid, data),
+            _ => continue,
+        };
+        let event = match plugin {
+            CdcPlugin::Pgoutput => {
+                decode_hex(data).and_then(|bytes| decoder.decode(lsn, xid, &bytes))
+            }
+            CdcPlugin::Wal2json => {
+                serde_json::from_str::<serde_json::Value>(data)
+                    .ok()
+                    .map(|mut event| {
+                        event["lsn"] = serde_json::Value::String(lsn.to_string());
+                        event
+                    })
+            }
+        };
+        events.extend(event);
+        last_lsn = Some(lsn.to_string());
+    }
+    Ok((events, last_lsn))
+}
+
+/// Record a delivered batch: checkpoint first, then move the slot forward.
+fn cdc_acknowledge(
+    info: &InstanceInfo,
+    database: &str,
+    slot: &str,
+    checkpoint_file: &Path,
+    lsn: &str,
+) -> Result<(), CliError> {
+    fs::write(checkpoint_file, lsn)?;
+    run_sql(
+        info,
+        database,
+        &format!(
+            "SELECT pg_replication_slot_advance({}, {}::pg_lsn);",
+            quote_literal(slot),
+            quote_literal(lsn)
+        ),
+    )?;
+    Ok(())
+}
+
+fn cdc(
+    name: String,
+    slot: String,
+    plugin: CdcPlugin,
+    tables: Vec<String>,
+    database: Option<String>,
+    follow: bool,
+) -> Result<(), CliError> {
+    use std::io::Write;
+
+    let info = load_running_instance(&name)?;
+    let database = database.unwrap_or_else(|| info.database.clone());
+    let info = ensure_cdc_slot(&name, info, &database, &slot, plugin, &tables)?;
+
+    let checkpoint_file = get_cdc_checkpoint_file(&name, &slot)?;
+    cdc_restore_checkpoint(&info, &database, &slot, &checkpoint_file)?;
+
+    let peek_sql = cdc_peek_sql(&slot, plugin, &tables);
+    let mut decoder = PgOutputDecoder::default();
+    let stdout = std::io::stdout();
+
+    loop {
+        let (events, last_lsn) = cdc_fetch(&info, &database, &peek_sql, plugin, &mut decoder)?;
+        {
+            let mut out = stdout.lock();
+            for event in &events {
+                // A closed pipe (e.g. `| head`) ends the stream without
+                // acknowledging the unread part of the batch.
+                if writeln!(out, "{}", event).is_err() {
+                    return Ok(());
+                }
+            }
+            if out.flush().is_err() {
+                return Ok(());
+            }
+        }
+
+        // Acknowledge the batch only after it has been written out
+        // (at-least-once delivery).
+        match &last_lsn {
+            Some(lsn) => cdc_acknowledge(&info, &database, &slot, &checkpoint_file, lsn)?,
+            None => {
+                if !follow {
+                    return Ok(());
+                }
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            }
+        }
+    }
+}
+
+/// Drop a CDC slot, its publication and its checkpoint.
+fn drop_cdc_slot(
+    name: &str,
+    info: &InstanceInfo,
+    database: &str,
+    slot: &str,
+) -> Result<(), CliError> {
+    run_sql(
+        info,
+        database,
+        &format!(
+            "SELECT pg_drop_replication_slot(slot_name) FROM pg_replication_slots WHERE slot_name = {};",
+            quote_literal(slot)
+        ),
+    )?;
+    run_sql(
+        info,
+        database,
+        &format!("DROP PUBLICATION IF EXISTS \"{}_pub\";", slot),
+    )?;
+    let checkpoint_file = get_cdc_checkpoint_file(name, slot)?;
+    if checkpoint_file.exists() {
+        fs::remove_file(&checkpoint_file)?;
+    }
+    Ok(())
+}
+
+fn cdc_drop(name: String, slot: String, database: Option<String>) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let database = database.unwrap_or_else(|| info.database.clone());
+    drop_cdc_slot(&name, &info, &database, &slot)?;
+    println!("Dropped CDC slot '{}' on '{}'.", slot, name);
+    Ok(())
+}
+
+/// Where a CDC sink delivers captured changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SinkTarget {
+    /// Each batch is POSTed as a JSON array
+    Webhook { url: String },
+    /// Each change is produced as one record to partition 0 of the topic
+    Kafka { broker: String, topic: String },
+}
+
+impl std::fmt::Display for SinkTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SinkTarget::Webhook { url } => write!(f, "webhook {}", url),
+            SinkTarget::Kafka { broker, topic } => write!(f, "kafka {}/{}", broker, topic),
+        }
+    }
+}
+
+/// A CDC sink as stored in the instance's cdc/sinks.json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CdcSink {
+    id: String,
+    target: SinkTarget,
+    database: String,
+    #[serde(default)]
+    tables: Vec<String>,
+}
+
+impl CdcSink {
+    fn slot(&self) -> String {
+        pg0_object_name("pg0_sink_", &self.id)
+    }
+}
+
+fn get_cdc_dir(name: &str) -> Result<PathBuf, CliError> {
+    Ok(get_instance_dir(name)?.join("cdc"))
+}
+
+fn load_cdc_sinks(name: &str) -> Result<Vec<CdcSink>, CliError> {
+    let path = get_cdc_dir(name)?.join("sinks.json");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn save_cdc_sinks(name: &str, sinks: &[CdcSink]) -> Result<(), CliError> {
+    let dir = get_cdc_dir(name)?;
+    fs::create_dir_all(&dir)?;
+    write_file_atomic(
+        &dir.join("sinks.json"),
+        serde_json::to_string_pretty(sinks)?.as_bytes(),
+    )
+}
+
+/// PID of a background worker recorded in `pid_file`, if it is running. The
+/// file holds the PID and, on the next line, the worker's [`ProcessIdentity`];
+/// a PID that now belongs to another process, or one recorded without an
+/// identity to check it against, doesn't count.
+fn running_worker(pid_file: &Path) -> Option<u32> {
+    let content = fs::read_to_string(pid_file).ok()?;
+    let mut lines = content.lines();
+    let pid: u32 = lines.next()?.trim().parse().ok()?;
+    let recorded: ProcessIdentity = serde_json::from_str(lines.next()?).ok()?;
+    if !is_process_running(pid) {
+        return None;
+    }
+    match process_identity(pid) {
+        Some(current) if !recorded.matches(&current) => None,
+        _ => Some(pid),
+    }
+}
+
+/// Launch a detached `pg0 <args>` background process unless the one recorded
+/// in `pid_file` is still running. Its output is appended to `log_file`.
+fn spawn_worker(args: &[&str], pid_file: &Path, log_file: &Path) -> Result<u32, CliError> {
+    if let Some(pid) = running_worker(pid_file) {
+        return Ok(pid);
+    }
+
+    if let Some(parent) = pid_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+
+    let mut cmd = std::process::Command::new(std::env::current_exe()?);
+    cmd.args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
     #[cfg(unix)]
     {
-        use std::process::Command;
-        Command::new("kill")
-            .args(["-0", &pid.to_string()])
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false)
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP
+        cmd.creation_flags(0x0000_0008 | 0x0000_0200);
+    }
+    let child = cmd.spawn()?;
+    // Without an identity the PID can't be told from a later process.
+    let identity = process_identity(child.id()).unwrap_or(ProcessIdentity {
+        started_at: unix_now(),
+        executable: None,
+    });
+    let content = format!("{}\n{}\n", child.id(), serde_json::to_string(&identity)?);
+    write_file_atomic(pid_file, content.as_bytes())?;
+    Ok(child.id())
+}
+
+fn stop_worker(pid_file: &Path) {
+    // Waits for the worker to exit, so its port is free once this returns.
+    if let Some(pid) = running_worker(pid_file) {
+        if let Err(e) = terminate_process(pid, std::time::Duration::from_secs(2)) {
+            eprintln!("Warning: failed to stop worker (pid {}): {}", pid, e);
+        }
+    }
+    let _ = fs::remove_file(pid_file);
+}
+
+fn sink_worker_files(name: &str, sink_id: &str) -> Result<(PathBuf, PathBuf), CliError> {
+    let dir = get_cdc_dir(name)?;
+    Ok((
+        dir.join(format!("{}.pid", sink_id)),
+        dir.join(format!("{}.log", sink_id)),
+    ))
+}
+
+/// PID of a sink's background worker, if it is running.
+fn running_sink_worker(name: &str, sink_id: &str) -> Option<u32> {
+    let (pid_file, _) = sink_worker_files(name, sink_id).ok()?;
+    running_worker(&pid_file)
+}
+
+/// Launch the background delivery worker for a sink unless one is already
+/// running. The worker is a detached `pg0 cdc-worker` process logging to
+/// cdc/<id>.log; it exits on its own when the instance stops.
+fn spawn_sink_worker(name: &str, sink_id: &str) -> Result<u32, CliError> {
+    let (pid_file, log_file) = sink_worker_files(name, sink_id)?;
+    spawn_worker(
+        &["cdc-worker", "--name", name, "--sink", sink_id],
+        &pid_file,
+        &log_file,
+    )
+}
+
+fn stop_sink_worker(name: &str, sink_id: &str) {
+    if let Ok((pid_file, _)) = sink_worker_files(name, sink_id) {
+        stop_worker(&pid_file);
+    }
+}
+
+/// Restart the delivery workers of all sinks on an instance (after `pg0 start`).
+fn resume_sink_workers(name: &str) {
+    for sink in load_cdc_sinks(name).unwrap_or_default() {
+        if let Err(e) = spawn_sink_worker(name, &sink.id) {
+            eprintln!("Warning: Failed to start CDC sink '{}': {}", sink.id, e);
+        }
+    }
+}
+
+fn cdc_sink_add(
+    name: String,
+    id: Option<String>,
+    url: Option<String>,
+    kafka: Option<String>,
+    tables: Vec<String>,
+    database: Option<String>,
+) -> Result<(), CliError> {
+    let target = match (url, kafka) {
+        (Some(url), None) => SinkTarget::Webhook { url },
+        (None, Some(kafka)) => {
+            let (broker, topic) = kafka.rsplit_once('/').ok_or_else(|| {
+                CliError::Other(format!(
+                    "Invalid --kafka '{}': expected broker/topic",
+                    kafka
+                ))
+            })?;
+            SinkTarget::Kafka {
+                broker: broker.to_string(),
+                topic: topic.to_string(),
+            }
+        }
+        _ => {
+            return Err(CliError::Other(
+                "Specify exactly one of --url or --kafka".to_string(),
+            ))
+        }
+    };
+
+    let info = load_running_instance(&name)?;
+    let mut sinks = load_cdc_sinks(&name)?;
+    let id = id.unwrap_or_else(|| {
+        (1..)
+            .map(|n| format!("sink{}", n))
+            .find(|candidate| !sinks.iter().any(|s| &s.id == candidate))
+            .unwrap_or_default()
+    });
+    // The id names the worker's cdc/<id>.pid and .log files.
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(CliError::Other(format!(
+            "Invalid sink id '{}': use letters, digits, '_' and '-'",
+            id
+        )));
+    }
+    if sinks.iter().any(|s| s.id == id) {
+        return Err(CliError::Other(format!(
+            "CDC sink '{}' already exists on '{}'",
+            id, name
+        )));
+    }
+
+    let sink = CdcSink {
+        id: id.clone(),
+        target,
+        database: database.unwrap_or_else(|| info.database.clone()),
+        tables,
+    };
+    ensure_cdc_slot(
+        &name,
+        info,
+        &sink.database,
+        &sink.slot(),
+        CdcPlugin::Pgoutput,
+        &sink.tables,
+    )?;
+    sinks.push(sink.clone());
+    save_cdc_sinks(&name, &sinks)?;
+
+    let pid = spawn_sink_worker(&name, &id)?;
+    println!("Added CDC sink '{}' on '{}'.", id, name);
+    println!("  Target:  {}", sink.target);
+    println!("  Slot:    {}", sink.slot());
+    println!("  Worker:  pid {}", pid);
+    println!(
+        "  Log:     {}",
+        get_cdc_dir(&name)?.join(format!("{}.log", id)).display()
+    );
+    Ok(())
+}
+
+fn cdc_sink_list(name: String) -> Result<(), CliError> {
+    let sinks = load_cdc_sinks(&name)?;
+    if sinks.is_empty() {
+        println!("No CDC sinks on '{}'.", name);
+        return Ok(());
+    }
+    for sink in sinks {
+        let status = match running_sink_worker(&name, &sink.id) {
+            Some(pid) => format!("running, pid {}", pid),
+            None => "stopped".to_string(),
+        };
+        let cursor = get_cdc_checkpoint_file(&name, &sink.slot())
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|lsn| lsn.trim().to_string())
+            .unwrap_or_else(|| "-".to_string());
+        println!("{} ({})", sink.id, status);
+        println!("  Target:  {}", sink.target);
+        println!("  Cursor:  {}", cursor);
+    }
+    Ok(())
+}
+
+fn cdc_sink_remove(name: String, id: String) -> Result<(), CliError> {
+    let mut sinks = load_cdc_sinks(&name)?;
+    let sink = sinks
+        .iter()
+        .position(|s| s.id == id)
+        .map(|idx| sinks.remove(idx))
+        .ok_or_else(|| CliError::Other(format!("No CDC sink '{}' on '{}'", id, name)))?;
+
+    stop_sink_worker(&name, &id);
+    match load_running_instance(&name) {
+        Ok(info) => drop_cdc_slot(&name, &info, &sink.database, &sink.slot())?,
+        Err(_) => eprintln!(
+            "Warning: '{}' is not running; slot '{}' was left in place.",
+            name,
+            sink.slot()
+        ),
+    }
+    save_cdc_sinks(&name, &sinks)?;
+    let _ = fs::remove_file(get_cdc_dir(&name)?.join(format!("{}.log", id)));
+
+    println!("Removed CDC sink '{}' from '{}'.", id, name);
+    Ok(())
+}
+
+/// Deliver one batch to a sink. Returns only once the target accepted it.
+fn deliver_to_sink(
+    target: &SinkTarget,
+    client: &reqwest::blocking::Client,
+    events: &[serde_json::Value],
+) -> Result<(), CliError> {
+    match target {
+        SinkTarget::Webhook { url } => {
+            let response = client
+                .post(url)
+                .json(events)
+                .send()
+                .map_err(|e| CliError::Other(format!("POST {} failed: {}", url, e)))?;
+            if !response.status().is_success() {
+                return Err(CliError::Other(format!(
+                    "POST {} returned {}",
+                    url,
+                    response.status()
+                )));
+            }
+            Ok(())
+        }
+        SinkTarget::Kafka { broker, topic } => {
+            let records: Vec<(Option<Vec<u8>>, Vec<u8>)> = events
+                .iter()
+                .map(|event| {
+                    let key = match (event["schema"].as_str(), event["table"].as_str()) {
+                        (Some(schema), Some(table)) => {
+                            Some(format!("{}.{}", schema, table).into_bytes())
+                        }
+                        _ => None,
+                    };
+                    (key, event.to_string().into_bytes())
+                })
+                .collect();
+            kafka_produce(broker, topic, &records)
+        }
+    }
+}
+
+/// Body of the hidden `pg0 cdc-worker` process: stream a sink's slot to its
+/// target with at-least-once delivery, retrying failed batches with backoff.
+fn cdc_worker(name: String, sink_id: String) -> Result<(), CliError> {
+    let sink = load_cdc_sinks(&name)?
+        .into_iter()
+        .find(|s| s.id == sink_id)
+        .ok_or_else(|| CliError::Other(format!("No CDC sink '{}' on '{}'", sink_id, name)))?;
+    let slot = sink.slot();
+    let checkpoint_file = get_cdc_checkpoint_file(&name, &slot)?;
+    let peek_sql = cdc_peek_sql(&slot, CdcPlugin::Pgoutput, &sink.tables);
+    let client = reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .map_err(|e| CliError::Other(e.to_string()))?;
+
+    let log = |message: String| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        eprintln!("{} [{}] {}", now, sink_id, message);
+    };
+    log(format!("started, delivering to {}", sink.target));
+
+    let mut info = load_running_instance(&name)?;
+    cdc_restore_checkpoint(&info, &sink.database, &slot, &checkpoint_file)?;
+    let mut decoder = PgOutputDecoder::default();
+    let mut backoff = std::time::Duration::from_secs(1);
+
+    loop {
+        // The instance may have been stopped or restarted on another pid.
+        match load_instance(&name)? {
+            Some(current) if is_instance_running(&current) => info = current,
+            _ => {
+                log("instance stopped, exiting".to_string());
+                return Ok(());
+            }
+        }
+        if !load_cdc_sinks(&name)?.iter().any(|s| s.id == sink_id) {
+            log("sink removed, exiting".to_string());
+            return Ok(());
+        }
+
+        let result = cdc_fetch(
+            &info,
+            &sink.database,
+            &peek_sql,
+            CdcPlugin::Pgoutput,
+            &mut decoder,
+        )
+        .and_then(|(events, last_lsn)| {
+            if let Some(lsn) = &last_lsn {
+                if !events.is_empty() {
+                    deliver_to_sink(&sink.target, &client, &events)?;
+                }
+                cdc_acknowledge(&info, &sink.database, &slot, &checkpoint_file, lsn)?;
+                if !events.is_empty() {
+                    log(format!(
+                        "delivered {} change(s) up to {}",
+                        events.len(),
+                        lsn
+                    ));
+                }
+            }
+            Ok(last_lsn.is_some())
+        });
+
+        match result {
+            Ok(true) => backoff = std::time::Duration::from_secs(1),
+            Ok(false) => std::thread::sleep(std::time::Duration::from_millis(500)),
+            Err(e) => {
+                log(format!(
+                    "delivery failed, retrying in {}s: {}",
+                    backoff.as_secs(),
+                    e
+                ));
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(std::time::Duration::from_secs(60));
+            }
+        }
+    }
+}
+
+/// CRC-32C (Castagnoli), as used by Kafka record batches.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Zigzag varint, as used inside Kafka records.
+fn put_varint(buf: &mut Vec<u8>, value: i64) {
+    let mut v = ((value << 1) ^ (value >> 63)) as u64;
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_kafka_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as i16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Minimal Kafka client connection: just enough of the wire protocol
+/// (Metadata v1, Produce v3) for CDC sinks, without a native librdkafka.
+struct KafkaConnection {
+    stream: std::net::TcpStream,
+    correlation_id: i32,
+}
+
+impl KafkaConnection {
+    fn connect(address: &str) -> Result<Self, CliError> {
+        let stream = std::net::TcpStream::connect(address).map_err(|e| {
+            CliError::Other(format!("Cannot connect to Kafka broker {}: {}", address, e))
+        })?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(30)))?;
+        stream.set_write_timeout(Some(std::time::Duration::from_secs(30)))?;
+        Ok(KafkaConnection {
+            stream,
+            correlation_id: 0,
+        })
+    }
+
+    /// Send one request and return the response body after the correlation id.
+    fn request(
+        &mut self,
+        api_key: i16,
+        api_version: i16,
+        body: &[u8],
+    ) -> Result<Vec<u8>, CliError> {
+        use std::io::{Read, Write};
+
+        self.correlation_id += 1;
+        let mut message = Vec::new();
+        message.extend_from_slice(&api_key.to_be_bytes());
+        message.extend_from_slice(&api_version.to_be_bytes());
+        message.extend_from_slice(&self.correlation_id.to_be_bytes());
+        put_kafka_string(&mut message, "pg0");
+        message.extend_from_slice(body);
+
+        self.stream
+            .write_all(&(message.len() as i32).to_be_bytes())?;
+        self.stream.write_all(&message)?;
+
+        let mut size = [0u8; 4];
+        self.stream.read_exact(&mut size)?;
+        let mut response = vec![0u8; i32::from_be_bytes(size).max(0) as usize];
+        self.stream.read_exact(&mut response)?;
+        if response.len() < 4 {
+            return Err(CliError::Other("Truncated Kafka response".to_string()));
+        }
+        Ok(response.split_off(4))
+    }
+}
+
+/// Big-endian reader for Kafka responses.
+struct KafkaReader<'a>(MessageReader<'a>);
+
+impl KafkaReader<'_> {
+    fn i16(&mut self) -> Result<i16, CliError> {
+        self.0.u16().map(|v| v as i16).ok_or_else(Self::truncated)
+    }
+
+    fn i32(&mut self) -> Result<i32, CliError> {
+        self.0.u32().map(|v| v as i32).ok_or_else(Self::truncated)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), CliError> {
+        self.0.bytes(len).map(|_| ()).ok_or_else(Self::truncated)
+    }
+
+    fn string(&mut self) -> Result<Option<String>, CliError> {
+        let len = self.i16()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let bytes = self.0.bytes(len as usize).ok_or_else(Self::truncated)?;
+        Ok(Some(String::from_utf8_lossy(bytes).to_string()))
+    }
+
+    fn truncated() -> CliError {
+        CliError::Other("Truncated Kafka response".to_string())
+    }
+}
+
+/// Find the address of the leader of partition 0 of a topic.
+fn kafka_partition_leader(conn: &mut KafkaConnection, topic: &str) -> Result<String, CliError> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&1i32.to_be_bytes());
+    put_kafka_string(&mut body, topic);
+    let response = conn.request(3, 1, &body)?;
+    let mut reader = KafkaReader(MessageReader::new(&response));
+
+    let mut brokers = HashMap::new();
+    for _ in 0..reader.i32()? {
+        let node_id = reader.i32()?;
+        let host = reader.string()?.unwrap_or_default();
+        let port = reader.i32()?;
+        let _rack = reader.string()?;
+        brokers.insert(node_id, format!("{}:{}", host, port));
+    }
+    let _controller_id = reader.i32()?;
+    for _ in 0..reader.i32()? {
+        let error_code = reader.i16()?;
+        let _name = reader.string()?;
+        reader.skip(1)?; // is_internal
+        if error_code != 0 {
+            return Err(CliError::Other(format!(
+                "Kafka metadata for topic '{}' failed with error code {}",
+                topic, error_code
+            )));
+        }
+        for _ in 0..reader.i32()? {
+            let _error_code = reader.i16()?;
+            let partition = reader.i32()?;
+            let leader = reader.i32()?;
+            let replicas = reader.i32()?;
+            reader.skip(replicas.max(0) as usize * 4)?;
+            let isr = reader.i32()?;
+            reader.skip(isr.max(0) as usize * 4)?;
+            if partition == 0 {
+                return brokers.get(&leader).cloned().ok_or_else(|| {
+                    CliError::Other(format!("Kafka topic '{}' has no available leader", topic))
+                });
+            }
+        }
+    }
+    Err(CliError::Other(format!(
+        "Kafka topic '{}' not found",
+        topic
+    )))
+}
+
+/// Encode records as a single uncompressed v2 record batch, timestamped
+/// `now` (milliseconds since the epoch).
+fn kafka_record_batch(records: &[(Option<Vec<u8>>, Vec<u8>)], now: i64) -> Vec<u8> {
+    // Everything from the attributes field on is covered by the CRC.
+    let mut body = Vec::new();
+    body.extend_from_slice(&0i16.to_be_bytes()); // attributes
+    body.extend_from_slice(&(records.len() as i32 - 1).to_be_bytes()); // last offset delta
+    body.extend_from_slice(&now.to_be_bytes()); // base timestamp
+    body.extend_from_slice(&now.to_be_bytes()); // max timestamp
+    body.extend_from_slice(&(-1i64).to_be_bytes()); // producer id
+    body.extend_from_slice(&(-1i16).to_be_bytes()); // producer epoch
+    body.extend_from_slice(&(-1i32).to_be_bytes()); // base sequence
+    body.extend_from_slice(&(records.len() as i32).to_be_bytes());
+    for (offset, (key, value)) in records.iter().enumerate() {
+        let mut record = vec![0u8]; // attributes
+        put_varint(&mut record, 0); // timestamp delta
+        put_varint(&mut record, offset as i64);
+        match key {
+            Some(key) => {
+                put_varint(&mut record, key.len() as i64);
+                record.extend_from_slice(key);
+            }
+            None => put_varint(&mut record, -1),
+        }
+        put_varint(&mut record, value.len() as i64);
+        record.extend_from_slice(value);
+        put_varint(&mut record, 0); // headers
+        put_varint(&mut body, record.len() as i64);
+        body.extend_from_slice(&record);
+    }
+
+    let mut batch = Vec::new();
+    batch.extend_from_slice(&0i64.to_be_bytes()); // base offset
+    batch.extend_from_slice(&(4 + 1 + 4 + body.len() as i32).to_be_bytes()); // batch length
+    batch.extend_from_slice(&(-1i32).to_be_bytes()); // partition leader epoch
+    batch.push(2); // magic
+    batch.extend_from_slice(&crc32c(&body).to_be_bytes());
+    batch.extend_from_slice(&body);
+    batch
+}
+
+/// Produce records to partition 0 of a topic and wait for all in-sync
+/// replicas to acknowledge them.
+fn kafka_produce(
+    broker: &str,
+    topic: &str,
+    records: &[(Option<Vec<u8>>, Vec<u8>)],
+) -> Result<(), CliError> {
+    let leader = kafka_partition_leader(&mut KafkaConnection::connect(broker)?, topic)?;
+    let mut conn = KafkaConnection::connect(&leader)?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    let batch = kafka_record_batch(records, now);
+    let mut body = Vec::new();
+    body.extend_from_slice(&(-1i16).to_be_bytes()); // no transactional id
+    body.extend_from_slice(&(-1i16).to_be_bytes()); // acks = all
+    body.extend_from_slice(&30_000i32.to_be_bytes()); // timeout
+    body.extend_from_slice(&1i32.to_be_bytes());
+    put_kafka_string(&mut body, topic);
+    body.extend_from_slice(&1i32.to_be_bytes());
+    body.extend_from_slice(&0i32.to_be_bytes()); // partition
+    body.extend_from_slice(&(batch.len() as i32).to_be_bytes());
+    body.extend_from_slice(&batch);
+
+    let response = conn.request(0, 3, &body)?;
+    let mut reader = KafkaReader(MessageReader::new(&response));
+    for _ in 0..reader.i32()? {
+        let _name = reader.string()?;
+        for _ in 0..reader.i32()? {
+            let _partition = reader.i32()?;
+            let error_code = reader.i16()?;
+            if error_code != 0 {
+                return Err(CliError::Other(format!(
+                    "Kafka produce to '{}' failed with error code {}",
+                    topic, error_code
+                )));
+            }
+            reader.skip(16)?; // base offset, log append time
+        }
+    }
+    Ok(())
+}
+
+/// Random hex string for access tokens.
+fn generate_token() -> Result<String, CliError> {
+    let mut bytes = [0u8; 24];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| CliError::Other(format!("Failed to generate a token: {}", e)))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Point every `host` rule of a running instance's pg_hba.conf at the
+/// method of its `AuthMode`, and reload if that changed anything.
+///
+/// For SCRAM, pg0's roles first get their password hashed again, while the
+/// old rules still accept whatever hash they had.
+fn set_host_auth(info: &InstanceInfo) -> Result<(), CliError> {
+    let hba_path = info.data_dir.join("pg_hba.conf");
+    let hba = fs::read_to_string(&hba_path)?;
+    let method = info.auth.hba_method();
+    let mut changed = false;
+    let mut lines: Vec<String> = Vec::new();
+    for line in hba.lines() {
+        // TYPE DATABASE USER ADDRESS METHOD [OPTIONS]
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let is_host_rule = matches!(fields.first(), Some(&"host" | &"hostssl" | &"hostnossl"))
+            && !is_managed_rule(line);
+        match fields.get(4) {
+            Some(current) if is_host_rule && *current != method => {
+                let rule: Vec<&str> = fields[..4]
+                    .iter()
+                    .copied()
+                    .chain([method])
+                    .chain(fields[5..].iter().copied())
+                    .collect();
+                lines.push(format!(
+                    "{:<8}{:<16}{:<16}{:<24}{}",
+                    rule[0],
+                    rule[1],
+                    rule[2],
+                    rule[3],
+                    rule[4..].join(" ")
+                ));
+                changed = true;
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    if !changed {
+        return Ok(());
+    }
+
+    if info.auth == AuthMode::Scram && info.replica_of.is_none() {
+        rehash_passwords_scram(info)?;
+    }
+    println!("Setting authentication of TCP connections to {}...", method);
+    fs::write(&hba_path, lines.join("\n") + "\n")?;
+    run_sql(info, "postgres", "SELECT pg_reload_conf();")?;
+    Ok(())
+}
+
+/// Hash the password of pg0's roles again as SCRAM-SHA-256.
+fn rehash_passwords_scram(info: &InstanceInfo) -> Result<(), CliError> {
+    let mut sql = "SET password_encryption = 'scram-sha-256';".to_string();
+    for role in [
+        info.username.as_str(),
+        postgresql_embedded::BOOTSTRAP_SUPERUSER,
+    ] {
+        sql.push_str(&format!(
+            " ALTER ROLE {} PASSWORD {};",
+            quote_ident(role),
+            quote_literal(&info.password)
+        ));
+    }
+    // On stdin: a -c argument would show the password in the process list.
+    run_sql_script(info, "postgres", sql.as_bytes())
+}
+
+/// Ends every pg_hba.conf rule written for `pg0 start --listen`, so they can
+/// be replaced on the next start.
+const REMOTE_RULE_MARKER: &str = "# pg0 start --listen";
+
+fn is_local_address(address: &str) -> bool {
+    matches!(address, "localhost" | "127.0.0.1" | "::1")
+}
+
+/// Longest socket path every platform accepts (`sun_path` on macOS).
+const MAX_SOCKET_PATH: usize = 103;
+
+/// Whether the server can put its socket for `port` into `dir`.
+fn check_socket_dir(dir: &Path, port: u16) -> Result<(), CliError> {
+    if cfg!(windows) {
+        return Err(CliError::Other("--socket-dir and --socket-only need Unix sockets, which pg0 doesn't support on Windows".to_string()));
+    }
+    let socket = dir.join(format!(".s.PGSQL.{}", port)).display().to_string();
+    // pg_ctl passes the server options through the shell.
+    if socket.contains(|c: char| c.is_whitespace() || "'\"\\$`".contains(c)) {
+        return Err(CliError::Other(format!(
+            "Socket directory '{}' must not contain spaces or quotes",
+            dir.display()
+        )));
+    }
+    if socket.len() > MAX_SOCKET_PATH {
+        return Err(CliError::Other(format!(
+            "Socket path '{}' is longer than {} bytes; pick a shorter --socket-dir",
+            socket, MAX_SOCKET_PATH
+        )));
+    }
+    Ok(())
+}
+
+/// `listen_addresses` for `--listen`. Localhost stays, since pg0 itself
+/// connects there, unless a wildcard covers it already.
+fn listen_addresses(listen: &[String]) -> String {
+    if listen
+        .iter()
+        .any(|address| matches!(address.as_str(), "*" | "0.0.0.0" | "::"))
+    {
+        return listen.join(",");
+    }
+    std::iter::once("localhost")
+        .chain(
+            listen
+                .iter()
+                .map(String::as_str)
+                .filter(|address| !is_local_address(address)),
+        )
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Normalize an `--allow` network; a bare address allows just that host.
+fn parse_cidr(value: &str) -> Result<String, CliError> {
+    let invalid = || {
+        CliError::Other(format!(
+            "Invalid network '{}': expected e.g. 192.168.1.0/24",
+            value
+        ))
+    };
+    let (address, prefix) = value.split_once('/').unwrap_or((value, ""));
+    let address: std::net::IpAddr = address.parse().map_err(|_| invalid())?;
+    let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+    let prefix: u8 = if prefix.is_empty() {
+        max_prefix
+    } else {
+        prefix
+            .parse()
+            .ok()
+            .filter(|prefix| *prefix <= max_prefix)
+            .ok_or_else(invalid)?
+    };
+    Ok(format!("{}/{}", address, prefix))
+}
+
+/// Replace the `--listen` rules at the end of pg_hba.conf: SCRAM for every
+/// database and user from `allow` (any address if empty), over TLS only
+/// when the instance serves it. Without `remote`, the rules are removed.
+fn write_remote_rules(
+    data_dir: &Path,
+    allow: &[String],
+    tls: bool,
+    remote: bool,
+) -> Result<(), CliError> {
+    let hba_path = data_dir.join("pg_hba.conf");
+    let hba = fs::read_to_string(&hba_path)?;
+    let mut lines: Vec<String> = hba
+        .lines()
+        .filter(|line| !line.contains(REMOTE_RULE_MARKER))
+        .map(str::to_string)
+        .collect();
+    if remote {
+        let any = ["0.0.0.0/0".to_string(), "::/0".to_string()];
+        let networks = if allow.is_empty() { &any[..] } else { allow };
+        let kind = if tls { "hostssl" } else { "host" };
+        for network in networks {
+            lines.push(format!(
+                "{:<8}{:<16}{:<16}{:<24}{:<16}{}",
+                kind, "all", "all", network, "scram-sha-256", REMOTE_RULE_MARKER
+            ));
+        }
+    }
+    let content = lines.join("\n") + "\n";
+    if content != hba {
+        fs::write(&hba_path, content)?;
+    }
+    Ok(())
+}
+
+/// Ends every pg_hba.conf rule added with `pg0 hba add`.
+const HBA_RULE_MARKER: &str = "# pg0 hba";
+
+/// Whether a pg_hba.conf line was written by `--listen` or `pg0 hba`, and is
+/// left alone by everything else that edits the file.
+fn is_managed_rule(line: &str) -> bool {
+    line.contains(REMOTE_RULE_MARKER) || line.contains(HBA_RULE_MARKER)
+}
+
+/// A pg_hba.conf rule of `pg0 hba add`, as stored in the instance's hba.json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HbaRule {
+    #[serde(rename = "type")]
+    kind: String,
+    database: String,
+    user: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    method: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    options: Vec<String>,
+}
+
+impl HbaRule {
+    fn line(&self) -> String {
+        let rule = format!(
+            "{:<8}{:<16}{:<16}{:<24}{}",
+            self.kind,
+            self.database,
+            self.user,
+            self.address.as_deref().unwrap_or(""),
+            std::iter::once(&self.method)
+                .chain(&self.options)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        format!("{:<79} {}", rule, HBA_RULE_MARKER)
+    }
+}
+
+fn get_hba_rules_file(name: &str) -> Result<PathBuf, CliError> {
+    Ok(get_instance_dir(name)?.join("hba.json"))
+}
+
+fn load_hba_rules(name: &str) -> Result<Vec<HbaRule>, CliError> {
+    let path = get_hba_rules_file(name)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn save_hba_rules(name: &str, rules: &[HbaRule]) -> Result<(), CliError> {
+    fs::write(
+        get_hba_rules_file(name)?,
+        serde_json::to_string_pretty(rules)?,
+    )?;
+    Ok(())
+}
+
+/// Replace the `pg0 hba` rules of pg_hba.conf with `rules`, ahead of every
+/// other rule since the first matching one wins.
+fn apply_hba_rules(data_dir: &Path, rules: &[HbaRule]) -> Result<(), CliError> {
+    let hba_path = data_dir.join("pg_hba.conf");
+    let hba = fs::read_to_string(&hba_path)?;
+    let mut lines: Vec<String> = Vec::new();
+    let mut pending = Some(rules.iter().map(HbaRule::line));
+    for line in hba.lines().filter(|line| !line.contains(HBA_RULE_MARKER)) {
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            lines.extend(pending.take().into_iter().flatten());
+        }
+        lines.push(line.to_string());
+    }
+    lines.extend(pending.take().into_iter().flatten());
+    let content = lines.join("\n") + "\n";
+    if content != hba {
+        fs::write(&hba_path, content)?;
+    }
+    Ok(())
+}
+
+/// Write `rules` to the instance's pg_hba.conf and reload it if running.
+/// A file the server can't parse is put back as it was.
+fn update_hba_rules(name: &str, info: &InstanceInfo, rules: &[HbaRule]) -> Result<(), CliError> {
+    let hba_path = info.data_dir.join("pg_hba.conf");
+    let original = fs::read_to_string(&hba_path)?;
+    apply_hba_rules(&info.data_dir, rules)?;
+    if is_instance_running(info) {
+        // Only this command's rules are checked; the rest of the file is
+        // the server's business.
+        let managed_lines: Vec<String> = fs::read_to_string(&hba_path)?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.contains(HBA_RULE_MARKER))
+            .map(|(index, _)| (index + 1).to_string())
+            .collect();
+        let errors = if managed_lines.is_empty() {
+            String::new()
+        } else {
+            run_sql(
+                info,
+                "postgres",
+                &format!(
+                    "SELECT 'line ' || line_number || ': ' || error FROM pg_hba_file_rules \
+                     WHERE error IS NOT NULL AND line_number IN ({});",
+                    managed_lines.join(", ")
+                ),
+            )?
+        };
+        if !errors.is_empty() {
+            fs::write(&hba_path, original)?;
+            return Err(CliError::Other(format!(
+                "Invalid pg_hba.conf rule:\n{}",
+                errors
+            )));
+        }
+        run_sql(info, "postgres", "SELECT pg_reload_conf();")?;
+    }
+    save_hba_rules(name, rules)
+}
+
+/// Instance whose pg_hba.conf can be edited: running or stopped, not archived.
+fn load_hba_instance(name: &str) -> Result<InstanceInfo, CliError> {
+    let info = load_instance(name)?.ok_or(CliError::NoInstance)?;
+    if info.archive.is_some() {
+        return Err(CliError::Other(format!(
+            "Instance '{}' is archived. Use 'pg0 unarchive --name {}' first.",
+            name, name
+        )));
+    }
+    Ok(info)
+}
+
+fn hba_list(name: String) -> Result<(), CliError> {
+    let rules = load_hba_rules(&name)?;
+    if rules.is_empty() {
+        println!("No pg_hba.conf rules added to '{}'.", name);
+        return Ok(());
+    }
+    println!(
+        "{:<4}{:<12}{:<16}{:<16}{:<24}METHOD",
+        "#", "TYPE", "DATABASE", "USER", "ADDRESS"
+    );
+    for (number, rule) in rules.iter().enumerate() {
+        println!(
+            "{:<4}{:<12}{:<16}{:<16}{:<24}{}",
+            number + 1,
+            rule.kind,
+            rule.database,
+            rule.user,
+            rule.address.as_deref().unwrap_or("-"),
+            std::iter::once(&rule.method)
+                .chain(&rule.options)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+    Ok(())
+}
+
+fn hba_add(
+    name: String,
+    kind: String,
+    database: String,
+    user: String,
+    address: Option<String>,
+    method: String,
+    options: Vec<String>,
+) -> Result<(), CliError> {
+    const TYPES: &[&str] = &[
+        "local",
+        "host",
+        "hostssl",
+        "hostnossl",
+        "hostgssenc",
+        "hostnogssenc",
+    ];
+    if !TYPES.contains(&kind.as_str()) {
+        return Err(CliError::Other(format!(
+            "Unknown rule type '{}': expected one of {}",
+            kind,
+            TYPES.join(", ")
+        )));
+    }
+    let address = match (kind.as_str(), address) {
+        ("local", None) => None,
+        ("local", Some(_)) => {
+            return Err(CliError::Other("local rules take no --address".to_string()))
+        }
+        (_, None) => return Err(CliError::Other(format!("{} rules need an --address", kind))),
+        // Host names and keywords like samenet are passed through.
+        (_, Some(address))
+            if address.contains('/') || address.parse::<std::net::IpAddr>().is_ok() =>
+        {
+            Some(parse_cidr(&address)?)
+        }
+        (_, Some(address)) => Some(address),
+    };
+    if let Some(option) = options.iter().find(|option| !option.contains('=')) {
+        return Err(CliError::Other(format!(
+            "Invalid option '{}', expected KEY=VALUE",
+            option
+        )));
+    }
+
+    let info = load_hba_instance(&name)?;
+    let rule = HbaRule {
+        kind,
+        database,
+        user,
+        address,
+        method,
+        options,
+    };
+    let mut rules = load_hba_rules(&name)?;
+    rules.push(rule.clone());
+    update_hba_rules(&name, &info, &rules)?;
+    println!("Added rule {} to '{}':", rules.len(), name);
+    println!(
+        "  {}",
+        rule.line().trim_end_matches(HBA_RULE_MARKER).trim_end()
+    );
+    if !is_instance_running(&info) {
+        println!("It takes effect when the instance starts.");
+    }
+    Ok(())
+}
+
+fn hba_remove(name: String, number: usize) -> Result<(), CliError> {
+    let info = load_hba_instance(&name)?;
+    let mut rules = load_hba_rules(&name)?;
+    if number == 0 || number > rules.len() {
+        return Err(CliError::Other(format!(
+            "No rule {} on '{}' (see pg0 hba list --name {})",
+            number, name, name
+        )));
+    }
+    let rule = rules.remove(number - 1);
+    update_hba_rules(&name, &info, &rules)?;
+    println!("Removed rule {} from '{}':", number, name);
+    println!(
+        "  {}",
+        rule.line().trim_end_matches(HBA_RULE_MARKER).trim_end()
+    );
+    Ok(())
+}
+
+/// Certificate files of an instance started with `--tls`, under its
+/// instance directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TlsConfig {
+    /// CA certificate for clients to verify the server with
+    ca_file: PathBuf,
+    cert_file: PathBuf,
+    key_file: PathBuf,
+}
+
+/// The instance's TLS files, generated on first use: a CA of its own and a
+/// server certificate for localhost and 127.0.0.1/::1 signed by it. The CA
+/// key isn't kept, so nothing else can be signed with it.
+fn ensure_tls_files(instance_dir: &Path) -> Result<TlsConfig, CliError> {
+    let tls_dir = instance_dir.join("tls");
+    let tls = TlsConfig {
+        ca_file: tls_dir.join("ca.crt"),
+        cert_file: tls_dir.join("server.crt"),
+        key_file: tls_dir.join("server.key"),
+    };
+    if tls.ca_file.exists() && tls.cert_file.exists() && tls.key_file.exists() {
+        return Ok(tls);
+    }
+
+    let tls_error =
+        |e: rcgen::Error| CliError::Other(format!("Failed to generate TLS certificates: {}", e));
+    let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).map_err(tls_error)?;
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    ca_params.distinguished_name.push(
+        rcgen::DnType::CommonName,
+        format!("pg0 local CA ({})", instance_dir.display()),
+    );
+    ca_params.key_usages = vec![
+        rcgen::KeyUsagePurpose::KeyCertSign,
+        rcgen::KeyUsagePurpose::CrlSign,
+    ];
+    let ca_key = rcgen::KeyPair::generate().map_err(tls_error)?;
+    let ca_cert = ca_params.self_signed(&ca_key).map_err(tls_error)?;
+
+    let names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    let mut params = rcgen::CertificateParams::new(names).map_err(tls_error)?;
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "localhost");
+    let key = rcgen::KeyPair::generate().map_err(tls_error)?;
+    let cert = params
+        .signed_by(&key, &ca_cert, &ca_key)
+        .map_err(tls_error)?;
+
+    fs::create_dir_all(&tls_dir)?;
+    fs::write(&tls.ca_file, ca_cert.pem())?;
+    fs::write(&tls.cert_file, cert.pem())?;
+    // PostgreSQL refuses a key that others can read.
+    fs::write(&tls.key_file, key.serialize_pem())?;
+    restrict_to_owner(&tls.key_file)?;
+    println!("Generated TLS certificates in {}", tls_dir.display());
+    Ok(tls)
+}
+
+/// Give every `host` rule of pg_hba.conf a `hostssl` twin ahead of it, unless
+/// the file has `hostssl` rules already. The `host` rules stay, so pg0's own
+/// tools and clients without TLS still connect.
+fn ensure_hostssl_rules(data_dir: &Path) -> Result<(), CliError> {
+    let hba_path = data_dir.join("pg_hba.conf");
+    let hba = fs::read_to_string(&hba_path)?;
+    let is_rule = |line: &str, kind: &str| {
+        line.split_whitespace().next() == Some(kind) && !is_managed_rule(line)
+    };
+    if hba.lines().any(|line| is_rule(line, "hostssl")) {
+        return Ok(());
+    }
+    // Keep the columns aligned where the padding allows it.
+    let ssl_rules: Vec<String> = hba
+        .lines()
+        .filter(|line| is_rule(line, "host"))
+        .map(|line| {
+            let rest = &line.trim_start()["host".len()..];
+            format!("hostssl{}", rest.strip_prefix("   ").unwrap_or(rest))
+        })
+        .collect();
+    // Rules match in order: the TLS rules go before the first host rule.
+    let mut lines: Vec<String> = Vec::new();
+    let mut ssl_rules = Some(ssl_rules);
+    for line in hba.lines() {
+        if is_rule(line, "host") {
+            lines.extend(ssl_rules.take().unwrap_or_default());
+        }
+        lines.push(line.to_string());
+    }
+    fs::write(&hba_path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Random alphanumeric password for `pg0 start --random-password`.
+fn generate_password() -> Result<String, CliError> {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut password = String::new();
+    while password.len() < 24 {
+        let mut bytes = [0u8; 32];
+        getrandom::getrandom(&mut bytes)
+            .map_err(|e| CliError::Other(format!("Failed to generate a password: {}", e)))?;
+        // Skip bytes past the last multiple of the charset size, so every
+        // character is equally likely.
+        password.extend(
+            bytes
+                .iter()
+                .filter(|&&b| (b as usize) < 256 / CHARSET.len() * CHARSET.len())
+                .map(|&b| CHARSET[b as usize % CHARSET.len()] as char),
+        );
+    }
+    password.truncate(24);
+    Ok(password)
+}
+
+/// The instance's stored `pg0 serve` token, created on first use.
+fn load_or_create_serve_token(name: &str) -> Result<String, CliError> {
+    let path = get_instance_dir(name)?.join("serve.token");
+    if let Ok(token) = fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let token = generate_token()?;
+    write_file_atomic(&path, token.as_bytes())?;
+    Ok(token)
+}
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    sql: String,
+    #[serde(default)]
+    database: Option<String>,
+}
+
+/// Lowercased first word of a statement, skipping comment lines.
+fn first_sql_word(sql: &str) -> String {
+    sql.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with("--"))
+        .flat_map(|l| l.split_whitespace())
+        .next()
+        .unwrap_or("")
+        .trim_start_matches('(')
+        .to_lowercase()
+}
+
+/// Whether a statement may return rows that can be wrapped in a CTE and
+/// aggregated to JSON. Only a guess: the wrapped query can still fail, e.g.
+/// for a WITH ... DELETE without RETURNING.
+fn returns_rows(sql: &str) -> bool {
+    matches!(
+        first_sql_word(sql).as_str(),
+        "select" | "with" | "values" | "table"
+    ) || sql.to_lowercase().contains("returning")
+}
+
+/// Run a statement and return its result set as text: column names, then
+/// rows. For statements like EXPLAIN and SHOW that can't go in a CTE.
+fn run_sql_table(
+    info: &InstanceInfo,
+    database: &str,
+    sql: &str,
+) -> Result<(Vec<String>, Vec<Vec<String>>), CliError> {
+    let psql_path = find_instance_binary(info, "psql")?;
+    ensure_runtime_libs_for_binary(&psql_path)?;
+
+    // Unit separator between fields, NUL after each record: neither can be
+    // part of a text value.
+    let output = std::process::Command::new(&psql_path)
+        .arg(instance_uri_without_password(info, database))
+        .env("PGPASSWORD", &info.password)
+        .args([
+            "-X",
+            "-A",
+            "-q",
+            "-F",
+            "\u{1f}",
+            "-0",
+            "-P",
+            "footer=off",
+            "-v",
+            "ON_ERROR_STOP=1",
+            "-c",
+        ])
+        .arg(sql)
+        .output()?;
+    if !output.status.success() {
+        return Err(CliError::Other(format!(
+            "Query failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut records = stdout
+        .split('\0')
+        .filter(|r| !r.is_empty())
+        .map(|r| r.split('\u{1f}').map(str::to_string).collect::<Vec<_>>());
+    let columns = records.next().unwrap_or_default();
+    Ok((columns, records.collect()))
+}
+
+/// Run one gateway query: rows as JSON objects for statements that return
+/// rows, otherwise the command tag.
+fn gateway_query(
+    info: &InstanceInfo,
+    request: &QueryRequest,
+) -> Result<serde_json::Value, CliError> {
+    let database = request.database.as_deref().unwrap_or(&info.database);
+    let sql = request.sql.trim().trim_end_matches(';');
+    let rows_response = |rows: serde_json::Value| {
+        let row_count = rows.as_array().map(|r| r.len()).unwrap_or(0);
+        serde_json::json!({ "rows": rows, "row_count": row_count })
+    };
+
+    if matches!(first_sql_word(sql).as_str(), "explain" | "show") {
+        let (columns, rows) = run_sql_table(info, database, sql)?;
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .cloned()
+                    .zip(row.into_iter().map(serde_json::Value::String))
+                    .collect()
+            })
+            .map(serde_json::Value::Object)
+            .collect();
+        return Ok(rows_response(serde_json::Value::Array(rows)));
+    }
+    if returns_rows(sql) {
+        // A failed statement has no effect, so running it unwrapped after
+        // the wrapped one failed is safe; its own error is the one to report.
+        let wrapped = format!(
+            "WITH q AS ({}\n) SELECT coalesce(json_agg(q), '[]'::json) FROM q;",
+            sql
+        );
+        if let Ok(json) = run_sql(info, database, &wrapped) {
+            return Ok(rows_response(serde_json::from_str(&json)?));
+        }
+    }
+    let command = run_sql_command(info, database, sql)?;
+    Ok(serde_json::json!({ "command": command }))
+}
+
+/// Compare secrets in time that depends only on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn json_response(
+    status: u16,
+    body: &serde_json::Value,
+) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let mut response =
+        tiny_http::Response::from_data(body.to_string().into_bytes()).with_status_code(status);
+    for (header, value) in [
+        ("Content-Type", "application/json"),
+        ("Access-Control-Allow-Origin", "*"),
+        (
+            "Access-Control-Allow-Headers",
+            "Authorization, Content-Type",
+        ),
+        ("Access-Control-Allow-Methods", "POST, OPTIONS"),
+    ] {
+        if let Ok(header) = tiny_http::Header::from_bytes(header, value) {
+            response.add_header(header);
+        }
+    }
+    response
+}
+
+fn handle_gateway_request(
+    name: &str,
+    token: &str,
+    request: &mut tiny_http::Request,
+) -> (u16, serde_json::Value) {
+    use std::io::Read;
+
+    if *request.method() == tiny_http::Method::Options {
+        return (204, serde_json::json!({}));
+    }
+    if request.url() != "/query" {
+        return (
+            404,
+            serde_json::json!({ "error": "not found; use POST /query" }),
+        );
+    }
+    if *request.method() != tiny_http::Method::Post {
+        return (405, serde_json::json!({ "error": "use POST" }));
+    }
+
+    let authorized = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| {
+            constant_time_eq(
+                h.value.as_str().as_bytes(),
+                format!("Bearer {}", token).as_bytes(),
+            )
+        })
+        .unwrap_or(false);
+    if !authorized {
+        return (
+            401,
+            serde_json::json!({ "error": "missing or invalid bearer token" }),
+        );
+    }
+
+    let mut body = String::new();
+    if request
+        .as_reader()
+        .take(GATEWAY_MAX_BODY as u64 + 1)
+        .read_to_string(&mut body)
+        .is_err()
+    {
+        return (
+            400,
+            serde_json::json!({ "error": "request body is not valid UTF-8" }),
+        );
+    }
+    if body.len() > GATEWAY_MAX_BODY {
+        return (
+            413,
+            serde_json::json!({ "error": format!("request body is larger than {} bytes", GATEWAY_MAX_BODY) }),
+        );
+    }
+    let is_json = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"));
+    // JSON bodies carry {"sql": ..., "database": ...}; anything else is the SQL itself.
+    let query = if is_json {
+        match serde_json::from_str::<QueryRequest>(&body) {
+            Ok(query) => query,
+            Err(e) => {
+                return (
+                    400,
+                    serde_json::json!({ "error": format!("invalid request: {}", e) }),
+                )
+            }
+        }
+    } else {
+        QueryRequest {
+            sql: body,
+            database: None,
+        }
+    };
+
+    let info = match load_running_instance(name) {
+        Ok(info) => info,
+        Err(e) => return (503, serde_json::json!({ "error": e.to_string() })),
+    };
+    match gateway_query(&info, &query) {
+        Ok(result) => (200, result),
+        Err(CliError::Other(message)) => (
+            400,
+            serde_json::json!({ "error": message.trim_start_matches("Query failed: ") }),
+        ),
+        Err(e) => (500, serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+/// Requests `pg0 serve` handles at once, each with its own psql.
+const GATEWAY_HANDLERS: usize = 8;
+
+/// Largest request body `pg0 serve` accepts.
+const GATEWAY_MAX_BODY: usize = 1024 * 1024;
+
+fn serve(name: String, port: u16, host: String, token: Option<String>) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let token = match token.or_else(|| std::env::var("PG0_SERVE_TOKEN").ok()) {
+        Some(token) => token,
+        None => load_or_create_serve_token(&name)?,
+    };
+
+    let server = tiny_http::Server::http((host.as_str(), port))
+        .map_err(|e| CliError::Other(format!("Cannot listen on {}:{}: {}", host, port, e)))?;
+
+    println!("Serving '{}' (database {}) over HTTP", name, info.database);
+    println!("  Endpoint: POST http://{}:{}/query", host, port);
+    println!("  Token:    {}", token);
+    println!();
+    println!(
+        "  curl -H 'Authorization: Bearer {}' -d 'SELECT now()' http://{}:{}/query",
+        token, host, port
+    );
+    println!();
+    println!("Press Ctrl+C to stop.");
+
+    // A fixed pool of handlers; further requests wait for a free one.
+    let server = std::sync::Arc::new(server);
+    let handlers: Vec<_> = (0..GATEWAY_HANDLERS)
+        .map(|_| {
+            let (server, name, token) = (server.clone(), name.clone(), token.clone());
+            std::thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let (status, body) = handle_gateway_request(&name, &token, &mut request);
+                    let _ = request.respond(json_response(status, &body));
+                }
+            })
+        })
+        .collect();
+    for handler in handlers {
+        let _ = handler.join();
+    }
+    Ok(())
+}
+
+/// Watchdog for background workers tied to one postmaster: exits the process
+/// once the instance stops or restarts, or `still_enabled` turns false.
+fn exit_when_inactive(
+    name: &str,
+    pid: u32,
+    worker: &'static str,
+    still_enabled: fn(&InstanceInfo) -> bool,
+) {
+    let name = name.to_string();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        let active = load_instance(&name).ok().flatten().is_some_and(|current| {
+            current.pid == pid && still_enabled(&current) && is_instance_running(&current)
+        });
+        if !active {
+            eprintln!("{}: instance stopped or worker disabled, exiting", worker);
+            std::process::exit(0);
+        }
+    });
+}
+
+/// Prometheus metrics endpoint settings for an instance (see `pg0 metrics enable`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MetricsConfig {
+    port: u16,
+}
+
+fn metrics_worker_files(name: &str) -> Result<(PathBuf, PathBuf), CliError> {
+    let dir = get_instance_dir(name)?;
+    Ok((dir.join("metrics.pid"), dir.join("metrics.log")))
+}
+
+fn running_metrics_worker(name: &str) -> Option<u32> {
+    let (pid_file, _) = metrics_worker_files(name).ok()?;
+    running_worker(&pid_file)
+}
+
+/// Start the metrics exporter for an instance if it has one configured.
+fn resume_metrics_worker(name: &str, info: &InstanceInfo) {
+    if info.metrics.is_none() {
+        return;
+    }
+    let result = metrics_worker_files(name).and_then(|(pid_file, log_file)| {
+        spawn_worker(&["metrics-worker", "--name", name], &pid_file, &log_file)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: Failed to start the metrics exporter: {}", e);
+    }
+}
+
+fn metrics_enable(name: String, port: u16) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let mut info = load_running_instance(&name)?;
+    let (pid_file, log_file) = metrics_worker_files(&name)?;
+    stop_worker(&pid_file);
+    if !is_port_available(port) {
+        return Err(CliError::Other(format!("Port {} is already in use", port)));
+    }
+    assign_port(&format!("{}/metrics", name), port, true)?;
+
+    info.metrics = Some(MetricsConfig { port });
+    save_instance(&name, &info)?;
+    spawn_worker(&["metrics-worker", "--name", &name], &pid_file, &log_file)?;
+
+    println!("Metrics enabled for '{}'.", name);
+    println!("  Endpoint: http://127.0.0.1:{}/metrics", port);
+    println!();
+    println!("Prometheus scrape config:");
+    println!("  - job_name: pg0-{}", name);
+    println!("    static_configs:");
+    println!("      - targets: ['127.0.0.1:{}']", port);
+    Ok(())
+}
+
+fn metrics_disable(name: String) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let mut info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    let (pid_file, _) = metrics_worker_files(&name)?;
+    stop_worker(&pid_file);
+    info.metrics = None;
+    save_instance(&name, &info)?;
+    release_port(&format!("{}/metrics", name))?;
+    println!("Metrics disabled for '{}'.", name);
+    Ok(())
+}
+
+/// Exported metrics: name, type and help text, in output order.
+const METRICS: &[(&str, &str, &str)] = &[
+    (
+        "pg_up",
+        "gauge",
+        "Whether the instance answered the metrics query",
+    ),
+    (
+        "pg_in_recovery",
+        "gauge",
+        "Whether the instance is a standby",
+    ),
+    ("pg_max_connections", "gauge", "Configured max_connections"),
+    ("pg_connections", "gauge", "Backends by state"),
+    ("pg_xact_commit_total", "counter", "Committed transactions"),
+    (
+        "pg_xact_rollback_total",
+        "counter",
+        "Rolled back transactions",
+    ),
+    (
+        "pg_blks_hit_total",
+        "counter",
+        "Block reads served from shared buffers",
+    ),
+    ("pg_blks_read_total", "counter", "Block reads from disk"),
+    (
+        "pg_cache_hit_ratio",
+        "gauge",
+        "Shared buffer cache hit ratio across databases",
+    ),
+    ("pg_deadlocks_total", "counter", "Deadlocks detected"),
+    ("pg_database_size_bytes", "gauge", "Database size on disk"),
+    (
+        "pg_replication_lag_bytes",
+        "gauge",
+        "WAL not yet replayed by each streaming replica",
+    ),
+    (
+        "pg_replication_lag_seconds",
+        "gauge",
+        "Time since the last replayed transaction (standbys)",
+    ),
+];
+
+/// One query returning `metric|labels|value` rows for everything in METRICS.
+const METRICS_SQL: &str = "\
+    SELECT 'pg_in_recovery', '', pg_is_in_recovery()::int::text \
+    UNION ALL SELECT 'pg_max_connections', '', current_setting('max_connections') \
+    UNION ALL SELECT 'pg_connections', format('state=\"%s\"', coalesce(state, 'background')), count(*)::text \
+        FROM pg_stat_activity GROUP BY state \
+    UNION ALL SELECT 'pg_xact_commit_total', format('datname=\"%s\"', datname), xact_commit::text \
+        FROM pg_stat_database WHERE datname IS NOT NULL \
+    UNION ALL SELECT 'pg_xact_rollback_total', format('datname=\"%s\"', datname), xact_rollback::text \
+        FROM pg_stat_database WHERE datname IS NOT NULL \
+    UNION ALL SELECT 'pg_blks_hit_total', format('datname=\"%s\"', datname), blks_hit::text \
+        FROM pg_stat_database WHERE datname IS NOT NULL \
+    UNION ALL SELECT 'pg_blks_read_total', format('datname=\"%s\"', datname), blks_read::text \
+        FROM pg_stat_database WHERE datname IS NOT NULL \
+    UNION ALL SELECT 'pg_cache_hit_ratio', '', \
+        coalesce(sum(blks_hit)::float8 / nullif(sum(blks_hit) + sum(blks_read), 0), 1)::text FROM pg_stat_database \
+    UNION ALL SELECT 'pg_deadlocks_total', format('datname=\"%s\"', datname), deadlocks::text \
+        FROM pg_stat_database WHERE datname IS NOT NULL \
+    UNION ALL SELECT 'pg_database_size_bytes', format('datname=\"%s\"', datname), pg_database_size(oid)::text \
+        FROM pg_database WHERE datallowconn \
+    UNION ALL SELECT 'pg_replication_lag_bytes', format('application_name=\"%s\"', application_name), \
+        coalesce(pg_wal_lsn_diff(pg_current_wal_lsn(), replay_lsn), 0)::text \
+        FROM pg_stat_replication WHERE NOT pg_is_in_recovery() \
+    UNION ALL SELECT 'pg_replication_lag_seconds', '', \
+        coalesce(extract(epoch FROM now() - pg_last_xact_replay_timestamp()), 0)::text WHERE pg_is_in_recovery();";
+
+/// Current metric samples keyed by metric name, as (labels, value) pairs.
+/// `pg_up` is always present.
+fn query_metric_samples(info: &InstanceInfo) -> HashMap<String, Vec<(String, String)>> {
+    let mut samples: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    let up = match run_sql(info, "postgres", METRICS_SQL) {
+        Ok(rows) => {
+            for row in rows.lines() {
+                let mut cols = row.splitn(3, '|');
+                if let (Some(metric), Some(labels), Some(value)) =
+                    (cols.next(), cols.next(), cols.next())
+                {
+                    samples
+                        .entry(metric.to_string())
+                        .or_default()
+                        .push((labels.to_string(), value.to_string()));
+                }
+            }
+            1
+        }
+        Err(_) => 0,
+    };
+    samples.insert("pg_up".to_string(), vec![(String::new(), up.to_string())]);
+    samples
+}
+
+/// Render the Prometheus text exposition for an instance.
+fn collect_metrics(info: &InstanceInfo) -> String {
+    let samples = query_metric_samples(info);
+    let mut out = String::new();
+    for (metric, kind, help) in METRICS {
+        let Some(values) = samples.get(*metric) else {
+            continue;
+        };
+        out.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} {}\n",
+            metric, help, metric, kind
+        ));
+        for (labels, value) in values {
+            if labels.is_empty() {
+                out.push_str(&format!("{} {}\n", metric, value));
+            } else {
+                out.push_str(&format!("{}{{{}}} {}\n", metric, labels, value));
+            }
+        }
+    }
+    out
+}
+
+/// Body of the hidden `pg0 metrics-worker` process: serve /metrics until the
+/// instance stops or metrics are disabled.
+fn metrics_worker(name: String) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let config = info
+        .metrics
+        .clone()
+        .ok_or_else(|| CliError::Other(format!("Metrics are not enabled for '{}'", name)))?;
+    let server = tiny_http::Server::http(("127.0.0.1", config.port))
+        .map_err(|e| CliError::Other(format!("Cannot listen on port {}: {}", config.port, e)))?;
+    eprintln!("pg0 metrics listening on 127.0.0.1:{}", config.port);
+    exit_when_inactive(&name, info.pid, "pg0 metrics", |current| {
+        current.metrics.is_some()
+    });
+
+    for request in server.incoming_requests() {
+        let response = if request.url() == "/metrics" {
+            let mut response = tiny_http::Response::from_string(collect_metrics(&info));
+            if let Ok(header) =
+                tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+            {
+                response.add_header(header);
+            }
+            response
+        } else {
+            tiny_http::Response::from_string("See /metrics\n").with_status_code(404)
+        };
+        let _ = request.respond(response);
+    }
+    Ok(())
+}
+
+/// Health check server settings for an instance (see `pg0 start --health-port`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HealthConfig {
+    port: u16,
+    /// Listen address; instances from before --health-host listen on localhost
+    #[serde(default = "default_health_host")]
+    host: String,
+}
+
+fn default_health_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+impl HealthConfig {
+    /// Base URL of the health check server, as reachable from this machine.
+    fn url(&self) -> String {
+        match self.host.parse::<std::net::IpAddr>() {
+            Ok(ip) if ip.is_unspecified() => format!("http://127.0.0.1:{}", self.port),
+            Ok(std::net::IpAddr::V6(ip)) => format!("http://[{}]:{}", ip, self.port),
+            _ => format!("http://{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// How long a probe may take before the instance counts as wedged.
+const HEALTH_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+fn health_worker_files(name: &str) -> Result<(PathBuf, PathBuf), CliError> {
+    let dir = get_instance_dir(name)?;
+    Ok((dir.join("health.pid"), dir.join("health.log")))
+}
+
+fn running_health_worker(name: &str) -> Option<u32> {
+    let (pid_file, _) = health_worker_files(name).ok()?;
+    running_worker(&pid_file)
+}
+
+fn resume_health_worker(name: &str, info: &InstanceInfo) {
+    if info.health.is_none() {
+        return;
+    }
+    let result = health_worker_files(name).and_then(|(pid_file, log_file)| {
+        stop_worker(&pid_file);
+        spawn_worker(&["health-worker", "--name", name], &pid_file, &log_file)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: Failed to start the health check server: {}", e);
+    }
+}
+
+/// What happens to an instance when its `--ttl` expires.
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TtlAction {
+    /// Stop the instance, keeping its data
+    Stop,
+    /// Stop the instance and delete it with all its data
+    Drop,
+}
+
+impl TtlAction {
+    fn verb(self) -> &'static str {
+        match self {
+            TtlAction::Stop => "stopping",
+            TtlAction::Drop => "dropping",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct TtlConfig {
+    /// Unix timestamp at which the instance expires.
+    expires_at: u64,
+    action: TtlAction,
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn print_ttl(ttl: &TtlConfig) {
+    let remaining = ttl.expires_at.saturating_sub(unix_now());
+    match ttl.action {
+        TtlAction::Stop => println!("  TTL:      stops in {}", format_duration_secs(remaining)),
+        TtlAction::Drop => println!(
+            "  TTL:      dropped (with all data) in {}",
+            format_duration_secs(remaining)
+        ),
+    }
+}
+
+fn autostop_worker_files(name: &str) -> Result<(PathBuf, PathBuf), CliError> {
+    let dir = get_instance_dir(name)?;
+    Ok((dir.join("autostop.pid"), dir.join("autostop.log")))
+}
+
+fn resume_autostop_worker(name: &str, info: &InstanceInfo) {
+    if info.idle_timeout.is_none() && info.ttl.is_none() {
+        return;
+    }
+    let result = autostop_worker_files(name).and_then(|(pid_file, log_file)| {
+        stop_worker(&pid_file);
+        spawn_worker(&["autostop-worker", "--name", name], &pid_file, &log_file)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: Failed to start the idle monitor: {}", e);
+    }
+}
+
+/// Body of the hidden `pg0 autostop-worker` process: stops the instance once
+/// no client (or replica) has been connected for its idle timeout, and stops
+/// or drops it when its TTL expires.
+fn autostop_worker(name: String) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let mut interval = 30;
+    if let Some(timeout) = info.idle_timeout {
+        eprintln!(
+            "pg0 autostop: stopping '{}' after {} idle",
+            name,
+            format_duration_secs(timeout)
+        );
+        interval = interval.min(timeout / 6);
+    }
+    if let Some(ttl) = &info.ttl {
+        let remaining = ttl.expires_at.saturating_sub(unix_now());
+        eprintln!(
+            "pg0 autostop: {} '{}' in {}",
+            ttl.action.verb(),
+            name,
+            format_duration_secs(remaining)
+        );
+        interval = interval.min(remaining / 6);
+    }
+    if info.idle_timeout.is_none() && info.ttl.is_none() {
+        return Err(CliError::Other(format!(
+            "No idle timeout or TTL configured for '{}'",
+            name
+        )));
+    }
+
+    // Not exit_when_inactive: its watchdog would kill this process halfway
+    // through the stop below.
+    let interval = std::time::Duration::from_secs(interval.max(1));
+    let mut last_active = std::time::Instant::now();
+    loop {
+        std::thread::sleep(interval);
+        let active = load_instance(&name)?.is_some_and(|current| {
+            current.pid == info.pid
+                && (current.idle_timeout.is_some() || current.ttl.is_some())
+                && is_instance_running(&info)
+        });
+        if !active {
+            eprintln!("pg0 autostop: instance stopped or restarted, exiting");
+            return Ok(());
+        }
+
+        if let Some(ttl) = &info.ttl {
+            if unix_now() >= ttl.expires_at {
+                eprintln!("pg0 autostop: TTL of '{}' expired", name);
+                return match ttl.action {
+                    TtlAction::Stop => stop(name, &StopOptions::default()),
+                    TtlAction::Drop => drop_instance(name, true),
+                };
+            }
+        }
+        let Some(timeout) = info.idle_timeout else {
+            continue;
+        };
+        // A failing query (e.g. during a restart) counts as activity.
+        let connections = run_sql(
+            &info,
+            "postgres",
+            "SELECT count(*) FROM pg_stat_activity \
+             WHERE backend_type IN ('client backend', 'walsender') AND pid <> pg_backend_pid();",
+        )
+        .ok()
+        .and_then(|count| count.trim().parse::<u64>().ok());
+        if connections != Some(0) {
+            last_active = std::time::Instant::now();
+        } else if last_active.elapsed().as_secs() >= timeout {
+            eprintln!(
+                "pg0 autostop: no connections for {}, stopping '{}'",
+                format_duration_secs(timeout),
+                name
+            );
+            return stop(name, &StopOptions::default());
+        }
+    }
+}
+
+/// Text of the 'M' field of an ErrorResponse body.
+fn pg_error_message(body: &[u8]) -> String {
+    body.split(|b| *b == 0)
+        .find_map(|field| field.strip_prefix(b"M"))
+        .map(|m| String::from_utf8_lossy(m).into_owned())
+        .unwrap_or_else(|| "unknown server error".to_string())
+}
+
+enum ProbeFailure {
+    /// No answer from the postmaster: crashed, wedged or unreachable
+    Unresponsive(String),
+    /// The server answered but refused the connection or query
+    Refused(String),
+}
+
+/// Open a real connection as the instance's user and run `SELECT 1`, giving
+/// up after `HEALTH_PROBE_TIMEOUT` at every step.
+fn probe_instance(info: &InstanceInfo) -> Result<(), ProbeFailure> {
+    let unresponsive = |e: std::io::Error| {
+        ProbeFailure::Unresponsive(match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                format!("no response within {}s", HEALTH_PROBE_TIMEOUT.as_secs())
+            }
+            _ => e.to_string(),
+        })
+    };
+    let address = std::net::SocketAddr::from(([127, 0, 0, 1], info.port));
+    let stream = std::net::TcpStream::connect_timeout(&address, HEALTH_PROBE_TIMEOUT)
+        .map_err(unresponsive)?;
+    stream
+        .set_read_timeout(Some(HEALTH_PROBE_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(HEALTH_PROBE_TIMEOUT)))
+        .map_err(unresponsive)?;
+
+    let key = (
+        info.username.clone(),
+        info.database.clone(),
+        info.password.clone(),
+    );
+    let mut conn = server_handshake(stream, &key).map_err(|e| match e {
+        ServerConnectError::Io(e) => unresponsive(e),
+        ServerConnectError::Rejected(body) => ProbeFailure::Refused(pg_error_message(&body)),
+    })?;
+    write_pg_message(&mut conn.stream, b'Q', b"SELECT 1\0").map_err(unresponsive)?;
+    let mut error = None;
+    loop {
+        let (tag, body) = read_pg_message(&mut conn.stream).map_err(unresponsive)?;
+        match tag {
+            b'E' => error = Some(ProbeFailure::Refused(pg_error_message(&body))),
+            b'Z' => break,
+            _ => {}
+        }
+    }
+    let _ = write_pg_message(&mut conn.stream, b'X', &[]);
+    error.map_or(Ok(()), Err)
+}
+
+/// Body of the hidden `pg0 health-worker` process.
+///
+/// `/healthz` (liveness) fails only when the postmaster does not complete a
+/// connection handshake in time: a crashed or wedged server. `/readyz`
+/// (readiness) also requires the query to succeed, so it fails while the
+/// server is starting up, shutting down or refusing connections.
+fn health_worker(name: String) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let config = info
+        .health
+        .clone()
+        .ok_or_else(|| CliError::Other(format!("No health port configured for '{}'", name)))?;
+    let server = tiny_http::Server::http((config.host.as_str(), config.port)).map_err(|e| {
+        CliError::Other(format!(
+            "Cannot listen on {}:{}: {}",
+            config.host, config.port, e
+        ))
+    })?;
+    eprintln!("pg0 health listening on {}:{}", config.host, config.port);
+    exit_when_inactive(&name, info.pid, "pg0 health", |current| {
+        current.health.is_some()
+    });
+
+    for request in server.incoming_requests() {
+        let path = request.url().split('?').next().unwrap_or_default();
+        let (status, body) = match path {
+            "/healthz" | "/readyz" => {
+                let probe = if is_instance_running(&info) {
+                    probe_instance(&info)
+                } else {
+                    Err(ProbeFailure::Unresponsive(
+                        "postmaster is not running".to_string(),
+                    ))
+                };
+                // Server messages only go to the log: they can name roles and
+                // databases, and the endpoint may be reachable by anyone.
+                match probe {
+                    Ok(()) => (200, "ok\n"),
+                    // A refusal such as "the database system is starting up"
+                    // still proves the postmaster is alive.
+                    Err(ProbeFailure::Refused(reason)) => {
+                        eprintln!("pg0 health: {} refused: {}", path, reason);
+                        if path == "/healthz" {
+                            (200, "ok\n")
+                        } else {
+                            (503, "unhealthy: server refused the connection\n")
+                        }
+                    }
+                    Err(ProbeFailure::Unresponsive(reason)) => {
+                        eprintln!("pg0 health: {} unresponsive: {}", path, reason);
+                        (503, "unhealthy: server is not responding\n")
+                    }
+                }
+            }
+            _ => (404, "See /healthz and /readyz\n"),
+        };
+        let _ = request.respond(tiny_http::Response::from_string(body).with_status_code(status));
+    }
+    Ok(())
+}
+
+/// Where to send OpenTelemetry data, from the standard OTEL_* environment
+/// variables. Only OTLP over HTTP is supported; payloads are JSON encoded.
+struct OtlpConfig {
+    traces_endpoint: Option<String>,
+    metrics_endpoint: Option<String>,
+    headers: Vec<(String, String)>,
+    resource: Vec<(String, String)>,
+}
+
+/// Parse a `key=value,key2=value2` OTEL list (headers, resource attributes).
+fn parse_otel_list(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (percent_decode(k.trim()), percent_decode(v.trim())))
+        .collect()
+}
+
+fn otlp_config() -> Option<&'static OtlpConfig> {
+    static CONFIG: std::sync::OnceLock<Option<OtlpConfig>> = std::sync::OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let env = |key: &str| std::env::var(key).ok().filter(|v| !v.trim().is_empty());
+            if env("OTEL_SDK_DISABLED").is_some_and(|v| v.eq_ignore_ascii_case("true")) {
+                return None;
+            }
+            if env("OTEL_EXPORTER_OTLP_PROTOCOL").is_some_and(|p| p == "grpc") {
+                eprintln!("Warning: pg0 exports OpenTelemetry over OTLP/HTTP only; OTEL_EXPORTER_OTLP_PROTOCOL=grpc is ignored");
+                return None;
+            }
+            let base = env("OTEL_EXPORTER_OTLP_ENDPOINT").map(|e| e.trim_end_matches('/').to_string());
+            let endpoint = |signal: &str, exporter_var: &str| {
+                if env(exporter_var).is_some_and(|e| e == "none") {
+                    return None;
+                }
+                env(&format!("OTEL_EXPORTER_OTLP_{}_ENDPOINT", signal.to_uppercase()))
+                    .or_else(|| base.as_ref().map(|b| format!("{}/v1/{}", b, signal)))
+            };
+            let traces_endpoint = endpoint("traces", "OTEL_TRACES_EXPORTER");
+            let metrics_endpoint = endpoint("metrics", "OTEL_METRICS_EXPORTER");
+            if traces_endpoint.is_none() && metrics_endpoint.is_none() {
+                return None;
+            }
+
+            let mut resource = vec![
+                (
+                    "service.name".to_string(),
+                    env("OTEL_SERVICE_NAME").unwrap_or_else(|| "pg0".to_string()),
+                ),
+                ("service.version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+            ];
+            resource.extend(
+                parse_otel_list(&env("OTEL_RESOURCE_ATTRIBUTES").unwrap_or_default())
+                    .into_iter()
+                    .filter(|(k, _)| k != "service.name"),
+            );
+            Some(OtlpConfig {
+                traces_endpoint,
+                metrics_endpoint,
+                headers: parse_otel_list(&env("OTEL_EXPORTER_OTLP_HEADERS").unwrap_or_default()),
+                resource,
+            })
+        })
+        .as_ref()
+}
+
+fn unix_nanos() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    let _ = getrandom::getrandom(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A completed pg0 operation, waiting to be exported.
+struct FinishedSpan {
+    name: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    start_ns: u64,
+    end_ns: u64,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+static FINISHED_SPANS: std::sync::Mutex<Vec<FinishedSpan>> = std::sync::Mutex::new(Vec::new());
+static ACTIVE_SPANS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+fn trace_id() -> &'static str {
+    static TRACE_ID: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    TRACE_ID.get_or_init(|| random_hex(16))
+}
+
+/// A timed pg0 operation, recorded when dropped. Spans nest: one started
+/// while another is active becomes its child. A no-op unless OTLP export is
+/// configured.
+struct OperationSpan {
+    name: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    start_ns: u64,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+    enabled: bool,
+}
+
+fn operation_span(name: &str) -> OperationSpan {
+    let enabled = otlp_config().is_some();
+    let span_id = if enabled {
+        random_hex(8)
+    } else {
+        String::new()
+    };
+    let parent_span_id = if enabled {
+        let mut active = ACTIVE_SPANS.lock().unwrap();
+        let parent = active.last().cloned();
+        active.push(span_id.clone());
+        parent
+    } else {
+        None
+    };
+    OperationSpan {
+        name: name.to_string(),
+        span_id,
+        parent_span_id,
+        start_ns: unix_nanos(),
+        attributes: Vec::new(),
+        error: None,
+        enabled,
+    }
+}
+
+impl OperationSpan {
+    fn attr(&mut self, key: &str, value: impl std::fmt::Display) {
+        if self.enabled {
+            self.attributes.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    fn fail(&mut self, error: impl std::fmt::Display) {
+        self.error = Some(error.to_string());
+    }
+}
+
+impl Drop for OperationSpan {
+    fn drop(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        ACTIVE_SPANS
+            .lock()
+            .unwrap()
+            .retain(|id| id != &self.span_id);
+        FINISHED_SPANS.lock().unwrap().push(FinishedSpan {
+            name: std::mem::take(&mut self.name),
+            span_id: std::mem::take(&mut self.span_id),
+            parent_span_id: self.parent_span_id.take(),
+            start_ns: self.start_ns,
+            end_ns: unix_nanos(),
+            attributes: std::mem::take(&mut self.attributes),
+            error: self.error.take(),
+        });
+    }
+}
+
+fn otlp_attributes(attributes: &[(String, String)]) -> serde_json::Value {
+    attributes
+        .iter()
+        .map(|(key, value)| serde_json::json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+fn otlp_resource(config: &OtlpConfig) -> serde_json::Value {
+    serde_json::json!({ "attributes": otlp_attributes(&config.resource) })
+}
+
+fn otlp_scope() -> serde_json::Value {
+    serde_json::json!({ "name": "pg0", "version": env!("CARGO_PKG_VERSION") })
+}
+
+/// POST one OTLP/JSON payload. Telemetry must never break pg0, so failures
+/// are only reported in verbose logs.
+fn otlp_post(config: &OtlpConfig, endpoint: &str, payload: &serde_json::Value) {
+    let client = match reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+    {
+        Ok(client) => client,
+        Err(_) => return,
+    };
+    let mut request = client.post(endpoint).json(payload);
+    for (key, value) in &config.headers {
+        request = request.header(key.as_str(), value.as_str());
     }
-    #[cfg(windows)]
+    match request.send() {
+        Ok(response) if !response.status().is_success() => {
+            tracing::debug!("OTLP export to {} returned {}", endpoint, response.status())
+        }
+        Err(e) => tracing::debug!("OTLP export to {} failed: {}", endpoint, e),
+        _ => {}
+    }
+}
+
+/// Export the spans recorded by this process, plus a duration metric per
+/// operation.
+fn flush_telemetry() {
+    let Some(config) = otlp_config() else {
+        return;
+    };
+    let spans: Vec<FinishedSpan> = std::mem::take(&mut *FINISHED_SPANS.lock().unwrap());
+    if spans.is_empty() {
+        return;
+    }
+
+    if let Some(endpoint) = &config.traces_endpoint {
+        let otlp_spans: Vec<serde_json::Value> = spans
+            .iter()
+            .map(|span| {
+                let mut otlp = serde_json::json!({
+                    "traceId": trace_id(),
+                    "spanId": span.span_id,
+                    "name": span.name,
+                    "kind": 1,
+                    "startTimeUnixNano": span.start_ns.to_string(),
+                    "endTimeUnixNano": span.end_ns.to_string(),
+                    "attributes": otlp_attributes(&span.attributes),
+                    "status": match &span.error {
+                        Some(message) => serde_json::json!({ "code": 2, "message": message }),
+                        None => serde_json::json!({ "code": 1 }),
+                    },
+                });
+                if let Some(parent) = &span.parent_span_id {
+                    otlp["parentSpanId"] = serde_json::Value::String(parent.clone());
+                }
+                otlp
+            })
+            .collect();
+        let payload = serde_json::json!({
+            "resourceSpans": [{
+                "resource": otlp_resource(config),
+                "scopeSpans": [{ "scope": otlp_scope(), "spans": otlp_spans }],
+            }]
+        });
+        otlp_post(config, endpoint, &payload);
+    }
+
+    if let Some(endpoint) = &config.metrics_endpoint {
+        let data_points: Vec<serde_json::Value> = spans
+            .iter()
+            .map(|span| {
+                let mut attributes = vec![("operation".to_string(), span.name.clone())];
+                attributes.push((
+                    "status".to_string(),
+                    if span.error.is_some() { "error" } else { "ok" }.to_string(),
+                ));
+                serde_json::json!({
+                    "asDouble": (span.end_ns - span.start_ns) as f64 / 1e9,
+                    "timeUnixNano": span.end_ns.to_string(),
+                    "attributes": otlp_attributes(&attributes),
+                })
+            })
+            .collect();
+        let payload = serde_json::json!({
+            "resourceMetrics": [{
+                "resource": otlp_resource(config),
+                "scopeMetrics": [{
+                    "scope": otlp_scope(),
+                    "metrics": [{
+                        "name": "pg0.operation.duration",
+                        "description": "Duration of pg0 operations",
+                        "unit": "s",
+                        "gauge": { "dataPoints": data_points },
+                    }],
+                }],
+            }]
+        });
+        otlp_post(config, endpoint, &payload);
+    }
+}
+
+fn otel_worker_files(name: &str) -> Result<(PathBuf, PathBuf), CliError> {
+    let dir = get_instance_dir(name)?;
+    Ok((dir.join("otel.pid"), dir.join("otel.log")))
+}
+
+/// Start periodic database stats export for an instance when an OTLP metrics
+/// endpoint is configured in pg0's environment (which the worker inherits).
+fn resume_otel_worker(name: &str) {
+    if otlp_config()
+        .and_then(|c| c.metrics_endpoint.as_ref())
+        .is_none()
     {
-        use std::process::Command;
-        Command::new("tasklist")
-            .args(["/FI", &format!("PID eq {}", pid)])
-            .output()
-            .map(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
-            .unwrap_or(false)
+        return;
+    }
+    let result = otel_worker_files(name).and_then(|(pid_file, log_file)| {
+        spawn_worker(&["otel-worker", "--name", name], &pid_file, &log_file)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: Failed to start OpenTelemetry export: {}", e);
+    }
+}
+
+/// Body of the hidden `pg0 otel-worker` process: export the instance's
+/// database stats every OTEL_METRIC_EXPORT_INTERVAL (default 60s).
+fn otel_worker(name: String) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let config = otlp_config()
+        .filter(|c| c.metrics_endpoint.is_some())
+        .ok_or_else(|| CliError::Other("No OTLP metrics endpoint configured".to_string()))?;
+    let endpoint = config.metrics_endpoint.clone().unwrap_or_default();
+    let interval = std::env::var("OTEL_METRIC_EXPORT_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60_000u64);
+    eprintln!(
+        "pg0 otel exporting stats for '{}' to {} every {}ms",
+        name, endpoint, interval
+    );
+    exit_when_inactive(&name, info.pid, "pg0 otel", |_| true);
+
+    let start_ns = unix_nanos();
+    let mut resource = config.resource.clone();
+    resource.push(("db.system".to_string(), "postgresql".to_string()));
+    resource.push(("pg0.instance".to_string(), name.clone()));
+    let resource = serde_json::json!({ "attributes": otlp_attributes(&resource) });
+
+    loop {
+        let now = unix_nanos().to_string();
+        let samples = query_metric_samples(&info);
+        let metrics: Vec<serde_json::Value> = METRICS
+            .iter()
+            .filter_map(|(metric, kind, help)| {
+                let values = samples.get(*metric)?;
+                let data_points: Vec<serde_json::Value> = values
+                    .iter()
+                    .filter_map(|(labels, value)| {
+                        let attributes: Vec<(String, String)> = labels
+                            .split(',')
+                            .filter_map(|l| l.split_once('='))
+                            .map(|(k, v)| (k.to_string(), v.trim_matches('"').to_string()))
+                            .collect();
+                        Some(serde_json::json!({
+                            "asDouble": value.parse::<f64>().ok()?,
+                            "startTimeUnixNano": start_ns.to_string(),
+                            "timeUnixNano": now,
+                            "attributes": otlp_attributes(&attributes),
+                        }))
+                    })
+                    .collect();
+                let name = metric.trim_end_matches("_total").replacen("pg_", "postgresql.", 1);
+                Some(if *kind == "counter" {
+                    serde_json::json!({
+                        "name": name,
+                        "description": help,
+                        "sum": { "aggregationTemporality": 2, "isMonotonic": true, "dataPoints": data_points },
+                    })
+                } else {
+                    serde_json::json!({ "name": name, "description": help, "gauge": { "dataPoints": data_points } })
+                })
+            })
+            .collect();
+        let payload = serde_json::json!({
+            "resourceMetrics": [{
+                "resource": resource,
+                "scopeMetrics": [{ "scope": otlp_scope(), "metrics": metrics }],
+            }]
+        });
+        otlp_post(config, &endpoint, &payload);
+        std::thread::sleep(std::time::Duration::from_millis(interval));
+    }
+}
+
+/// Built-in connection pooler settings for an instance (see `pg0 pool enable`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PoolConfig {
+    port: u16,
+    mode: PoolMode,
+    size: usize,
+}
+
+/// When a pooled client gives its server connection back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum PoolMode {
+    /// After each transaction (session state such as SET or named prepared
+    /// statements does not survive across transactions)
+    Transaction,
+    /// When the client disconnects
+    Session,
+}
+
+impl PoolMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PoolMode::Transaction => "transaction",
+            PoolMode::Session => "session",
+        }
+    }
+}
+
+fn pool_worker_files(name: &str) -> Result<(PathBuf, PathBuf), CliError> {
+    let dir = get_instance_dir(name)?;
+    Ok((dir.join("pool.pid"), dir.join("pool.log")))
+}
+
+/// PID of the instance's pooler process, if it is running.
+fn running_pool_worker(name: &str) -> Option<u32> {
+    let (pid_file, _) = pool_worker_files(name).ok()?;
+    running_worker(&pid_file)
+}
+
+/// Start the pooler process for an instance if it has one configured.
+fn resume_pool_worker(name: &str, info: &InstanceInfo) {
+    if info.pool.is_none() {
+        return;
+    }
+    let result = pool_worker_files(name).and_then(|(pid_file, log_file)| {
+        spawn_worker(&["pool-worker", "--name", name], &pid_file, &log_file)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: Failed to start the connection pooler: {}", e);
+    }
+}
+
+fn pool_enable(
+    name: String,
+    mode: PoolMode,
+    size: usize,
+    port: Option<u16>,
+    show_secrets: bool,
+) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let mut info = load_running_instance(&name)?;
+    if size == 0 {
+        return Err(CliError::Other("--size must be at least 1".to_string()));
+    }
+    if info.socket_only {
+        return Err(CliError::Other(format!(
+            "Instance '{}' was started with --socket-only; the pooler needs TCP",
+            name
+        )));
+    }
+
+    // Re-enabling replaces the running pooler, so its port is free to reuse.
+    let (pid_file, log_file) = pool_worker_files(&name)?;
+    stop_worker(&pid_file);
+    let port = match port {
+        Some(port) => {
+            if !is_port_available(port) {
+                return Err(CliError::Other(format!("Port {} is already in use", port)));
+            }
+            assign_port(&format!("{}/pool", name), port, true)?
+        }
+        None => {
+            let preferred = info
+                .pool
+                .as_ref()
+                .map_or(info.port.saturating_add(1), |pool| pool.port);
+            assign_port(&format!("{}/pool", name), preferred, false)?
+        }
+    };
+
+    info.pool = Some(PoolConfig { port, mode, size });
+    save_instance(&name, &info)?;
+    spawn_worker(&["pool-worker", "--name", &name], &pid_file, &log_file)?;
+
+    println!("Connection pooler enabled for '{}'.", name);
+    println!("  Mode:     {}", mode.as_str());
+    println!("  Size:     {} server connections per user/database", size);
+    println!("  Port:     {}", port);
+    println!();
+    let secret = info.secret_password || info.keychain_account.is_some();
+    let password = if secret && !show_secrets {
+        REDACTED
+    } else {
+        info.password.as_str()
+    };
+    println!(
+        "Pooled URI: postgresql://{}:{}@127.0.0.1:{}/{}",
+        info.username, password, port, info.database
+    );
+    Ok(())
+}
+
+fn pool_disable(name: String) -> Result<(), CliError> {
+    let _lock = lock_instance(&name)?;
+    let mut info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    let (pid_file, _) = pool_worker_files(&name)?;
+    stop_worker(&pid_file);
+    info.pool = None;
+    save_instance(&name, &info)?;
+    release_port(&format!("{}/pool", name))?;
+    println!("Connection pooler disabled for '{}'.", name);
+    Ok(())
+}
+
+/// Largest protocol message accepted from an authenticated peer; the same
+/// limit PostgreSQL puts on its own allocations (MaxAllocSize).
+const MAX_PG_MESSAGE: usize = 0x3fff_ffff;
+
+/// Largest message accepted before the client has authenticated (startup
+/// packet, password).
+const MAX_STARTUP_MESSAGE: usize = 10_000;
+
+/// Read one length-prefixed message (type byte, then int32 length that
+/// includes itself) from a frontend/backend protocol stream.
+fn read_pg_message(stream: &mut impl std::io::Read) -> std::io::Result<(u8, Vec<u8>)> {
+    read_pg_message_limited(stream, MAX_PG_MESSAGE)
+}
+
+/// `read_pg_message`, rejecting bodies longer than `max` bytes.
+fn read_pg_message_limited(
+    stream: &mut impl std::io::Read,
+    max: usize,
+) -> std::io::Result<(u8, Vec<u8>)> {
+    use std::io::Read;
+    let mut header = [0u8; 5];
+    stream.read_exact(&mut header)?;
+    let len = i32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+    if len < 4 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "invalid message length",
+        ));
+    }
+    let len = len as usize - 4;
+    if len > max {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds the {} byte limit", len, max),
+        ));
+    }
+    // Grow the buffer as data arrives instead of trusting the peer's length.
+    let mut body = Vec::new();
+    stream.take(len as u64).read_to_end(&mut body)?;
+    if body.len() < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok((header[0], body))
+}
+
+fn write_pg_message(stream: &mut impl std::io::Write, tag: u8, body: &[u8]) -> std::io::Result<()> {
+    let mut message = Vec::with_capacity(body.len() + 5);
+    message.push(tag);
+    message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+    message.extend_from_slice(body);
+    stream.write_all(&message)
+}
+
+/// Body of an ErrorResponse carrying `message`.
+fn pg_error_body(code: &str, message: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (field, value) in [
+        (b'S', "FATAL"),
+        (b'V', "FATAL"),
+        (b'C', code),
+        (b'M', message),
+    ] {
+        body.push(field);
+        body.extend_from_slice(value.as_bytes());
+        body.push(0);
+    }
+    body.push(0);
+    body
+}
+
+/// Server connections are pooled per user, database and password; a pooled
+/// connection therefore also proves the client's password.
+type PoolKey = (String, String, String);
+
+/// An authenticated connection to the instance.
+struct ServerConn<S = std::net::TcpStream> {
+    stream: S,
+    /// ParameterStatus bodies from the server's startup, replayed to clients
+    parameters: Vec<Vec<u8>>,
+    /// BackendKeyData body (process id and secret) for cancel requests
+    backend_key: Option<[u8; 8]>,
+}
+
+enum ServerConnectError {
+    Io(std::io::Error),
+    /// The server refused the connection; an ErrorResponse body to forward
+    Rejected(Vec<u8>),
+}
+
+impl From<std::io::Error> for ServerConnectError {
+    fn from(e: std::io::Error) -> Self {
+        ServerConnectError::Io(e)
+    }
+}
+
+fn connect_server(port: u16, key: &PoolKey) -> Result<ServerConn, ServerConnectError> {
+    let stream = std::net::TcpStream::connect(("127.0.0.1", port))?;
+    stream.set_nodelay(true)?;
+    server_handshake(stream, key)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    use hmac::Mac;
+    let mut mac =
+        hmac::Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// SCRAM's Hi(): PBKDF2 with HMAC-SHA-256 and a single output block.
+fn scram_salted_password(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut block = hmac_sha256(password, &[salt, &1u32.to_be_bytes()].concat());
+    let mut result = block;
+    for _ in 1..iterations {
+        block = hmac_sha256(password, &block);
+        result.iter_mut().zip(&block).for_each(|(r, b)| *r ^= b);
+    }
+    result
+}
+
+/// Answer a SCRAM-SHA-256 server-first message: returns the client-final
+/// message and the server signature the server-final message must carry.
+///
+/// PostgreSQL takes the user name from the startup packet and pg0 never
+/// offers channel binding, so the GS2 header is always `n,,`. Passwords are
+/// used as-is (SASLprep leaves ASCII passwords unchanged).
+fn scram_client_final(
+    password: &str,
+    client_first_bare: &str,
+    server_first: &str,
+) -> Result<(String, [u8; 32]), String> {
+    use base64::Engine;
+    use sha2::Digest;
+    let b64 = base64::engine::general_purpose::STANDARD;
+
+    let attribute = |name: &str| {
+        server_first
+            .split(',')
+            .find_map(|field| field.strip_prefix(name))
+            .ok_or_else(|| format!("SCRAM server-first message lacks '{}'", name))
+    };
+    let nonce = attribute("r=")?;
+    let client_nonce = client_first_bare
+        .split(',')
+        .find_map(|field| field.strip_prefix("r="))
+        .unwrap_or_default();
+    if !nonce.starts_with(client_nonce) || nonce.len() == client_nonce.len() {
+        return Err("SCRAM server nonce does not extend the client nonce".to_string());
+    }
+    let salt = b64
+        .decode(attribute("s=")?)
+        .map_err(|e| format!("invalid SCRAM salt: {}", e))?;
+    let iterations: u32 = attribute("i=")?
+        .parse()
+        .ok()
+        .filter(|&i| i > 0)
+        .ok_or("invalid SCRAM iteration count")?;
+
+    let salted = scram_salted_password(password.as_bytes(), &salt, iterations);
+    let client_key = hmac_sha256(&salted, b"Client Key");
+    let stored_key = sha2::Sha256::digest(client_key);
+    let without_proof = format!("c=biws,r={}", nonce);
+    let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+    let signature = hmac_sha256(&stored_key, auth_message.as_bytes());
+    let proof: Vec<u8> = client_key
+        .iter()
+        .zip(&signature)
+        .map(|(k, s)| k ^ s)
+        .collect();
+    let server_key = hmac_sha256(&salted, b"Server Key");
+    let server_signature = hmac_sha256(&server_key, auth_message.as_bytes());
+    Ok((
+        format!("{},p={}", without_proof, b64.encode(proof)),
+        server_signature,
+    ))
+}
+
+/// Check a SCRAM server-final message against the expected server signature.
+fn scram_verify_server_final(server_final: &str, expected: &[u8; 32]) -> Result<(), String> {
+    use base64::Engine;
+    if let Some(error) = server_final.strip_prefix("e=") {
+        return Err(format!("SCRAM authentication failed: {}", error));
+    }
+    let signature = server_final
+        .strip_prefix("v=")
+        .and_then(|v| {
+            base64::engine::general_purpose::STANDARD
+                .decode(v.split(',').next()?)
+                .ok()
+        })
+        .ok_or("invalid SCRAM server-final message")?;
+    if !constant_time_eq(&signature, expected) {
+        return Err("SCRAM server signature does not match".to_string());
+    }
+    Ok(())
+}
+
+/// Run the startup and authentication exchange on a fresh server
+/// connection. Cleartext password and SCRAM-SHA-256 authentication are
+/// supported, which covers both `--auth` modes.
+fn server_handshake<S: std::io::Read + std::io::Write>(
+    mut stream: S,
+    key: &PoolKey,
+) -> Result<ServerConn<S>, ServerConnectError> {
+    let (user, database, password) = key;
+    let rejected = |message: String| ServerConnectError::Rejected(pg_error_body("28000", &message));
+
+    let mut startup = Vec::new();
+    startup.extend_from_slice(&196608i32.to_be_bytes()); // protocol 3.0
+    for (param, value) in [("user", user), ("database", database)] {
+        startup.extend_from_slice(param.as_bytes());
+        startup.push(0);
+        startup.extend_from_slice(value.as_bytes());
+        startup.push(0);
+    }
+    startup.push(0);
+    let mut packet = (startup.len() as i32 + 4).to_be_bytes().to_vec();
+    packet.extend_from_slice(&startup);
+    stream.write_all(&packet)?;
+
+    let mut parameters = Vec::new();
+    let mut backend_key = None;
+    // client-first-message-bare, then the expected server signature
+    let mut scram_first = None;
+    let mut scram_signature = None;
+    loop {
+        let (tag, body) = read_pg_message(&mut stream)?;
+        match tag {
+            b'R' => {
+                match body
+                    .get(..4)
+                    .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                {
+                    Some(0) => {}
+                    Some(3) => {
+                        let mut message = password.as_bytes().to_vec();
+                        message.push(0);
+                        write_pg_message(&mut stream, b'p', &message)?;
+                    }
+                    // AuthenticationSASL: a list of mechanism names
+                    Some(10) => {
+                        if !body[4..].split(|&b| b == 0).any(|m| m == b"SCRAM-SHA-256") {
+                            return Err(rejected(
+                                "server offers no supported SASL mechanism".to_string(),
+                            ));
+                        }
+                        let mut nonce = [0u8; 18];
+                        getrandom::getrandom(&mut nonce)
+                            .map_err(|e| std::io::Error::other(e.to_string()))?;
+                        let nonce: String = {
+                            use base64::Engine;
+                            base64::engine::general_purpose::STANDARD.encode(nonce)
+                        };
+                        let first_bare = format!("n=,r={}", nonce);
+                        let client_first = format!("n,,{}", first_bare);
+                        let mut message = b"SCRAM-SHA-256\0".to_vec();
+                        message.extend_from_slice(&(client_first.len() as i32).to_be_bytes());
+                        message.extend_from_slice(client_first.as_bytes());
+                        write_pg_message(&mut stream, b'p', &message)?;
+                        scram_first = Some(first_bare);
+                    }
+                    // AuthenticationSASLContinue: the server-first message
+                    Some(11) => {
+                        let first_bare = scram_first
+                            .as_deref()
+                            .ok_or_else(|| rejected("unexpected SCRAM message".to_string()))?;
+                        let server_first = String::from_utf8_lossy(&body[4..]);
+                        let (client_final, signature) =
+                            scram_client_final(password, first_bare, &server_first)
+                                .map_err(rejected)?;
+                        write_pg_message(&mut stream, b'p', client_final.as_bytes())?;
+                        scram_signature = Some(signature);
+                    }
+                    // AuthenticationSASLFinal: proves the server knows the password
+                    Some(12) => {
+                        let expected = scram_signature
+                            .ok_or_else(|| rejected("unexpected SCRAM message".to_string()))?;
+                        scram_verify_server_final(&String::from_utf8_lossy(&body[4..]), &expected)
+                            .map_err(rejected)?;
+                    }
+                    _ => return Err(rejected(
+                        "pg0 only supports password and scram-sha-256 authentication to the server"
+                            .to_string(),
+                    )),
+                }
+            }
+            b'S' => parameters.push(body),
+            b'K' => backend_key = body.as_slice().try_into().ok(),
+            b'E' => return Err(ServerConnectError::Rejected(body)),
+            b'Z' => {
+                return Ok(ServerConn {
+                    stream,
+                    parameters,
+                    backend_key,
+                })
+            }
+            _ => {}
+        }
     }
 }
 
-/// Read the PID from PostgreSQL's postmaster.pid file
-fn read_postmaster_pid(data_dir: &PathBuf) -> Result<u32, CliError> {
-    let pid_file = data_dir.join("postmaster.pid");
-    let content = fs::read_to_string(&pid_file)?;
-    // First line of postmaster.pid is the PID
-    content
-        .lines()
-        .next()
-        .and_then(|line| line.trim().parse().ok())
-        .ok_or(CliError::PidParse)
+#[derive(Default)]
+struct PoolState {
+    idle: HashMap<PoolKey, Vec<ServerConn>>,
+    open: HashMap<PoolKey, usize>,
 }
 
-/// Expand ~ to home directory
-fn expand_path(path: &str) -> PathBuf {
-    if path.starts_with("~/") {
-        if let Some(home) = dirs::home_dir() {
-            return home.join(&path[2..]);
+struct Pool {
+    server_port: u16,
+    size: usize,
+    state: std::sync::Mutex<PoolState>,
+    available: std::sync::Condvar,
+    /// Connected clients by the process id of the BackendKeyData pg0 gave
+    /// them, with its secret
+    clients: std::sync::Mutex<HashMap<i32, (i32, SharedAttachment)>>,
+    next_client_id: std::sync::atomic::AtomicI32,
+}
+
+/// How long a client waits for a server connection before giving up.
+const POOL_ACQUIRE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+impl Pool {
+    fn acquire(&self, key: &PoolKey) -> Result<ServerConn, ServerConnectError> {
+        let deadline = std::time::Instant::now() + POOL_ACQUIRE_TIMEOUT;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(conn) = state.idle.get_mut(key).and_then(Vec::pop) {
+                return Ok(conn);
+            }
+            let open = state.open.entry(key.clone()).or_insert(0);
+            if *open < self.size {
+                *open += 1;
+                drop(state);
+                return connect_server(self.server_port, key).inspect_err(|_| self.discard(key));
+            }
+            let now = std::time::Instant::now();
+            if now >= deadline {
+                return Err(ServerConnectError::Rejected(pg_error_body(
+                    "53300",
+                    "timed out waiting for a pooled server connection",
+                )));
+            }
+            state = self
+                .available
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn release(&self, key: &PoolKey, conn: ServerConn) {
+        let mut state = self.state.lock().unwrap();
+        state.idle.entry(key.clone()).or_default().push(conn);
+        self.available.notify_one();
+    }
+
+    /// Forget a connection that was closed instead of released.
+    fn discard(&self, key: &PoolKey) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(open) = state.open.get_mut(key) {
+            *open = open.saturating_sub(1);
+        }
+        self.available.notify_one();
+    }
+
+    /// Forward a client's CancelRequest to the server connection the client
+    /// is attached to, translating pg0's key into the server's.
+    fn cancel(&self, client_id: i32, secret: i32) {
+        let server_key = match self.clients.lock().unwrap().get(&client_id) {
+            Some((expected, attachment)) if *expected == secret => {
+                let att = attachment.lock().unwrap();
+                att.server.as_ref().and(att.server_key)
+            }
+            _ => None,
+        };
+        let Some(server_key) = server_key else { return };
+        let mut request = 16i32.to_be_bytes().to_vec();
+        request.extend_from_slice(&80877102i32.to_be_bytes());
+        request.extend_from_slice(&server_key);
+        if let Ok(mut server) = std::net::TcpStream::connect(("127.0.0.1", self.server_port)) {
+            let _ = std::io::Write::write_all(&mut server, &request);
         }
     }
-    PathBuf::from(path)
 }
 
-/// Check if a port is available for binding
-fn is_port_available(port: u16) -> bool {
-    std::net::TcpListener::bind(("127.0.0.1", port)).is_ok()
+/// A client's entry in `Pool::clients`, removed when the client goes away.
+struct ClientRegistration<'a> {
+    pool: &'a Pool,
+    id: i32,
 }
 
-/// Find an available port, starting from the given port
-fn find_available_port(start_port: u16) -> u16 {
-    let mut port = start_port;
-    while !is_port_available(port) {
-        port += 1;
-        if port > 65535 - 100 {
-            // Wrap around to a random high port if we've gone too far
-            port = 49152; // Start of dynamic/private port range
+impl Drop for ClientRegistration<'_> {
+    fn drop(&mut self) {
+        self.pool.clients.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Per-client view of the server connection it currently holds.
+struct Attachment {
+    /// Write half of the attached server connection
+    server: Option<std::net::TcpStream>,
+    /// BackendKeyData of the attached server connection
+    server_key: Option<[u8; 8]>,
+    /// Queries/syncs sent whose ReadyForQuery hasn't come back yet
+    pending: usize,
+    /// Extended-protocol messages sent since the last Sync
+    unsynced: bool,
+    /// Whether the last ReadyForQuery reported an idle (non-transaction) state
+    idle: bool,
+    /// The client disconnected; release the server as soon as it is idle
+    closing: bool,
+}
+
+/// An `Attachment` shared by a client's thread and its relay thread.
+type SharedAttachment = std::sync::Arc<std::sync::Mutex<Attachment>>;
+
+/// Relay server messages to the client until the server can go back to the
+/// pool (transaction end, or client gone in session mode).
+fn relay_server(
+    pool: std::sync::Arc<Pool>,
+    key: PoolKey,
+    mode: PoolMode,
+    mut conn: ServerConn,
+    mut client: std::net::TcpStream,
+    attachment: SharedAttachment,
+) {
+    loop {
+        let (tag, body) = match read_pg_message(&mut conn.stream) {
+            Ok(message) => message,
+            Err(_) => {
+                attachment.lock().unwrap().server = None;
+                pool.discard(&key);
+                let _ = client.shutdown(std::net::Shutdown::Both);
+                return;
+            }
+        };
+        let mut att = attachment.lock().unwrap();
+        if !att.closing {
+            // A failed write means the client went away; the client thread
+            // notices too and sets `closing`.
+            let _ = write_pg_message(&mut client, tag, &body);
+        }
+        if tag == b'Z' {
+            att.pending = att.pending.saturating_sub(1);
+            att.idle = body.first() == Some(&b'I');
+            let done = att.pending == 0 && !att.unsynced && att.idle;
+            if done && (mode == PoolMode::Transaction || att.closing) {
+                att.server = None;
+                drop(att);
+                pool.release(&key, conn);
+                return;
+            }
         }
     }
-    port
 }
 
-/// Read the latest PostgreSQL log file content (last 20 lines)
-fn read_latest_pg_log(data_dir: &PathBuf) -> Option<String> {
-    let log_dir = data_dir.join("log");
-    let entries = fs::read_dir(&log_dir).ok()?;
+fn pool_client(
+    pool: std::sync::Arc<Pool>,
+    mode: PoolMode,
+    mut client: std::net::TcpStream,
+) -> std::io::Result<()> {
+    use std::io::Read;
 
-    // Find the most recent log file
-    let mut log_files: Vec<_> = entries
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map(|ext| ext == "log").unwrap_or(false))
-        .collect();
-    log_files.sort_by_key(|e| {
-        std::cmp::Reverse(
-            e.metadata()
-                .and_then(|m| m.modified())
-                .unwrap_or(std::time::SystemTime::UNIX_EPOCH),
-        )
+    client.set_nodelay(true)?;
+
+    // Startup: decline SSL/GSS encryption, then read the startup packet.
+    let params = loop {
+        let mut len = [0u8; 4];
+        client.read_exact(&mut len)?;
+        let len = i32::from_be_bytes(len);
+        if !(8..=MAX_STARTUP_MESSAGE as i32).contains(&len) {
+            return Ok(());
+        }
+        let mut body = vec![0u8; len as usize - 4];
+        client.read_exact(&mut body)?;
+        match i32::from_be_bytes([body[0], body[1], body[2], body[3]]) {
+            80877103 | 80877104 => std::io::Write::write_all(&mut client, b"N")?,
+            196608 => {
+                let fields: Vec<String> = body[4..]
+                    .split(|&b| b == 0)
+                    .map(|f| String::from_utf8_lossy(f).to_string())
+                    .collect();
+                let params: HashMap<String, String> = fields
+                    .chunks(2)
+                    .filter(|pair| pair.len() == 2 && !pair[0].is_empty())
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                break params;
+            }
+            80877102 if body.len() == 12 => {
+                let client_id = i32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+                let secret = i32::from_be_bytes([body[8], body[9], body[10], body[11]]);
+                pool.cancel(client_id, secret);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+    };
+    let user = params.get("user").cloned().unwrap_or_default();
+    let database = params
+        .get("database")
+        .cloned()
+        .unwrap_or_else(|| user.clone());
+
+    // Ask for the password in cleartext (over localhost), so it can be used
+    // to authenticate the pooled server connections.
+    write_pg_message(&mut client, b'R', &3i32.to_be_bytes())?;
+    let (tag, body) = read_pg_message_limited(&mut client, MAX_STARTUP_MESSAGE)?;
+    if tag != b'p' {
+        return Ok(());
+    }
+    let password =
+        String::from_utf8_lossy(body.split(|&b| b == 0).next().unwrap_or(&[])).to_string();
+    let key: PoolKey = (user, database, password);
+
+    // The first server connection validates the credentials.
+    let first = match pool.acquire(&key) {
+        Ok(conn) => conn,
+        Err(ServerConnectError::Rejected(error)) => {
+            return write_pg_message(&mut client, b'E', &error)
+        }
+        Err(ServerConnectError::Io(e)) => {
+            return write_pg_message(&mut client, b'E', &pg_error_body("08006", &e.to_string()))
+        }
+    };
+    write_pg_message(&mut client, b'R', &0i32.to_be_bytes())?;
+    for parameter in &first.parameters {
+        write_pg_message(&mut client, b'S', parameter)?;
+    }
+
+    // Clients get a key of pg0's own: the server connection behind them
+    // changes, so cancel requests are translated when they arrive.
+    let attachment = std::sync::Arc::new(std::sync::Mutex::new(Attachment {
+        server: None,
+        server_key: None,
+        pending: 0,
+        unsynced: false,
+        idle: true,
+        closing: false,
+    }));
+    let mut secret = [0u8; 4];
+    getrandom::getrandom(&mut secret).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let registration = ClientRegistration {
+        pool: &pool,
+        id: pool
+            .next_client_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+    };
+    pool.clients.lock().unwrap().insert(
+        registration.id,
+        (i32::from_be_bytes(secret), attachment.clone()),
+    );
+    let mut backend_key = registration.id.to_be_bytes().to_vec();
+    backend_key.extend_from_slice(&secret);
+    write_pg_message(&mut client, b'K', &backend_key)?;
+    write_pg_message(&mut client, b'Z', b"I")?;
+    let client_handle = client.try_clone()?;
+    let attach = |conn: ServerConn| -> std::io::Result<()> {
+        let writer = conn.stream.try_clone()?;
+        let client_writer = client_handle.try_clone()?;
+        let mut att = attachment.lock().unwrap();
+        att.server = Some(writer);
+        att.server_key = conn.backend_key;
+        att.pending = 0;
+        att.idle = true;
+        let (pool, key, attachment) = (pool.clone(), key.clone(), attachment.clone());
+        std::thread::spawn(move || relay_server(pool, key, mode, conn, client_writer, attachment));
+        Ok(())
+    };
+    match mode {
+        PoolMode::Session => attach(first)?,
+        PoolMode::Transaction => pool.release(&key, first),
+    }
+
+    'client: while let Ok((tag, body)) = read_pg_message(&mut client) {
+        if tag == b'X' {
+            break;
+        }
+
+        // Holding the lock keeps the relay from releasing the server between
+        // the check and the write.
+        let mut att = loop {
+            let att = attachment.lock().unwrap();
+            if att.server.is_some() {
+                break att;
+            }
+            drop(att);
+            match pool.acquire(&key) {
+                Ok(conn) => attach(conn)?,
+                Err(ServerConnectError::Rejected(error)) => {
+                    let _ = write_pg_message(&mut client, b'E', &error);
+                    break 'client;
+                }
+                Err(ServerConnectError::Io(_)) => break 'client,
+            }
+        };
+        match tag {
+            b'Q' | b'F' => att.pending += 1,
+            b'S' => {
+                att.pending += 1;
+                att.unsynced = false;
+            }
+            b'P' | b'B' | b'E' | b'D' | b'C' | b'H' => att.unsynced = true,
+            _ => {}
+        }
+        let sent = match att.server.as_mut() {
+            Some(server) => write_pg_message(server, tag, &body).is_ok(),
+            None => false,
+        };
+        if !sent {
+            break;
+        }
+    }
+
+    // The client is gone. An idle session-mode connection is reset and goes
+    // back to the pool; one left mid-transaction is closed (rolling it back).
+    let mut att = attachment.lock().unwrap();
+    att.closing = true;
+    let reusable = att.pending == 0 && !att.unsynced && att.idle;
+    if let Some(server) = att.server.as_mut() {
+        let reset = reusable && {
+            let mut query = b"DISCARD ALL".to_vec();
+            query.push(0);
+            write_pg_message(server, b'Q', &query).is_ok()
+        };
+        if reset {
+            att.pending += 1;
+        } else {
+            let _ = server.shutdown(std::net::Shutdown::Both);
+        }
+    }
+    Ok(())
+}
+
+/// Body of the hidden `pg0 pool-worker` process: accept clients on the pool
+/// port until the instance stops or the pooler is disabled.
+fn pool_worker(name: String) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+    let config = info.pool.clone().ok_or_else(|| {
+        CliError::Other(format!("No connection pooler configured for '{}'", name))
+    })?;
+    let listener = std::net::TcpListener::bind(("127.0.0.1", config.port))?;
+    eprintln!(
+        "pg0 pool listening on 127.0.0.1:{} ({} mode, size {}) for port {}",
+        config.port,
+        config.mode.as_str(),
+        config.size,
+        info.port
+    );
+
+    exit_when_inactive(&name, info.pid, "pg0 pool", |current| {
+        current.pool.is_some()
     });
 
-    let log_file = log_files.first()?;
-    let content = fs::read_to_string(log_file.path()).ok()?;
+    let pool = std::sync::Arc::new(Pool {
+        server_port: info.port,
+        size: config.size,
+        state: std::sync::Mutex::new(PoolState::default()),
+        available: std::sync::Condvar::new(),
+        clients: std::sync::Mutex::new(HashMap::new()),
+        next_client_id: std::sync::atomic::AtomicI32::new(1),
+    });
+    for client in listener.incoming() {
+        let Ok(client) = client else { continue };
+        let pool = pool.clone();
+        std::thread::spawn(move || {
+            let _ = pool_client(pool, config.mode, client);
+        });
+    }
+    Ok(())
+}
 
-    // Get last 20 lines
-    let lines: Vec<&str> = content.lines().collect();
-    let last_lines: Vec<&str> = lines.iter().rev().take(20).rev().cloned().collect();
+/// File name of the managed configuration layer inside the data directory.
+/// postgresql.conf includes it, and ALTER SYSTEM (postgresql.auto.conf) still
+/// takes precedence over it.
+const MANAGED_CONFIG_FILE: &str = "pg0.conf";
 
-    if last_lines.is_empty() {
-        None
+/// Settings that have to stay on the postmaster command line even when the
+/// managed layer sets them: postgresql_embedded always passes -F, which
+/// would otherwise win over fsync from any config file.
+const COMMAND_LINE_SETTINGS: &[&str] = &["fsync"];
+
+/// Settings pg0 passes on every start, regardless of preset and durability.
+const SERVER_SETTINGS: &[(&str, &str)] = &[
+    // Enable logging to files (required for `pg0 logs` command)
+    ("logging_collector", "on"),
+    ("log_directory", "log"),
+    ("log_filename", "postgresql-%Y-%m-%d.log"),
+    ("log_rotation_age", "1d"),
+    ("log_rotation_size", "100MB"),
+    // Pin timezone to UTC so PostgreSQL never reads the tzdata directory at startup.
+    // The theseus-rs binaries are compiled with --with-system-tzdata=/usr/share/zoneinfo,
+    // which doesn't exist on NixOS (tzdata lives at /etc/zoneinfo) and causes a FATAL
+    // "could not find a suitable time zone abbreviations file" on server start.
+    ("timezone", "UTC"),
+    ("log_timezone", "UTC"),
+];
+
+const MANAGED_CONFIG_HEADER: &str = "\
+# pg0 managed configuration
+#
+# Settings here override pg0's defaults and are applied on every start.
+# Edit with 'pg0 config edit' so changes are validated and reloaded.
+# Format: name = value
+";
+
+/// One `name = value` line of a configuration file.
+struct ConfigEntry {
+    line: usize,
+    key: String,
+    value: String,
+}
+
+/// Parse postgresql.conf-style content. Returns the entries, or a list of
+/// `line N: ...` syntax errors.
+fn parse_config(content: &str) -> Result<Vec<ConfigEntry>, Vec<String>> {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+
+    for (idx, raw) in content.lines().enumerate() {
+        let line_no = idx + 1;
+        // A '#' starts a comment unless it is inside a quoted value
+        let mut in_quotes = false;
+        let comment_start = raw.char_indices().find_map(|(i, c)| {
+            match c {
+                '\'' => in_quotes = !in_quotes,
+                '#' if !in_quotes => return Some(i),
+                _ => {}
+            }
+            None
+        });
+        let line = raw[..comment_start.unwrap_or(raw.len())].trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => match line.split_once(char::is_whitespace) {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => {
+                    errors.push(format!(
+                        "line {}: expected 'name = value', got '{}'",
+                        line_no, line
+                    ));
+                    continue;
+                }
+            },
+        };
+
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            errors.push(format!("line {}: invalid setting name '{}'", line_no, key));
+            continue;
+        }
+
+        let value = if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+            value[1..value.len() - 1].replace("''", "'")
+        } else if value.starts_with('\'') {
+            errors.push(format!(
+                "line {}: unterminated quoted value for '{}'",
+                line_no, key
+            ));
+            continue;
+        } else {
+            value.to_string()
+        };
+
+        entries.push(ConfigEntry {
+            line: line_no,
+            key: key.to_lowercase(),
+            value,
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(entries)
     } else {
-        Some(last_lines.join("\n"))
+        Err(errors)
     }
 }
 
-/// Name of the main PostgreSQL server binary for the current target platform.
-/// theseus-rs bundles `postgres.exe` on Windows and `postgres` everywhere else.
-#[cfg(windows)]
-const POSTGRES_BINARY: &str = "postgres.exe";
-#[cfg(not(windows))]
-const POSTGRES_BINARY: &str = "postgres";
+/// Read the managed configuration layer as (name, value) pairs. A missing
+/// file means no overrides.
+fn read_managed_config(data_dir: &Path) -> Result<Vec<(String, String)>, CliError> {
+    let path = data_dir.join(MANAGED_CONFIG_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)?;
+    let entries = parse_config(&content).map_err(|errors| {
+        CliError::Other(format!(
+            "Invalid {}:\n  {}",
+            path.display(),
+            errors.join("\n  ")
+        ))
+    })?;
+    Ok(entries.into_iter().map(|e| (e.key, e.value)).collect())
+}
 
-/// Extract the embedded bundle into `version_dir`, stripping the top-level
-/// directory entry (e.g. "postgresql-18.1.0-<target>/"). theseus-rs publishes
-/// the Windows bundle as a ZIP and every other platform as tar.gz.
-#[cfg(not(windows))]
-fn extract_postgresql_archive(bundle: &[u8], version_dir: &std::path::Path) -> Result<(), CliError> {
-    let decoder = GzDecoder::new(bundle);
-    let mut archive = Archive::new(decoder);
+/// Read the settings made with ALTER SYSTEM (postgresql.auto.conf) as
+/// (name, value) pairs.
+fn read_auto_config(data_dir: &Path) -> Result<Vec<(String, String)>, CliError> {
+    let path = data_dir.join("postgresql.auto.conf");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)?;
+    let entries = parse_config(&content).map_err(|errors| {
+        CliError::Other(format!(
+            "Invalid {}:\n  {}",
+            path.display(),
+            errors.join("\n  ")
+        ))
+    })?;
+    Ok(entries.into_iter().map(|e| (e.key, e.value)).collect())
+}
 
-    for entry in archive.entries()? {
-        let mut entry = entry?;
-        let path = entry.path()?;
+/// Take settings off the command line that `pg_ctl restart` reuses
+/// (postmaster.opts), so the values from the configuration files apply
+/// after the restart. Settings that have to stay on the command line get
+/// the new value instead.
+fn update_postmaster_opts(data_dir: &Path, changes: &[(String, String)]) -> Result<(), CliError> {
+    let path = data_dir.join("postmaster.opts");
+    if !path.exists() {
+        return Ok(());
+    }
+    let content = fs::read_to_string(&path)?;
+    let args: Vec<&str> = content.trim_end().split(" \"").collect();
+    let mut kept: Vec<String> = Vec::new();
+    let mut iter = args.into_iter().peekable();
+    while let Some(arg) = iter.next() {
+        if arg == "-c\"" {
+            if let Some(next) = iter.peek() {
+                let setting = next.trim_end_matches('"');
+                let key = setting.split_once('=').map(|(k, _)| k).unwrap_or(setting);
+                if let Some((_, value)) = changes.iter().find(|(k, _)| k == key) {
+                    iter.next();
+                    if COMMAND_LINE_SETTINGS.contains(&key) {
+                        kept.push(arg.to_string());
+                        kept.push(format!("{}={}\"", key, value));
+                    }
+                    continue;
+                }
+            }
+        }
+        kept.push(arg.to_string());
+    }
+    fs::write(&path, kept.join(" \"") + "\n")?;
+    Ok(())
+}
 
-        let stripped_path: PathBuf = path.components().skip(1).collect();
-        if stripped_path.as_os_str().is_empty() {
+/// Set one value in the managed configuration layer, replacing an existing
+/// line for the same setting or appending a new one. Comments and the rest
+/// of the file are left untouched.
+fn set_managed_config_value(data_dir: &Path, key: &str, value: &str) -> Result<(), CliError> {
+    let path = data_dir.join(MANAGED_CONFIG_FILE);
+    let content = if path.exists() {
+        fs::read_to_string(&path)?
+    } else {
+        MANAGED_CONFIG_HEADER.to_string()
+    };
+    let existing_line = parse_config(&content)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|e| e.key == key)
+        .map(|e| e.line);
+
+    let new_line = format!("{} = {}", key, quote_literal(value));
+    let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+    match existing_line {
+        Some(line_no) => lines[line_no - 1] = new_line,
+        None => lines.push(new_line),
+    }
+    fs::write(&path, lines.join("\n") + "\n")?;
+    ensure_managed_config_include(data_dir)?;
+    Ok(())
+}
+
+/// Remove a setting from the managed configuration layer so the server falls
+/// back to its previous value. Returns whether a line was removed.
+fn remove_managed_config_value(data_dir: &Path, key: &str) -> Result<bool, CliError> {
+    let path = data_dir.join(MANAGED_CONFIG_FILE);
+    if !path.exists() {
+        return Ok(false);
+    }
+    let content = fs::read_to_string(&path)?;
+    let remove: Vec<usize> = parse_config(&content)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|e| e.key == key)
+        .map(|e| e.line)
+        .collect();
+    if remove.is_empty() {
+        return Ok(false);
+    }
+    let lines: Vec<&str> = content
+        .lines()
+        .enumerate()
+        .filter(|(idx, _)| !remove.contains(&(idx + 1)))
+        .map(|(_, l)| l)
+        .collect();
+    fs::write(&path, lines.join("\n") + "\n")?;
+    Ok(true)
+}
+
+/// Make postgresql.conf include the managed layer. Idempotent; also upgrades
+/// data directories created before the managed layer existed.
+fn ensure_managed_config_include(data_dir: &Path) -> Result<(), CliError> {
+    let conf_path = data_dir.join("postgresql.conf");
+    if !conf_path.exists() {
+        return Ok(());
+    }
+    let include_line = format!("include_if_exists = '{}'", MANAGED_CONFIG_FILE);
+    let mut content = fs::read_to_string(&conf_path)?;
+    if content.lines().any(|l| l.trim() == include_line) {
+        return Ok(());
+    }
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str("\n# pg0 managed configuration (edit with 'pg0 config edit')\n");
+    content.push_str(&include_line);
+    content.push('\n');
+    fs::write(&conf_path, content)?;
+    Ok(())
+}
+
+/// What the running server knows about a setting, from pg_settings.
+struct SettingInfo {
+    context: String,
+    vartype: String,
+    enumvals: Vec<String>,
+}
+
+fn query_setting_catalog(info: &InstanceInfo) -> Result<HashMap<String, SettingInfo>, CliError> {
+    let rows = run_sql(
+        info,
+        "postgres",
+        "SELECT name, context, vartype, coalesce(array_to_string(enumvals, ','), '') FROM pg_settings;",
+    )?;
+    let mut catalog = HashMap::new();
+    for row in rows.lines() {
+        let cols: Vec<&str> = row.split('|').collect();
+        if cols.len() != 4 {
             continue;
         }
+        catalog.insert(
+            cols[0].to_string(),
+            SettingInfo {
+                context: cols[1].to_string(),
+                vartype: cols[2].to_string(),
+                enumvals: cols[3]
+                    .split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+                    .collect(),
+            },
+        );
+    }
+    Ok(catalog)
+}
 
-        let dest_path = version_dir.join(&stripped_path);
-        if let Some(parent) = dest_path.parent() {
-            fs::create_dir_all(parent)?;
+/// Config linter: syntax, duplicates and - when a catalog from a running
+/// server is available - unknown names and malformed values. Returns
+/// `line N: ...` problems; empty means the configuration is valid.
+fn lint_config(content: &str, catalog: Option<&HashMap<String, SettingInfo>>) -> Vec<String> {
+    let entries = match parse_config(content) {
+        Ok(entries) => entries,
+        Err(errors) => return errors,
+    };
+
+    let mut problems = Vec::new();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for entry in &entries {
+        if let Some(first) = seen.insert(&entry.key, entry.line) {
+            problems.push(format!(
+                "line {}: '{}' is already set on line {}",
+                entry.line, entry.key, first
+            ));
         }
 
-        if entry.header().entry_type().is_dir() {
-            fs::create_dir_all(&dest_path)?;
+        let catalog = match catalog {
+            Some(catalog) => catalog,
+            None => continue,
+        };
+        // Dotted names are extension/custom settings the server may not know
+        // about until the library is loaded.
+        if entry.key.contains('.') {
+            continue;
+        }
+        let setting = match catalog.get(&entry.key) {
+            Some(setting) => setting,
+            None => {
+                problems.push(format!(
+                    "line {}: unknown setting '{}'",
+                    entry.line, entry.key
+                ));
+                continue;
+            }
+        };
+
+        let value = entry.value.as_str();
+        let valid = match setting.vartype.as_str() {
+            "bool" => matches!(
+                value.to_lowercase().as_str(),
+                "on" | "off" | "true" | "false" | "yes" | "no" | "1" | "0"
+            ),
+            "enum" => setting
+                .enumvals
+                .iter()
+                .any(|v| v.eq_ignore_ascii_case(value)),
+            "integer" | "real" => {
+                let number = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+                let unit = &value[number.len()..];
+                number.trim().parse::<f64>().is_ok()
+                    && matches!(
+                        unit,
+                        "" | "B"
+                            | "kB"
+                            | "MB"
+                            | "GB"
+                            | "TB"
+                            | "us"
+                            | "ms"
+                            | "s"
+                            | "min"
+                            | "h"
+                            | "d"
+                    )
+            }
+            _ => true,
+        };
+        if !valid {
+            let hint = if setting.vartype == "enum" {
+                format!(" (expected one of: {})", setting.enumvals.join(", "))
+            } else {
+                format!(" (expected {})", setting.vartype)
+            };
+            problems.push(format!(
+                "line {}: invalid value '{}' for '{}'{}",
+                entry.line, value, entry.key, hint
+            ));
+        }
+    }
+    problems
+}
+
+/// Settings `pg0 debug queries on` adds to the managed configuration layer.
+const QUERY_LOG_SETTINGS: &[(&str, &str)] = &[("log_statement", "all"), ("log_duration", "on")];
+
+fn debug_queries_toggle(name: String, enable: bool) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+
+    for (key, value) in QUERY_LOG_SETTINGS {
+        if enable {
+            set_managed_config_value(&info.data_dir, key, value)?;
         } else {
-            entry.unpack(&dest_path)?;
+            remove_managed_config_value(&info.data_dir, key)?;
         }
     }
+    run_sql(&info, "postgres", "SELECT pg_reload_conf();")?;
+
+    if enable {
+        println!("Query logging enabled for '{}'.", name);
+        println!(
+            "  Watch statements with: pg0 debug queries tail --name {}",
+            name
+        );
+        println!(
+            "  Turn it off with:      pg0 debug queries off --name {}",
+            name
+        );
+    } else {
+        println!("Query logging disabled for '{}'.", name);
+    }
     Ok(())
 }
 
-#[cfg(windows)]
-fn extract_postgresql_archive(bundle: &[u8], version_dir: &std::path::Path) -> Result<(), CliError> {
-    use std::io::Cursor;
-    let reader = Cursor::new(bundle);
-    let mut archive = zip::ZipArchive::new(reader)
-        .map_err(|e| CliError::Other(format!("Failed to read PostgreSQL ZIP archive: {}", e)))?;
+/// Most recently modified file in an instance's server log directory.
+fn latest_log_file(log_dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(log_dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .max_by_key(|e| e.metadata().and_then(|m| m.modified()).ok())
+        .map(|e| e.path())
+}
 
-    for i in 0..archive.len() {
-        let mut entry = archive
-            .by_index(i)
-            .map_err(|e| CliError::Other(format!("Failed to read ZIP entry {}: {}", i, e)))?;
+/// One server log line split into its prefix fields. Relies on the default
+/// log_line_prefix ('%m [%p] '), e.g.
+/// `2026-01-01 12:00:00.123 UTC [4242] LOG:  statement: SELECT 1`.
+struct ServerLogLine<'a> {
+    time: &'a str,
+    pid: &'a str,
+    level: &'a str,
+    message: &'a str,
+}
 
-        let entry_path = match entry.enclosed_name() {
-            Some(p) => p.to_path_buf(),
-            None => continue, // Skip unsafe / absolute / traversal-containing names
-        };
+fn parse_server_log_line(line: &str) -> Option<ServerLogLine<'_>> {
+    let open = line.find(" [")?;
+    let close = open + line[open..].find("] ")?;
+    let (level, message) = line[close + 2..].split_once(":  ")?;
+    // Keep just the time of day: "2026-01-01 12:00:00.123 UTC" -> "12:00:00.123"
+    let time = line[..open].split(' ').nth(1).unwrap_or(&line[..open]);
+    Some(ServerLogLine {
+        time,
+        pid: &line[open + 2..close],
+        level,
+        message,
+    })
+}
 
-        let stripped_path: PathBuf = entry_path.components().skip(1).collect();
-        if stripped_path.as_os_str().is_empty() {
-            continue;
-        }
+/// A logged statement waiting for its duration line.
+struct PendingStatement {
+    time: String,
+    sql: String,
+    parameters: Option<String>,
+}
 
-        let dest_path = version_dir.join(&stripped_path);
-        if entry.is_dir() {
-            fs::create_dir_all(&dest_path)?;
-        } else {
-            if let Some(parent) = dest_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-            let mut out = fs::File::create(&dest_path)?;
-            std::io::copy(&mut entry, &mut out)?;
-        }
+fn print_statement(pid: &str, statement: &PendingStatement, duration: Option<&str>) {
+    println!(
+        "{}  [{}]  {:>12}  {}",
+        statement.time,
+        pid,
+        duration.unwrap_or(""),
+        statement.sql
+    );
+    if let Some(parameters) = &statement.parameters {
+        println!("{:>34}{}", "", parameters);
     }
-    Ok(())
 }
 
-/// Extract the bundled PostgreSQL to the installation directory
-/// Returns the path to the version-specific directory (e.g., ~/.pg0/installation/18.1.0)
-fn extract_bundled_postgresql(installation_dir: &PathBuf, pg_version: &str) -> Result<PathBuf, CliError> {
-    let version_dir = installation_dir.join(pg_version);
-
-    // Check if already extracted
-    let bin_dir = version_dir.join("bin");
-    let already_extracted = bin_dir.exists() && bin_dir.join(POSTGRES_BINARY).exists();
+fn debug_queries_tail(name: String) -> Result<(), CliError> {
+    use std::io::{BufRead, BufReader, Seek, SeekFrom};
 
-    if !already_extracted {
-        if POSTGRESQL_BUNDLE.is_empty() {
-            return Err(CliError::Other(
-                "PostgreSQL bundle is empty - this binary was not built with BUNDLE_POSTGRESQL=true".to_string()
-            ));
-        }
+    let info = load_running_instance(&name)?;
+    let log_dir = info.data_dir.join("log");
 
-        println!("Extracting bundled PostgreSQL {}...", pg_version);
-        fs::create_dir_all(&version_dir)?;
+    let enabled = run_sql(&info, "postgres", "SHOW log_statement;")?;
+    if enabled == "none" {
+        eprintln!(
+            "Warning: Statement logging is off. Enable it with: pg0 debug queries on --name {}",
+            name
+        );
+    }
+    println!(
+        "Tailing statements for instance '{}' (Ctrl+C to exit):",
+        name
+    );
+    println!();
 
-        extract_postgresql_archive(POSTGRESQL_BUNDLE, &version_dir)?;
+    // Start at the end of the current log: only statements sent from now on.
+    let mut current = latest_log_file(&log_dir);
+    let mut pos = match &current {
+        Some(path) => fs::metadata(path)?.len(),
+        None => 0,
+    };
+    let mut pending: HashMap<String, PendingStatement> = HashMap::new();
+    let mut last_pid: Option<String> = None;
 
-        if !bin_dir.join(POSTGRES_BINARY).exists() {
-            return Err(CliError::Other(format!(
-                "PostgreSQL extraction failed - {} not found at {}",
-                POSTGRES_BINARY,
-                bin_dir.display()
-            )));
+    loop {
+        // Follow the daily log rotation.
+        let latest = latest_log_file(&log_dir);
+        if latest != current {
+            current = latest;
+            pos = 0;
         }
 
-        // Make binaries executable on Unix
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            if let Ok(entries) = fs::read_dir(&bin_dir) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if path.is_file() {
-                        if let Ok(metadata) = path.metadata() {
-                            let mut perms = metadata.permissions();
-                            perms.set_mode(0o755);
-                            let _ = fs::set_permissions(&path, perms);
-                        }
+        if let Some(path) = &current {
+            let mut file = fs::File::open(path)?;
+            file.seek(SeekFrom::Start(pos))?;
+            let mut reader = BufReader::new(file);
+            let mut line = String::new();
+            // Only consume complete lines; a partial one is re-read next pass.
+            while reader.read_line(&mut line)? > 0 && line.ends_with('\n') {
+                pos += line.len() as u64;
+                let text = line.trim_end_matches(['\n', '\r']);
+
+                if let Some(rest) = text.strip_prefix('\t') {
+                    // Continuation of a multi-line statement.
+                    if let Some(statement) = last_pid.as_ref().and_then(|pid| pending.get_mut(pid))
+                    {
+                        statement.sql.push(' ');
+                        statement.sql.push_str(rest.trim());
                     }
-                }
-            }
-            // Also make lib files executable/accessible
-            let lib_dir = version_dir.join("lib");
-            if let Ok(entries) = fs::read_dir(&lib_dir) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if path.is_file() {
-                        if let Ok(metadata) = path.metadata() {
-                            let mut perms = metadata.permissions();
-                            perms.set_mode(0o755);
-                            let _ = fs::set_permissions(&path, perms);
+                } else if let Some(entry) = parse_server_log_line(text) {
+                    let pid = entry.pid.to_string();
+                    let statement = entry.message.strip_prefix("statement: ").or_else(|| {
+                        entry
+                            .message
+                            .split_once(": ")
+                            .filter(|(kind, _)| kind.starts_with("execute "))
+                            .map(|(_, sql)| sql)
+                    });
+                    if entry.level == "LOG" && statement.is_some() {
+                        if let Some(previous) = pending.remove(&pid) {
+                            print_statement(&pid, &previous, None);
+                        }
+                        pending.insert(
+                            pid.clone(),
+                            PendingStatement {
+                                time: entry.time.to_string(),
+                                sql: statement.unwrap_or_default().to_string(),
+                                parameters: None,
+                            },
+                        );
+                    } else if entry.level == "LOG" && entry.message.starts_with("duration: ") {
+                        // Parse/bind durations arrive without a pending statement
+                        // and are dropped.
+                        if let Some(statement) = pending.remove(&pid) {
+                            print_statement(
+                                &pid,
+                                &statement,
+                                entry.message.strip_prefix("duration: "),
+                            );
                         }
+                    } else if entry.level == "DETAIL" {
+                        if let Some(statement) = pending.get_mut(&pid) {
+                            statement.parameters = Some(entry.message.to_string());
+                        }
+                    } else if entry.level == "ERROR" {
+                        if let Some(statement) = pending.remove(&pid) {
+                            print_statement(&pid, &statement, Some("ERROR"));
+                        }
+                        println!("{:>34}{}", "", entry.message);
                     }
+                    last_pid = Some(pid);
                 }
+                line.clear();
             }
         }
-    } else {
-        tracing::debug!("PostgreSQL already extracted at {}", version_dir.display());
+
+        std::thread::sleep(std::time::Duration::from_millis(200));
     }
+}
 
-    // Always ensure the runtime libs are unpacked and LD_LIBRARY_PATH points
-    // at them. Runs even when the postgres install is cached so users
-    // upgrading from a pg0 version that didn't ship the bundle pick up the
-    // new libs without manually wiping ~/.pg0/installation/.
-    ensure_runtime_libs(&version_dir)?;
-    #[cfg(target_os = "linux")]
-    prepend_lib_dir_to_ld_library_path(&version_dir.join("lib"));
+/// A statement shape from the slow query log, with literals replaced by `?`.
+#[derive(Serialize)]
+struct SlowStatement {
+    query: String,
+    count: u64,
+    total_ms: f64,
+    mean_ms: f64,
+    max_ms: f64,
+}
 
-    // Final guard: if any required .so is still unresolved, surface a clear
-    // error instead of letting initdb / postgres start with a confusing
-    // dlopen failure.
-    #[cfg(target_os = "linux")]
-    check_shared_libraries(&bin_dir)?;
+#[derive(Serialize)]
+struct LogErrorCount {
+    level: String,
+    message: String,
+    count: u64,
+}
 
-    if !already_extracted {
-        println!("PostgreSQL {} extracted successfully.", pg_version);
-    }
-    Ok(version_dir)
+#[derive(Serialize, Default)]
+struct CheckpointSummary {
+    total: u64,
+    /// Checkpoints forced by WAL volume rather than checkpoint_timeout
+    requested_by_wal: u64,
+    /// "checkpoints are occurring too frequently" warnings
+    too_frequent: u64,
+    longest_seconds: f64,
 }
 
-/// Unpack RUNTIME_LIBS_BUNDLE into `<version_dir>/lib/` and create the SONAME
-/// symlinks the dynamic linker looks up (e.g. libxml2.so.2 ->
-/// libxml2.so.2.9.14). No-op when the bundle is empty (non-Linux-GNU targets)
-/// or when the libs are already present.
-fn ensure_runtime_libs(version_dir: &Path) -> Result<(), CliError> {
-    if RUNTIME_LIBS_BUNDLE.is_empty() {
-        return Ok(());
+#[derive(Serialize)]
+struct LogReport {
+    log_files: usize,
+    slow_statements: Vec<SlowStatement>,
+    errors: Vec<LogErrorCount>,
+    checkpoints: CheckpointSummary,
+}
+
+/// Collapse whitespace and replace string and numeric literals with `?`, so
+/// runs of the same statement group together.
+fn normalize_statement(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut prev: Option<char> = None;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // Skip to the closing quote; '' is an escaped quote.
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            out.push('?');
+            prev = Some('?');
+        } else if c.is_ascii_digit()
+            && !prev.is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '$')
+        {
+            while chars
+                .peek()
+                .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+            {
+                chars.next();
+            }
+            out.push('?');
+            prev = Some('?');
+        } else if c.is_whitespace() {
+            if prev.is_some_and(|p| p != ' ') {
+                out.push(' ');
+            }
+            prev = Some(' ');
+        } else {
+            out.push(c);
+            prev = Some(c);
+        }
     }
+    out.trim_end().to_string()
+}
 
-    let lib_dir = version_dir.join("lib");
-    fs::create_dir_all(&lib_dir)?;
+/// Split a log_min_duration_statement entry ("duration: 12.3 ms  statement:
+/// SELECT ...") into its duration and SQL.
+fn parse_duration_entry(message: &str) -> Option<(f64, &str)> {
+    let rest = message.strip_prefix("duration: ")?;
+    let (ms, rest) = rest.split_once(" ms")?;
+    let rest = rest.trim_start();
+    let sql = if let Some(sql) = rest.strip_prefix("statement: ") {
+        sql
+    } else if rest.starts_with("execute ") {
+        rest.split_once(": ")?.1
+    } else {
+        return None;
+    };
+    Some((ms.parse().ok()?, sql))
+}
 
-    let decoder = GzDecoder::new(RUNTIME_LIBS_BUNDLE);
-    let mut archive = Archive::new(decoder);
-    let mut extracted_names: Vec<String> = Vec::new();
+fn analyze_server_logs(log_dir: &Path, top: usize) -> Result<LogReport, CliError> {
+    let mut files: Vec<(Option<std::time::SystemTime>, PathBuf)> = fs::read_dir(log_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "log"))
+        .map(|p| (fs::metadata(&p).and_then(|m| m.modified()).ok(), p))
+        .collect();
+    files.sort();
 
-    for entry in archive.entries()? {
-        let mut entry = entry?;
-        let path = entry.path()?.to_path_buf();
-        let basename = match path.file_name().and_then(|s| s.to_str()) {
-            Some(name) => name.to_string(),
-            None => continue,
-        };
-        let dest = lib_dir.join(&basename);
-        if !dest.exists() {
-            entry.unpack(&dest)?;
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                let mut perms = fs::metadata(&dest)?.permissions();
-                perms.set_mode(0o755);
-                let _ = fs::set_permissions(&dest, perms);
+    let mut slow: HashMap<String, SlowStatement> = HashMap::new();
+    let mut errors: HashMap<(String, String), u64> = HashMap::new();
+    let mut checkpoints = CheckpointSummary::default();
+
+    let mut record = |level: &str, message: &str| {
+        if level == "LOG" {
+            if let Some((ms, sql)) = parse_duration_entry(message) {
+                let query = normalize_statement(sql);
+                let entry = slow.entry(query.clone()).or_insert(SlowStatement {
+                    query,
+                    count: 0,
+                    total_ms: 0.0,
+                    mean_ms: 0.0,
+                    max_ms: 0.0,
+                });
+                entry.count += 1;
+                entry.total_ms += ms;
+                entry.max_ms = entry.max_ms.max(ms);
+            } else if let Some(reason) = message.strip_prefix("checkpoint starting: ") {
+                checkpoints.total += 1;
+                if reason.split(' ').any(|r| r == "wal" || r == "xlog") {
+                    checkpoints.requested_by_wal += 1;
+                }
+            } else if message.starts_with("checkpoints are occurring too frequently") {
+                checkpoints.too_frequent += 1;
+            } else if message.starts_with("checkpoint complete:") {
+                let seconds = message
+                    .split_once("total=")
+                    .and_then(|(_, rest)| rest.split(' ').next())
+                    .and_then(|s| s.parse::<f64>().ok());
+                if let Some(seconds) = seconds {
+                    checkpoints.longest_seconds = checkpoints.longest_seconds.max(seconds);
+                }
             }
+        } else if matches!(level, "WARNING" | "ERROR" | "FATAL" | "PANIC") {
+            *errors
+                .entry((level.to_string(), message.to_string()))
+                .or_insert(0) += 1;
         }
-        extracted_names.push(basename);
-    }
+    };
 
-    // Create SONAME symlinks: libxml2.so.2 -> libxml2.so.2.9.14, etc.
-    // The dynamic linker only looks up files by SONAME (".so.<major>"), not
-    // the fully-versioned filename, so the symlinks are what actually makes
-    // the bundled libs reachable.
-    #[cfg(unix)]
-    for name in &extracted_names {
-        if let Some(soname) = soname_for(name) {
-            let link = lib_dir.join(&soname);
-            if link.exists() {
+    for (_, path) in &files {
+        let content = fs::read(path)?;
+        let content = String::from_utf8_lossy(&content);
+        // Multi-line statements continue on lines starting with a tab.
+        let mut current: Option<(String, String)> = None;
+        for line in content.lines() {
+            if let Some(continuation) = line.strip_prefix('\t') {
+                if let Some((_, message)) = &mut current {
+                    message.push('\n');
+                    message.push_str(continuation);
+                }
                 continue;
             }
-            // Symlink relative to lib_dir so it stays valid if the install
-            // directory is moved.
-            if let Err(e) = std::os::unix::fs::symlink(name, &link) {
-                if e.kind() != std::io::ErrorKind::AlreadyExists {
-                    return Err(e.into());
-                }
+            if let Some((level, message)) = current.take() {
+                record(&level, &message);
+            }
+            current =
+                parse_server_log_line(line).map(|e| (e.level.to_string(), e.message.to_string()));
+        }
+        if let Some((level, message)) = current.take() {
+            record(&level, &message);
+        }
+    }
+
+    let mut slow_statements: Vec<SlowStatement> = slow.into_values().collect();
+    for statement in &mut slow_statements {
+        statement.mean_ms = statement.total_ms / statement.count as f64;
+    }
+    slow_statements.sort_by(|a, b| b.max_ms.total_cmp(&a.max_ms));
+    slow_statements.truncate(top);
+
+    let mut errors: Vec<LogErrorCount> = errors
+        .into_iter()
+        .map(|((level, message), count)| LogErrorCount {
+            level,
+            message,
+            count,
+        })
+        .collect();
+    errors.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.message.cmp(&b.message))
+    });
+    errors.truncate(top);
+
+    Ok(LogReport {
+        log_files: files.len(),
+        slow_statements,
+        errors,
+        checkpoints,
+    })
+}
+
+fn logs_analyze(
+    name: String,
+    min_duration: Option<String>,
+    top: usize,
+    output_format: OutputFormat,
+) -> Result<(), CliError> {
+    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+
+    if let Some(min_duration) = &min_duration {
+        let info = load_running_instance(&name)?;
+        if min_duration == "off" {
+            remove_managed_config_value(&info.data_dir, "log_min_duration_statement")?;
+        } else {
+            let ms: u64 = min_duration.parse().map_err(|_| {
+                CliError::Other(format!(
+                    "Invalid --min-duration '{}': expected milliseconds or 'off'",
+                    min_duration
+                ))
+            })?;
+            set_managed_config_value(
+                &info.data_dir,
+                "log_min_duration_statement",
+                &format!("{}ms", ms),
+            )?;
+        }
+        run_sql(&info, "postgres", "SELECT pg_reload_conf();")?;
+        if matches!(output_format, OutputFormat::Text) {
+            if min_duration == "off" {
+                println!("Slow statement logging disabled for '{}'.", name);
+            } else {
+                println!(
+                    "Logging statements slower than {}ms on '{}'. Run your workload, then run 'pg0 logs analyze' again.",
+                    min_duration, name
+                );
             }
+            println!();
         }
     }
 
+    let log_dir = info.data_dir.join("log");
+    if !log_dir.exists() {
+        return Err(CliError::Other(format!(
+            "Log directory not found for instance '{}'. Has PostgreSQL been started?",
+            name
+        )));
+    }
+    let report = analyze_server_logs(&log_dir, top)?;
+
+    if matches!(output_format, OutputFormat::Json) {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!(
+        "Log analysis for '{}' ({} log file(s))",
+        name, report.log_files
+    );
+    println!();
+    println!("Slowest statements:");
+    if report.slow_statements.is_empty() {
+        println!(
+            "  None logged. Enable with: pg0 logs analyze --min-duration 100 --name {}",
+            name
+        );
+    } else {
+        println!(
+            "  {:>10} {:>10} {:>6}  QUERY",
+            "MAX(ms)", "MEAN(ms)", "COUNT"
+        );
+        for statement in &report.slow_statements {
+            let mut query = statement.query.replace('\n', " ");
+            if query.chars().count() > 80 {
+                query = format!("{}...", query.chars().take(77).collect::<String>());
+            }
+            println!(
+                "  {:>10.1} {:>10.1} {:>6}  {}",
+                statement.max_ms, statement.mean_ms, statement.count, query
+            );
+        }
+    }
+    println!();
+    println!("Errors and warnings:");
+    if report.errors.is_empty() {
+        println!("  None.");
+    } else {
+        for error in &report.errors {
+            let message = error.message.lines().next().unwrap_or_default();
+            println!("  {:>6}x {:<7} {}", error.count, error.level, message);
+        }
+    }
+    println!();
+    let checkpoints = &report.checkpoints;
+    println!("Checkpoints:");
+    println!(
+        "  {} checkpoint(s), {} requested by WAL volume, longest {:.1}s",
+        checkpoints.total, checkpoints.requested_by_wal, checkpoints.longest_seconds
+    );
+    if checkpoints.too_frequent > 0 {
+        println!(
+            "  Warning: 'checkpoints are occurring too frequently' logged {} time(s); consider raising max_wal_size:",
+            checkpoints.too_frequent
+        );
+        println!("    pg0 config edit --name {}", name);
+    }
     Ok(())
 }
 
-/// Given a fully-versioned shared-library filename like
-/// `libxml2.so.2.9.13` or `libicudata.so.70.1`, return the SONAME the dynamic
-/// linker actually resolves: `libxml2.so.2`, `libicudata.so.70`. Returns None
-/// for filenames we don't recognise (so we don't accidentally create bogus
-/// symlinks).
-fn soname_for(filename: &str) -> Option<String> {
-    let so_idx = filename.find(".so.")?;
-    let (stem, rest) = filename.split_at(so_idx + ".so.".len());
-    // `stem` ends with ".so."; `rest` is the version tail e.g. "2.9.14".
-    let major = rest.split('.').next()?;
-    if major.is_empty() {
-        return None;
+fn config_edit(name: String) -> Result<(), CliError> {
+    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    if info.archive.is_some() {
+        return Err(CliError::Other(format!(
+            "Instance '{}' is archived. Use 'pg0 unarchive --name {}' first.",
+            name, name
+        )));
     }
-    Some(format!("{}{}", stem, major))
-}
 
-/// Walk up from a `bin/psql` path to the version-specific install dir and
-/// make sure the runtime libs are present + LD_LIBRARY_PATH points at them.
-/// Used by `pg0 psql`, which spawns psql against an instance whose install
-/// directory was set up by an earlier `pg0 start` (possibly from a previous
-/// pg0 release that didn't ship the libs bundle).
-fn ensure_runtime_libs_for_psql(psql_path: &Path) -> Result<(), CliError> {
-    let version_dir = match psql_path.parent().and_then(|p| p.parent()) {
-        Some(p) => p.to_path_buf(),
-        None => return Ok(()),
+    let running = is_instance_running(&info);
+    let config_path = info.data_dir.join(MANAGED_CONFIG_FILE);
+    let original = if config_path.exists() {
+        fs::read_to_string(&config_path)?
+    } else {
+        MANAGED_CONFIG_HEADER.to_string()
     };
-    ensure_runtime_libs(&version_dir)?;
-    #[cfg(target_os = "linux")]
-    prepend_lib_dir_to_ld_library_path(&version_dir.join("lib"));
-    Ok(())
-}
 
-/// Prepend `lib_dir` to the process LD_LIBRARY_PATH so that subprocesses
-/// (initdb, postgres, pg_ctl, psql) find the bundled libs first. Existing
-/// entries are preserved.
-#[cfg(target_os = "linux")]
-fn prepend_lib_dir_to_ld_library_path(lib_dir: &Path) {
-    let lib_dir_s = lib_dir.to_string_lossy().to_string();
-    let new = match std::env::var("LD_LIBRARY_PATH") {
-        Ok(existing) if !existing.is_empty() => {
-            // Avoid duplicating ourselves on repeat calls.
-            if existing
-                .split(':')
-                .any(|p| p == lib_dir_s)
-            {
-                return;
-            }
-            format!("{}:{}", lib_dir_s, existing)
-        }
-        _ => lib_dir_s,
+    // Values can only be checked against pg_settings of a live server.
+    let catalog = if running {
+        Some(query_setting_catalog(&info)?)
+    } else {
+        println!(
+            "Instance '{}' is not running; only the syntax will be checked.",
+            name
+        );
+        None
     };
-    std::env::set_var("LD_LIBRARY_PATH", new);
-}
 
-/// Check that the postgres binary can find all required shared libraries.
-/// Only called on Linux. If ldd is unavailable, silently skips the check.
-#[cfg(target_os = "linux")]
-fn check_shared_libraries(bin_dir: &std::path::Path) -> Result<(), CliError> {
-    let postgres_path = bin_dir.join("postgres");
-    let output = match std::process::Command::new("ldd")
-        .arg(&postgres_path)
-        .output()
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| {
+            if cfg!(windows) {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        });
+    let mut editor_parts = editor.split_whitespace();
+    let editor_program = editor_parts
+        .next()
+        .ok_or_else(|| CliError::Other("$EDITOR is empty".to_string()))?
+        .to_string();
+    let editor_args: Vec<String> = editor_parts.map(|s| s.to_string()).collect();
+
+    // A private copy next to instance.json; /tmp is shared with other users.
+    let scratch_path = get_instance_dir(&name)?.join(format!("edit-{}", MANAGED_CONFIG_FILE));
+    let _ = fs::remove_file(&scratch_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
     {
-        Ok(output) => output,
-        Err(e) => {
-            tracing::debug!("Could not run ldd to check shared libraries: {}", e);
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    std::io::Write::write_all(&mut options.open(&scratch_path)?, original.as_bytes())?;
+
+    let edited = loop {
+        let status = std::process::Command::new(&editor_program)
+            .args(&editor_args)
+            .arg(&scratch_path)
+            .status()?;
+        if !status.success() {
+            let _ = fs::remove_file(&scratch_path);
+            return Err(CliError::Other(format!(
+                "Editor '{}' exited with an error",
+                editor
+            )));
+        }
+
+        let edited = fs::read_to_string(&scratch_path)?;
+        let problems = lint_config(&edited, catalog.as_ref());
+        if problems.is_empty() {
+            break edited;
+        }
+
+        println!("The configuration has problems:");
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+        print!("Re-open the editor? [Y/n] ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        if input.trim().eq_ignore_ascii_case("n") {
+            let _ = fs::remove_file(&scratch_path);
+            println!("Discarded changes.");
             return Ok(());
         }
     };
+    let _ = fs::remove_file(&scratch_path);
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let missing: Vec<&str> = stdout
-        .lines()
-        .filter(|line| line.contains("not found"))
-        .map(|line| line.trim())
-        .collect();
-
-    if missing.is_empty() {
+    if edited == original {
+        println!("No changes.");
         return Ok(());
     }
 
-    let missing_list = missing.join("\n  ");
-    Err(CliError::Other(format!(
-        "The bundled PostgreSQL binary is missing required system libraries:\n  \
-         {}\n\n\
-         Install the missing libraries using your system package manager. For example:\n  \
-         Arch Linux:    sudo pacman -S <package>\n  \
-         Ubuntu/Debian: sudo apt install <package>\n  \
-         Fedora/RHEL:   sudo dnf install <package>",
-        missing_list
-    )))
-}
-
-/// Install pgvector extension files into the PostgreSQL installation
-fn install_pgvector(installation_dir: &PathBuf, pg_version: &str) -> Result<(), CliError> {
-    let pg_major = pg_version.split('.').next().unwrap_or("16");
-    let pgvector_version = env!("PGVECTOR_VERSION");
-
-    // Find the version-specific installation directory
-    let version_dir = fs::read_dir(installation_dir)?
-        .filter_map(|e| e.ok())
-        .find(|e| e.path().is_dir() && e.file_name().to_string_lossy().starts_with(pg_major))
-        .map(|e| e.path())
-        .ok_or_else(|| std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "PostgreSQL installation directory not found"
-        ))?;
+    fs::write(&config_path, &edited)?;
+    println!("Saved {}", config_path.display());
 
-    let lib_dir = version_dir.join("lib");
-    let extension_dir = version_dir.join("share").join("extension");
+    let before: HashMap<String, String> = parse_config(&original)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|e| (e.key, e.value))
+        .collect();
+    let after: HashMap<String, String> = parse_config(&edited)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|e| (e.key, e.value))
+        .collect();
+    let mut changed: Vec<&String> = before
+        .keys()
+        .chain(after.keys())
+        .filter(|k| before.get(*k) != after.get(*k))
+        .collect();
+    changed.sort();
+    changed.dedup();
 
-    // Check if pgvector is already installed
-    if extension_dir.join("vector.control").exists() {
-        tracing::debug!("pgvector already installed");
+    if !running {
+        println!("Changes will take effect the next time '{}' starts.", name);
         return Ok(());
     }
-
-    if PGVECTOR_BUNDLE.is_empty() {
-        return Err(CliError::Other(
-            "pgvector bundle is empty - this binary was not built with BUNDLE_POSTGRESQL=true".to_string()
-        ));
+    if changed.is_empty() {
+        return Ok(());
     }
 
-    println!("Installing pgvector {}...", pgvector_version);
+    ensure_managed_config_include(&info.data_dir)?;
+    run_sql(&info, "postgres", "SELECT pg_reload_conf();")?;
+    // The reload is signalled asynchronously; give the postmaster a moment
+    // before checking what it picked up.
+    std::thread::sleep(std::time::Duration::from_millis(500));
 
-    // Extract bundled pgvector
-    let decoder = GzDecoder::new(PGVECTOR_BUNDLE);
-    let mut archive = Archive::new(decoder);
+    let names = changed
+        .iter()
+        .map(|k| quote_literal(k))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let rows = run_sql(
+        &info,
+        "postgres",
+        &format!(
+            "SELECT name, setting, coalesce(unit, ''), source, pending_restart FROM pg_settings WHERE name IN ({}) ORDER BY name;",
+            names
+        ),
+    )?;
 
-    for entry in archive.entries()? {
-        let mut entry = entry?;
-        let path = entry.path()?;
+    let mut applied = Vec::new();
+    let mut needs_restart = Vec::new();
+    for row in rows.lines() {
+        let cols: Vec<&str> = row.split('|').collect();
+        if cols.len() != 5 {
+            continue;
+        }
+        let (key, setting, unit, source, pending_restart) =
+            (cols[0], cols[1], cols[2], cols[3], cols[4]);
+        let is_restart_only = catalog
+            .as_ref()
+            .and_then(|c| c.get(key))
+            .map(|s| s.context == "postmaster")
+            .unwrap_or(false);
+        // Command-line settings (pg0's defaults at the time of the last
+        // start) shadow the file until the next start.
+        if pending_restart == "t" || source == "command line" || is_restart_only {
+            needs_restart.push(key.to_string());
+        } else {
+            applied.push(format!("{} = {}{}", key, setting, unit));
+        }
+    }
 
-        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-            if name.ends_with(".so") || name.ends_with(".dylib") || name.ends_with(".dll") {
-                let dest = lib_dir.join(name);
-                entry.unpack(&dest)?;
-                // Make library executable on Unix
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    if let Ok(metadata) = dest.metadata() {
-                        let mut perms = metadata.permissions();
-                        perms.set_mode(0o755);
-                        let _ = fs::set_permissions(&dest, perms);
-                    }
-                }
-            } else if name == "vector.control" || name.starts_with("vector--") {
-                let dest = extension_dir.join(name);
-                entry.unpack(&dest)?;
-            }
+    if !applied.is_empty() {
+        println!("Applied:");
+        for line in &applied {
+            println!("  {}", line);
+        }
+    }
+    if !needs_restart.is_empty() {
+        println!("Requires a restart to take effect:");
+        for key in &needs_restart {
+            println!("  {}", key);
         }
+        println!(
+            "Restart with 'pg0 stop --name {}' and 'pg0 start --name {}'.",
+            name, name
+        );
     }
 
-    println!("pgvector {} installed successfully!", pgvector_version);
     Ok(())
 }
 
-fn start(
-    name: String,
-    port: u16,
-    port_was_specified: bool,
-    version: String,
-    data_dir: Option<String>,
-    username: String,
-    password: String,
-    database: String,
-    config: Vec<String>,
-) -> Result<(), CliError> {
-    // Check if already running
-    if let Some(info) = load_instance(&name)? {
-        if is_process_running(info.pid) {
-            return Err(CliError::AlreadyRunning(info.pid));
-        }
-        // Stale instance: clean up instance metadata but preserve data directory.
-        // Remove stale postmaster.pid so PostgreSQL can start with existing data.
-        let pid_file = info.data_dir.join("postmaster.pid");
-        if pid_file.exists() {
-            println!("Removing stale postmaster.pid (process {} no longer running)...", info.pid);
-            fs::remove_file(&pid_file)?;
-        }
-        remove_instance(&name)?;
+fn config_set(name: String, settings: Vec<String>, restart: bool) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+
+    let mut changes: Vec<(String, String)> = Vec::new();
+    for setting in &settings {
+        let (key, value) = setting.split_once('=').ok_or_else(|| {
+            CliError::Other(format!("Invalid setting '{}', expected KEY=VALUE", setting))
+        })?;
+        changes.push((key.trim().to_lowercase(), value.trim().to_string()));
     }
 
-    // Auto-allocate port if the requested port is in use (only if port wasn't explicitly specified)
-    let port = if !port_was_specified && !is_port_available(port) {
-        let new_port = find_available_port(port);
-        println!("Port {} is in use, using port {} instead.", port, new_port);
-        new_port
-    } else {
-        port
-    };
+    let needs_restart = alter_system(&info, &changes)?;
+    if needs_restart.is_empty() {
+        return Ok(());
+    }
 
-    let base_dir = get_base_dir()?;
-    let instance_dir = get_instance_dir(&name)?;
+    println!("Requires a restart to take effect:");
+    for key in &needs_restart {
+        println!("  {}", key);
+    }
+    restart_or_defer(&name, restart)
+}
 
-    // Use provided data_dir or default to instance-specific directory
-    let data_dir = match data_dir {
-        Some(dir) => expand_path(&dir),
-        None => instance_dir.join("data"),
+/// Restart `name` if `restart` is set or the user agrees to, otherwise say
+/// how to do it later.
+fn restart_or_defer(name: &str, restart: bool) -> Result<(), CliError> {
+    let restart = restart || {
+        print!("Restart '{}' now? [y/N] ", name);
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        input.trim().eq_ignore_ascii_case("y")
     };
+    if restart {
+        restart_instance(name)?;
+        println!("Restarted '{}'.", name);
+    } else {
+        println!(
+            "Restart later with 'pg0 stop --name {}' and 'pg0 start --name {}'.",
+            name, name
+        );
+    }
+    Ok(())
+}
 
-    let installation_dir = base_dir.join("installation");
-
-    fs::create_dir_all(&data_dir)?;
-    fs::create_dir_all(&installation_dir)?;
-
-    println!("Setting up PostgreSQL {}...", version);
+/// The shared_preload_libraries an instance's configuration files set, and
+/// whether ALTER SYSTEM set them (which shadows the managed configuration).
+fn configured_preload_libraries(data_dir: &Path) -> Result<(Vec<String>, bool), CliError> {
+    let auto = read_auto_config(data_dir)?
+        .into_iter()
+        .rev()
+        .find(|(key, _)| key == "shared_preload_libraries");
+    let value = match &auto {
+        Some((_, value)) => Some(value.clone()),
+        None => read_managed_config(data_dir)?
+            .into_iter()
+            .rev()
+            .find(|(key, _)| key == "shared_preload_libraries")
+            .map(|(_, value)| value),
+    };
+    let libraries = value
+        .unwrap_or_default()
+        .split(',')
+        .map(|l| l.trim().trim_matches('"').to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    Ok((libraries, auto.is_some()))
+}
 
-    let version_req: VersionReq = version.parse().map_err(|e| {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            format!("Invalid version: {}", e),
+fn preload_list(name: String) -> Result<(), CliError> {
+    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    let lib_dir = info.installation_dir.join(&info.version).join("lib");
+    let (configured, _) = configured_preload_libraries(&info.data_dir)?;
+    let loaded: Option<Vec<String>> = if is_instance_running(&info) {
+        let value = run_sql(&info, "postgres", "SHOW shared_preload_libraries;")?;
+        Some(
+            value
+                .split(',')
+                .map(|l| l.trim().trim_matches('"').to_string())
+                .filter(|l| !l.is_empty())
+                .collect(),
         )
-    })?;
-
-    // Build configuration HashMap with sensible defaults
-    let mut configuration: HashMap<String, String> = HashMap::new();
-
-    // Apply opinionated defaults optimized for vector/AI workloads
-    configuration.insert("shared_buffers".to_string(), "256MB".to_string());
-    configuration.insert("maintenance_work_mem".to_string(), "512MB".to_string());
-    configuration.insert("effective_cache_size".to_string(), "1GB".to_string());
-    configuration.insert("max_parallel_maintenance_workers".to_string(), "4".to_string());
-    configuration.insert("work_mem".to_string(), "64MB".to_string());
+    } else {
+        None
+    };
 
-    // Enable logging to files (required for `pg0 logs` command)
-    configuration.insert("logging_collector".to_string(), "on".to_string());
-    configuration.insert("log_directory".to_string(), "log".to_string());
-    configuration.insert("log_filename".to_string(), "postgresql-%Y-%m-%d.log".to_string());
-    configuration.insert("log_rotation_age".to_string(), "1d".to_string());
-    configuration.insert("log_rotation_size".to_string(), "100MB".to_string());
+    if configured.is_empty() && loaded.as_ref().is_none_or(|l| l.is_empty()) {
+        println!("'{}' preloads no libraries.", name);
+        return Ok(());
+    }
+    println!("Preloaded libraries of '{}':", name);
+    for library in &configured {
+        let mut line = format!("  {}", library);
+        if !has_library(&lib_dir, library) {
+            line.push_str(" (not found in the installation)");
+        } else if loaded.as_ref().is_some_and(|l| !l.contains(library)) {
+            line.push_str(" (restart pending)");
+        }
+        println!("{}", line);
+    }
+    for library in loaded.iter().flatten().filter(|l| !configured.contains(l)) {
+        println!(
+            "  {} (loaded; removed since the last restart, or set by pg0 start flags)",
+            library
+        );
+    }
+    Ok(())
+}
 
-    // Pin timezone to UTC so PostgreSQL never reads the tzdata directory at startup.
-    // The theseus-rs binaries are compiled with --with-system-tzdata=/usr/share/zoneinfo,
-    // which doesn't exist on NixOS (tzdata lives at /etc/zoneinfo) and causes a FATAL
-    // "could not find a suitable time zone abbreviations file" on server start.
-    configuration.insert("timezone".to_string(), "UTC".to_string());
-    configuration.insert("log_timezone".to_string(), "UTC".to_string());
+/// `pg0 preload add` (`add` = true) and `pg0 preload remove`.
+fn preload_edit(
+    name: String,
+    libraries: Vec<String>,
+    add: bool,
+    restart: bool,
+) -> Result<(), CliError> {
+    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    let lib_dir = info.installation_dir.join(&info.version).join("lib");
+    let (mut configured, from_alter_system) = configured_preload_libraries(&info.data_dir)?;
+    if from_alter_system {
+        return Err(CliError::Other(format!(
+            "shared_preload_libraries of '{}' is set with ALTER SYSTEM, which overrides pg0's configuration. Run 'ALTER SYSTEM RESET shared_preload_libraries' first.",
+            name
+        )));
+    }
 
-    // Parse and apply custom config options (these override defaults)
-    for cfg in &config {
-        if let Some((key, value)) = cfg.split_once('=') {
-            configuration.insert(key.trim().to_string(), value.trim().to_string());
+    let before = configured.clone();
+    for library in &libraries {
+        if add {
+            if !has_library(&lib_dir, library) {
+                return Err(CliError::Other(format!(
+                    "Library '{}' is not in {}. Install it first with 'pg0 install-extension --name {} <extension>'.",
+                    library,
+                    lib_dir.display(),
+                    name
+                )));
+            }
+            if !configured.contains(library) {
+                configured.push(library.clone());
+            }
+        } else if !configured.contains(library) {
+            return Err(CliError::Other(format!(
+                "'{}' does not preload '{}'",
+                name, library
+            )));
         } else {
-            eprintln!("Warning: Invalid config format '{}', expected KEY=VALUE", cfg);
+            configured.retain(|l| l != library);
         }
     }
+    if configured == before {
+        println!(
+            "Nothing to change; '{}' already preloads {}.",
+            name,
+            libraries.join(", ")
+        );
+        return Ok(());
+    }
 
-    // Extract bundled PostgreSQL
-    let version_install_dir = extract_bundled_postgresql(&installation_dir, &version)?;
+    if configured.is_empty() {
+        remove_managed_config_value(&info.data_dir, "shared_preload_libraries")?;
+    } else {
+        set_managed_config_value(
+            &info.data_dir,
+            "shared_preload_libraries",
+            &configured.join(","),
+        )?;
+    }
+    ensure_managed_config_include(&info.data_dir)?;
+    println!(
+        "shared_preload_libraries = '{}' for '{}'.",
+        configured.join(","),
+        name
+    );
 
-    let settings = Settings {
-        version: version_req,
-        port,
-        username: username.clone(),
-        password: password.clone(),
-        data_dir: data_dir.clone(),
-        installation_dir: version_install_dir,
-        configuration,
-        trust_installation_dir: true, // Use our extracted files
-        temporary: false, // Never delete data directory on drop - pg0 manages data lifecycle explicitly
-        timeout: Some(std::time::Duration::from_secs(600)), // 10 minute timeout for slow systems (ARM64 emulation under QEMU)
-        ..Default::default()
-    };
+    if !is_instance_running(&info) {
+        println!("Takes effect the next time '{}' starts.", name);
+        return Ok(());
+    }
+    restart_or_defer(&name, restart)
+}
 
-    let mut postgresql = PostgreSQL::new(settings);
-    postgresql.setup()?;
+/// Validate `changes`, persist them with ALTER SYSTEM and reload. Prints the
+/// settings that took effect and returns the ones that need a restart; the
+/// next restart is prepared to pick those up.
+fn alter_system(
+    info: &InstanceInfo,
+    changes: &[(String, String)],
+) -> Result<Vec<String>, CliError> {
+    // Validate everything before changing anything.
+    let catalog = query_setting_catalog(info)?;
+    let content: String = changes
+        .iter()
+        .map(|(key, value)| format!("{} = {}\n", key, quote_literal(value)))
+        .collect();
+    let problems = lint_config(&content, Some(&catalog));
+    if !problems.is_empty() {
+        return Err(CliError::Other(format!(
+            "Invalid settings:\n  {}",
+            problems
+                .iter()
+                .map(|p| p.split_once(": ").map(|(_, msg)| msg).unwrap_or(p))
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        )));
+    }
 
-    // Install pgvector extension
-    if let Err(e) = install_pgvector(&installation_dir, &version) {
-        eprintln!("Warning: Failed to install pgvector: {}", e);
-        eprintln!("You can try installing it manually with: pg0 install-extension vector");
+    for (key, value) in changes {
+        run_sql(
+            info,
+            "postgres",
+            &format!("ALTER SYSTEM SET {} = {};", key, quote_literal(value)),
+        )?;
     }
+    run_sql(info, "postgres", "SELECT pg_reload_conf();")?;
+    // The reload is signalled asynchronously; give the postmaster a moment
+    // before checking what it picked up.
+    std::thread::sleep(std::time::Duration::from_millis(500));
 
-    println!("Starting PostgreSQL on port {}...", port);
-    if let Err(e) = postgresql.start() {
-        // Try to read the PostgreSQL log for more context
-        let log_context = read_latest_pg_log(&data_dir);
-        let error_msg = if let Some(log) = log_context {
-            format!("Failed to start PostgreSQL: {}\n\nPostgreSQL log:\n{}", e, log)
+    let names = changes
+        .iter()
+        .map(|(k, _)| quote_literal(k))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let rows = run_sql(
+        info,
+        "postgres",
+        &format!(
+            "SELECT name, setting, coalesce(unit, ''), source, pending_restart, context FROM pg_settings WHERE name IN ({}) ORDER BY name;",
+            names
+        ),
+    )?;
+
+    let mut applied = Vec::new();
+    let mut needs_restart = Vec::new();
+    for row in rows.lines() {
+        let cols: Vec<&str> = row.split('|').collect();
+        if cols.len() != 6 {
+            continue;
+        }
+        let (key, setting, unit, source, pending_restart, context) =
+            (cols[0], cols[1], cols[2], cols[3], cols[4], cols[5]);
+        // Values pg0 passed on the command line at start shadow
+        // postgresql.auto.conf until the next restart.
+        if pending_restart == "t" || source == "command line" || context == "postmaster" {
+            needs_restart.push(key.to_string());
         } else {
-            format!("Failed to start PostgreSQL: {}", e)
-        };
-        return Err(CliError::Other(error_msg));
+            applied.push(format!("{} = {}{}", key, setting, unit));
+        }
     }
 
-    // Create the user if it's not the default 'postgres'
-    // Note: postgresql_embedded always creates 'postgres' as the superuser
-    if username != "postgres" {
-        println!("Creating user '{}'...", username);
-        let psql_path = find_psql_binary(&installation_dir)?;
-        let create_user_sql = format!(
-            "DO $$ BEGIN IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = '{}') THEN CREATE USER \"{}\" WITH SUPERUSER PASSWORD '{}'; END IF; END $$;",
-            username, username, password.replace('\'', "''")
-        );
-        let status = std::process::Command::new(&psql_path)
-            .arg(&format!("postgresql://postgres:{}@127.0.0.1:{}/postgres", password, port))
-            .arg("-c")
-            .arg(&create_user_sql)
-            .status()?;
-        if !status.success() {
-            eprintln!("Warning: Failed to create user '{}'", username);
+    if !applied.is_empty() {
+        println!("Applied:");
+        for line in &applied {
+            println!("  {}", line);
         }
     }
+    if !needs_restart.is_empty() {
+        update_postmaster_opts(&info.data_dir, changes)?;
+    }
+    Ok(needs_restart)
+}
 
-    // Create the database if it doesn't exist and it's not the default 'postgres'
-    if database != "postgres" {
-        // Pre-check existence rather than relying on the duplicate-database error
-        // string, which is localized by PostgreSQL's lc_messages (e.g. on Windows
-        // with a Chinese locale: `数据库 "x" 已经存在`). See vectorize-io/pg0#13.
-        if !postgresql.database_exists(&database)? {
-            println!("Creating database '{}'...", database);
-            postgresql.create_database(&database)?;
+/// The scripts of `pg0 start --init-dir`, in the order they run: file name
+/// order, as the shell sorts /docker-entrypoint-initdb.d. Other files are
+/// left out with a note.
+fn list_init_scripts(dir: &Path) -> Result<Vec<PathBuf>, CliError> {
+    let entries = fs::read_dir(dir).map_err(|e| {
+        CliError::Other(format!(
+            "Could not read init directory {}: {}",
+            dir.display(),
+            e
+        ))
+    })?;
+    let mut scripts = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if [".sql", ".sql.gz", ".sh"]
+            .iter()
+            .any(|suffix| file_name.ends_with(suffix))
+        {
+            scripts.push(path);
+        } else if path.is_file() {
+            println!(
+                "Ignoring {} (init scripts end in .sql, .sql.gz or .sh)",
+                path.display()
+            );
         }
-        // Grant privileges to the user on the database
-        if username != "postgres" {
-            let psql_path = find_psql_binary(&installation_dir)?;
-            let grant_sql = format!("GRANT ALL PRIVILEGES ON DATABASE \"{}\" TO \"{}\";", database, username);
-            let _ = std::process::Command::new(&psql_path)
-                .arg(&format!("postgresql://postgres:{}@127.0.0.1:{}/postgres", password, port))
-                .arg("-c")
-                .arg(&grant_sql)
-                .status();
+    }
+    scripts.sort();
+    Ok(scripts)
+}
+
+/// Run init scripts (`--init-dir`, `--init-sql`) against a new instance.
+/// Anything but a `.sh` file is SQL. SQL goes to the instance
+/// database as the instance user; shell scripts get the libpq variables of
+/// `pg0 env` (plus the POSTGRES_* ones of the Docker image) and the bundled
+/// psql on PATH.
+fn run_init_scripts(info: &InstanceInfo, scripts: &[PathBuf]) -> Result<(), CliError> {
+    for script in scripts {
+        let file_name = script
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        println!("Running init script {}...", file_name);
+        let failed = |e: CliError| {
+            CliError::Other(format!("Init script {} failed: {}", script.display(), e))
+        };
+        if file_name.ends_with(".sh") {
+            run_init_shell_script(info, script).map_err(failed)?;
+        } else {
+            let mut sql = Vec::new();
+            let file = fs::File::open(script)?;
+            if file_name.ends_with(".gz") {
+                std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(file), &mut sql)?;
+            } else {
+                std::io::Read::read_to_end(&mut std::io::BufReader::new(file), &mut sql)?;
+            }
+            run_sql_script(info, &info.database, &sql).map_err(failed)?;
         }
     }
+    Ok(())
+}
 
-    // Read PID from postmaster.pid file
-    let pid = read_postmaster_pid(&data_dir)?;
+fn run_init_shell_script(info: &InstanceInfo, script: &Path) -> Result<(), CliError> {
+    let psql_path = find_instance_binary(info, "psql")?;
+    ensure_runtime_libs_for_binary(&psql_path)?;
+    let mut path = vec![psql_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default()];
+    if let Some(existing) = std::env::var_os("PATH") {
+        path.extend(std::env::split_paths(&existing));
+    }
+    let path = std::env::join_paths(path).map_err(|e| CliError::Other(e.to_string()))?;
 
-    let info = InstanceInfo {
-        pid,
-        port,
-        data_dir: data_dir.clone(),
-        installation_dir,
-        username: username.clone(),
-        password: password.clone(),
-        database: database.clone(),
-        version: version.clone(),
+    // Like the Docker image: executable scripts run on their own, others
+    // through the shell.
+    #[cfg(unix)]
+    let executable = {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(script)?.permissions().mode() & 0o111 != 0
     };
-
-    save_instance(&name, &info)?;
-
-    println!();
-    println!("PostgreSQL is running!");
-    println!("  Instance: {}", name);
-    println!("  PID:      {}", pid);
-    println!("  Port:     {}", port);
-    println!("  Username: {}", username);
-    println!("  Password: {}", password);
-    println!("  Database: {}", database);
-    println!("  Data dir: {}", data_dir.display());
-    println!();
-    println!(
-        "Connection URI: postgresql://{}:{}@127.0.0.1:{}/{}",
-        username, password, port, database
-    );
-    println!();
-    if name == DEFAULT_INSTANCE_NAME {
-        println!("Use 'pg0 stop' to stop the server.");
+    #[cfg(not(unix))]
+    let executable = false;
+    let mut command = if executable {
+        std::process::Command::new(script)
     } else {
-        println!("Use 'pg0 stop --name {}' to stop the server.", name);
+        let mut command = std::process::Command::new("sh");
+        command.arg(script);
+        command
+    };
+    let status = command
+        .envs(env_vars(info))
+        .env("POSTGRES_USER", &info.username)
+        .env("POSTGRES_PASSWORD", &info.password)
+        .env("POSTGRES_DB", &info.database)
+        .env("PATH", path)
+        .status()
+        .map_err(|e| CliError::Other(format!("Could not run {}: {}", script.display(), e)))?;
+    if !status.success() {
+        return Err(CliError::Other(format!("exited with {}", status)));
     }
+    Ok(())
+}
 
-    // Detach - let the process continue running
-    std::mem::forget(postgresql);
+/// Declarative instance spec read by `pg0 apply` (pg0.toml).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceSpec {
+    #[serde(default = "default_instance_name")]
+    name: String,
+    version: Option<String>,
+    port: Option<u16>,
+    preset: Option<Preset>,
+    durability: Option<Durability>,
+    /// Settings persisted with ALTER SYSTEM.
+    #[serde(default)]
+    config: std::collections::BTreeMap<String, toml::Value>,
+    #[serde(default, alias = "roles")]
+    users: Vec<UserSpec>,
+    #[serde(default)]
+    databases: Vec<DatabaseSpec>,
+    /// Lifecycle hooks, replacing the instance's when given.
+    hooks: Option<std::collections::BTreeMap<HookEvent, String>>,
+}
 
-    Ok(())
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserSpec {
+    name: String,
+    password: Option<String>,
+    #[serde(default)]
+    superuser: bool,
+    #[serde(default)]
+    createdb: bool,
+    /// Privileges granted in `databases`.
+    access: Option<RoleAccess>,
+    /// Databases `access` applies to; defaults to the instance database.
+    #[serde(default)]
+    databases: Vec<String>,
 }
 
-fn stop(name: String) -> Result<(), CliError> {
-    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+/// What a role created by `pg0 start --role` or `pg0 apply` may do in a
+/// database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RoleAccess {
+    /// Read tables and sequences
+    Readonly,
+    /// Read and write the rows of tables, use sequences
+    Readwrite,
+    /// Own the database and its public schema
+    Owner,
+}
 
-    if !is_process_running(info.pid) {
-        println!("PostgreSQL instance '{}' is not running.", name);
-        return Ok(());
+impl RoleAccess {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RoleAccess::Readonly => "readonly",
+            RoleAccess::Readwrite => "readwrite",
+            RoleAccess::Owner => "owner",
+        }
     }
+}
 
-    println!("Stopping PostgreSQL instance '{}' (pid: {})...", name, info.pid);
-
-    // Send SIGTERM to gracefully stop
-    #[cfg(unix)]
+/// Parse `--role NAME:PASSWORD[:OPTIONS]`. Roles without an access option
+/// (and not superusers) get readwrite access.
+fn parse_role(value: &str) -> Result<UserSpec, CliError> {
+    let mut parts = value.splitn(3, ':');
+    let (name, password) = match (parts.next(), parts.next()) {
+        (Some(name), Some(password)) if !name.is_empty() && !password.is_empty() => {
+            (name, password)
+        }
+        _ => {
+            return Err(CliError::Other(format!(
+                "Invalid role '{}', expected NAME:PASSWORD[:OPTIONS]",
+                value
+            )))
+        }
+    };
+    let mut role = UserSpec {
+        name: name.to_string(),
+        password: Some(password.to_string()),
+        superuser: false,
+        createdb: false,
+        access: None,
+        databases: Vec::new(),
+    };
+    for option in parts
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
     {
-        use std::process::Command;
-        let _ = Command::new("kill")
-            .args(["-TERM", &info.pid.to_string()])
-            .output();
+        let access = match option {
+            "superuser" => {
+                role.superuser = true;
+                continue;
+            }
+            "createdb" => {
+                role.createdb = true;
+                continue;
+            }
+            "readonly" => RoleAccess::Readonly,
+            "readwrite" => RoleAccess::Readwrite,
+            "owner" => RoleAccess::Owner,
+            _ => {
+                return Err(CliError::Other(format!(
+                    "Unknown option '{}' of role '{}' (expected readonly, readwrite, owner, superuser or createdb)",
+                    option, name
+                )))
+            }
+        };
+        if role.access.is_some_and(|other| other != access) {
+            return Err(CliError::Other(format!(
+                "Role '{}' can only have one of readonly, readwrite and owner",
+                name
+            )));
+        }
+        role.access = Some(access);
     }
-    #[cfg(windows)]
-    {
-        use std::process::Command;
-        let _ = Command::new("taskkill")
-            .args(["/PID", &info.pid.to_string()])
-            .output();
+    if role.access.is_none() && !role.superuser {
+        role.access = Some(RoleAccess::Readwrite);
     }
+    Ok(role)
+}
 
-    // Wait a bit for graceful shutdown
-    std::thread::sleep(std::time::Duration::from_secs(2));
-
-    // Force kill if still running
-    if is_process_running(info.pid) {
-        #[cfg(unix)]
-        {
-            use std::process::Command;
-            let _ = Command::new("kill")
-                .args(["-9", &info.pid.to_string()])
-                .output();
-        }
-        #[cfg(windows)]
-        {
-            use std::process::Command;
-            let _ = Command::new("taskkill")
-                .args(["/F", "/PID", &info.pid.to_string()])
-                .output();
+/// Create `user` as a login role, or bring an existing role in line with it.
+fn ensure_role(info: &InstanceInfo, user: &UserSpec) -> Result<(), CliError> {
+    let existing = run_sql(
+        info,
+        "postgres",
+        &format!(
+            "SELECT rolsuper, rolcreatedb FROM pg_roles WHERE rolname = {};",
+            quote_literal(&user.name)
+        ),
+    )?;
+    let mut attributes = String::from(if user.superuser {
+        " SUPERUSER"
+    } else {
+        " NOSUPERUSER"
+    });
+    attributes.push_str(if user.createdb {
+        " CREATEDB"
+    } else {
+        " NOCREATEDB"
+    });
+    if let Some(password) = &user.password {
+        attributes.push_str(&format!(" PASSWORD {}", quote_literal(password)));
+    }
+    // On stdin: a -c argument would show the password in the process list.
+    if existing.is_empty() {
+        let sql = format!(
+            "CREATE ROLE {} LOGIN{};",
+            quote_ident(&user.name),
+            attributes
+        );
+        run_sql_script(info, "postgres", sql.as_bytes())?;
+        println!("Created user '{}'.", user.name);
+    } else {
+        let sql = format!(
+            "ALTER ROLE {} LOGIN{};",
+            quote_ident(&user.name),
+            attributes
+        );
+        run_sql_script(info, "postgres", sql.as_bytes())?;
+        let wanted = format!(
+            "{}|{}",
+            if user.superuser { "t" } else { "f" },
+            if user.createdb { "t" } else { "f" }
+        );
+        if existing != wanted {
+            println!("Updated user '{}'.", user.name);
         }
     }
-
-    println!("PostgreSQL instance '{}' stopped.", name);
-
     Ok(())
 }
 
-fn drop_instance(name: String, force: bool) -> Result<(), CliError> {
-    let instance = load_instance(&name)?;
-
-    if instance.is_none() {
-        println!("Instance '{}' does not exist.", name);
+/// Grant `role` its `access` to `database`, including tables created later
+/// by the instance user or the database owner.
+fn grant_access(
+    info: &InstanceInfo,
+    database: &str,
+    role: &str,
+    access: RoleAccess,
+) -> Result<(), CliError> {
+    let grantee = quote_ident(role);
+    if access == RoleAccess::Owner {
+        run_sql(
+            info,
+            "postgres",
+            &format!(
+                "ALTER DATABASE {} OWNER TO {};",
+                quote_ident(database),
+                grantee
+            ),
+        )?;
+        // Before PostgreSQL 15, public belongs to the bootstrap superuser.
+        run_sql(
+            info,
+            database,
+            &format!("GRANT ALL ON SCHEMA public TO {};", grantee),
+        )?;
         return Ok(());
     }
+    let (tables, sequences) = match access {
+        RoleAccess::Readonly => ("SELECT", "SELECT"),
+        _ => ("SELECT, INSERT, UPDATE, DELETE", "USAGE, SELECT, UPDATE"),
+    };
+    let schemas = run_sql(
+        info,
+        database,
+        "SELECT nspname FROM pg_namespace WHERE nspname NOT LIKE 'pg\\_%' AND nspname <> 'information_schema';",
+    )?;
+    let owner = run_sql(
+        info,
+        "postgres",
+        &format!(
+            "SELECT pg_get_userbyid(datdba) FROM pg_database WHERE datname = {};",
+            quote_literal(database)
+        ),
+    )?;
+    let mut creators = vec![quote_ident(&info.username)];
+    if !owner.is_empty() && owner != info.username {
+        creators.push(quote_ident(&owner));
+    }
+    let creators = creators.join(", ");
 
-    let info = instance.unwrap();
+    let mut sql = format!(
+        "GRANT CONNECT ON DATABASE {} TO {};\n",
+        quote_ident(database),
+        grantee
+    );
+    for schema in schemas.lines() {
+        let schema = quote_ident(schema);
+        sql.push_str(&format!(
+            "GRANT USAGE ON SCHEMA {} TO {};\n",
+            schema, grantee
+        ));
+        sql.push_str(&format!(
+            "GRANT {} ON ALL TABLES IN SCHEMA {} TO {};\n",
+            tables, schema, grantee
+        ));
+        sql.push_str(&format!(
+            "GRANT {} ON ALL SEQUENCES IN SCHEMA {} TO {};\n",
+            sequences, schema, grantee
+        ));
+    }
+    for (privileges, objects) in [
+        ("USAGE", "SCHEMAS"),
+        (tables, "TABLES"),
+        (sequences, "SEQUENCES"),
+    ] {
+        sql.push_str(&format!(
+            "ALTER DEFAULT PRIVILEGES FOR ROLE {} GRANT {} ON {} TO {};\n",
+            creators, privileges, objects, grantee
+        ));
+    }
+    run_sql_script(info, database, sql.as_bytes())
+}
 
-    // Confirmation prompt unless --force
-    if !force {
-        println!("This will permanently delete instance '{}' and all its data:", name);
-        println!("  Data dir: {}", info.data_dir.display());
-        println!();
-        print!("Are you sure? [y/N] ");
-        std::io::Write::flush(&mut std::io::stdout())?;
+/// Grant every role in `roles` its access to its databases (by default
+/// `database`). Owners come first, so the others' default privileges cover
+/// what the owner creates.
+fn grant_roles(info: &InstanceInfo, roles: &[UserSpec], database: &str) -> Result<(), CliError> {
+    let mut granted: Vec<(&UserSpec, RoleAccess)> = roles
+        .iter()
+        .filter_map(|role| role.access.map(|access| (role, access)))
+        .collect();
+    granted.sort_by_key(|(_, access)| *access != RoleAccess::Owner);
+    for (role, access) in granted {
+        let default = [database.to_string()];
+        let databases = if role.databases.is_empty() {
+            &default[..]
+        } else {
+            &role.databases[..]
+        };
+        for database in databases {
+            grant_access(info, database, &role.name, access)?;
+        }
+    }
+    Ok(())
+}
 
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        if !input.trim().eq_ignore_ascii_case("y") {
-            println!("Aborted.");
-            return Ok(());
+/// Create the roles of `pg0 start --role` and grant their access.
+fn create_roles(info: &InstanceInfo, roles: &[UserSpec], database: &str) -> Result<(), CliError> {
+    for role in roles {
+        ensure_role(info, role)?;
+    }
+    grant_roles(info, roles, database)?;
+    for role in roles {
+        match role.access {
+            Some(access) => println!(
+                "Role '{}': {} access to '{}'.",
+                role.name,
+                access.as_str(),
+                database
+            ),
+            None => println!("Role '{}': superuser.", role.name),
         }
     }
+    Ok(())
+}
 
-    // Stop if running
-    if is_process_running(info.pid) {
-        println!("Stopping PostgreSQL instance '{}' (pid: {})...", name, info.pid);
-        #[cfg(unix)]
-        {
-            use std::process::Command;
-            let _ = Command::new("kill")
-                .args(["-TERM", &info.pid.to_string()])
-                .output();
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DatabaseSpec {
+    name: String,
+    /// Defaults to the instance user.
+    owner: Option<String>,
+    #[serde(default)]
+    extensions: Vec<String>,
+    /// SQL scripts (relative to the spec file) run once, when the database
+    /// is created.
+    #[serde(default)]
+    seed: Vec<String>,
+}
+
+fn default_instance_name() -> String {
+    DEFAULT_INSTANCE_NAME.to_string()
+}
+
+/// Converge an instance to the spec in `file`: start or restart it, then
+/// create or alter configuration, users, databases and extensions as needed.
+fn apply(file: String) -> Result<(), CliError> {
+    let path = expand_path(&file);
+    let content = fs::read_to_string(&path)
+        .map_err(|e| CliError::Other(format!("Could not read {}: {}", path.display(), e)))?;
+    let spec: InstanceSpec = toml::from_str(&content)
+        .map_err(|e| CliError::Other(format!("Invalid {}: {}", path.display(), e)))?;
+    let spec_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let name = spec.name.clone();
+    let _lock = lock_instance(&name)?;
+
+    // Instance: a different major version needs a new cluster; port, preset
+    // and durability only need a restart.
+    let existing = load_instance(&name)?;
+    if let (Some(info), Some(version)) = (&existing, &spec.version) {
+        let version = resolve_version(version);
+        if info.version != version && !info.version.starts_with(&format!("{}.", version)) {
+            return Err(CliError::Other(format!(
+                "Instance '{}' runs PostgreSQL {}, but {} asks for {}. Drop it with 'pg0 drop --name {}' to recreate it.",
+                name,
+                info.version,
+                path.display(),
+                version,
+                name
+            )));
+        }
+    }
+    let running = existing.as_ref().filter(|info| is_instance_running(info));
+    if let Some(info) = running {
+        let mut drift = Vec::new();
+        if spec.port.is_some_and(|port| port != info.port) {
+            drift.push("port");
+        }
+        if spec.preset.is_some_and(|preset| preset != info.preset) {
+            drift.push("preset");
         }
-        #[cfg(windows)]
+        if spec
+            .durability
+            .is_some_and(|durability| durability != info.durability)
         {
-            use std::process::Command;
-            let _ = Command::new("taskkill")
-                .args(["/PID", &info.pid.to_string()])
-                .output();
+            drift.push("durability");
         }
-        std::thread::sleep(std::time::Duration::from_secs(2));
-
-        if is_process_running(info.pid) {
-            #[cfg(unix)]
-            {
-                use std::process::Command;
-                let _ = Command::new("kill")
-                    .args(["-9", &info.pid.to_string()])
-                    .output();
-            }
-            #[cfg(windows)]
-            {
-                use std::process::Command;
-                let _ = Command::new("taskkill")
-                    .args(["/F", "/PID", &info.pid.to_string()])
-                    .output();
-            }
+        if !drift.is_empty() {
+            println!("Restarting '{}' to change {}...", name, drift.join(", "));
+            stop(name.clone(), &StopOptions::default())?;
         }
     }
+    if !load_instance(&name)?.is_some_and(|info| is_instance_running(&info)) {
+        start(StartOptions {
+            name: name.clone(),
+            port: spec.port.unwrap_or(5432),
+            port_was_specified: spec.port.is_some(),
+            version: spec.version.clone(),
+            preset: spec.preset,
+            durability: spec.durability,
+            hooks: spec
+                .hooks
+                .iter()
+                .flatten()
+                .map(|(event, command)| format!("{}={}", event.as_str(), command))
+                .collect(),
+            ..Default::default()
+        })?;
+        println!();
+    }
+    let mut info = load_running_instance(&name)?;
+    if let Some(hooks) = spec.hooks.as_ref().filter(|hooks| **hooks != info.hooks) {
+        info.hooks = hooks.clone();
+        save_instance(&name, &info)?;
+        println!("Updated hooks of '{}'.", name);
+    }
 
-    // Delete data directory
-    if info.data_dir.exists() {
-        println!("Deleting data directory: {}", info.data_dir.display());
-        fs::remove_dir_all(&info.data_dir)?;
+    // Configuration: only settings whose persisted value differs.
+    let persisted: HashMap<String, String> =
+        read_auto_config(&info.data_dir)?.into_iter().collect();
+    let changes: Vec<(String, String)> = spec
+        .config
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (key.to_lowercase(), value)
+        })
+        .filter(|(key, value)| persisted.get(key) != Some(value))
+        .collect();
+    let needs_restart = if changes.is_empty() {
+        Vec::new()
+    } else {
+        alter_system(&info, &changes)?
+    };
+    let info = if needs_restart.is_empty() {
+        info
+    } else {
+        restart_instance(&name)?
+    };
+
+    for user in &spec.users {
+        ensure_role(&info, user)?;
     }
 
-    // Delete instance directory (contains instance.json)
-    let instance_dir = get_instance_dir(&name)?;
-    if instance_dir.exists() {
-        fs::remove_dir_all(&instance_dir)?;
+    for database in &spec.databases {
+        let owner = database.owner.as_deref().unwrap_or(&info.username);
+        let current_owner = run_sql(
+            &info,
+            "postgres",
+            &format!(
+                "SELECT pg_get_userbyid(datdba) FROM pg_database WHERE datname = {};",
+                quote_literal(&database.name)
+            ),
+        )?;
+        let created = current_owner.is_empty();
+        if created {
+            run_sql(
+                &info,
+                "postgres",
+                &format!(
+                    "CREATE DATABASE {} OWNER {};",
+                    quote_ident(&database.name),
+                    quote_ident(owner)
+                ),
+            )?;
+            println!("Created database '{}'.", database.name);
+        } else if current_owner != owner {
+            run_sql(
+                &info,
+                "postgres",
+                &format!(
+                    "ALTER DATABASE {} OWNER TO {};",
+                    quote_ident(&database.name),
+                    quote_ident(owner)
+                ),
+            )?;
+            println!(
+                "Changed owner of database '{}' to '{}'.",
+                database.name, owner
+            );
+        }
+
+        for extension in &database.extensions {
+            create_extension(&name, &info, &database.name, extension)?;
+        }
+
+        if created {
+            for seed in &database.seed {
+                let seed_path = spec_dir.join(seed);
+                let script = fs::read(&seed_path).map_err(|e| {
+                    CliError::Other(format!(
+                        "Could not read seed script {}: {}",
+                        seed_path.display(),
+                        e
+                    ))
+                })?;
+                // Objects created by the seed belong to the database owner.
+                let mut sql = format!("SET ROLE {};\n", quote_ident(owner)).into_bytes();
+                sql.extend(script);
+                run_sql_script(&info, &database.name, &sql)
+                    .map_err(|e| CliError::Other(format!("Seed script {} failed: {}", seed, e)))?;
+                println!("Ran {} in '{}'.", seed, database.name);
+            }
+        }
     }
 
-    println!("Instance '{}' dropped.", name);
+    // Access goes last, once the databases it refers to exist.
+    grant_roles(&info, &spec.users, &info.database)?;
 
+    println!("Instance '{}' matches {}.", name, path.display());
     Ok(())
 }
 
-fn info(name: String, output_format: OutputFormat) -> Result<(), CliError> {
-    let instance = load_instance(&name)?;
+/// One row of `pg0 config show`.
+#[derive(Serialize, Deserialize)]
+struct EffectiveSetting {
+    name: String,
+    value: String,
+    /// PostgreSQL's own source for the value (pg_settings.source).
+    source: String,
+    sourcefile: String,
+    pending_restart: bool,
+    /// Value from the configuration files that waits for a restart.
+    pending_value: Option<String>,
+}
 
-    let output = match instance {
-        Some(info) => {
-            let running = is_process_running(info.pid);
-            if running {
-                let uri = format!(
-                    "postgresql://{}:{}@127.0.0.1:{}/{}",
-                    info.username, info.password, info.port, info.database
-                );
-                InfoOutput {
-                    name: name.clone(),
-                    running: true,
-                    pid: Some(info.pid),
-                    port: Some(info.port),
-                    version: Some(info.version),
-                    username: Some(info.username),
-                    database: Some(info.database),
-                    data_dir: Some(info.data_dir.display().to_string()),
-                    uri: Some(uri),
-                }
-            } else {
-                // Stopped but instance exists - show data_dir
-                InfoOutput {
-                    name: name.clone(),
-                    running: false,
-                    pid: None,
-                    port: Some(info.port),
-                    version: Some(info.version),
-                    username: Some(info.username),
-                    database: Some(info.database),
-                    data_dir: Some(info.data_dir.display().to_string()),
-                    uri: None,
-                }
-            }
-        }
-        None => {
-            // Instance doesn't exist
-            InfoOutput {
-                name: name.clone(),
-                running: false,
-                pid: None,
-                port: None,
-                version: None,
-                username: None,
-                database: None,
-                data_dir: None,
-                uri: None,
-            }
+impl EffectiveSetting {
+    fn is_default(&self) -> bool {
+        self.source == "default" || self.source == "override"
+    }
+
+    /// Where the value comes from, in pg0's terms.
+    fn origin(&self) -> String {
+        let file = Path::new(&self.sourcefile)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match self.source.as_str() {
+            "default" | "override" => "default".to_string(),
+            "command line" => "pg0 start".to_string(),
+            "configuration file" if file == MANAGED_CONFIG_FILE => MANAGED_CONFIG_FILE.to_string(),
+            "configuration file" if file == "postgresql.auto.conf" => "ALTER SYSTEM".to_string(),
+            "configuration file" => file,
+            other => other.to_string(),
         }
-    };
+    }
+}
+
+fn config_show(name: String, diff: bool, output_format: OutputFormat) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+
+    let json = run_sql(
+        &info,
+        "postgres",
+        "SELECT coalesce(json_agg(json_build_object(\
+           'name', s.name, \
+           'value', current_setting(s.name), \
+           'source', s.source, \
+           'sourcefile', coalesce(s.sourcefile, ''), \
+           'pending_restart', s.pending_restart, \
+           'pending_value', CASE WHEN s.pending_restart THEN \
+             (SELECT f.setting FROM pg_file_settings f WHERE f.name = s.name ORDER BY f.seqno DESC LIMIT 1) END\
+         ) ORDER BY s.name), '[]') FROM pg_settings s;",
+    )?;
+    let all: Vec<EffectiveSetting> = serde_json::from_str(&json)?;
+
+    // pg0 manages its own defaults plus anything set through it: -c
+    // overrides, auto-tuning and auto_explain end up on the command line,
+    // edits in pg0.conf or ALTER SYSTEM.
+    let defaults: std::collections::HashSet<&str> = SERVER_SETTINGS
+        .iter()
+        .chain(info.preset.settings())
+        .chain(info.durability.settings())
+        .map(|(key, _)| *key)
+        .collect();
+    let settings: Vec<EffectiveSetting> = all
+        .into_iter()
+        .filter(|s| {
+            defaults.contains(s.name.as_str())
+                || matches!(
+                    s.origin().as_str(),
+                    "pg0 start" | MANAGED_CONFIG_FILE | "ALTER SYSTEM"
+                )
+        })
+        .filter(|s| !diff || !s.is_default())
+        .collect();
 
     match output_format {
-        OutputFormat::Json => {
-            println!("{}", serde_json::to_string_pretty(&output)?);
-        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&settings)?),
         OutputFormat::Text => {
-            if output.running {
-                println!("PostgreSQL instance '{}' is running", name);
-                println!("  PID:      {}", output.pid.unwrap());
-                println!("  Port:     {}", output.port.unwrap());
-                println!("  Version:  {}", output.version.as_ref().unwrap());
-                println!("  Username: {}", output.username.as_ref().unwrap());
-                println!("  Database: {}", output.database.as_ref().unwrap());
-                println!("  Data dir: {}", output.data_dir.as_ref().unwrap());
-                println!();
-                println!("URI: {}", output.uri.as_ref().unwrap());
-            } else if output.data_dir.is_some() {
-                println!("PostgreSQL instance '{}' is stopped", name);
-                println!("  Port:     {}", output.port.unwrap());
-                println!("  Version:  {}", output.version.as_ref().unwrap());
-                println!("  Username: {}", output.username.as_ref().unwrap());
-                println!("  Database: {}", output.database.as_ref().unwrap());
-                println!("  Data dir: {}", output.data_dir.as_ref().unwrap());
+            if settings.is_empty() {
+                println!("All settings are at their defaults.");
+                return Ok(());
+            }
+            println!("{:<36} {:<32} SOURCE", "NAME", "VALUE");
+            for setting in &settings {
+                let mut line = format!(
+                    "{:<36} {:<32} {}",
+                    setting.name,
+                    setting.value,
+                    setting.origin()
+                );
+                if setting.pending_restart {
+                    match &setting.pending_value {
+                        Some(value) => line.push_str(&format!(" (restart pending: {})", value)),
+                        None => line.push_str(" (restart pending)"),
+                    }
+                }
+                println!("{}", line.trim_end());
+            }
+            if settings.iter().any(|s| s.pending_restart) {
                 println!();
-                println!("Use 'pg0 start --name {}' to start it.", name);
-            } else {
-                println!("PostgreSQL instance '{}' does not exist", name);
+                println!(
+                    "Some changes need a restart: 'pg0 stop --name {}' and 'pg0 start --name {}'.",
+                    name, name
+                );
             }
         }
     }
-
     Ok(())
 }
 
-fn find_psql_binary(installation_dir: &PathBuf) -> Result<PathBuf, CliError> {
-    let psql_name = if cfg!(windows) { "psql.exe" } else { "psql" };
-
-    // Look for psql in installation_dir/*/bin/psql (version subdirectory)
-    if let Ok(entries) = fs::read_dir(installation_dir) {
-        for entry in entries.flatten() {
-            let psql_path = entry.path().join("bin").join(psql_name);
-            if psql_path.exists() {
-                return Ok(psql_path);
-            }
+fn matrix_run(
+    versions: Vec<String>,
+    keep: bool,
+    force: bool,
+    command: Vec<String>,
+) -> Result<(), CliError> {
+    // Every version starts from a fresh cluster, but an existing instance of
+    // the same name may hold data someone still wants.
+    let mut existing = Vec::new();
+    for version in &versions {
+        let name = format!("matrix-{}", version);
+        if load_instance(&name)?.is_some() {
+            existing.push(name);
         }
     }
-
-    // Fallback: try direct path (in case structure changes)
-    let direct_path = installation_dir.join("bin").join(psql_name);
-    if direct_path.exists() {
-        return Ok(direct_path);
+    if !existing.is_empty() && !force {
+        return Err(CliError::Other(format!(
+            "Refusing to replace existing instance(s) {}: drop them with 'pg0 drop', or pass --force",
+            existing.join(", ")
+        )));
     }
 
-    Err(CliError::Io(std::io::Error::new(
-        std::io::ErrorKind::NotFound,
-        format!(
-            "{} not found in {}",
-            psql_name,
-            installation_dir.display()
-        ),
-    )))
-}
+    let mut results: Vec<(String, Result<(), String>)> = Vec::new();
 
-fn psql(name: String, args: Vec<String>) -> Result<(), CliError> {
-    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    for version in &versions {
+        let name = format!("matrix-{}", version);
+        println!("=== PostgreSQL {} ===", version);
 
-    if !is_process_running(info.pid) {
-        remove_instance(&name)?;
-        return Err(CliError::NoInstance);
-    }
+        if existing.contains(&name) {
+            drop_instance(name.clone(), true)?;
+        }
 
-    let psql_path = find_psql_binary(&info.installation_dir)?;
-    // psql is dynamic-linked against the same libxml2/libicu as postgres, so
-    // make sure subprocess can find the bundled libs even when this command is
-    // invoked against an instance that another `pg0 start` already extracted.
-    ensure_runtime_libs_for_psql(&psql_path)?;
+        let outcome = start(StartOptions {
+            name: name.clone(),
+            version: Some(version.clone()),
+            ..Default::default()
+        })
+        .map_err(|e| e.to_string())
+        .and_then(|_| {
+            let info = load_instance(&name)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| CliError::NoInstance.to_string())?;
+            let uri = instance_uri(&info, &info.database);
+            println!();
+            println!(
+                "Running '{}' against PostgreSQL {}...",
+                command.join(" "),
+                info.version
+            );
+            let status = std::process::Command::new(&command[0])
+                .args(&command[1..])
+                .env("DATABASE_URL", &uri)
+                .env("PGHOST", instance_host(&info))
+                .env("PGPORT", info.port.to_string())
+                .env("PGUSER", &info.username)
+                .env("PGPASSWORD", &info.password)
+                .env("PGDATABASE", &info.database)
+                .status()
+                .map_err(|e| format!("failed to run {}: {}", command[0], e))?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(match status.code() {
+                    Some(code) => format!("exit code {}", code),
+                    None => "terminated by signal".to_string(),
+                })
+            }
+        });
 
-    // Build connection URI
-    let uri = format!(
-        "postgresql://{}:{}@127.0.0.1:{}/{}",
-        info.username, info.password, info.port, info.database
-    );
+        if !keep {
+            if let Err(e) = drop_instance(name.clone(), true) {
+                eprintln!("Warning: Failed to drop instance '{}': {}", name, e);
+            }
+        }
+        println!();
+        results.push((version.clone(), outcome));
+    }
 
-    // Execute psql with the connection URI and any additional args
-    let status = std::process::Command::new(&psql_path)
-        .arg(&uri)
-        .args(&args)
-        .status()?;
+    println!("Matrix results:");
+    for (version, outcome) in &results {
+        match outcome {
+            Ok(()) => println!("  {:<10} pass", version),
+            Err(reason) => println!("  {:<10} FAIL ({})", version, reason),
+        }
+    }
 
-    if !status.success() {
-        std::process::exit(status.code().unwrap_or(1));
+    let failed = results.iter().filter(|(_, o)| o.is_err()).count();
+    if failed > 0 {
+        return Err(CliError::Other(format!(
+            "{} of {} versions failed",
+            failed,
+            results.len()
+        )));
     }
 
     Ok(())
 }
 
-fn logs(name: String, lines: Option<usize>, follow: bool) -> Result<(), CliError> {
-    let instance_dir = get_instance_dir(&name)?;
-    let log_dir = instance_dir.join("data").join("log");
+/// The manifest.json of a bundle written by `pg0 bundle export`.
+#[derive(Serialize, Deserialize)]
+struct BundleManifest {
+    /// pg0 release that wrote the bundle
+    pg0_version: String,
+    /// `<os>-<arch>` the binaries in the bundle are built for
+    platform: String,
+    postgresql: Vec<String>,
+    extensions: Vec<BundleExtension>,
+    /// SHA256 of every other file in the bundle, by path
+    files: std::collections::BTreeMap<String, String>,
+}
 
-    if !log_dir.exists() {
-        return Err(CliError::Other(format!(
-            "Log directory not found for instance '{}'. Has PostgreSQL been started?",
-            name
-        )));
-    }
+/// An extension in a bundle.
+#[derive(Serialize, Deserialize)]
+struct BundleExtension {
+    name: String,
+    version: String,
+    /// The PostgreSQL version it was built for
+    postgresql: String,
+    /// Its archive below registry/ in the bundle; none when the extension is
+    /// part of the PostgreSQL installation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archive: Option<String>,
+}
 
-    // Find the most recent log file
-    let mut log_files: Vec<_> = fs::read_dir(&log_dir)?
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_file())
-        .collect();
+/// The platform bundles written by this binary are for.
+fn bundle_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
 
-    if log_files.is_empty() {
+fn bundle_export(
+    output: PathBuf,
+    versions: Vec<String>,
+    extensions: Vec<String>,
+) -> Result<(), CliError> {
+    if output.exists() {
         return Err(CliError::Other(format!(
-            "No log files found for instance '{}'",
-            name
+            "{} already exists",
+            output.display()
         )));
     }
+    let installation_dir = get_base_dir()?.join("installation");
+    fs::create_dir_all(&installation_dir)?;
+    let versions = if versions.is_empty() {
+        vec![env!("PG_VERSION").to_string()]
+    } else {
+        versions
+    };
 
-    // Sort by modification time, most recent first
-    log_files.sort_by_key(|e| std::cmp::Reverse(
-        e.metadata().and_then(|m| m.modified()).ok()
-    ));
-
-    let log_file = &log_files[0].path();
-
-    if follow {
-        // Follow mode - use tail -f equivalent
-        println!("Following logs for instance '{}' (Ctrl+C to exit):", name);
-        println!("Log file: {}", log_file.display());
-        println!();
+    // Write to a temporary file first so an interrupted export never leaves
+    // a truncated bundle behind that looks complete.
+    let mut partial_path = output.clone().into_os_string();
+    partial_path.push(".partial");
+    let partial_path = PathBuf::from(partial_path);
+    let mut builder = tar::Builder::new(fs::File::create(&partial_path)?);
+    let manifest = match write_bundle(&mut builder, &installation_dir, &versions, &extensions) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            drop(builder);
+            let _ = fs::remove_file(&partial_path);
+            return Err(e);
+        }
+    };
+    builder.into_inner()?;
+    fs::rename(&partial_path, &output)?;
 
-        let mut file = fs::File::open(log_file)?;
-        let mut pos = file.metadata()?.len();
+    let size = fs::metadata(&output)?.len();
+    println!();
+    println!("Bundle written to {} ({} bytes)", output.display(), size);
+    println!("  PostgreSQL: {}", manifest.postgresql.join(", "));
+    if !manifest.extensions.is_empty() {
+        let mut names: Vec<&str> = manifest
+            .extensions
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        names.sort();
+        names.dedup();
+        println!("  Extensions: {}", names.join(", "));
+    }
+    println!();
+    println!(
+        "Copy it to the target machine and run 'pg0 bundle import {}'.",
+        output.display()
+    );
+    Ok(())
+}
 
-        // Print existing content first
-        use std::io::{BufRead, BufReader, Seek, SeekFrom};
-        file.seek(SeekFrom::Start(0))?;
-        let reader = BufReader::new(&file);
-        for line in reader.lines() {
-            println!("{}", line?);
+/// Add the PostgreSQL `versions`, each with `extensions`, to `builder`, then
+/// the manifest.
+fn write_bundle(
+    builder: &mut tar::Builder<fs::File>,
+    installation_dir: &Path,
+    versions: &[String],
+    extensions: &[String],
+) -> Result<BundleManifest, CliError> {
+    let mut manifest = BundleManifest {
+        pg0_version: env!("CARGO_PKG_VERSION").to_string(),
+        platform: bundle_platform(),
+        postgresql: Vec::new(),
+        extensions: Vec::new(),
+        files: Default::default(),
+    };
+    for requested in versions {
+        let version_dir = bundle_postgresql(installation_dir, requested)?;
+        let version = version_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if manifest.postgresql.contains(&version) {
+            continue;
         }
+        println!("Adding PostgreSQL {}...", version);
+        let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        archive.follow_symlinks(false);
+        archive.append_dir_all(".", &version_dir)?;
+        let data = archive.into_inner()?.finish()?;
+        append_bundle_file(
+            builder,
+            &mut manifest,
+            &format!("postgresql/{}.tar.gz", version),
+            &data,
+        )?;
+        manifest.postgresql.push(version.clone());
 
-        // Now follow new content
-        loop {
-            file.seek(SeekFrom::Start(pos))?;
-            let reader = BufReader::new(&file);
-            for line in reader.lines() {
-                println!("{}", line?);
-            }
-            pos = file.metadata()?.len();
-            std::thread::sleep(std::time::Duration::from_millis(100));
+        for extension in extensions {
+            bundle_extension(builder, &mut manifest, &version_dir, &version, extension)?;
         }
-    } else {
-        // Show logs (optionally limited to N lines)
-        use std::io::{BufRead, BufReader};
-        let file = fs::File::open(log_file)?;
-        let reader = BufReader::new(file);
-        let all_lines: Vec<_> = reader.lines().collect::<Result<_, _>>()?;
+    }
+    let json = serde_json::to_vec_pretty(&manifest)?;
+    builder.append_data(
+        &mut bundle_file_header(json.len()),
+        "manifest.json",
+        &json[..],
+    )?;
+    Ok(manifest)
+}
 
-        let lines_to_show = if let Some(n) = lines {
-            &all_lines[all_lines.len().saturating_sub(n)..]
-        } else {
-            &all_lines[..]
-        };
+/// The installation of PostgreSQL `requested` ("16", "17.2.0", ...) to put
+/// into a bundle: the bundled release, an installed one, or a download.
+fn bundle_postgresql(installation_dir: &Path, requested: &str) -> Result<PathBuf, CliError> {
+    let version = resolve_version(requested);
+    if version == env!("PG_VERSION") {
+        return extract_bundled_postgresql(installation_dir, &version);
+    }
+    let version_req: VersionReq = version
+        .parse()
+        .map_err(|e| CliError::Other(format!("Invalid version '{}': {}", requested, e)))?;
+    install_downloaded_postgresql(installation_dir, &version, &version_req)
+}
 
-        println!("Logs for instance '{}' ({})", name, log_file.display());
-        println!();
-        for line in lines_to_show {
-            println!("{}", line);
+/// Add `requested` (NAME or NAME@VERSION) for the PostgreSQL installation at
+/// `version_dir` to a bundle. Extensions installed there travel with the
+/// installation; others are taken from the bundled extensions or the
+/// extension registries and stored like a registry, below registry/.
+fn bundle_extension(
+    builder: &mut tar::Builder<fs::File>,
+    manifest: &mut BundleManifest,
+    version_dir: &Path,
+    pg_version: &str,
+    requested: &str,
+) -> Result<(), CliError> {
+    let (name, pinned) = match requested.split_once('@') {
+        Some((name, version)) if !name.is_empty() && !version.is_empty() => {
+            (name.to_lowercase(), Some(version))
+        }
+        Some(_) => {
+            return Err(CliError::Other(format!(
+                "Invalid extension '{}', expected NAME or NAME@VERSION",
+                requested
+            )))
         }
+        None => (requested.to_lowercase(), None),
+    };
+    let control = version_dir
+        .join("share")
+        .join("extension")
+        .join(format!("{}.control", name));
+    if let Some(installed) =
+        control_default_version(&control).filter(|v| pinned.is_none_or(|p| p == v))
+    {
+        println!("  {} {} (installed)", name, installed);
+        manifest.extensions.push(BundleExtension {
+            name,
+            version: installed,
+            postgresql: pg_version.to_string(),
+            archive: None,
+        });
+        return Ok(());
     }
 
+    let pg_major = pg_version.split('.').next().unwrap_or_default();
+    let bundled = find_bundled_extension(&name).filter(|bundle| {
+        pg_version == env!("PG_VERSION") && pinned.is_none_or(|p| p == bundle.version)
+    });
+    let (name, version, archive) = match bundled {
+        Some(bundle) => (bundle.name.to_string(), bundle.version.to_string(), bundle.archive.to_vec()),
+        None => match fetch_mirrored_extension(&name, pinned, pg_major, false)? {
+            Some(mirrored) => (mirrored.name, mirrored.version, mirrored.archive),
+            None => {
+                return Err(CliError::Other(format!(
+                    "Cannot bundle '{}' for PostgreSQL {}: it is not installed there and no extension registry has it. Install it first with 'pg0 install-extension', or add a registry with --extension-registry.",
+                    requested, pg_version
+                )))
+            }
+        },
+    };
+    let path = format!(
+        "registry/{}",
+        registry_archive_path(&name, &version, pg_major)?
+    );
+    println!("  {} {}", name, version);
+    append_bundle_file(builder, manifest, &path, &archive)?;
+    manifest.extensions.push(BundleExtension {
+        name,
+        version,
+        postgresql: pg_version.to_string(),
+        archive: Some(path),
+    });
     Ok(())
 }
 
-fn find_installed_version(installation_dir: &PathBuf) -> Result<String, CliError> {
-    if let Ok(entries) = fs::read_dir(installation_dir) {
-        for entry in entries.flatten() {
-            if entry.path().is_dir() {
-                if let Some(name) = entry.file_name().to_str() {
-                    // Check if it looks like a version directory
-                    if name.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false) {
-                        return Ok(name.to_string());
-                    }
-                }
-            }
-        }
-    }
-    Err(CliError::Io(std::io::Error::new(
-        std::io::ErrorKind::NotFound,
-        "No PostgreSQL version found in installation directory",
-    )))
+/// The tar header of a `size`-byte file written into a bundle now.
+fn bundle_file_header(size: usize) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    );
+    header
 }
 
-fn install_extension(instance_name: String, extension_name: String) -> Result<(), CliError> {
-    let info = load_instance(&instance_name)?.ok_or(CliError::NoInstance)?;
+/// Add a file to a bundle and record its checksum in the manifest.
+fn append_bundle_file(
+    builder: &mut tar::Builder<fs::File>,
+    manifest: &mut BundleManifest,
+    path: &str,
+    data: &[u8],
+) -> Result<(), CliError> {
+    use sha2::{Digest, Sha256};
+    builder.append_data(&mut bundle_file_header(data.len()), path, data)?;
+    manifest
+        .files
+        .insert(path.to_string(), format!("{:x}", Sha256::digest(data)));
+    Ok(())
+}
 
-    if !is_process_running(info.pid) {
-        remove_instance(&instance_name)?;
-        return Err(CliError::NoInstance);
+fn bundle_import(bundle: PathBuf) -> Result<(), CliError> {
+    use std::io::Read;
+
+    // The manifest comes last, after the checksums it holds are known
+    let mut manifest: Option<BundleManifest> = None;
+    for entry in Archive::new(fs::File::open(&bundle)?).entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() == "manifest.json" {
+            let mut json = Vec::new();
+            entry.read_to_end(&mut json)?;
+            manifest = Some(serde_json::from_slice(&json)?);
+        }
+    }
+    let manifest = manifest.ok_or_else(|| {
+        CliError::Other(format!(
+            "{} is not a pg0 bundle (it has no manifest.json)",
+            bundle.display()
+        ))
+    })?;
+    if manifest.platform != bundle_platform() {
+        return Err(CliError::Other(format!(
+            "{} was exported for {}, but this machine is {}; export it with a pg0 binary for {}",
+            bundle.display(),
+            manifest.platform,
+            bundle_platform(),
+            bundle_platform()
+        )));
+    }
+    if manifest.pg0_version != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "Warning: {} was exported by pg0 {} (this is {})",
+            bundle.display(),
+            manifest.pg0_version,
+            env!("CARGO_PKG_VERSION")
+        );
     }
 
-    println!("Fetching available extensions...");
+    let installation_dir = get_base_dir()?.join("installation");
+    let registry_dir = imported_registry_dir()?;
+    let mut imported = std::collections::HashSet::new();
+    for entry in Archive::new(fs::File::open(&bundle)?).entries()? {
+        let mut entry = entry?;
+        let Some(components) = archive_entry_components(&entry) else {
+            continue;
+        };
+        let path = components.join("/");
+        if path == "manifest.json" {
+            continue;
+        }
+        let Some(expected) = manifest.files.get(&path) else {
+            eprintln!(
+                "Warning: skipping {}, which the bundle's manifest doesn't list",
+                path
+            );
+            continue;
+        };
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        verify_artifact(&path, &data, Some(expected), false)?;
 
-    let available = postgresql_extensions::blocking::get_available_extensions()?;
+        match components.as_slice() {
+            [dir, file] if dir == "postgresql" => {
+                let version = file
+                    .strip_suffix(".tar.gz")
+                    .filter(|v| postgresql_embedded::Version::parse(v).is_ok())
+                    .ok_or_else(|| {
+                        CliError::Other(format!("Unexpected file {} in the bundle", path))
+                    })?;
+                import_postgresql(&installation_dir, version, &data)?;
+            }
+            [dir, rest @ ..] if dir == "registry" && !rest.is_empty() => {
+                let dest = rest
+                    .iter()
+                    .fold(registry_dir.clone(), |dest, part| dest.join(part));
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&dest, &data)?;
+            }
+            _ => {
+                eprintln!("Warning: skipping unexpected file {} in the bundle", path);
+                continue;
+            }
+        }
+        imported.insert(path);
+    }
+    let missing: Vec<&str> = manifest
+        .files
+        .keys()
+        .filter(|path| !imported.contains(*path))
+        .map(|path| path.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(CliError::Other(format!(
+            "{} is incomplete, it lacks: {}",
+            bundle.display(),
+            missing.join(", ")
+        )));
+    }
 
-    // Find the extension (case-insensitive search)
-    let ext = available
+    let registry_extensions: Vec<&BundleExtension> = manifest
+        .extensions
         .iter()
-        .find(|e| e.name().to_lowercase() == extension_name.to_lowercase())
-        .ok_or_else(|| CliError::ExtensionNotFound(extension_name.clone()))?;
-
-    let ext_name = ext.name().to_string();
-    let ext_namespace = ext.namespace().to_string();
-    println!("Installing extension '{}'...", ext_name);
-
-    // Get installed PostgreSQL version
-    let pg_version = find_installed_version(&info.installation_dir)?;
-    let version_req: VersionReq = pg_version.parse().map_err(|e| {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            format!("Invalid version: {}", e),
-        )
-    })?;
-
-    // Build Settings for the extension installer
-    // The installation_dir needs to point to the version-specific directory
-    let version_install_dir = info.installation_dir.join(&pg_version);
-    // Make sure the bundled libxml2/libicu are present and on the loader path
-    // before pg_config / pg_ctl are spawned on a host where the system libs
-    // are missing or have a different SONAME.
-    ensure_runtime_libs(&version_install_dir)?;
-    #[cfg(target_os = "linux")]
-    prepend_lib_dir_to_ld_library_path(&version_install_dir.join("lib"));
-    let settings = Settings {
-        version: version_req.clone(),
-        port: info.port,
-        username: info.username.clone(),
-        password: info.password.clone(),
-        data_dir: info.data_dir.clone(),
-        installation_dir: version_install_dir,
-        ..Default::default()
-    };
-
-    postgresql_extensions::blocking::install(
-        &settings,
-        &ext_namespace,
-        &ext_name,
-        &version_req,
-    )?;
+        .filter(|e| e.archive.is_some())
+        .collect();
+    if !registry_extensions.is_empty() {
+        update_imported_registry(&registry_dir, &manifest, &registry_extensions)?;
+    }
 
-    println!("Extension '{}' installed successfully!", ext_name);
     println!();
-    println!("To enable it in your database, run:");
-    println!("  pg0 psql -c \"CREATE EXTENSION IF NOT EXISTS {};\"", ext_name);
-
+    println!("Imported {}:", bundle.display());
+    println!("  PostgreSQL: {}", manifest.postgresql.join(", "));
+    for extension in &manifest.extensions {
+        println!(
+            "  {} {} (PostgreSQL {})",
+            extension.name, extension.version, extension.postgresql
+        );
+    }
+    println!();
+    println!(
+        "Start an instance without network access with 'pg0 --offline start --version <VERSION>'."
+    );
     Ok(())
 }
 
-fn list(output_format: OutputFormat) -> Result<(), CliError> {
-    let instance_names = list_instances()?;
+/// Unpack the PostgreSQL installation archive `data` into
+/// `installation_dir/<version>`, unless that version is installed already.
+fn import_postgresql(installation_dir: &Path, version: &str, data: &[u8]) -> Result<(), CliError> {
+    let version_dir = installation_dir.join(version);
+    if version_dir.join("bin").join(POSTGRES_BINARY).exists() {
+        println!("PostgreSQL {} is already installed, skipping it", version);
+        return Ok(());
+    }
+    println!("Installing PostgreSQL {}...", version);
+    // Unpack next to the installation so a failed import leaves nothing
+    // that looks installed
+    let staging = installation_dir.join(format!("{}.import", version));
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(&staging)?;
+    Archive::new(GzDecoder::new(data)).unpack(&staging)?;
+    let _ = fs::remove_dir_all(&version_dir);
+    fs::rename(&staging, &version_dir)?;
+    Ok(())
+}
 
-    let mut instances: Vec<InfoOutput> = Vec::new();
-    for name in &instance_names {
-        if let Some(info) = load_instance(name)? {
-            let running = is_process_running(info.pid);
-            let output = if running {
-                let uri = format!(
-                    "postgresql://{}:{}@127.0.0.1:{}/{}",
-                    info.username, info.password, info.port, info.database
-                );
-                InfoOutput {
-                    name: name.clone(),
-                    running: true,
-                    pid: Some(info.pid),
-                    port: Some(info.port),
-                    version: Some(info.version),
-                    username: Some(info.username),
-                    database: Some(info.database),
-                    data_dir: Some(info.data_dir.display().to_string()),
-                    uri: Some(uri),
-                }
-            } else {
-                InfoOutput {
-                    name: name.clone(),
-                    running: false,
-                    pid: None,
-                    port: Some(info.port),
-                    version: Some(info.version),
-                    username: Some(info.username),
-                    database: Some(info.database),
-                    data_dir: Some(info.data_dir.display().to_string()),
-                    uri: None,
-                }
-            };
-            instances.push(output);
+/// Add a bundle's registry extensions to the index.json and SHA256SUMS of
+/// the imported extension registry.
+fn update_imported_registry(
+    registry_dir: &Path,
+    manifest: &BundleManifest,
+    extensions: &[&BundleExtension],
+) -> Result<(), CliError> {
+    let index_path = registry_dir.join("index.json");
+    let mut index: Vec<RegistryExtension> = fs::read(&index_path)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    let mut checksums: std::collections::BTreeMap<String, String> =
+        registry_checksums(&format!("file://{}", registry_dir.display()))
+            .into_iter()
+            .collect();
+    for extension in extensions {
+        match index.iter_mut().find(|e| e.name == extension.name) {
+            Some(entry) if entry.versions.contains(&extension.version) => {}
+            Some(entry) => entry.versions.push(extension.version.clone()),
+            None => index.push(RegistryExtension {
+                name: extension.name.clone(),
+                description: String::new(),
+                versions: vec![extension.version.clone()],
+            }),
+        }
+        if let Some(archive) = &extension.archive {
+            if let (Some(path), Some(sha256)) = (
+                archive.strip_prefix("registry/"),
+                manifest.files.get(archive),
+            ) {
+                checksums.insert(path.to_string(), sha256.clone());
+            }
         }
     }
+    let sums: String = checksums
+        .iter()
+        .map(|(path, sha256)| format!("{}  {}\n", sha256, path))
+        .collect();
+    fs::write(registry_dir.join("SHA256SUMS"), sums)?;
+    // The index goes last: its presence makes the registry used
+    fs::write(&index_path, serde_json::to_vec_pretty(&index)?)?;
+    Ok(())
+}
 
-    match output_format {
-        OutputFormat::Json => {
-            println!("{}", serde_json::to_string_pretty(&instances)?);
+fn list_extensions() -> Result<(), CliError> {
+    if !BUNDLED_EXTENSIONS.is_empty() {
+        println!(
+            "Bundled extensions (PostgreSQL {}, no download needed):",
+            env!("PG_VERSION")
+        );
+        println!();
+        for ext in BUNDLED_EXTENSIONS {
+            println!("  {} {}", ext.name, ext.version);
         }
-        OutputFormat::Text => {
-            if instances.is_empty() {
-                println!("No instances found.");
-            } else {
-                println!("Instances:");
+        println!();
+    }
+
+    let registries = extension_registries();
+    for registry in &registries {
+        println!("Fetching extensions from {}...", registry);
+        match registry_index(registry) {
+            Ok(index) => {
                 println!();
-                for instance in &instances {
-                    let status = if instance.running { "running" } else { "stopped" };
-                    if instance.running {
-                        println!(
-                            "  {} ({}) - port {} - {}",
-                            instance.name,
-                            status,
-                            instance.port.unwrap(),
-                            instance.uri.as_ref().unwrap()
-                        );
+                for ext in index {
+                    if ext.description.is_empty() {
+                        println!("  {} {}", ext.name, ext.versions.join(", "));
                     } else {
                         println!(
-                            "  {} ({}) - port {} - {}",
-                            instance.name,
-                            status,
-                            instance.port.unwrap(),
-                            instance.data_dir.as_ref().unwrap()
+                            "  {} {} - {}",
+                            ext.name,
+                            ext.versions.join(", "),
+                            ext.description
                         );
                     }
                 }
+                println!();
             }
+            Err(e) => eprintln!("Warning: {}", e),
         }
     }
 
-    Ok(())
-}
-
-fn list_extensions() -> Result<(), CliError> {
+    if let Err(e) = ensure_network("The extension list", "https://api.github.com", "") {
+        eprintln!("Note: {}", e.to_string().trim_end());
+        return Ok(());
+    }
     println!("Fetching available extensions...");
 
-    let extensions = postgresql_extensions::blocking::get_available_extensions()?;
+    let extensions = match postgresql_extensions::blocking::get_available_extensions() {
+        Ok(extensions) => extensions,
+        // Mirrors stand in for the built-in sources where those are blocked
+        Err(e) if !registries.is_empty() => {
+            eprintln!(
+                "Warning: could not fetch the built-in extension list: {}",
+                e
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     println!();
     println!("Available extensions:");
@@ -1493,6 +18939,230 @@ fn list_extensions() -> Result<(), CliError> {
     Ok(())
 }
 
+/// One row of `pg0 list-extensions --installed`.
+#[derive(Serialize)]
+struct InstalledExtension {
+    name: String,
+    default_version: String,
+    bundled: bool,
+    /// Installed for this instance only (`install-extension --isolated`)
+    isolated: bool,
+    /// Databases the extension is created in, with the created version
+    databases: Vec<ExtensionDatabase>,
+}
+
+#[derive(Serialize)]
+struct ExtensionDatabase {
+    database: String,
+    version: String,
+}
+
+fn list_installed_extensions(name: String, output_format: OutputFormat) -> Result<(), CliError> {
+    let info = load_instance(&name)?.ok_or(CliError::NoInstance)?;
+    let extension_dir = info
+        .installation_dir
+        .join(&info.version)
+        .join("share")
+        .join("extension");
+    let running = is_instance_running(&info);
+
+    let mut extensions: Vec<InstalledExtension> = fs::read_dir(&extension_dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let file_name = e.file_name().to_string_lossy().to_string();
+            let name = file_name.strip_suffix(".control")?.to_string();
+            // The instance's private copy (install-extension --isolated)
+            // takes precedence
+            let control = extension_control_file(&info, &name);
+            let default_version = control_default_version(&control).unwrap_or_default();
+            Some(InstalledExtension {
+                bundled: info.version == env!("PG_VERSION")
+                    && BUNDLED_EXTENSIONS.iter().any(|b| b.name == name),
+                isolated: control != e.path(),
+                name,
+                default_version,
+                databases: Vec::new(),
+            })
+        })
+        .collect();
+    // Extensions only this instance has
+    let private_dir = private_extension_dir(&info).join("share").join("extension");
+    for entry in fs::read_dir(&private_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+    {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some(name) = file_name.strip_suffix(".control") else {
+            continue;
+        };
+        if extensions.iter().any(|e| e.name == name) {
+            continue;
+        }
+        extensions.push(InstalledExtension {
+            name: name.to_string(),
+            default_version: control_default_version(&entry.path()).unwrap_or_default(),
+            bundled: false,
+            isolated: true,
+            databases: Vec::new(),
+        });
+    }
+    extensions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if running {
+        let databases = run_sql(
+            &info,
+            "postgres",
+            "SELECT datname FROM pg_database WHERE datallowconn AND NOT datistemplate ORDER BY datname;",
+        )?;
+        for database in databases.lines() {
+            let created = run_sql(
+                &info,
+                database,
+                "SELECT extname, extversion FROM pg_extension ORDER BY extname;",
+            )?;
+            for (extname, version) in created.lines().filter_map(|l| l.split_once('|')) {
+                if let Some(extension) = extensions.iter_mut().find(|e| e.name == extname) {
+                    extension.databases.push(ExtensionDatabase {
+                        database: database.to_string(),
+                        version: version.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    match output_format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&extensions)?),
+        OutputFormat::Text => {
+            println!(
+                "Extensions installed for '{}' (PostgreSQL {}):",
+                name, info.version
+            );
+            println!();
+            println!("{:<28} {:<12} CREATED IN", "EXTENSION", "VERSION");
+            for extension in &extensions {
+                let created = if !running {
+                    "?".to_string()
+                } else if extension.databases.is_empty() {
+                    "-".to_string()
+                } else {
+                    extension
+                        .databases
+                        .iter()
+                        .map(|d| {
+                            if d.version == extension.default_version {
+                                d.database.clone()
+                            } else {
+                                format!("{} ({})", d.database, d.version)
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                let name = if extension.isolated {
+                    format!("{} (isolated)", extension.name)
+                } else if extension.bundled {
+                    format!("{} (bundled)", extension.name)
+                } else {
+                    extension.name.clone()
+                };
+                println!("{:<28} {:<12} {}", name, extension.default_version, created);
+            }
+            if !running {
+                println!();
+                println!(
+                    "'{}' is not running; start it to see which databases use each extension.",
+                    name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One row of `pg0 list-collations`.
+#[derive(Serialize)]
+struct CollationInfo {
+    name: String,
+    locale: String,
+}
+
+fn list_collations(
+    name: String,
+    filter: Option<String>,
+    provider: LocaleProvider,
+    output_format: OutputFormat,
+) -> Result<(), CliError> {
+    let info = load_running_instance(&name)?;
+
+    // The locale column was renamed between releases (collcollate for libc,
+    // colliculocale in 15-16, colllocale from 17); to_jsonb reads whichever exists.
+    let mut sql = format!(
+        "SELECT collname, coalesce(to_jsonb(c)->>'colllocale', to_jsonb(c)->>'colliculocale', collcollate, '') \
+         FROM pg_collation c WHERE collprovider = '{}'",
+        provider.catalog_code()
+    );
+    if let Some(filter) = &filter {
+        sql.push_str(&format!(
+            " AND collname ILIKE {}",
+            quote_literal(&format!("%{}%", filter))
+        ));
+    }
+    sql.push_str(" ORDER BY collname;");
+    let collations: Vec<CollationInfo> = run_sql(&info, &info.database, &sql)?
+        .lines()
+        .filter_map(|line| line.split_once('|'))
+        .map(|(name, locale)| CollationInfo {
+            name: name.to_string(),
+            locale: locale.to_string(),
+        })
+        .collect();
+
+    match output_format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&collations)?),
+        OutputFormat::Text => {
+            let default = run_sql(
+                &info,
+                &info.database,
+                "SELECT coalesce(to_jsonb(d)->>'datlocprovider', 'c'), datcollate, \
+                 coalesce(to_jsonb(d)->>'datlocale', to_jsonb(d)->>'daticulocale', '') \
+                 FROM pg_database d WHERE datname = current_database();",
+            )?;
+            let fields: Vec<&str> = default.split('|').collect();
+            if let [code, collate, locale] = fields[..] {
+                match code {
+                    "i" => println!("Database '{}' uses ICU locale '{}'.", info.database, locale),
+                    "b" => println!(
+                        "Database '{}' uses the builtin locale '{}'.",
+                        info.database, locale
+                    ),
+                    _ => println!(
+                        "Database '{}' uses libc locale '{}'.",
+                        info.database, collate
+                    ),
+                }
+                println!();
+            }
+            if collations.is_empty() {
+                println!("No {} collations found.", provider.as_str());
+                return Ok(());
+            }
+            println!("{:<32} LOCALE", "COLLATION");
+            for collation in &collations {
+                println!("{:<32} {}", collation.name, collation.locale);
+            }
+            println!();
+            println!(
+                "{} collations. Use one with: ORDER BY name COLLATE \"{}\"",
+                collations.len(),
+                collations[0].name
+            );
+        }
+    }
+    Ok(())
+}
+
 fn init_logging(verbose: bool) {
     let filter = if verbose {
         EnvFilter::new("debug")
@@ -1510,6 +19180,23 @@ fn main() {
     let cli = Cli::parse();
 
     init_logging(cli.verbose);
+    // Through the environment, the base directory also reaches the
+    // background workers pg0 spawns.
+    if let Some(base_dir) = &cli.base_dir {
+        std::env::set_var("PG0_HOME", base_dir);
+    }
+    if !cli.extension_registries.is_empty() {
+        std::env::set_var("PG0_EXTENSION_REGISTRY", cli.extension_registries.join(";"));
+    }
+    if cli.offline {
+        std::env::set_var("PG0_OFFLINE", "1");
+    }
+
+    let operation = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .unwrap_or_default();
+    let mut root_span = operation_span(&format!("pg0 {}", operation));
 
     let result = match cli.command {
         Commands::Start {
@@ -1517,27 +19204,882 @@ fn main() {
             port,
             version,
             data_dir,
+            in_memory,
             username,
             password,
+            random_password,
+            show_secrets,
+            keychain,
+            auth,
+            tls,
+            listen,
+            allow,
+            socket_dir,
+            socket_only,
             database,
+            roles,
+            init_dir,
+            init_sql,
+            extensions,
             config,
+            preset,
+            auto_tune,
+            durability,
+            no_durability,
+            health_port,
+            health_host,
+            idle_timeout,
+            ttl,
+            ttl_action,
+            memory_limit,
+            cpu_limit,
+            auto_explain,
+            audit,
+            locale,
+            encoding,
+            lc_collate,
+            locale_provider,
+            icu_locale,
+            auth_host,
+            initdb_arg,
+            label,
+            hook,
+            reset,
+            local,
         } => {
+            let name = if local {
+                local_instance_name()
+            } else {
+                Ok(name)
+            };
             let port_was_specified = port.is_some();
             let port = port.unwrap_or(5432);
-            start(name, port, port_was_specified, version, data_dir, username, password, database, config)
+            let durability = if no_durability {
+                Some(Durability::Fast)
+            } else {
+                durability
+            };
+            name.and_then(|name| {
+                start(StartOptions {
+                    name,
+                    port,
+                    port_was_specified,
+                    version,
+                    data_dir,
+                    in_memory,
+                    username,
+                    password,
+                    random_password,
+                    show_secrets,
+                    keychain,
+                    auth,
+                    tls,
+                    listen,
+                    allow,
+                    socket_dir,
+                    socket_only,
+                    database,
+                    roles,
+                    init_dir,
+                    init_sql,
+                    extensions,
+                    config,
+                    preset,
+                    auto_tune,
+                    durability,
+                    health_port,
+                    health_host,
+                    idle_timeout,
+                    ttl,
+                    ttl_action,
+                    memory_limit,
+                    cpu_limit,
+                    auto_explain,
+                    audit,
+                    initdb: InitdbOptions {
+                        locale,
+                        encoding,
+                        lc_collate,
+                        locale_provider,
+                        icu_locale,
+                        auth_host,
+                        extra_args: initdb_arg,
+                    },
+                    labels: label,
+                    hooks: hook,
+                    reset,
+                })
+            })
         }
-        Commands::Stop { name } => stop(name),
-        Commands::Drop { name, force } => drop_instance(name, force),
-        Commands::Info { name, output } => info(name, output),
-        Commands::List { output } => list(output),
+        Commands::Stop {
+            name,
+            filter,
+            mode,
+            timeout,
+            force,
+        } => parse_duration_secs(&timeout).and_then(|timeout_secs| {
+            let options = StopOptions {
+                mode,
+                timeout_secs,
+                force,
+            };
+            if filter.is_empty() {
+                stop(name, &options)
+            } else {
+                stop_matching(&filter, &options)
+            }
+        }),
+        Commands::Drop {
+            name,
+            force,
+            filter,
+        } if filter.is_empty() => drop_instance(name, force),
+        Commands::Drop { force, filter, .. } => drop_matching(&filter, force),
+        Commands::Info {
+            name,
+            watch: true,
+            show_secrets,
+            ..
+        } => info_watch(name, show_secrets),
+        Commands::Info {
+            name,
+            format: Some(format),
+            ..
+        } => info_connection_string(name, format),
+        Commands::Info {
+            name,
+            output,
+            show_secrets,
+            ..
+        } => info(name, output, show_secrets),
+        Commands::Url {
+            name,
+            database,
+            no_password,
+        } => url(name, database, no_password),
+        Commands::Env { name, dotenv } => env(name, dotenv),
+        Commands::List {
+            filter,
+            output,
+            show_secrets,
+        } => list(&filter, output, show_secrets),
+        Commands::Report { output } => report(output),
+        Commands::Du { sort, output } => du(sort, output),
+        Commands::Doctor { output } => doctor(output),
+        Commands::Ui => ui(),
+        Commands::Top { name } => top(name),
+        Commands::Sessions { name, output } => sessions(name, output),
+        Commands::Locks { name, output } => locks(name, output),
+        Commands::Bloat {
+            name,
+            database,
+            threshold,
+            output,
+        } => bloat(name, database, threshold, output),
+        Commands::Maintain {
+            name,
+            database,
+            analyze,
+            full,
+            jobs,
+        } => maintain(name, database, analyze, full, jobs),
+        Commands::Reindex {
+            name,
+            database,
+            index,
+            table,
+            all,
+            concurrently,
+            jobs,
+        } => reindex(name, database, index, table, all, concurrently, jobs),
+        Commands::Verify {
+            name,
+            database,
+            heapallindexed,
+            jobs,
+        } => verify(name, database, heapallindexed, jobs),
+        Commands::KillSession {
+            name,
+            pid,
+            idle_in_transaction,
+        } => kill_session(name, pid, idle_in_transaction),
         Commands::Psql { name, args } => psql(name, args),
-        Commands::Logs { name, lines, follow } => logs(name, lines, follow),
-        Commands::InstallExtension { name, extension } => install_extension(name, extension),
-        Commands::ListExtensions => list_extensions(),
+        Commands::Logs {
+            command:
+                Some(LogsCommands::Analyze {
+                    name,
+                    min_duration,
+                    top,
+                    output,
+                }),
+            ..
+        } => logs_analyze(name, min_duration, top, output),
+        Commands::Logs {
+            command: None,
+            name,
+            lines,
+            follow,
+            plans,
+            grep,
+        } => {
+            if plans {
+                logs_plans(name, lines)
+            } else {
+                logs(name, lines, follow, grep)
+            }
+        }
+        Commands::InstallExtension {
+            name,
+            extension,
+            from,
+            isolated,
+            create,
+            upgrade,
+            insecure,
+            build_from_source,
+            databases,
+        } => install_extension(
+            name,
+            extension,
+            from,
+            ExtensionInstallOptions {
+                isolated,
+                upgrade,
+                insecure,
+                build_from_source,
+            },
+            create,
+            databases,
+        ),
+        Commands::UninstallExtension {
+            name,
+            extension,
+            cascade,
+            force,
+        } => uninstall_extension(name, extension, cascade, force),
+        Commands::ListExtensions {
+            name,
+            installed,
+            output,
+        } => {
+            if installed {
+                list_installed_extensions(name, output)
+            } else {
+                list_extensions()
+            }
+        }
+        Commands::ListCollations {
+            name,
+            filter,
+            provider,
+            output,
+        } => list_collations(name, filter, provider, output),
+        Commands::Basebackup {
+            name,
+            output,
+            no_verify,
+            force,
+        } => basebackup(name, output, no_verify, force),
+        Commands::Archive { name, to } => archive(name, to),
+        Commands::Unarchive { name, keep, from } => unarchive(name, keep, from),
+        Commands::Replica { command } => match command {
+            ReplicaCommands::Create { of, name, port } => replica_create(of, name, port),
+        },
+        Commands::Promote { name, repoint } => promote(name, repoint),
+        Commands::Replicate {
+            from,
+            to,
+            tables,
+            database,
+            copy_schema,
+            drop,
+        } => {
+            if drop {
+                replicate_drop(from, to, database)
+            } else {
+                replicate(from, to, tables, database, copy_schema)
+            }
+        }
+        Commands::Fdw { command } => match command {
+            FdwCommands::Add {
+                name,
+                uri,
+                instance,
+                database,
+                remote_schema,
+                local_schema,
+            } => fdw_add(instance, name, uri, database, remote_schema, local_schema),
+            FdwCommands::Remove {
+                name,
+                instance,
+                database,
+                local_schema,
+            } => fdw_remove(instance, name, database, local_schema),
+        },
+        Commands::Cdc {
+            command: Some(CdcCommands::Sink { command }),
+            ..
+        } => match command {
+            SinkCommands::Add {
+                name,
+                id,
+                url,
+                kafka,
+                tables,
+                database,
+            } => cdc_sink_add(name, id, url, kafka, tables, database),
+            SinkCommands::List { name } => cdc_sink_list(name),
+            SinkCommands::Remove { id, name } => cdc_sink_remove(name, id),
+        },
+        Commands::Cdc {
+            command: None,
+            name,
+            slot,
+            plugin,
+            tables,
+            database,
+            follow,
+            drop,
+        } => {
+            if drop {
+                cdc_drop(name, slot, database)
+            } else {
+                cdc(name, slot, plugin, tables, database, follow)
+            }
+        }
+        Commands::Apply { file } => apply(file),
+        Commands::Config { command } => match command {
+            ConfigCommands::Edit { name } => config_edit(name),
+            ConfigCommands::Set {
+                name,
+                settings,
+                restart,
+            } => config_set(name, settings, restart),
+            ConfigCommands::Show { name, diff, output } => config_show(name, diff, output),
+        },
+        Commands::Preload { command } => match command {
+            PreloadCommands::List { name } => preload_list(name),
+            PreloadCommands::Add {
+                name,
+                libraries,
+                restart,
+            } => preload_edit(name, libraries, true, restart),
+            PreloadCommands::Remove {
+                name,
+                libraries,
+                restart,
+            } => preload_edit(name, libraries, false, restart),
+        },
+        Commands::Hba { command } => match command {
+            HbaCommands::List { name } => hba_list(name),
+            HbaCommands::Add {
+                name,
+                kind,
+                database,
+                user,
+                address,
+                method,
+                options,
+            } => hba_add(name, kind, database, user, address, method, options),
+            HbaCommands::Remove { number, name } => hba_remove(name, number),
+        },
+        Commands::CdcWorker { name, sink } => cdc_worker(name, sink),
+        Commands::Serve {
+            name,
+            port,
+            host,
+            token,
+        } => serve(name, port, host, token),
+        Commands::Pool { command } => match command {
+            PoolCommands::Enable {
+                name,
+                mode,
+                size,
+                port,
+                show_secrets,
+            } => pool_enable(name, mode, size, port, show_secrets),
+            PoolCommands::Disable { name } => pool_disable(name),
+        },
+        Commands::PoolWorker { name } => pool_worker(name),
+        Commands::Metrics { command } => match command {
+            MetricsCommands::Enable { name, port } => metrics_enable(name, port),
+            MetricsCommands::Disable { name } => metrics_disable(name),
+        },
+        Commands::MetricsWorker { name } => metrics_worker(name),
+        Commands::OtelWorker { name } => otel_worker(name),
+        Commands::HealthWorker { name } => health_worker(name),
+        Commands::AutostopWorker { name } => autostop_worker(name),
+        Commands::Debug { command } => match command {
+            DebugCommands::Queries { action, name } => match action {
+                QueryLogAction::On => debug_queries_toggle(name, true),
+                QueryLogAction::Off => debug_queries_toggle(name, false),
+                QueryLogAction::Tail => debug_queries_tail(name),
+            },
+        },
+        Commands::Stats { command } => match command {
+            StatsCommands::Statements {
+                name,
+                database,
+                order_by,
+                limit,
+                reset,
+                output,
+            } => stats_statements(name, database, order_by, limit, reset, output),
+        },
+        Commands::Matrix { command } => match command {
+            MatrixCommands::Run {
+                versions,
+                keep,
+                force,
+                command,
+            } => matrix_run(versions, keep, force, command),
+        },
+        Commands::Bundle { command } => match command {
+            BundleCommands::Export {
+                output,
+                versions,
+                extensions,
+            } => bundle_export(output, versions, extensions),
+            BundleCommands::Import { bundle } => bundle_import(bundle),
+        },
     };
 
+    if let Err(e) = &result {
+        root_span.fail(e);
+    }
+    drop(root_span);
+    flush_telemetry();
+
     if let Err(e) = result {
         eprintln!("Error: {}", e);
         process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_check_values() {
+        // RFC 3720 (iSCSI) appendix B.4 and the common check value.
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(&[0u8; 32]), 0x8A91_36AA);
+        assert_eq!(crc32c(&[0xFFu8; 32]), 0x62A8_AB43);
+    }
+
+    #[test]
+    fn kafka_record_batch_encoding() {
+        let records = vec![(Some(b"k".to_vec()), b"v".to_vec()), (None, b"w".to_vec())];
+        let batch = kafka_record_batch(&records, 1_700_000_000_000);
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0, 0, 0, 0, 0, 0, 0, 0, // base offset
+            0, 0, 0, 0x42, // batch length
+            0xff, 0xff, 0xff, 0xff, // partition leader epoch
+            2, // magic
+            0x46, 0xe0, 0x76, 0xb8, // crc
+            0, 0, // attributes
+            0, 0, 0, 1, // last offset delta
+            0, 0, 0x01, 0x8b, 0xcf, 0xe5, 0x68, 0, // base timestamp
+            0, 0, 0x01, 0x8b, 0xcf, 0xe5, 0x68, 0, // max timestamp
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // producer id
+            0xff, 0xff, // producer epoch
+            0xff, 0xff, 0xff, 0xff, // base sequence
+            0, 0, 0, 2, // records
+            0x10, 0, 0, 0, 0x02, b'k', 0x02, b'v', 0, // length 8, key "k", value "v"
+            0x0e, 0, 0, 0x02, 0x01, 0x02, b'w', 0, // length 7, offset 1, null key, value "w"
+        ];
+        assert_eq!(batch, expected);
+    }
+
+    #[test]
+    fn put_varint_zigzag() {
+        let encode = |value| {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, value);
+            buf
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(-1), [0x01]);
+        assert_eq!(encode(1), [0x02]);
+        assert_eq!(encode(63), [0x7e]);
+        assert_eq!(encode(64), [0x80, 0x01]);
+        assert_eq!(encode(-65), [0x81, 0x01]);
+    }
+
+    /// Messages captured from pg_logical_slot_peek_binary_changes() on
+    /// PostgreSQL 16 for a table cap(id int primary key, name text, note text).
+    const RELATION: &str = "52000040007075626c696300636170006400030169640000000017ffffffff006e616d650000000019ffffffff006e6f74650000000019ffffffff";
+
+    fn decode(decoder: &mut PgOutputDecoder, xid: &str, hex: &str) -> Option<serde_json::Value> {
+        decoder.decode("0/1529B20", xid, &decode_hex(hex).unwrap())
+    }
+
+    #[test]
+    fn pgoutput_row_changes() {
+        let mut decoder = PgOutputDecoder::default();
+        // BEGIN and RELATION are bookkeeping.
+        assert_eq!(
+            decode(
+                &mut decoder,
+                "727",
+                "420000000001529c00000301151e39e99c000002d7"
+            ),
+            None
+        );
+        assert_eq!(decode(&mut decoder, "727", RELATION), None);
+
+        // INSERT INTO cap VALUES (1, 'a', NULL)
+        assert_eq!(
+            decode(
+                &mut decoder,
+                "727",
+                "49000040004e00037400000001317400000001616e"
+            ),
+            Some(serde_json::json!({
+                "lsn": "0/1529B20",
+                "xid": 727,
+                "action": "insert",
+                "schema": "public",
+                "table": "cap",
+                "columns": {"id": "1", "name": "a", "note": null},
+            }))
+        );
+        // UPDATE cap SET name = 'b' WHERE id = 1
+        assert_eq!(
+            decode(
+                &mut decoder,
+                "728",
+                "55000040004e00037400000001317400000001626e"
+            ),
+            Some(serde_json::json!({
+                "lsn": "0/1529B20",
+                "xid": 728,
+                "action": "update",
+                "schema": "public",
+                "table": "cap",
+                "columns": {"id": "1", "name": "b", "note": null},
+            }))
+        );
+        // DELETE FROM cap WHERE id = 1: only the replica identity is sent.
+        assert_eq!(
+            decode(&mut decoder, "729", "44000040004b00037400000001316e6e"),
+            Some(serde_json::json!({
+                "lsn": "0/1529B20",
+                "xid": 729,
+                "action": "delete",
+                "schema": "public",
+                "table": "cap",
+                "identity": {"id": "1", "name": null, "note": null},
+            }))
+        );
+        // TRUNCATE cap
+        assert_eq!(
+            decode(&mut decoder, "730", "54000000010000004000"),
+            Some(serde_json::json!({
+                "lsn": "0/1529B20",
+                "xid": 730,
+                "action": "truncate",
+                "tables": ["public.cap"],
+            }))
+        );
+        // COMMIT
+        assert_eq!(
+            decode(
+                &mut decoder,
+                "727",
+                "43000000000001529c000000000001529c30000301151e39e99c"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn pgoutput_needs_relation_and_complete_messages() {
+        let mut decoder = PgOutputDecoder::default();
+        // A row change for a relation that hasn't been described is skipped.
+        assert_eq!(
+            decode(
+                &mut decoder,
+                "727",
+                "49000040004e00037400000001317400000001616e"
+            ),
+            None
+        );
+        decode(&mut decoder, "727", RELATION);
+        // Truncated mid-value.
+        assert_eq!(
+            decode(&mut decoder, "727", "49000040004e000374000000013174000000"),
+            None
+        );
+    }
+
+    #[test]
+    fn pg_message_framing() {
+        let mut buf = Vec::new();
+        write_pg_message(&mut buf, b'Q', b"SELECT 1\0").unwrap();
+        assert_eq!(&buf[..5], &[b'Q', 0, 0, 0, 13]);
+        assert_eq!(
+            read_pg_message(&mut buf.as_slice()).unwrap(),
+            (b'Q', b"SELECT 1\0".to_vec())
+        );
+
+        // Lengths below 4 and above the limit are rejected before any
+        // allocation; a body cut short is an EOF.
+        let error = read_pg_message(&mut [b'Q', 0, 0, 0, 3].as_slice()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let error = read_pg_message_limited(&mut [b'p', 0x7f, 0xff, 0xff, 0xff].as_slice(), 10_000)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let error = read_pg_message(&mut [b'p', 0x7f, 0xff, 0xff, 0xff].as_slice()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let error = read_pg_message(&mut [b'Q', 0, 0, 0, 13, b'S'].as_slice()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn scram_rfc7677_exchange() {
+        let (client_final, server_signature) = scram_client_final(
+            "pencil",
+            "n=user,r=rOprNGfwEbeRWgbNEkqO",
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        )
+        .unwrap();
+        assert_eq!(
+            client_final,
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        scram_verify_server_final(
+            "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=",
+            &server_signature,
+        )
+        .unwrap();
+        assert!(scram_verify_server_final(
+            "v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=",
+            &server_signature
+        )
+        .is_err());
+        assert!(scram_verify_server_final("e=invalid-proof", &server_signature).is_err());
+
+        // The server must extend the client's nonce.
+        assert!(scram_client_final(
+            "pencil",
+            "n=,r=abc",
+            "r=xyz123,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+        )
+        .is_err());
+    }
+
+    /// In-memory server: every message the client writes is answered by
+    /// `respond`, whose reply becomes readable.
+    struct FakeServer<F: FnMut(&[u8]) -> Vec<u8>> {
+        respond: F,
+        replies: std::collections::VecDeque<u8>,
+        received: Vec<Vec<u8>>,
+    }
+
+    impl<F: FnMut(&[u8]) -> Vec<u8>> FakeServer<F> {
+        fn new(respond: F) -> Self {
+            FakeServer {
+                respond,
+                replies: Default::default(),
+                received: Vec::new(),
+            }
+        }
+    }
+
+    impl<F: FnMut(&[u8]) -> Vec<u8>> std::io::Read for FakeServer<F> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            std::io::Read::read(&mut self.replies, buf)
+        }
+    }
+
+    impl<F: FnMut(&[u8]) -> Vec<u8>> std::io::Write for FakeServer<F> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let reply = (self.respond)(buf);
+            self.replies.extend(reply);
+            self.received.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn messages(messages: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (tag, body) in messages {
+            write_pg_message(&mut buf, *tag, body).unwrap();
+        }
+        buf
+    }
+
+    fn auth(code: i32, data: &[u8]) -> Vec<u8> {
+        [&code.to_be_bytes(), data].concat()
+    }
+
+    /// Everything a server sends after successful authentication.
+    fn startup_done() -> Vec<u8> {
+        messages(&[
+            (b'R', &auth(0, b"")),
+            (b'S', b"server_version\x0016.4\x00"),
+            (b'K', &[0, 0, 0, 42, 1, 2, 3, 4]),
+            (b'Z', b"I"),
+        ])
+    }
+
+    fn pool_key(password: &str) -> PoolKey {
+        ("app".to_string(), "appdb".to_string(), password.to_string())
+    }
+
+    #[test]
+    fn server_handshake_cleartext() {
+        let mut step = 0;
+        let server = FakeServer::new(|message: &[u8]| {
+            step += 1;
+            match step {
+                1 => messages(&[(b'R', &auth(3, b""))]),
+                _ if message == messages(&[(b'p', b"secret\0")]) => startup_done(),
+                _ => messages(&[(b'E', &pg_error_body("28P01", "wrong password"))]),
+            }
+        });
+        let Ok(conn) = server_handshake(server, &pool_key("secret")) else {
+            panic!("handshake failed");
+        };
+        assert_eq!(
+            conn.parameters,
+            vec![b"server_version\x0016.4\x00".to_vec()]
+        );
+        assert_eq!(conn.backend_key, Some([0, 0, 0, 42, 1, 2, 3, 4]));
+        let startup = &conn.stream.received[0];
+        assert_eq!(&startup[4..8], &196608i32.to_be_bytes());
+        assert!(startup.ends_with(b"user\0app\0database\0appdb\0\0"));
+
+        let server = FakeServer::new(|_: &[u8]| messages(&[(b'R', &auth(5, b"salt"))]));
+        assert!(matches!(
+            server_handshake(server, &pool_key("secret")),
+            Err(ServerConnectError::Rejected(_))
+        ));
+    }
+
+    /// Answer a SCRAM exchange for `password` the way PostgreSQL does;
+    /// `tamper` corrupts the server signature.
+    fn scram_server(password: &'static str, tamper: bool) -> impl FnMut(&[u8]) -> Vec<u8> {
+        let mut exchange: Option<(String, String)> = None;
+        move |message: &[u8]| {
+            if message[4..8] == 196608i32.to_be_bytes() {
+                return messages(&[(b'R', &auth(10, b"SCRAM-SHA-256\0\0"))]);
+            }
+            let (tag, body) = read_pg_message(&mut &message[..]).unwrap();
+            assert_eq!(tag, b'p');
+            match exchange.take() {
+                // SASLInitialResponse: mechanism, length, client-first-message
+                None => {
+                    assert!(body.starts_with(b"SCRAM-SHA-256\0"));
+                    let client_first = String::from_utf8(body[18..].to_vec()).unwrap();
+                    let first_bare = client_first.strip_prefix("n,,").unwrap().to_string();
+                    let nonce = first_bare.strip_prefix("n=,r=").unwrap();
+                    let server_first =
+                        format!("r={}srvnonce,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096", nonce);
+                    exchange = Some((first_bare, server_first.clone()));
+                    messages(&[(b'R', &auth(11, server_first.as_bytes()))])
+                }
+                // SASLResponse: client-final-message with the proof
+                Some((first_bare, server_first)) => {
+                    use base64::Engine;
+                    let (expected, mut signature) =
+                        scram_client_final(password, &first_bare, &server_first).unwrap();
+                    if body != expected.as_bytes() {
+                        return messages(&[(
+                            b'E',
+                            &pg_error_body("28P01", "password authentication failed"),
+                        )]);
+                    }
+                    if tamper {
+                        signature[0] ^= 1;
+                    }
+                    let server_final = format!(
+                        "v={}",
+                        base64::engine::general_purpose::STANDARD.encode(signature)
+                    );
+                    [
+                        messages(&[(b'R', &auth(12, server_final.as_bytes()))]),
+                        startup_done(),
+                    ]
+                    .concat()
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn server_handshake_scram() {
+        let conn = server_handshake(
+            FakeServer::new(scram_server("secret", false)),
+            &pool_key("secret"),
+        );
+        assert!(matches!(
+            conn,
+            Ok(ServerConn {
+                backend_key: Some(_),
+                ..
+            })
+        ));
+
+        let wrong_password = server_handshake(
+            FakeServer::new(scram_server("secret", false)),
+            &pool_key("other"),
+        );
+        assert!(
+            matches!(wrong_password, Err(ServerConnectError::Rejected(body)) if pg_error_message(&body) == "password authentication failed")
+        );
+
+        // A server that doesn't know the password can't complete the exchange.
+        let impostor = server_handshake(
+            FakeServer::new(scram_server("secret", true)),
+            &pool_key("secret"),
+        );
+        assert!(
+            matches!(impostor, Err(ServerConnectError::Rejected(body)) if pg_error_message(&body).contains("signature"))
+        );
+    }
+
+    #[test]
+    fn gateway_statement_classification() {
+        assert_eq!(first_sql_word("-- list them\n  (SELECT 1)"), "select");
+        assert_eq!(first_sql_word("EXPLAIN ANALYZE SELECT 1"), "explain");
+        assert_eq!(first_sql_word("-- only a comment"), "");
+
+        assert!(returns_rows("select 1"));
+        assert!(returns_rows("WITH x AS (SELECT 1) SELECT * FROM x"));
+        assert!(returns_rows("VALUES (1), (2)"));
+        assert!(returns_rows("TABLE notes"));
+        assert!(returns_rows("INSERT INTO notes VALUES ('a') RETURNING id"));
+        assert!(!returns_rows("CREATE TABLE notes (body text)"));
+        assert!(!returns_rows("UPDATE notes SET body = 'b'"));
+
+        assert!(constant_time_eq(b"Bearer abc", b"Bearer abc"));
+        assert!(!constant_time_eq(b"Bearer abc", b"Bearer abd"));
+        assert!(!constant_time_eq(b"Bearer abc", b"Bearer ab"));
+    }
+
+    #[test]
+    fn port_range_from_environment() {
+        // The only test that touches PG0_PORT_RANGE.
+        std::env::remove_var("PG0_PORT_RANGE");
+        assert_eq!(port_range().unwrap(), 5432..=5999);
+        std::env::set_var("PG0_PORT_RANGE", " 6000 - 6010 ");
+        assert_eq!(port_range().unwrap(), 6000..=6010);
+        for invalid in ["6000", "6010-6000", "0-10", "a-b", "6000-70000"] {
+            std::env::set_var("PG0_PORT_RANGE", invalid);
+            assert!(port_range().is_err(), "{} should be rejected", invalid);
+        }
+        std::env::remove_var("PG0_PORT_RANGE");
+
+        assert_eq!(port_owner("app/pool"), "app");
+        assert_eq!(port_owner("app"), "app");
+    }
+}